@@ -14,6 +14,10 @@ impl RError {
             details: msg.to_string(),
         }
     }
+
+    pub fn kind(&self) -> &RErrorKind {
+        &self.kind
+    }
 }
 
 impl fmt::Display for RError {
@@ -34,6 +38,8 @@ pub enum RErrorKind {
     ConvertionError,
     InputOutputError,
     ReadHeaderError,
+    LimitExceeded,
+    FileChangedDuringTransfer,
 }
 
 impl RErrorKind {
@@ -43,6 +49,8 @@ impl RErrorKind {
             RErrorKind::ConvertionError => "ConvertionError".to_string(),
             RErrorKind::InputOutputError => "InputOutputError".to_string(),
             RErrorKind::ReadHeaderError => "ReadHeaderError".to_string(),
+            RErrorKind::LimitExceeded => "LimitExceeded".to_string(),
+            RErrorKind::FileChangedDuringTransfer => "FileChangedDuringTransfer".to_string(),
         }
     }
 }
@@ -1,304 +1,2961 @@
+use chrono::Utc;
 use std::{
-    fs::{metadata, File, OpenOptions},
-    io::{BufReader, Error, Read, Seek, SeekFrom, Write}
+    fs::{self, metadata, File, OpenOptions},
+    hash::Hasher,
+    io::{BufReader, Error, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
 use crate::{
     error::error::{RError, RErrorKind},
-    general::general::{write_hex_in_header, write_in_header, Header},
-    hash::hash::get_hash,
+    general::general::{
+        write_hex_in_header, write_in_header, FileTransferLog, Header, LogEntry, TransferLog,
+    },
+    hash::hash::{format_siphash, get_file_hash, get_hash, new_siphasher},
 };
 use crate::{
     general::general::{
-        calc_chunk_count, check_chunk_hash, create_header,
-        write_to_log_file, HeaderData, BUFFER_SIZE, CHUNK_HASH_TYPE, CHUNK_SIZE,
-        USER_HASH,
+        calc_chunk_count, check_chunk_hash, create_header, get_chunk_len_with_size,
+        read_log_file, validate_log_file, HeaderData, BUFFER_SIZE, CHUNK_HASH_TYPE, CHUNK_SIZE,
+        LOGGER_REGEX, MAX_DATA_VEC_LEN, USER_HASH,
     },
     hash::hash::Hash,
+    offer::offer::{read_offer_file, Offer, OfferLimits, OFFER_FILE_EXTENSION},
 };
 
+/// Decides what happens if the output file of a transfer already exists.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverwritePolicy {
+    /// Replace the existing file.
+    Overwrite,
+    /// Decline the transfer.
+    Skip,
+    /// Write to a new file named `name (1).ext`, `name (2).ext`, ...
+    Rename,
+}
 
-/// Writes a data vector to a file.
+impl FromStr for OverwritePolicy {
+    type Err = RError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Overwrite" => Ok(OverwritePolicy::Overwrite),
+            "Skip" => Ok(OverwritePolicy::Skip),
+            "Rename" => Ok(OverwritePolicy::Rename),
+            _ => Err(RError::new(
+                RErrorKind::ConvertionError,
+                "Unknown overwrite policy.",
+            )),
+        }
+    }
+}
+
+/// Resolves the path a received file is written to.
+/// Unless the policy is Overwrite, the returned path is reserved by creating an empty file with `create_new`,
+/// so a file appearing between the check and the write is never replaced.
 ///
 /// # Arguments
-/// 
-/// * header_data - The header data containing information about the file.
-/// * data_vector - The vector of data to be written to the file.
-/// * output_path - The path where the file will be written.
+///
+/// * output_path - The path chosen for the received file.
+/// * policy - What to do if a file already exists at output_path.
 ///
 /// # Returns
 ///
 /// The function returns a Result with two possible outcomes:
-/// * Ok(log_path) - If the data vector is successfully written to the file. log_path is the path of the log file generated during the write operation.
-/// * Err(error) - If there is an error during the write operation, including cases where the data is corrupted and the hash cannot be verified.
-/// 
+/// * Ok(path) - The path the file should be written to.
+/// * Err(error) - If the file exists and the policy is Skip, or the path can't be reserved.
+///
 /// # Errors
 ///
-/// The function can return an error if the data vector is corrupted and the hash cannot be verified. The Error type contains details about the error.
-/// 
-pub fn write_data_vec(
-    header_data: &HeaderData,
-    data_vector: &Vec<u8>,
-    output_path: &str,
-) -> Result<String, Error> {
+/// The function returns an error of kind AlreadyExists if the transfer should be declined.
+///
+pub fn resolve_output_path(output_path: &str, policy: OverwritePolicy) -> Result<String, Error> {
+    let path = Path::new(output_path);
 
-    if check_chunk_hash(
-        &header_data.chunk_hash,
-        &header_data.chunk_hash_alg,
-        &data_vector,
-    ) == true
-    {
-        _ = merge_file_on_path(
-            &output_path,
-            &data_vector,
-            header_data.chunk_pos,
-            CHUNK_SIZE,
-        )?;
-        let logfile_path = format!("{}.rdroplog", output_path);
-        let _log_path = write_to_log_file(
-            &logfile_path,
-            &header_data.user_hash,
-            &header_data.file_hash_alg,
-            header_data.chunk_pos,
-            header_data.chunk_max,
-            header_data.chunk_length as u64,
-            &header_data.file_hash,
-            &header_data.chunk_hash_alg,
-            &header_data.chunk_hash,
-        )?;
-        return Ok(_log_path);
+    match policy {
+        OverwritePolicy::Overwrite => Ok(output_path.to_string()),
+        OverwritePolicy::Skip => match reserve_path(path) {
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => Err(Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "File already exists, transfer skipped.",
+            )),
+            result => result.map(|_| output_path.to_string()),
+        },
+        OverwritePolicy::Rename => {
+            match reserve_path(path) {
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+                result => return result.map(|_| output_path.to_string()),
+            }
+
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let extension = path
+                .extension()
+                .map(|extension| format!(".{}", extension.to_string_lossy()))
+                .unwrap_or_default();
+
+            let mut number: u64 = 1;
+            loop {
+                let candidate = path.with_file_name(format!("{} ({}){}", stem, number, extension));
+                match reserve_path(&candidate) {
+                    Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => number += 1,
+                    result => return result.map(|_| candidate.to_string_lossy().to_string()),
+                }
+            }
+        }
     }
+}
 
-    return Err(Error::new(
-        std::io::ErrorKind::InvalidData,
-        "Corrupted Data, can't verify hash",
-    ));
+/// Creates an empty file at path, failing with AlreadyExists if any file is there.
+fn reserve_path(path: &Path) -> Result<(), Error> {
+    OpenOptions::new().write(true).create_new(true).open(path)?;
+    return Ok(());
 }
 
-/// Splits a file into a single part.
+/// Removes the empty file reserving output_path, see `resolve_output_path`.
+/// Used if a transfer ends before its file is placed. A file which is not empty is kept.
 ///
-/// # Arguments:
-/// 
-/// * buf_reader - A mutable reference to a BufReader wrapping the file to be split.
-/// * part_num - The part number indicating the current part being split.
-/// * file_size - The total size of the file.
-/// * reg_chunk_size - The regular chunk size used for splitting the file.
-/// * file_hash - The hash of the file.
-/// * chunk_count_max - The maximum number of chunks the file can be split into.
-/// * user_hash - The user hash.
-/// * header - A mutable reference to the Header struct containing header information.
-/// * chunk_hash - An optional hash value for the chunk.
+/// # Arguments
+///
+/// * output_path - The path returned by `resolve_output_path`.
+pub fn release_output_path(output_path: &str) {
+    if let Ok(metadata) = fs::metadata(output_path) {
+        if metadata.is_file() && metadata.len() == 0 {
+            let _ = fs::remove_file(output_path);
+        }
+    }
+}
+
+/// Sanitizes a file name offered by a peer, so it can be used below an output directory.
+/// Directory traversal (`..`) and current directory components are removed,
+/// `\\` is treated like `/` so names from any platform are handled the same.
+///
+/// # Arguments
+///
+/// * name - The offered file name.
 ///
 /// # Returns
 ///
-/// The function returns a Result with the following outcomes:
-/// * Ok(byte_vec) - If the file part is successfully split. byte_vec is a vector of bytes containing the header and the data of the part.
-/// * Err(error) - If there is an error during the splitting process, including input/output errors.
-/// 
-pub fn split_file_single(
-    buf_reader: &mut BufReader<&mut File>,
-    part_num: usize,
-    file_size: usize,
-    reg_chunk_size: usize,
-    file_hash: &str,
-    chunk_count_max: u64,
-    user_hash: &str,
-    header: &mut Header,
-    chunk_hash: &Option<Hash>,
-) -> Result<Vec<u8>, RError> {
-    let mut chunk_size = reg_chunk_size;
+/// The function returns the relative path the file may be written to.
+///
+/// # Errors
+///
+/// The function returns an `InputOutputError` if the name is an absolute path or nothing is left after sanitizing.
+pub fn sanitize_output_name(name: &str) -> Result<PathBuf, RError> {
+    let name = name.replace('\\', "/");
+    let is_drive = name.len() >= 2 && name.as_bytes()[1] == b':' && name.as_bytes()[0].is_ascii_alphabetic();
 
-    if part_num as u64 == chunk_count_max {
-        chunk_size = file_size - (chunk_size * (part_num - 1));
+    if name.starts_with('/') || is_drive {
+        return Err(RError::new(
+            RErrorKind::InputOutputError,
+            "Offered name is an absolute path.",
+        ));
     }
 
-    let start_pos: u64;
+    let mut sanitized = PathBuf::new();
+    for component in Path::new(&name).components() {
+        match component {
+            Component::Normal(part) => sanitized.push(part),
+            Component::CurDir | Component::ParentDir => {}
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(RError::new(
+                    RErrorKind::InputOutputError,
+                    "Offered name is an absolute path.",
+                ));
+            }
+        }
+    }
 
-    if part_num == 1 {
-        start_pos = 0;
-    } else {
-        start_pos = (part_num as u64 - 1) * reg_chunk_size as u64;
+    if sanitized.as_os_str().is_empty() {
+        return Err(RError::new(
+            RErrorKind::InputOutputError,
+            "Offered name is empty.",
+        ));
     }
-    let mut buffer = vec![0; chunk_size];
 
-    buf_reader
-        .seek(SeekFrom::Start(start_pos))
-        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+    return Ok(sanitized);
+}
 
-    buf_reader
-        .read(&mut buffer)
-        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+/// Returns the path an offered file is written to below an output directory.
+///
+/// # Arguments
+///
+/// * output_dir - The directory all received files are confined to.
+/// * name - The offered file name, see `sanitize_output_name`.
+///
+/// # Returns
+///
+/// The function returns the path of the file below output_dir.
+///
+/// # Errors
+///
+/// The function returns an `InputOutputError` if the name can't be sanitized.
+pub fn confine_output_path(output_dir: &str, name: &str) -> Result<PathBuf, RError> {
+    return Ok(Path::new(output_dir).join(sanitize_output_name(name)?));
+}
 
-    write_in_header(
-        &mut header.fix_header,
-        part_num as u64,
-        header.chunk_pos_s,
-        header.chunk_pos_e,
-    );
-    write_in_header(
-        &mut header.fix_header,
-        chunk_count_max,
-        header.chunk_max_pos_s,
-        header.chunk_max_pos_e,
-    );
-    write_in_header(
-        &mut header.fix_header,
-        chunk_size as u64,
-        header.chunk_length_pos_s,
-        header.chunk_length_pos_e,
-    );
-    write_hex_in_header(
-        &mut header.fix_header,
-        user_hash,
-        header.user_pos_s,
-        header.user_pos_e,
-    )?;
-    write_hex_in_header(
-        &mut header.fix_header,
-        file_hash,
-        header.file_hash_pos_s,
-        header.file_hash_pos_e,
-    )?;
+/// Template for the name of a received file, which keeps the offered name.
+pub const DEFAULT_NAME_TEMPLATE: &str = "{name}";
 
-    if chunk_hash.is_some() {
-        let chunk_hash = get_hash(&buffer, &chunk_hash.as_ref().expect("chunk_hash not set."));
-        write_hex_in_header(
-            &mut header.fix_header,
-            &chunk_hash,
-            header.chunk_hash_pos_s.expect("chunk_hash not set."),
-            header.chunk_hash_pos_e.expect("chunk_hash not set."),
-        )?;
-    }
+/// Renders the name a received file is written to, e.g. `{date}_{name}` to namespace received files by day.
+/// Supported placeholders are `{name}` (offered name), `{hash}` (file hash), `{date}` (current UTC date as `YYYY-MM-DD`)
+/// and `{ext}` (extension without the dot). Other text, including unknown placeholders, is kept as it is.
+/// The result still has to be sanitized, see `templated_output_path`.
+///
+/// # Arguments
+///
+/// * template - The template of the name.
+/// * offer - The offer of the received file.
+///
+/// # Returns
+///
+/// The function returns the rendered name.
+pub fn render_output_name(template: &str, offer: &Offer) -> String {
+    let date = Utc::now().format("%Y-%m-%d").to_string();
+    return render_output_name_on(template, offer, &date);
+}
 
-    let mut byte_vec: Vec<u8> = Vec::new();
-    byte_vec.extend(header.fix_header.iter());
-    byte_vec.extend(buffer.iter());
+fn render_output_name_on(template: &str, offer: &Offer, date: &str) -> String {
+    let mut rendered = String::with_capacity(template.len() + offer.name.len());
+    let mut rest = template;
 
-    return Ok(byte_vec);
-}
+    // placeholders are replaced in one pass, so values containing braces are never expanded
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
 
+        let value = match rest.find('}').map(|end| &rest[..=end]) {
+            Some("{name}") => offer.name.as_str(),
+            Some("{hash}") => offer.file_hash.as_str(),
+            Some("{date}") => date,
+            Some("{ext}") => offer.extension.as_str(),
+            _ => {
+                rendered.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        rendered.push_str(value);
+        rest = &rest[rest.find('}').unwrap_or(0) + 1..];
+    }
+    rendered.push_str(rest);
 
-/// Merges a data vector into a file at the specified position.
+    return rendered;
+}
+
+/// Returns the path an offered file is written to below an output directory, named by a template.
 ///
 /// # Arguments
 ///
-/// * output_path - The path of the output file.
-/// * byte_vec - The data vector to be merged into the file.
-/// * chunk_number - The chunk number indicating the position within the file where the data vector should be merged.
-/// * reg_chunk_size - The regular chunk size used for calculating the start position.
+/// * output_dir - The directory all received files are confined to.
+/// * template - The template of the name, see `render_output_name`.
+/// * offer - The offer of the received file.
 ///
 /// # Returns
 ///
-/// The function returns a Result with two possible outcomes:
-/// * Ok(result_path) - If the data vector is successfully merged into the file. result_path is the path of the output file.
-/// * Err(error) - If there is an error during the merging process, including file I/O errors.
+/// The function returns the path of the file below output_dir.
 ///
 /// # Errors
 ///
-/// The function can return an error if there is an error creating or opening the output file, checking the file size, or performing file I/O operations. The Error type contains details about the error.
-/// 
-pub fn merge_file_on_path(
-    output_path: &str,
-    byte_vec: &Vec<u8>,
-    chunk_number: u64,
-    reg_chunk_size: usize,
-) -> Result<String, Error> {
-    //create output file in outputDir if not there
-    let mut output_file = match OpenOptions::new()
-        .write(true)
-        .append(false)
-        .create(true)
-        .open(&output_path)
-    {
-        Ok(file) => file,
-        Err(err) => {
-            return Err(err);
-        }
-    };
+/// The function returns an `InputOutputError` if the rendered name can't be sanitized, see `sanitize_output_name`.
+pub fn templated_output_path(output_dir: &str, template: &str, offer: &Offer) -> Result<PathBuf, RError> {
+    return confine_output_path(output_dir, &render_output_name(template, offer));
+}
 
-    //get start_pos
-    let start_pos = reg_chunk_size as u64 * (chunk_number - 1);
+/// Makes sure a path ends with the given extension, so a received file keeps its type
+/// even if its name was changed by the receiver.
+///
+/// # Arguments
+///
+/// * output_path - The path chosen for the received file.
+/// * extension - The extension of the offered file without the dot. An extension which is empty or contains other
+///   characters than ASCII letters and digits leaves the path unchanged, it comes from the peer.
+///
+/// # Returns
+///
+/// The function returns output_path, with `.extension` appended if it has a different extension.
+pub fn ensure_extension(output_path: &str, extension: &str) -> String {
+    if extension.is_empty() || !extension.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return output_path.to_string();
+    }
 
-    //check file_size
-    let output_file_size = output_file.metadata().unwrap().len();
+    let has_extension = Path::new(output_path)
+        .extension()
+        .map_or(false, |current| current.to_string_lossy().eq_ignore_ascii_case(extension));
 
+    if has_extension {
+        return output_path.to_string();
+    }
 
-    //fill file with 0
-    if output_file_size < start_pos {
-        output_file.seek(SeekFrom::End(0)).unwrap();
+    return format!("{}.{}", output_path, extension);
+}
 
-        let size_to_append = start_pos - output_file_size;
+/// Extension of the temporary file a received file is written to until it is complete.
+pub const TEMP_FILE_EXTENSION: &str = "rdroppart";
 
-        let zero_byte_vec: [u8; 256000] = [0; 256000];
+/// Extension of the logfile stored next to the received data of a transfer.
+pub const LOGFILE_EXTENSION: &str = "rdroplog";
 
-        for _ in 0..(size_to_append / zero_byte_vec.len() as u64) {
-            output_file.write_all(&zero_byte_vec).unwrap();
-        }
+/// Extension of the backup an existing file is renamed to while received files are moved to their final paths,
+/// see `move_temp_files`.
+const BACKUP_FILE_EXTENSION: &str = "rdropbackup";
 
-        let size_modulo = size_to_append % zero_byte_vec.len() as u64;
+/// Extension of the copy a temporary file is written to in the target directory before it is renamed to its final path.
+const COPY_FILE_EXTENSION: &str = "rdropcopy";
 
-        if size_modulo != 0 {
-            output_file
-                .write_all(&zero_byte_vec[0..size_modulo as usize])
-                .unwrap();
-        }
+/// Returns the path of the temporary file a received file is written to.
+/// The logfile and the offer file of the transfer are named after the temporary file.
+///
+/// # Arguments
+///
+/// * output_path - The final path of the received file.
+/// * temp_dir - The directory for temporary files. If None, the directory of output_path is used.
+///
+/// # Returns
+///
+/// The function returns the path of the temporary file.
+pub fn get_temp_path(output_path: &str, temp_dir: Option<&str>) -> String {
+    let path = Path::new(output_path);
+    let file_name = format!(
+        "{}.{}",
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        TEMP_FILE_EXTENSION
+    );
+
+    let temp_path = match temp_dir {
+        Some(dir) => Path::new(dir).join(file_name),
+        None => path.with_file_name(file_name),
+    };
+
+    return temp_path.to_string_lossy().to_string();
+}
+
+/// A file handle whose written data can be forced from the OS buffers to the disk.
+pub trait SyncAll: Write {
+    fn sync_all(&self) -> std::io::Result<()>;
+}
+
+impl SyncAll for File {
+    fn sync_all(&self) -> std::io::Result<()> {
+        return File::sync_all(self);
     }
+}
 
-    output_file.seek(SeekFrom::Start(start_pos)).unwrap();
-    output_file.write_all(byte_vec).unwrap();
+/// Flushes a file and waits until its data reached the disk. The file is closed afterwards.
+///
+/// # Arguments
+///
+/// * file - The file handle to sync.
+///
+/// # Errors
+///
+/// The function returns an error if the data cannot be flushed or synced.
+pub fn sync_file<F: SyncAll>(mut file: F) -> Result<(), RError> {
+    file.flush()
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+    file.sync_all()
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+    drop(file);
 
-    return Ok(output_path.to_string());
+    return Ok(());
 }
 
+/// Opens a file for writing and syncs it to the disk, see `sync_file`.
+fn sync_path(path: &str) -> Result<(), RError> {
+    let file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
 
+    return sync_file(file);
+}
 
+/// Syncs the directory containing a path, so an entry renamed into it survives a crash.
+/// Directories can only be synced on Unix, on other systems the rename is left to the file system.
+fn sync_parent_dir(path: &str) -> Result<(), RError> {
+    #[cfg(unix)]
+    {
+        let parent = match Path::new(path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(parent)
+            .and_then(|dir| dir.sync_all())
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
 
+    return Ok(());
+}
 
-/// Creates a data vector from a file.
+/// Moves a completely received temporary file to its final path.
+/// The file is only moved if its hash matches the hash of the offer. The logfile and the offer file are removed.
+/// The data is synced to the disk before the file is moved and its directory afterwards, so a finished file survives a crash.
 ///
 /// # Arguments
 ///
-/// * path - The path of the file.
-/// * chunk_num - The chunk number indicating the position of the data vector within the file.
-/// * file_hash - The file hash.
+/// * temp_path - The path of the temporary file.
+/// * output_path - The final path of the received file.
+/// * file_hash - The expected hash of the file.
+/// * hash_type - The algorithm of file_hash.
 ///
 /// # Returns
 ///
-/// The function returns a Result with two possible outcomes:
-/// * Ok(split_vec) - If the data vector is successfully created from the file. split_vec is the resulting data vector.
-/// * Err(error) - If there is an error during the creation of the data vector, including file I/O errors.
+/// The function returns Ok(()) if the file was moved to output_path.
 ///
 /// # Errors
 ///
-/// The function can return an error if there is an error opening the file, reading its metadata, or performing file I/O operations. The RError type contains details about the error.
-pub fn create_data_vec(path: &str, chunk_num: u64, file_hash: &str) -> Result<Vec<u8>, RError> {
-    let mut file = File::open(path)
-        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
-    let file_size = metadata(path)
-        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?
-        .len();
-    let mut buf_reader = BufReader::with_capacity(BUFFER_SIZE, &mut file);
-    let max_chunk_count = calc_chunk_count(CHUNK_SIZE, file_size)?;
-
-    let mut header = create_header(
-        file_size,
-        max_chunk_count,
-        &Hash::SIPHASH24,
-        &Some(CHUNK_HASH_TYPE),
-    );
-    let split_vec = split_file_single(
-        &mut buf_reader,
-        chunk_num as usize,
-        file_size as usize,
-        CHUNK_SIZE,
-        &file_hash,
-        max_chunk_count,
-        &USER_HASH,
-        &mut header,
-        &Some(CHUNK_HASH_TYPE),
-    )?;
+/// The function returns an error if the hash does not match or the file cannot be moved. The temporary file is kept in this case.
+pub fn finish_temp_file(
+    temp_path: &str,
+    output_path: &str,
+    file_hash: &str,
+    hash_type: &Hash,
+) -> Result<(), RError> {
+    verify_temp_file(temp_path, file_hash, hash_type)?;
+    place_temp_file(temp_path, output_path)?;
+    remove_temp_logs(temp_path);
 
-    return Ok(split_vec);
+    return Ok(());
 }
 
-
+/// Checks the hash of a completely received temporary file.
+///
+/// # Arguments
+///
+/// * temp_path - The path of the temporary file.
+/// * file_hash - The expected hash of the file.
+/// * hash_type - The algorithm of file_hash.
+///
+/// # Returns
+///
+/// The function returns Ok(()) if the hash of the file matches file_hash.
+///
+/// # Errors
+///
+/// The function returns an error if the hash does not match or the file cannot be read.
+pub fn verify_temp_file(temp_path: &str, file_hash: &str, hash_type: &Hash) -> Result<(), RError> {
+    let file = File::open(temp_path)
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+    let hash = get_file_hash(&file, BUFFER_SIZE, hash_type, 0)
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+    drop(file);
+
+    if !hash.eq_ignore_ascii_case(file_hash) {
+        return Err(RError::new(
+            RErrorKind::InputOutputError,
+            "Corrupted Data, file hash does not match",
+        ));
+    }
+
+    return Ok(());
+}
+
+/// Moves several verified temporary files to their final paths as a unit, e.g. the files of an offer group.
+/// Files which already exist at the final paths are renamed to backups first.
+/// If a file cannot be moved, the files moved before are moved back and the backups are restored,
+/// so either all or none of the files are in place. The backups, logfiles and offer files are only removed once every file was moved.
+///
+/// # Arguments
+///
+/// * files - The path of each temporary file and its final path.
+///
+/// # Returns
+///
+/// The function returns Ok(()) if every file was moved.
+///
+/// # Errors
+///
+/// The function returns the error of the file which could not be moved. The temporary files are kept in this case.
+pub fn move_temp_files(files: &[(&str, &str)]) -> Result<(), RError> {
+    let mut backups = Vec::new();
+    let mut placed = 0;
+    let mut result = Ok(());
+
+    for (temp_path, output_path) in files {
+        // a rename would replace the existing file, which could not be restored then
+        if Path::new(output_path).exists() {
+            let backup_path = format!("{}.{}", output_path, BACKUP_FILE_EXTENSION);
+            if let Err(err) = fs::rename(output_path, &backup_path) {
+                result = Err(RError::new(RErrorKind::InputOutputError, &err.to_string()));
+                break;
+            }
+            backups.push((*output_path, backup_path));
+        }
+
+        if let Err(err) = place_temp_file(temp_path, output_path) {
+            // the file may have been moved before syncing its directory failed
+            if !Path::new(temp_path).exists() && Path::new(output_path).exists() {
+                placed += 1;
+            }
+            result = Err(err);
+            break;
+        }
+        placed += 1;
+    }
+
+    if let Err(err) = result {
+        for (moved_temp, moved_output) in &files[..placed] {
+            if let Err(err) = place_temp_file(moved_output, moved_temp) {
+                println!("[FILE] can't move back {}: {}", moved_output, err);
+            }
+        }
+        for (output_path, backup_path) in &backups {
+            if let Err(err) = fs::rename(backup_path, output_path) {
+                println!("[FILE] can't restore {}: {}", output_path, err);
+            }
+        }
+        return Err(err);
+    }
+
+    for (_, backup_path) in &backups {
+        _ = fs::remove_file(backup_path);
+    }
+    for (temp_path, _) in files {
+        remove_temp_logs(temp_path);
+    }
+
+    return Ok(());
+}
+
+/// Syncs a file and moves it to another path, copying it if the paths are on different file systems.
+/// A copy is written next to the final path first and renamed, so the final path never holds a partial file.
+fn place_temp_file(temp_path: &str, output_path: &str) -> Result<(), RError> {
+    sync_path(temp_path)?;
+
+    // renaming fails if the temporary directory is on another file system
+    if fs::rename(temp_path, output_path).is_err() {
+        let copy_path = format!("{}.{}", output_path, COPY_FILE_EXTENSION);
+        let copied = fs::copy(temp_path, &copy_path)
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))
+            .and_then(|_| sync_path(&copy_path))
+            .and_then(|_| {
+                fs::rename(&copy_path, output_path)
+                    .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))
+            });
+        if let Err(err) = copied {
+            _ = fs::remove_file(&copy_path);
+            return Err(err);
+        }
+        fs::remove_file(temp_path)
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+    }
+
+    return sync_parent_dir(output_path);
+}
+
+/// Removes the logfile and the offer file of a temporary file.
+fn remove_temp_logs(temp_path: &str) {
+    _ = fs::remove_file(format!("{}.{}", temp_path, LOGFILE_EXTENSION));
+    _ = fs::remove_file(format!("{}.{}", temp_path, OFFER_FILE_EXTENSION));
+}
+
+/// Writes a manifest next to a received file, so its integrity can be verified later without the peer.
+/// The manifest is stored as JSON in `<output_path>.rdrop.json` and records the name, hash, hash algorithm, size,
+/// chunk count and the time of completion.
+///
+/// # Arguments
+///
+/// * output_path - The path of the received file.
+/// * offer - The offer of the received file.
+///
+/// # Returns
+///
+/// The function returns a Result containing the path of the manifest if successful.
+///
+/// # Errors
+///
+/// The function can return an error if the chunk count of the offered size is invalid or the manifest cannot be written.
+pub fn write_manifest_file(output_path: &str, offer: &Offer) -> Result<String, RError> {
+    let manifest_path = format!("{}.rdrop.json", output_path);
+    let chunk_count = calc_chunk_count(offer.chunk_size, offer.size)?;
+
+    let manifest = format!(
+        "{{\n  \"name\": \"{}\",\n  \"hash\": \"{}\",\n  \"algorithm\": \"{}\",\n  \"size\": {},\n  \"chunk_count\": {},\n  \"completed\": \"{}\"\n}}\n",
+        escape_json(&offer.name),
+        escape_json(&offer.file_hash),
+        offer.hash_type.to_string(),
+        offer.size,
+        chunk_count,
+        Utc::now().to_rfc3339(),
+    );
+
+    fs::write(&manifest_path, manifest)
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+
+    return Ok(manifest_path);
+}
+
+/// Escapes a string for use inside a JSON string literal.
+fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    return escaped;
+}
+
+/// Writes a data vector to a file.
+///
+/// # Arguments
+/// 
+/// * header_data - The header data containing information about the file.
+/// * data_vector - The vector of data to be written to the file.
+/// * output_path - The path where the file will be written.
+///
+/// # Returns
+///
+/// The function returns a Result with two possible outcomes:
+/// * Ok(log_path) - If the data vector is successfully written to the file. log_path is the path of the log file generated during the write operation.
+/// * Err(error) - If there is an error during the write operation, including cases where the data is corrupted and the hash cannot be verified.
+/// 
+/// # Errors
+///
+/// The function can return an error if the data vector is corrupted and the hash cannot be verified. The Error type contains details about the error.
+/// 
+pub fn write_data_vec(
+    header_data: &HeaderData,
+    data_vector: &Vec<u8>,
+    output_path: &str,
+) -> Result<String, Error> {
+    let mut log = FileTransferLog::new(&format!("{}.{}", output_path, LOGFILE_EXTENSION));
+    write_data_vec_to_log(header_data, data_vector, output_path, &mut log)?;
+
+    return Ok(log.path().to_string());
+}
+
+/// Writes a data vector to a file like `write_data_vec`, recording the chunk in the given log instead of the logfile.
+///
+/// # Arguments
+///
+/// * header_data - The header data containing information about the file.
+/// * data_vector - The vector of data to be written to the file.
+/// * output_path - The path where the file will be written.
+/// * log - The log the chunk is recorded in once it was written.
+///
+/// # Errors
+///
+/// The function can return an error if the data vector is corrupted and the hash cannot be verified,
+/// or if the file cannot be written. The chunk is not recorded in this case.
+/// It also returns an error if the chunk was written but can't be recorded, so it has to be requested again.
+pub fn write_data_vec_to_log<L: TransferLog + ?Sized>(
+    header_data: &HeaderData,
+    data_vector: &Vec<u8>,
+    output_path: &str,
+    log: &mut L,
+) -> Result<(), Error> {
+    if !check_chunk_hash(
+        &header_data.chunk_hash,
+        &header_data.chunk_hash_alg,
+        data_vector,
+    ) {
+        return Err(Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Corrupted Data, can't verify hash",
+        ));
+    }
+
+    _ = merge_file_on_path(
+        output_path,
+        data_vector,
+        header_data.chunk_pos,
+        CHUNK_SIZE,
+    )?;
+    log.record(&LogEntry::from_header_data(header_data))
+        .map_err(|err| Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+
+    return Ok(());
+}
+
+/// Decides how many received chunks are collected before they are written, see `BatchedChunkWriter`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WriteBatching {
+    /// The number of chunks collected before they are written. 1 writes every chunk immediately.
+    pub max_chunks: usize,
+    /// The time after which collected chunks are written, even if the batch is not full.
+    pub interval: Duration,
+}
+
+impl Default for WriteBatching {
+    fn default() -> Self {
+        WriteBatching {
+            max_chunks: 1,
+            interval: Duration::ZERO,
+        }
+    }
+}
+
+/// Writes received chunks and their log entries like `write_data_vec`, but in batches.
+/// Adjacent chunks of a batch are written with a single write, and the log entries of a batch are appended with a
+/// single write after all of its data was written and synced to disk. If the process or the system crashes during a
+/// batch, the logfile only lists chunks which were written. Collected chunks are written when the batch is full, when the interval elapsed,
+/// on `flush` and when the writer is dropped.
+pub struct BatchedChunkWriter {
+    output_path: String,
+    log: FileTransferLog,
+    batching: WriteBatching,
+    /// The collected chunks as offset and data.
+    chunks: Vec<(u64, Vec<u8>)>,
+    /// The log entries of the collected chunks.
+    entries: Vec<LogEntry>,
+    /// The algorithm of the offered file hash, logged instead of the one of the header.
+    file_hash_alg: Option<Hash>,
+    /// The size of the chunks, the offset of a chunk is its position times the size.
+    chunk_size: usize,
+    started: Instant,
+    output_file: Option<File>,
+}
+
+impl BatchedChunkWriter {
+    /// Creates a writer for a received file.
+    ///
+    /// # Arguments
+    ///
+    /// * output_path - The path the chunks are written to. The logfile is stored next to it.
+    /// * batching - How many chunks are collected before they are written.
+    pub fn new(output_path: &str, batching: WriteBatching) -> BatchedChunkWriter {
+        return BatchedChunkWriter {
+            output_path: output_path.to_string(),
+            log: FileTransferLog::new(&format!("{}.{}", output_path, LOGFILE_EXTENSION)),
+            batching,
+            chunks: Vec::new(),
+            entries: Vec::new(),
+            file_hash_alg: None,
+            chunk_size: CHUNK_SIZE,
+            started: Instant::now(),
+            output_file: None,
+        };
+    }
+
+    /// Logs runs of contiguous chunks in one line each instead of every chunk, see `FileTransferLog::compacted`.
+    /// The open run is written on `close` and when the writer is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * compact - If the log is compacted.
+    pub fn set_compact_log(&mut self, compact: bool) {
+        let log_path = self.log.path().to_string();
+        self.log = if compact {
+            FileTransferLog::compacted(&log_path)
+        } else {
+            FileTransferLog::new(&log_path)
+        };
+    }
+
+    /// Sets the algorithm of the offered file hash, which is logged for every chunk.
+    /// Chunks whose header names another algorithm are logged with the offered one all the same.
+    ///
+    /// # Arguments
+    ///
+    /// * file_hash_alg - The hash type of the offer, see `Offer::hash_type`.
+    pub fn set_file_hash_alg(&mut self, file_hash_alg: Hash) {
+        self.file_hash_alg = Some(file_hash_alg);
+    }
+
+    /// Sets the size of the chunks, which is `CHUNK_SIZE` unless the offer announced another one.
+    ///
+    /// # Arguments
+    ///
+    /// * chunk_size - The chunk size of the offer, see `Offer::chunk_size`.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size;
+    }
+
+    /// Verifies a chunk and adds it to the batch. The batch is written if it is full or its interval elapsed.
+    ///
+    /// # Arguments
+    ///
+    /// * header_data - The header data of the chunk.
+    /// * data_vector - The data of the chunk.
+    ///
+    /// # Returns
+    ///
+    /// The function returns the path of the logfile.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the chunk hash cannot be verified or the batch cannot be written.
+    pub fn write(&mut self, header_data: &HeaderData, data_vector: &Vec<u8>) -> Result<String, Error> {
+        if !check_chunk_hash(
+            &header_data.chunk_hash,
+            &header_data.chunk_hash_alg,
+            data_vector,
+        ) {
+            return Err(Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Corrupted Data, can't verify hash",
+            ));
+        }
+
+        return self.write_verified(header_data, data_vector);
+    }
+
+    /// Adds a chunk to the batch like `write`, for chunks which were already verified with `check_chunk_hash`.
+    ///
+    /// # Arguments
+    ///
+    /// * header_data - The header data of the chunk.
+    /// * data_vector - The data of the chunk.
+    ///
+    /// # Returns
+    ///
+    /// The function returns the path of the logfile.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the batch cannot be written.
+    pub fn write_verified(
+        &mut self,
+        header_data: &HeaderData,
+        data_vector: &[u8],
+    ) -> Result<String, Error> {
+        if self.chunks.is_empty() {
+            self.started = Instant::now();
+        }
+
+        let offset = self.chunk_size as u64 * header_data.chunk_pos.saturating_sub(1);
+        self.chunks.push((offset, data_vector.to_vec()));
+        let mut entry = LogEntry::from_header_data(header_data);
+        if let Some(file_hash_alg) = self.file_hash_alg {
+            entry.file_hash_alg = file_hash_alg;
+        }
+        self.entries.push(entry);
+
+        self.flush_if_due()?;
+
+        return Ok(self.log.path().to_string());
+    }
+
+    /// Writes the collected chunks if the batch is full or its interval elapsed, see `flush`.
+    pub fn flush_if_due(&mut self) -> Result<(), Error> {
+        if self.chunks.len() >= self.batching.max_chunks
+            || self.started.elapsed() >= self.batching.interval
+        {
+            self.flush()?;
+        }
+
+        return Ok(());
+    }
+
+    /// Writes the collected chunks and syncs them to disk, then appends their log entries.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the output file or the logfile cannot be written.
+    /// The log entries of the batch are kept in this case, so they are never logged without their data.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.chunks.is_empty() {
+            return Ok(());
+        }
+
+        if self.output_file.is_none() {
+            self.output_file = Some(
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&self.output_path)?,
+            );
+        }
+        let output_file = self.output_file.as_mut().unwrap();
+
+        self.chunks.sort_by_key(|(offset, _)| *offset);
+        let mut index = 0;
+        while index < self.chunks.len() {
+            // adjacent chunks are written at once
+            let (start, data) = &self.chunks[index];
+            let start = *start;
+            let mut run = data.clone();
+            let mut end = start + data.len() as u64;
+            index += 1;
+            while index < self.chunks.len() && self.chunks[index].0 == end {
+                run.extend_from_slice(&self.chunks[index].1);
+                end += self.chunks[index].1.len() as u64;
+                index += 1;
+            }
+
+            // writing behind the end of the file fills the gap with zeros
+            output_file.seek(SeekFrom::Start(start))?;
+            output_file.write_all(&run)?;
+        }
+        self.chunks.clear();
+        output_file.sync_data()?;
+
+        // sorted entries continue the runs of a compacted log
+        self.entries.sort_by_key(|entry| entry.chunk_part);
+        self.log
+            .record_all(&self.entries)
+            .map_err(|err| Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+        self.entries.clear();
+
+        return Ok(());
+    }
+
+    /// Writes the collected chunks like `flush` and the open run of a compacted log.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the output file or the logfile cannot be written.
+    pub fn close(mut self) -> Result<(), Error> {
+        self.flush()?;
+        self.log
+            .flush()
+            .map_err(|err| Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+
+        return Ok(());
+    }
+}
+
+impl Drop for BatchedChunkWriter {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            println!("[WRITER] can't write batch of {}: {}", self.output_path, err);
+        }
+    }
+}
+
+/// Splits a file into a single part.
+///
+/// # Arguments:
+/// 
+/// * buf_reader - A mutable reference to the seekable source to be split, e.g. a BufReader wrapping the file.
+/// * part_num - The part number indicating the current part being split.
+/// * file_size - The total size of the file.
+/// * reg_chunk_size - The regular chunk size used for splitting the file.
+/// * file_hash - The hash of the file.
+/// * chunk_count_max - The maximum number of chunks the file can be split into.
+/// * user_hash - The user hash.
+/// * header - A mutable reference to the Header struct containing header information.
+/// * chunk_hash - An optional hash value for the chunk.
+///
+/// # Returns
+///
+/// The function returns a Result with the following outcomes:
+/// * Ok(byte_vec) - If the file part is successfully split. byte_vec is a vector of bytes containing the header and the data of the part.
+/// * Err(error) - If there is an error during the splitting process, including input/output errors.
+/// 
+pub fn split_file_single<R: Read + Seek>(
+    buf_reader: &mut R,
+    part_num: usize,
+    file_size: usize,
+    reg_chunk_size: usize,
+    file_hash: &str,
+    chunk_count_max: u64,
+    user_hash: &str,
+    header: &mut Header,
+    chunk_hash: &Option<Hash>,
+) -> Result<Vec<u8>, RError> {
+    let mut byte_vec: Vec<u8> = Vec::new();
+    split_file_single_into(
+        buf_reader,
+        part_num,
+        file_size,
+        reg_chunk_size,
+        file_hash,
+        chunk_count_max,
+        user_hash,
+        header,
+        chunk_hash,
+        &mut byte_vec,
+    )?;
+
+    return Ok(byte_vec);
+}
+
+/// Splits a file into a single part like `split_file_single`, but writes the part into an existing buffer.
+/// The buffer is cleared first, so a recycled buffer never carries data of a previous chunk.
+///
+/// # Arguments:
+///
+/// * buf_reader - A mutable reference to the seekable source to be split.
+/// * part_num - The part number indicating the current part being split.
+/// * file_size - The total size of the file.
+/// * reg_chunk_size - The regular chunk size used for splitting the file.
+/// * file_hash - The hash of the file.
+/// * chunk_count_max - The maximum number of chunks the file can be split into.
+/// * user_hash - The user hash.
+/// * header - A mutable reference to the Header struct containing header information.
+/// * chunk_hash - An optional hash value for the chunk.
+/// * byte_vec - The buffer receiving the header and the data of the part.
+///
+/// # Errors
+///
+/// The function can return an error if seeking or reading the source fails. The buffer is left empty in that case.
+pub fn split_file_single_into<R: Read + Seek>(
+    buf_reader: &mut R,
+    part_num: usize,
+    file_size: usize,
+    reg_chunk_size: usize,
+    file_hash: &str,
+    chunk_count_max: u64,
+    user_hash: &str,
+    header: &mut Header,
+    chunk_hash: &Option<Hash>,
+    byte_vec: &mut Vec<u8>,
+) -> Result<(), RError> {
+    let mut chunk_size = reg_chunk_size;
+
+    if part_num as u64 == chunk_count_max {
+        chunk_size = file_size - (chunk_size * (part_num - 1));
+    }
+
+    let start_pos: u64;
+
+    if part_num == 1 {
+        start_pos = 0;
+    } else {
+        start_pos = (part_num as u64 - 1) * reg_chunk_size as u64;
+    }
+    let header_len = header.fix_header.len();
+    byte_vec.clear();
+    byte_vec.resize(header_len + chunk_size, 0);
+
+    let read_result = buf_reader
+        .seek(SeekFrom::Start(start_pos))
+        .and_then(|_| buf_reader.read_exact(&mut byte_vec[header_len..]));
+    if let Err(err) = read_result {
+        byte_vec.clear();
+        return Err(RError::new(RErrorKind::InputOutputError, &err.to_string()));
+    }
+    let buffer = &byte_vec[header_len..];
+
+    write_in_header(
+        &mut header.fix_header,
+        part_num as u64,
+        header.chunk_pos_s,
+        header.chunk_pos_e,
+    );
+    write_in_header(
+        &mut header.fix_header,
+        chunk_count_max,
+        header.chunk_max_pos_s,
+        header.chunk_max_pos_e,
+    );
+    write_in_header(
+        &mut header.fix_header,
+        chunk_size as u64,
+        header.chunk_length_pos_s,
+        header.chunk_length_pos_e,
+    );
+    write_hex_in_header(
+        &mut header.fix_header,
+        user_hash,
+        header.user_pos_s,
+        header.user_pos_e,
+    )?;
+    write_hex_in_header(
+        &mut header.fix_header,
+        file_hash,
+        header.file_hash_pos_s,
+        header.file_hash_pos_e,
+    )?;
+
+    if chunk_hash.is_some() && header.chunk_hash_pos_s.is_some() {
+        let chunk_hash = get_hash(buffer, &chunk_hash.as_ref().expect("chunk_hash not set."));
+        write_hex_in_header(
+            &mut header.fix_header,
+            &chunk_hash,
+            header.chunk_hash_pos_s.expect("chunk_hash not set."),
+            header.chunk_hash_pos_e.expect("chunk_hash not set."),
+        )?;
+    }
+
+    byte_vec[..header_len].copy_from_slice(&header.fix_header);
+
+    return Ok(());
+}
+
+
+/// Merges a data vector into a file at the specified position.
+///
+/// # Arguments
+///
+/// * output_path - The path of the output file.
+/// * byte_vec - The data vector to be merged into the file.
+/// * chunk_number - The chunk number indicating the position within the file where the data vector should be merged.
+/// * reg_chunk_size - The regular chunk size used for calculating the start position.
+///
+/// # Returns
+///
+/// The function returns a Result with two possible outcomes:
+/// * Ok(result_path) - If the data vector is successfully merged into the file. result_path is the path of the output file.
+/// * Err(error) - If there is an error during the merging process, including file I/O errors.
+///
+/// # Errors
+///
+/// The function can return an error if there is an error creating or opening the output file, checking the file size, or performing file I/O operations. The Error type contains details about the error.
+/// 
+pub fn merge_file_on_path(
+    output_path: &str,
+    byte_vec: &Vec<u8>,
+    chunk_number: u64,
+    reg_chunk_size: usize,
+) -> Result<String, Error> {
+    //create output file in outputDir if not there
+    let mut output_file = match OpenOptions::new()
+        .write(true)
+        .append(false)
+        .create(true)
+        .open(&output_path)
+    {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(err);
+        }
+    };
+
+    //get start_pos
+    let start_pos = reg_chunk_size as u64 * (chunk_number.saturating_sub(1));
+
+    //check file_size
+    let output_file_size = output_file.metadata()?.len();
+
+    //extend the file up to the chunk, sparse where supported
+    if output_file_size < start_pos {
+        output_file.set_len(start_pos)?;
+    }
+
+    output_file.seek(SeekFrom::Start(start_pos))?;
+    output_file.write_all(byte_vec)?;
+
+    return Ok(output_path.to_string());
+}
+
+/// Allocates the output file with its final size before any chunk is written.
+/// The file is sparse where the file system supports it. Chunks are written at their offsets afterwards.
+///
+/// # Arguments
+///
+/// * output_path - The path of the output file.
+/// * file_size - The final size of the file, as announced in the offer.
+///
+/// # Returns
+///
+/// The function returns Ok(()) if the file has the final size.
+///
+/// # Errors
+///
+/// The function can return an error if the file cannot be created or resized. The Error type contains details about the error.
+pub fn preallocate_file(output_path: &str, file_size: u64) -> Result<(), Error> {
+    let output_file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(&output_path)?;
+
+    if output_file.metadata()?.len() != file_size {
+        output_file.set_len(file_size)?;
+    }
+
+    return Ok(());
+}
+
+
+
+
+
+/// Creates a data vector from a file.
+///
+/// # Arguments
+///
+/// * path - The path of the file.
+/// * chunk_num - The chunk number indicating the position of the data vector within the file.
+/// * file_hash - The file hash.
+///
+/// # Returns
+///
+/// The function returns a Result with two possible outcomes:
+/// * Ok(split_vec) - If the data vector is successfully created from the file. split_vec is the resulting data vector.
+/// * Err(error) - If there is an error during the creation of the data vector, including file I/O errors.
+///
+/// # Errors
+///
+/// The function can return an error if there is an error opening the file, reading its metadata, or performing file I/O operations. The RError type contains details about the error.
+pub fn create_data_vec(path: &str, chunk_num: u64, file_hash: &str) -> Result<Vec<u8>, RError> {
+    return create_data_vec_with_chunk_hash(path, chunk_num, file_hash, &Hash::SIPHASH24, &Some(CHUNK_HASH_TYPE));
+}
+
+/// Creates a data vector from a file with the given chunk hash algorithm.
+///
+/// # Arguments
+///
+/// * path - The path of the file.
+/// * chunk_num - The chunk number indicating the position of the data vector within the file.
+/// * file_hash - The file hash.
+/// * hash_type - The algorithm of the file hash, as named in the offer.
+/// * chunk_hash - The algorithm used for the chunk hash. `None` or `Some(Hash::SIZE)` sends no chunk hash.
+///
+/// # Returns
+///
+/// The function returns a Result containing the data vector if successful.
+///
+/// # Errors
+///
+/// The function can return an error if there is an error opening the file, reading its metadata, or performing file I/O operations. The RError type contains details about the error.
+pub fn create_data_vec_with_chunk_hash(
+    path: &str,
+    chunk_num: u64,
+    file_hash: &str,
+    hash_type: &Hash,
+    chunk_hash: &Option<Hash>,
+) -> Result<Vec<u8>, RError> {
+    let mut file = File::open(path)
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+    let file_size = metadata(path)
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?
+        .len();
+    let mut buf_reader = BufReader::with_capacity(BUFFER_SIZE, &mut file);
+
+    return create_data_vec_from_reader(&mut buf_reader, file_size, chunk_num, file_hash, hash_type, chunk_hash);
+}
+
+/// Creates a data vector for a chunk of an offer, which may cover only a range of the file.
+/// The header carries the position of the chunk in the whole file, so the receiver writes it at the right offset.
+///
+/// # Arguments
+///
+/// * path - The path of the offered file.
+/// * chunk_num - The chunk number indicating the position of the data vector within the file.
+/// * offer - The offer the chunk is ordered for.
+///
+/// # Returns
+///
+/// The function returns a Result containing the data vector if successful.
+///
+/// # Errors
+///
+/// The function returns a `LimitExceeded` error if the chunk is not part of the offered range,
+/// and can return an error if there is an error reading the file. The RError type contains details about the error.
+pub fn create_range_data_vec(path: &str, chunk_num: u64, offer: &Offer) -> Result<Vec<u8>, RError> {
+    if !offer.contains_chunk(chunk_num) {
+        return Err(RError::new(
+            RErrorKind::LimitExceeded,
+            &format!("Chunk {} is not part of the offer.", chunk_num),
+        ));
+    }
+
+    return create_data_vec_with_chunk_hash(path, chunk_num, &offer.file_hash, &offer.hash_type, &Some(CHUNK_HASH_TYPE));
+}
+
+/// Creates a data vector from an open source.
+/// The source can be kept open across chunks, which avoids reopening the file for every chunk,
+/// and may be any seekable source like an in-memory buffer.
+///
+/// # Arguments
+///
+/// * reader - The source of the data. It is positioned at the start of the chunk before reading.
+/// * file_size - The total size of the source.
+/// * chunk_num - The chunk number indicating the position of the data vector within the source.
+/// * file_hash - The file hash.
+/// * hash_type - The algorithm of the file hash, as named in the offer.
+/// * chunk_hash - The algorithm used for the chunk hash. `None` or `Some(Hash::SIZE)` sends no chunk hash.
+///
+/// # Returns
+///
+/// The function returns a Result containing the data vector if successful.
+///
+/// # Errors
+///
+/// The function can return an error if seeking or reading the source fails, e.g. because it is shorter than `file_size`.
+/// The RError type contains details about the error.
+pub fn create_data_vec_from_reader<R: Read + Seek>(
+    reader: &mut R,
+    file_size: u64,
+    chunk_num: u64,
+    file_hash: &str,
+    hash_type: &Hash,
+    chunk_hash: &Option<Hash>,
+) -> Result<Vec<u8>, RError> {
+    let max_chunk_count = calc_chunk_count(CHUNK_SIZE, file_size)?;
+
+    let mut header = create_header(file_size, max_chunk_count, hash_type, chunk_hash)?;
+    let split_vec = split_file_single(
+        reader,
+        chunk_num as usize,
+        file_size as usize,
+        CHUNK_SIZE,
+        &file_hash,
+        max_chunk_count,
+        &USER_HASH,
+        &mut header,
+        chunk_hash,
+    )?;
+
+    return Ok(split_vec);
+}
+
+/// The number of buffers a `ChunkBufferPool` keeps by default.
+pub const DEFAULT_POOL_SIZE: usize = 8;
+
+/// A free list of chunk-sized buffers, so sustained transfers do not allocate a new buffer for every chunk.
+/// Buffers are cleared when they are returned, so no data of a previous chunk can leak into the next one.
+#[derive(Debug)]
+pub struct ChunkBufferPool {
+    buffers: Vec<Vec<u8>>,
+    max_buffers: usize,
+    allocations: usize,
+}
+
+impl ChunkBufferPool {
+    /// Creates an empty pool.
+    ///
+    /// # Arguments
+    ///
+    /// * max_buffers - The maximum number of idle buffers kept for reuse. Additional returned buffers are dropped.
+    pub fn new(max_buffers: usize) -> ChunkBufferPool {
+        return ChunkBufferPool {
+            buffers: Vec::new(),
+            max_buffers,
+            allocations: 0,
+        };
+    }
+
+    /// Hands out an empty buffer with room for a header and a complete chunk.
+    /// A new buffer is only allocated if no returned buffer is available.
+    pub fn take(&mut self) -> Vec<u8> {
+        if let Some(buffer) = self.buffers.pop() {
+            return buffer;
+        }
+
+        self.allocations += 1;
+        return Vec::with_capacity(MAX_DATA_VEC_LEN);
+    }
+
+    /// Returns a buffer to the pool. The buffer is cleared before it is stored.
+    pub fn put(&mut self, mut buffer: Vec<u8>) {
+        if self.buffers.len() >= self.max_buffers {
+            return;
+        }
+
+        buffer.clear();
+        self.buffers.push(buffer);
+    }
+
+    /// Returns the number of buffers allocated by the pool so far.
+    pub fn allocations(&self) -> usize {
+        return self.allocations;
+    }
+
+    /// Returns the number of idle buffers.
+    pub fn available(&self) -> usize {
+        return self.buffers.len();
+    }
+}
+
+impl Default for ChunkBufferPool {
+    fn default() -> Self {
+        return ChunkBufferPool::new(DEFAULT_POOL_SIZE);
+    }
+}
+
+/// A file which is sent, kept open for all of its chunks.
+#[derive(Debug)]
+pub struct ChunkSource {
+    path: String,
+    reader: BufReader<File>,
+    file_size: u64,
+    chunk_size: usize,
+}
+
+impl ChunkSource {
+    /// Opens a file to read its chunks.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path of the file.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the file can't be opened or its metadata can't be read.
+    pub fn open(path: &str) -> Result<ChunkSource, RError> {
+        let file = File::open(path)
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+        let file_size = file
+            .metadata()
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?
+            .len();
+
+        return Ok(ChunkSource {
+            path: path.to_string(),
+            reader: BufReader::with_capacity(BUFFER_SIZE, file),
+            file_size,
+            chunk_size: CHUNK_SIZE,
+        });
+    }
+
+    /// Sets the size of the chunks the file is read in, which is `CHUNK_SIZE` unless it was offered with another one.
+    ///
+    /// # Arguments
+    ///
+    /// * chunk_size - The chunk size of the offer, see `Offer::chunk_size`.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size;
+    }
+
+    /// Returns the path of the file.
+    pub fn path(&self) -> &str {
+        return &self.path;
+    }
+
+    /// Returns the size of the file when it was opened.
+    pub fn file_size(&self) -> u64 {
+        return self.file_size;
+    }
+
+    /// Returns the current size of the file, read from the open handle.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the metadata can't be read.
+    pub fn current_size(&self) -> Result<u64, RError> {
+        return Ok(self
+            .reader
+            .get_ref()
+            .metadata()
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?
+            .len());
+    }
+}
+
+/// Creates a data vector like `create_data_vec`, but reads from an open source and uses a buffer of the pool.
+/// The vector should be returned with `ChunkBufferPool::put` once it was sent.
+///
+/// # Arguments
+///
+/// * source - The open file.
+/// * chunk_num - The chunk number indicating the position of the data vector within the file.
+/// * file_hash - The file hash.
+/// * hash_type - The algorithm of the file hash, as named in the offer.
+/// * pool - The pool the buffer is taken from.
+///
+/// # Returns
+///
+/// The function returns a Result containing the data vector if successful.
+///
+/// # Errors
+///
+/// The function can return an error if there is an error reading the file.
+/// The buffer is returned to the pool in that case.
+pub fn create_data_vec_pooled(
+    source: &mut ChunkSource,
+    chunk_num: u64,
+    file_hash: &str,
+    hash_type: &Hash,
+    pool: &mut ChunkBufferPool,
+) -> Result<Vec<u8>, RError> {
+    let file_size = source.file_size;
+    let chunk_size = source.chunk_size;
+    let chunk_hash = Some(CHUNK_HASH_TYPE);
+    let max_chunk_count = calc_chunk_count(chunk_size, file_size)?;
+    let mut header = create_header(file_size, max_chunk_count, hash_type, &chunk_hash)?;
+
+    let mut byte_vec = pool.take();
+    if let Err(err) = split_file_single_into(
+        &mut source.reader,
+        chunk_num as usize,
+        file_size as usize,
+        chunk_size,
+        file_hash,
+        max_chunk_count,
+        &USER_HASH,
+        &mut header,
+        &chunk_hash,
+        &mut byte_vec,
+    ) {
+        pool.put(byte_vec);
+        return Err(err);
+    }
+
+    return Ok(byte_vec);
+}
+
+/// The hashes of all chunks of a file, taken when the file is offered.
+/// The sender compares every chunk it reads against them, see `create_data_vec_verified`,
+/// so a file that is edited while it is sent is noticed at the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkHashes {
+    file_size: u64,
+    chunk_size: usize,
+    hashes: Vec<String>,
+}
+
+impl ChunkHashes {
+    /// Hashes every chunk of `chunk_size` bytes of a file with `CHUNK_HASH_TYPE`.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path of the file.
+    /// * chunk_size - The chunk size the file is offered with, see `Offer::chunk_size`.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the file can't be read.
+    pub fn from_file(path: &str, chunk_size: usize) -> Result<ChunkHashes, RError> {
+        let file = File::open(path)
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+        let file_size = file
+            .metadata()
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?
+            .len();
+        let chunk_count = calc_chunk_count(chunk_size, file_size)?;
+
+        let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
+        let mut buffer = vec![0; chunk_size];
+        let mut hashes = Vec::with_capacity(chunk_count as usize);
+        for pos in 1..=chunk_count {
+            let chunk_len = get_chunk_len_with_size(file_size, pos, chunk_size) as usize;
+            reader
+                .read_exact(&mut buffer[..chunk_len])
+                .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+            hashes.push(get_hash(&buffer[..chunk_len], &CHUNK_HASH_TYPE));
+        }
+
+        return Ok(ChunkHashes { file_size, chunk_size, hashes });
+    }
+
+    /// Hashes every chunk like `from_file` and the whole file like `get_hash_from_file` in one pass,
+    /// so a file which is offered with chunk hashes is only read once.
+    ///
+    /// # Arguments
+    ///
+    /// * file - The open file, which is read from its start.
+    /// * chunk_size - The chunk size the file is offered with, see `Offer::chunk_size`.
+    ///
+    /// # Returns
+    ///
+    /// The function returns the `Hash::SIPHASH24` hash of the file and the hashes of its chunks.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the file can't be read.
+    pub fn with_file_hash(file: &File, chunk_size: usize) -> Result<(String, ChunkHashes), RError> {
+        let io_error = |err: Error| RError::new(RErrorKind::InputOutputError, &err.to_string());
+        let file_size = file.metadata().map_err(io_error)?.len();
+        let chunk_count = calc_chunk_count(chunk_size, file_size)?;
+
+        let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
+        reader.seek(SeekFrom::Start(0)).map_err(io_error)?;
+        let mut file_hasher = new_siphasher();
+        let mut buffer = vec![0; chunk_size];
+        let mut hashes = Vec::with_capacity(chunk_count as usize);
+        for pos in 1..=chunk_count {
+            let chunk_len = get_chunk_len_with_size(file_size, pos, chunk_size) as usize;
+            reader.read_exact(&mut buffer[..chunk_len]).map_err(io_error)?;
+            file_hasher.write(&buffer[..chunk_len]);
+            hashes.push(get_hash(&buffer[..chunk_len], &CHUNK_HASH_TYPE));
+        }
+
+        return Ok((format_siphash(file_hasher.finish()), ChunkHashes { file_size, chunk_size, hashes }));
+    }
+
+    /// Returns the size of the file when it was hashed.
+    pub fn file_size(&self) -> u64 {
+        return self.file_size;
+    }
+
+    /// Checks a chunk against the hash taken when the file was offered.
+    ///
+    /// # Arguments
+    ///
+    /// * chunk_pos - The position of the chunk, starting at 1.
+    /// * chunk - The data of the chunk.
+    ///
+    /// # Returns
+    ///
+    /// The function returns true if the chunk is unchanged.
+    pub fn matches(&self, chunk_pos: u64, chunk: &[u8]) -> bool {
+        return match chunk_pos.checked_sub(1).and_then(|index| self.hashes.get(index as usize)) {
+            Some(hash) => *hash == get_hash(chunk, &CHUNK_HASH_TYPE),
+            None => false,
+        };
+    }
+}
+
+/// Creates a data vector like `create_data_vec_pooled` and checks the chunk against the hashes taken when the file was offered.
+///
+/// # Arguments
+///
+/// * source - The open file.
+/// * chunk_num - The chunk number indicating the position of the data vector within the file.
+/// * file_hash - The file hash.
+/// * hash_type - The algorithm of the file hash, as named in the offer.
+/// * hashes - The chunk hashes of the file at the time it was offered.
+/// * pool - The pool the buffer is taken from.
+///
+/// # Returns
+///
+/// The function returns a Result containing the data vector if successful.
+///
+/// # Errors
+///
+/// The function returns a `FileChangedDuringTransfer` error if the size of the file or the chunk changed since it was offered,
+/// and can return an error if there is an error reading the file. The buffer is returned to the pool in both cases.
+/// The size is not read for every chunk: a file which shrank fails to read a chunk, a file which grew is noticed with its last chunk.
+pub fn create_data_vec_verified(
+    source: &mut ChunkSource,
+    chunk_num: u64,
+    file_hash: &str,
+    hash_type: &Hash,
+    hashes: &ChunkHashes,
+    pool: &mut ChunkBufferPool,
+) -> Result<Vec<u8>, RError> {
+    check_source_size(source, source.file_size(), hashes)?;
+
+    let byte_vec = match create_data_vec_pooled(source, chunk_num, file_hash, hash_type, pool) {
+        Ok(byte_vec) => byte_vec,
+        Err(err) => {
+            // a chunk of a file which shrank can't be read completely
+            check_source_size(source, source.current_size()?, hashes)?;
+            return Err(err);
+        }
+    };
+
+    // the data of the chunk follows the header
+    let chunk_len = get_chunk_len_with_size(hashes.file_size, chunk_num, hashes.chunk_size) as usize;
+    let chunk = &byte_vec[byte_vec.len().saturating_sub(chunk_len)..];
+    if !hashes.matches(chunk_num, chunk) {
+        pool.put(byte_vec);
+        return Err(RError::new(
+            RErrorKind::FileChangedDuringTransfer,
+            &format!("Chunk {} of {} changed since it was offered.", chunk_num, source.path()),
+        ));
+    }
+
+    // appended data is not part of any chunk, so it is only visible in the size
+    if chunk_num == hashes.hashes.len() as u64 {
+        if let Err(err) = source.current_size().and_then(|size| check_source_size(source, size, hashes)) {
+            pool.put(byte_vec);
+            return Err(err);
+        }
+    }
+
+    return Ok(byte_vec);
+}
+
+/// Checks the size of a sent file against its size when it was offered.
+///
+/// # Errors
+///
+/// The function returns a `FileChangedDuringTransfer` error if the sizes differ.
+fn check_source_size(source: &ChunkSource, file_size: u64, hashes: &ChunkHashes) -> Result<(), RError> {
+    if file_size == hashes.file_size {
+        return Ok(());
+    }
+
+    return Err(RError::new(
+        RErrorKind::FileChangedDuringTransfer,
+        &format!(
+            "The size of {} changed from {} to {} bytes since it was offered.",
+            source.path(),
+            hashes.file_size,
+            file_size
+        ),
+    ));
+}
+
+
+/// Reloads an interrupted transfer from the offer file and the logfile stored next to the output file.
+///
+/// # Arguments
+///
+/// * output_path - The path the received file is written to.
+///
+/// # Returns
+///
+/// The function returns a Result containing the accepted offer and the positions of all chunks that have not been received yet.
+///
+/// # Errors
+///
+/// The function can return an error if the offer file is missing or invalid, or if the logfile cannot be read.
+/// An offer exceeding `OfferLimits::default` is rejected with a `LimitExceeded` error before anything is allocated for it.
+/// The RError type contains details about the error.
+pub fn resume_from_disk(output_path: &str) -> Result<(Offer, Vec<u64>), RError> {
+    let offer = read_offer_file(output_path)?;
+    OfferLimits::default().check(&offer)?;
+    let chunk_count = calc_chunk_count(offer.chunk_size, offer.size)?;
+
+    let logfile_path = format!("{}.{}", output_path, LOGFILE_EXTENSION);
+    let log_entries = if Path::new(&logfile_path).exists() {
+        read_log_file(&logfile_path, BUFFER_SIZE, LOGGER_REGEX)
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?
+    } else {
+        Vec::new()
+    };
+
+    let mut received = vec![false; chunk_count as usize];
+    for entry in log_entries
+        .iter()
+        .filter(|entry| entry.file_hash.eq_ignore_ascii_case(&offer.file_hash))
+    {
+        for pos in entry.first_part.max(1)..=entry.chunk_part.min(chunk_count) {
+            received[(pos - 1) as usize] = true;
+        }
+    }
+
+    let missing = (1..=chunk_count)
+        .filter(|pos| !received[(*pos - 1) as usize])
+        .collect();
+
+    return Ok((offer, missing));
+}
+
+/// Result of verifying a received file against its logfile.
+#[derive(Debug)]
+pub struct VerifyReport {
+    /// The hash of the complete file, as logged.
+    pub file_hash: String,
+    /// The algorithm of file_hash.
+    pub hash_type: Hash,
+    /// The number of chunks of the complete file.
+    pub chunk_count: u64,
+    /// The positions of all chunks that have not been received yet.
+    pub missing: Vec<u64>,
+    /// The hash of the file on disk. None if the file does not exist.
+    pub computed_hash: Option<String>,
+}
+
+impl VerifyReport {
+    /// Checks if the hash of the file on disk matches the logged hash.
+    pub fn hash_matches(&self) -> bool {
+        match &self.computed_hash {
+            Some(hash) => hash.eq_ignore_ascii_case(&self.file_hash),
+            None => false,
+        }
+    }
+
+    /// Checks if all chunks were received and the file is intact.
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty() && self.hash_matches()
+    }
+}
+
+/// Verifies a received file against the logfile stored next to it, without contacting the peer.
+/// The logged chunks are checked for completeness and the whole file is hashed again.
+///
+/// # Arguments
+///
+/// * output_path - The path the received data is written to, i.e. the temporary file of an unfinished transfer.
+///
+/// # Returns
+///
+/// The function returns a Result containing the report of the missing chunks and the hash status.
+///
+/// # Errors
+///
+/// The function can return an error if the logfile is missing, empty or invalid, or if the file cannot be hashed.
+/// The RError type contains details about the error.
+pub fn verify_from_disk(output_path: &str) -> Result<VerifyReport, RError> {
+    let logfile_path = format!("{}.{}", output_path, LOGFILE_EXTENSION);
+    let mut log_entries = read_log_file(&logfile_path, BUFFER_SIZE, LOGGER_REGEX)
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+
+    if log_entries.is_empty() {
+        return Err(RError::new(
+            RErrorKind::InputOutputError,
+            "The logfile contains no entries.",
+        ));
+    }
+
+    // checks the logged chunk count against the limits
+    validate_log_file(&log_entries)?;
+
+    let first = log_entries.swap_remove(0);
+    let chunk_count = first.max_part;
+    let mut received = vec![false; chunk_count as usize];
+    for entry in log_entries
+        .iter()
+        .chain(std::iter::once(&first))
+        .filter(|entry| entry.file_hash.eq_ignore_ascii_case(&first.file_hash))
+    {
+        for pos in entry.first_part.max(1)..=entry.chunk_part.min(chunk_count) {
+            received[(pos - 1) as usize] = true;
+        }
+    }
+
+    let missing = (1..=chunk_count)
+        .filter(|pos| !received[(*pos - 1) as usize])
+        .collect();
+
+    let computed_hash = match File::open(output_path) {
+        Ok(file) => Some(
+            get_file_hash(&file, BUFFER_SIZE, &first.file_hash_alg, 0)
+                .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?,
+        ),
+        Err(_) => None,
+    };
+
+    return Ok(VerifyReport {
+        file_hash: first.file_hash,
+        hash_type: first.file_hash_alg,
+        chunk_count,
+        missing,
+        computed_hash,
+    });
+}
+
+/// Lists the logfiles in a directory.
+///
+/// # Arguments
+///
+/// * output_dir - The directory to search. Subdirectories are not searched.
+///
+/// # Returns
+///
+/// The function returns the paths of all files with the extension LOGFILE_EXTENSION.
+///
+/// # Errors
+///
+/// The function returns an InputOutputError if the directory can't be read.
+pub fn list_logs(output_dir: &str) -> Result<Vec<String>, RError> {
+    let entries = fs::read_dir(output_dir)
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+
+    let mut logs = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == LOGFILE_EXTENSION) {
+            logs.push(path.to_string_lossy().to_string());
+        }
+    }
+
+    logs.sort();
+    return Ok(logs);
+}
+
+/// Removes the logfiles of completed transfers which were last written to more than keep_for ago.
+/// Logfiles of incomplete transfers are kept, so the transfers can still be resumed.
+/// Logfiles without valid entries are kept as well, since their state is unknown.
+///
+/// # Arguments
+///
+/// * output_dir - The directory containing the logfiles, see list_logs.
+/// * keep_for - How long logfiles of completed transfers are kept after their last entry.
+///
+/// # Returns
+///
+/// The function returns the paths of the removed logfiles.
+///
+/// # Errors
+///
+/// The function returns an InputOutputError if the directory can't be read or a logfile can't be removed.
+pub fn prune_logs(output_dir: &str, keep_for: Duration) -> Result<Vec<String>, RError> {
+    let now = Utc::now().timestamp_millis();
+    let keep_for = i64::try_from(keep_for.as_millis()).unwrap_or(i64::MAX);
+
+    let mut pruned = Vec::new();
+    for log_path in list_logs(output_dir)? {
+        let log_entries = match read_log_file(&log_path, BUFFER_SIZE, LOGGER_REGEX) {
+            Ok(log_entries) if !log_entries.is_empty() => log_entries,
+            _ => continue,
+        };
+
+        let last_written = match log_entries
+            .iter()
+            .map(|entry| entry.timestamp_millis())
+            .collect::<Option<Vec<i64>>>()
+            .and_then(|timestamps| timestamps.into_iter().max())
+        {
+            Some(last_written) => last_written,
+            None => continue,
+        };
+        if now.saturating_sub(last_written) <= keep_for {
+            continue;
+        }
+
+        match validate_log_file(&log_entries) {
+            Ok((0, 0)) => {}
+            _ => continue,
+        }
+
+        fs::remove_file(&log_path)
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+        pruned.push(log_path);
+    }
+
+    return Ok(pruned);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::general::general::{
+        read_send_header, separate_header, validate_file, validate_transfer_log, write_to_log_file,
+        MemoryTransferLog, MIN_CHUNK_SIZE,
+    };
+    use crate::hash::hash::get_hash_from_file;
+    use crate::hash::hash::get_file_hash;
+    use crate::offer::offer::{
+        create_range_offer_byte_msg, create_size_only_offer_byte_msg, read_offer_vec, write_offer_file,
+    };
+    use crate::order::order::{create_order_byte_vec, read_order};
+    use crate::fixture::fixture::{test_dir, SourceFixture};
+    use std::fs;
+    use regex::Regex;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::time::Instant;
+
+    fn prepare_dir(name: &str) -> PathBuf {
+        let dir = test_dir(name);
+        fs::write(dir.join("report.txt"), b"existing").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_resolve_output_path_missing_file() {
+        let dir = prepare_dir("rdrop_resolve_missing");
+
+        for policy in [OverwritePolicy::Overwrite, OverwritePolicy::Skip, OverwritePolicy::Rename] {
+            let path = dir.join(format!("{:?}.txt", policy)).to_string_lossy().to_string();
+            assert_eq!(resolve_output_path(&path, policy).unwrap(), path);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_output_path_reserves_path() {
+        let dir = prepare_dir("rdrop_resolve_reserve");
+        let path = dir.join("other.txt").to_string_lossy().to_string();
+
+        assert_eq!(resolve_output_path(&path, OverwritePolicy::Skip).unwrap(), path);
+        // a second receiver of the same name can't take the reserved path
+        assert!(resolve_output_path(&path, OverwritePolicy::Skip).is_err());
+        assert_eq!(
+            resolve_output_path(&path, OverwritePolicy::Rename).unwrap(),
+            dir.join("other (1).txt").to_string_lossy()
+        );
+
+        release_output_path(&path);
+        assert!(!Path::new(&path).exists());
+        // an existing file with content is never released
+        release_output_path(&dir.join("report.txt").to_string_lossy());
+        assert!(dir.join("report.txt").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_output_path_overwrite() {
+        let dir = prepare_dir("rdrop_resolve_overwrite");
+        let path = dir.join("report.txt").to_string_lossy().to_string();
+
+        assert_eq!(resolve_output_path(&path, OverwritePolicy::Overwrite).unwrap(), path);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_output_path_skip() {
+        let dir = prepare_dir("rdrop_resolve_skip");
+        let path = dir.join("report.txt").to_string_lossy().to_string();
+
+        let err = resolve_output_path(&path, OverwritePolicy::Skip).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_output_path_rename() {
+        let dir = prepare_dir("rdrop_resolve_rename");
+        let path = dir.join("report.txt").to_string_lossy().to_string();
+
+        let renamed = resolve_output_path(&path, OverwritePolicy::Rename).unwrap();
+        assert_eq!(renamed, dir.join("report (1).txt").to_string_lossy());
+
+        fs::write(&renamed, b"existing").unwrap();
+        let renamed = resolve_output_path(&path, OverwritePolicy::Rename).unwrap();
+        assert_eq!(renamed, dir.join("report (2).txt").to_string_lossy());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sanitize_output_name_traversal() {
+        let path = confine_output_path("/srv/output", "../../etc/passwd").unwrap();
+        assert_eq!(path, Path::new("/srv/output/etc/passwd"));
+
+        let path = confine_output_path("/srv/output", "..\\..\\report.pdf").unwrap();
+        assert_eq!(path, Path::new("/srv/output/report.pdf"));
+        assert!(path.starts_with("/srv/output"));
+    }
+
+    #[test]
+    fn test_render_output_name() {
+        let mut offer = Offer::new("report.pdf", "16", "SIPHASH24", "0123456789abcdef").unwrap();
+        offer.set_extension_from_name();
+        let render = |template: &str| render_output_name_on(template, &offer, "2024-05-01");
+
+        assert_eq!(render(DEFAULT_NAME_TEMPLATE), "report.pdf");
+        assert_eq!(render("{date}_{name}"), "2024-05-01_report.pdf");
+        assert_eq!(render("{hash}_{name}"), "0123456789abcdef_report.pdf");
+        assert_eq!(render("{hash}.{ext}"), "0123456789abcdef.pdf");
+        assert_eq!(render("{other}_{name"), "{other}_{name");
+
+        let today = Utc::now().format("%Y-%m-%d").to_string();
+        assert_eq!(render_output_name("{date}_{name}", &offer), format!("{}_report.pdf", today));
+
+        offer.name = "{hash}.txt".to_string();
+        let rendered = render_output_name_on("{date}_{name}", &offer, "2024-05-01");
+        assert_eq!(rendered, "2024-05-01_{hash}.txt");
+    }
+
+    #[test]
+    fn test_templated_output_path() {
+        let dir = prepare_dir("rdrop_templated_output");
+        let output_dir = dir.to_string_lossy().to_string();
+        let source = dir.join("source.bin").to_string_lossy().to_string();
+        fs::write(&source, b"templated output").unwrap();
+        let hash = get_hash_from_file(&File::open(&source).unwrap()).unwrap();
+        let mut offer = Offer::new("notes.txt", "16", "SIPHASH24", &hash).unwrap();
+        offer.set_extension_from_name();
+
+        let path = templated_output_path(&output_dir, "{hash}_{name}", &offer).unwrap();
+        assert_eq!(path, dir.join(format!("{}_notes.txt", hash)));
+
+        let msg = create_data_vec(&source, 1, &hash).unwrap();
+        let (header, data) = separate_header(&msg).unwrap();
+        let header_data = read_send_header(&header).unwrap();
+        write_data_vec(&header_data, &data, &path.to_string_lossy()).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"templated output");
+
+        // the rendered name is sanitized like an offered name
+        offer.name = "../../etc/passwd".to_string();
+        let path = templated_output_path("/srv/output", "{name}", &offer).unwrap();
+        assert_eq!(path, PathBuf::from("/srv/output/etc/passwd"));
+        let path = templated_output_path("/srv/output", "{ext}/../../{name}", &offer).unwrap();
+        assert_eq!(path, PathBuf::from("/srv/output/txt/etc/passwd"));
+        assert!(templated_output_path("/srv/output", "/{name}", &offer).is_err());
+        offer.name = "..".to_string();
+        assert!(templated_output_path("/srv/output", "{name}", &offer).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sanitize_output_name_absolute() {
+        assert!(sanitize_output_name("/abs/path").is_err());
+        assert!(sanitize_output_name("\\abs\\path").is_err());
+        assert!(sanitize_output_name("C:\\abs\\path").is_err());
+        assert!(confine_output_path("/srv/output", "/abs/path").is_err());
+        assert!(sanitize_output_name("../..").is_err());
+        assert_eq!(sanitize_output_name("./report.pdf").unwrap(), PathBuf::from("report.pdf"));
+    }
+
+    #[test]
+    fn test_ensure_extension() {
+        assert_eq!(ensure_extension("/tmp/report", "pdf"), "/tmp/report.pdf");
+        assert_eq!(ensure_extension("/tmp/report.PDF", "pdf"), "/tmp/report.PDF");
+        assert_eq!(ensure_extension("/tmp/report.txt", "pdf"), "/tmp/report.txt.pdf");
+        assert_eq!(ensure_extension("/tmp/report", ""), "/tmp/report");
+        // extensions which would change the directory or add components are dropped
+        assert_eq!(ensure_extension("/tmp/report", "pdf/../../x"), "/tmp/report");
+        assert_eq!(ensure_extension("/tmp/report", "tar.gz"), "/tmp/report");
+        assert_eq!(ensure_extension("/tmp/report", "p\\df"), "/tmp/report");
+    }
+
+    #[test]
+    fn test_overwrite_policy_from_str() {
+        assert_eq!(OverwritePolicy::from_str("Rename").unwrap(), OverwritePolicy::Rename);
+        assert!(OverwritePolicy::from_str("rename").is_err());
+    }
+
+    #[test]
+    fn test_range_offer() {
+        let fixture = SourceFixture::new("rdrop_range_offer", CHUNK_SIZE * 22 + 100);
+        let SourceFixture { source, content, hash, .. } = &fixture;
+        let output = fixture.path("output.bin");
+
+        let msg = create_range_offer_byte_msg(&hash, content.len() as u64, &source, "", (10, 20)).unwrap();
+        let offer = read_offer_vec(&msg).unwrap();
+        assert_eq!(offer.range, Some((10, 20)));
+        assert_eq!(offer.size, content.len() as u64);
+        assert!(create_range_offer_byte_msg(&hash, content.len() as u64, &source, "", (20, 24)).is_err());
+
+        let (start, end) = offer.chunk_range().unwrap();
+        let mut order = create_order_byte_vec(start, end, &offer.file_hash).unwrap();
+        let order = read_order(&mut order).unwrap();
+        assert_eq!(order.ranges, vec![(10, 20)]);
+
+        let mut transferred = Vec::new();
+        for pos in order.start_num..=order.end_num {
+            let msg = create_range_data_vec(&source, pos, &offer).unwrap();
+            let (header, data) = separate_header(&msg).unwrap();
+            let header_data = read_send_header(&header).unwrap();
+            write_data_vec(&header_data, &data, &output).unwrap();
+            transferred.push(header_data.chunk_pos);
+        }
+        assert_eq!(transferred, (10..=20).collect::<Vec<u64>>());
+        assert!(create_range_data_vec(&source, 9, &offer).is_err());
+        assert!(create_range_data_vec(&source, 21, &offer).is_err());
+
+        let written = fs::read(&output).unwrap();
+        assert_eq!(written.len(), CHUNK_SIZE * 20);
+        assert!(written[..CHUNK_SIZE * 9].iter().all(|byte| *byte == 0));
+        assert_eq!(written[CHUNK_SIZE * 9..], content[CHUNK_SIZE * 9..CHUNK_SIZE * 20]);
+    }
+
+    #[test]
+    fn test_write_manifest_file() {
+        let fixture = SourceFixture::new("rdrop_manifest", CHUNK_SIZE * 2 + 100);
+        let SourceFixture { content, hash, .. } = &fixture;
+        let output = fixture.path("out \"1\".bin");
+
+        let temp = get_temp_path(&output, None);
+        for pos in 1..=3 {
+            let (header_data, data) = fixture.chunk(pos);
+            write_data_vec(&header_data, &data, &temp).unwrap();
+        }
+        finish_temp_file(&temp, &output, &hash, &Hash::SIPHASH24).unwrap();
+
+        let offer = Offer {
+            name: "out \"1\".bin".to_string(),
+            size: content.len() as u64,
+            hash_type: Hash::SIPHASH24,
+            file_hash: hash.clone(),
+            note: String::new(),
+            extension: "bin".to_string(),
+            mime: String::new(),
+            merkle_root: String::new(),
+            range: None,
+            group: None,
+            chunk_size: CHUNK_SIZE,
+            streaming: false,
+        };
+        let manifest_path = write_manifest_file(&output, &offer).unwrap();
+        assert_eq!(manifest_path, format!("{}.rdrop.json", output));
+
+        let manifest = fs::read_to_string(&manifest_path).unwrap();
+        assert!(manifest.contains("\"name\": \"out \\\"1\\\".bin\""));
+        assert!(manifest.contains("\"algorithm\": \"SIPHASH24\""));
+        assert!(manifest.contains(&format!("\"size\": {},", content.len())));
+        assert!(manifest.contains("\"chunk_count\": 3,"));
+
+        // the recorded hash matches the hash of the received file
+        let recorded = Regex::new(r#""hash": "([0-9a-f]+)""#)
+            .unwrap()
+            .captures(&manifest)
+            .unwrap()[1]
+            .to_string();
+        let computed = get_file_hash(&File::open(&output).unwrap(), BUFFER_SIZE, &Hash::SIPHASH24, 0).unwrap();
+        assert_eq!(recorded, computed);
+    }
+
+    /// Records the calls made on a file handle.
+    #[derive(Default)]
+    struct SyncRecorder {
+        calls: std::rc::Rc<std::cell::RefCell<Vec<&'static str>>>,
+    }
+
+    impl Write for SyncRecorder {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            return Ok(buf.len());
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.calls.borrow_mut().push("flush");
+            return Ok(());
+        }
+    }
+
+    impl SyncAll for SyncRecorder {
+        fn sync_all(&self) -> std::io::Result<()> {
+            self.calls.borrow_mut().push("sync_all");
+            return Ok(());
+        }
+    }
+
+    impl Drop for SyncRecorder {
+        fn drop(&mut self) {
+            self.calls.borrow_mut().push("close");
+        }
+    }
+
+    #[test]
+    fn test_sync_file() {
+        let recorder = SyncRecorder::default();
+        let calls = recorder.calls.clone();
+
+        sync_file(recorder).unwrap();
+        assert_eq!(*calls.borrow(), vec!["flush", "sync_all", "close"]);
+    }
+
+    #[test]
+    fn test_verify_from_disk() {
+        let fixture = SourceFixture::new("rdrop_verify", CHUNK_SIZE * 2 + 100);
+        let SourceFixture { dir, source, hash, .. } = &fixture;
+
+        let receive = |output: &str, positions: &[u64]| {
+            for pos in positions {
+                let (header_data, data) = fixture.chunk(*pos);
+                write_data_vec(&header_data, &data, output).unwrap();
+            }
+        };
+
+        let complete = dir.join("complete.bin").to_string_lossy().to_string();
+        receive(&complete, &[3, 1, 2]);
+        let report = verify_from_disk(&complete).unwrap();
+        assert_eq!(report.chunk_count, 3);
+        assert!(report.missing.is_empty());
+        assert_eq!(report.file_hash, *hash);
+        assert!(report.hash_matches());
+        assert!(report.is_complete());
+
+        let incomplete = dir.join("incomplete.bin").to_string_lossy().to_string();
+        receive(&incomplete, &[1, 3]);
+        let report = verify_from_disk(&incomplete).unwrap();
+        assert_eq!(report.missing, vec![2]);
+        assert!(report.computed_hash.is_some());
+        assert!(!report.hash_matches());
+        assert!(!report.is_complete());
+
+        // without a logfile there is nothing to verify against
+        assert!(verify_from_disk(&source).is_err());
+    }
+
+    #[test]
+    fn test_create_data_vec_from_reader() {
+        let fixture = SourceFixture::new("rdrop_data_vec_reader", CHUNK_SIZE * 2 + 100);
+        let SourceFixture { source, content, hash, .. } = &fixture;
+
+        let mut cursor = Cursor::new(content.clone());
+        for pos in [3, 1, 2] {
+            let from_path = create_data_vec(&source, pos, &hash).unwrap();
+            let from_reader = create_data_vec_from_reader(
+                &mut cursor,
+                content.len() as u64,
+                pos,
+                &hash,
+                &Hash::SIPHASH24,
+                &Some(CHUNK_HASH_TYPE),
+            )
+            .unwrap();
+            assert_eq!(from_path, from_reader);
+        }
+
+        // the source is shorter than announced
+        let mut short = Cursor::new(content[..CHUNK_SIZE].to_vec());
+        let err = create_data_vec_from_reader(&mut short, content.len() as u64, 2, &hash, &Hash::SIPHASH24, &None)
+            .unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::InputOutputError));
+    }
+
+    #[test]
+    fn test_chunk_buffer_pool() {
+        let fixture = SourceFixture::new("rdrop_buffer_pool", CHUNK_SIZE + 100);
+        let SourceFixture { source, hash, .. } = &fixture;
+
+        let mut pool = ChunkBufferPool::new(1);
+        let mut chunk_source = ChunkSource::open(&source).unwrap();
+        for pos in [1, 2, 1, 2] {
+            let msg = create_data_vec_pooled(&mut chunk_source, pos, &hash, &Hash::SIPHASH24, &mut pool).unwrap();
+            // the short last chunk must not contain leftovers of the full first chunk
+            assert_eq!(msg, create_data_vec(&source, pos, &hash).unwrap());
+            pool.put(msg);
+        }
+        assert_eq!(pool.allocations(), 1);
+        assert_eq!(pool.available(), 1);
+
+        let buffer = pool.take();
+        assert!(buffer.is_empty());
+        pool.put(buffer);
+        pool.put(vec![1, 2, 3]);
+        assert_eq!(pool.available(), 1);
+
+        // a failed read hands the buffer back
+        assert!(create_data_vec_pooled(&mut chunk_source, 3, &hash, &Hash::SIPHASH24, &mut pool).is_err());
+        assert_eq!(pool.available(), 1);
+
+        // the header names the algorithm of the offer
+        let sha256 = get_file_hash(&File::open(&source).unwrap(), BUFFER_SIZE, &Hash::SHA256, 0).unwrap();
+        let msg = create_data_vec_pooled(&mut chunk_source, 1, &sha256, &Hash::SHA256, &mut pool).unwrap();
+        let (header, _) = separate_header(&msg).unwrap();
+        let header_data = read_send_header(&header).unwrap();
+        assert_eq!(header_data.file_hash, sha256);
+        assert_eq!(header_data.file_hash_alg, Hash::SHA256);
+        pool.put(msg);
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn test_compacted_transfer_log() {
+        let dir = prepare_dir("rdrop_compacted_transfer_log");
+        let log_path = dir.join("output.bin.rdroplog").to_string_lossy().to_string();
+        let entry = |pos: u64| {
+            let size = if pos == 1000 { 100 } else { CHUNK_SIZE as u64 };
+            LogEntry::new(
+                "16.10.2026 - 12:00:00.000".to_string(),
+                USER_HASH.to_string(),
+                "0123456789abcdef".to_string(),
+                Hash::SIPHASH24,
+                pos,
+                1000,
+                size,
+                Some("00ff".to_string()),
+                Some(Hash::SIPHASH24),
+            )
+        };
+
+        let mut log = FileTransferLog::compacted(&log_path);
+        for pos in 1..=1000 {
+            log.record(&entry(pos)).unwrap();
+        }
+
+        // the last chunk is shorter, so it gets a line of its own
+        let lines = fs::read_to_string(&log_path).unwrap().lines().count();
+        assert_eq!(lines, 2);
+        // the runs are read without expanding them
+        assert_eq!(log.entries().unwrap().len(), 2);
+        assert_eq!(validate_file(&log_path, "0123456789abcdef").unwrap(), (0, 0));
+        drop(log);
+
+        // an interrupted run is written on drop
+        fs::remove_file(&log_path).unwrap();
+        let mut log = FileTransferLog::compacted(&log_path);
+        for pos in (1..=10).chain(20..=25) {
+            log.record(&entry(pos)).unwrap();
+        }
+        assert_eq!(validate_transfer_log(&log).unwrap(), (11, 1000));
+        drop(log);
+
+        assert_eq!(fs::read_to_string(&log_path).unwrap().lines().count(), 2);
+        assert_eq!(validate_file(&log_path, "0123456789abcdef").unwrap(), (11, 1000));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_memory_transfer_log() {
+        let fixture = SourceFixture::new("rdrop_memory_transfer_log", CHUNK_SIZE * 2 + 100);
+        let content = &fixture.content;
+        let output = fixture.path("output.bin");
+
+        let mut log = MemoryTransferLog::new();
+        assert!(validate_transfer_log(&log).is_err());
+
+        for pos in [3, 1, 2] {
+            let (header_data, data) = fixture.chunk(pos);
+            write_data_vec_to_log(&header_data, &data, &output, &mut log).unwrap();
+
+            if pos == 1 {
+                assert_eq!(validate_transfer_log(&log).unwrap(), (2, 2));
+            }
+        }
+
+        assert_eq!(validate_transfer_log(&log).unwrap(), (0, 0));
+        assert_eq!(fs::read(&output).unwrap(), *content);
+        // nothing is logged on disk
+        assert!(!Path::new(&format!("{}.{}", output, LOGFILE_EXTENSION)).exists());
+    }
+
+    #[test]
+    fn test_write_data_vec_log_error() {
+        let fixture = SourceFixture::new("rdrop_write_log_error", CHUNK_SIZE + 100);
+        let output = fixture.path("output.bin");
+        // a directory in place of the logfile can't be appended to
+        fs::create_dir(format!("{}.{}", output, LOGFILE_EXTENSION)).unwrap();
+
+        let (header_data, data) = fixture.chunk(1);
+        assert!(write_data_vec(&header_data, &data, &output).is_err());
+
+        let mut writer = BatchedChunkWriter::new(&output, WriteBatching::default());
+        assert!(writer.write(&header_data, &data).is_err());
+    }
+
+    #[test]
+    fn test_create_data_vec_verified() {
+        let fixture = SourceFixture::new("rdrop_verified_source", CHUNK_SIZE * 2 + 100);
+        let SourceFixture { source, hash, .. } = &fixture;
+        let mut content = fixture.content.clone();
+        let hashes = ChunkHashes::from_file(&source, CHUNK_SIZE).unwrap();
+        assert_eq!(hashes.file_size(), content.len() as u64);
+
+        let mut pool = ChunkBufferPool::new(1);
+        let mut chunk_source = ChunkSource::open(&source).unwrap();
+        let msg = create_data_vec_verified(&mut chunk_source, 1, &hash, &Hash::SIPHASH24, &hashes, &mut pool).unwrap();
+        assert_eq!(msg, create_data_vec(&source, 1, &hash).unwrap());
+        pool.put(msg);
+
+        // the user edits the second chunk while the first one is sent
+        content[CHUNK_SIZE + 10] ^= 0xFF;
+        fs::write(&source, &content).unwrap();
+        let err = create_data_vec_verified(&mut chunk_source, 2, &hash, &Hash::SIPHASH24, &hashes, &mut pool).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::FileChangedDuringTransfer));
+        assert_eq!(pool.available(), 1);
+        // untouched chunks are still sent
+        assert!(create_data_vec_verified(&mut chunk_source, 3, &hash, &Hash::SIPHASH24, &hashes, &mut pool).is_ok());
+
+        // appended data is noticed with the last chunk
+        let mut grown = content.clone();
+        grown.extend_from_slice(b"appended");
+        fs::write(&source, &grown).unwrap();
+        let err = create_data_vec_verified(&mut chunk_source, 3, &hash, &Hash::SIPHASH24, &hashes, &mut pool).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::FileChangedDuringTransfer));
+        assert_eq!(pool.available(), 1);
+
+        content.truncate(CHUNK_SIZE);
+        fs::write(&source, &content).unwrap();
+        let err = create_data_vec_verified(&mut chunk_source, 2, &hash, &Hash::SIPHASH24, &hashes, &mut pool).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::FileChangedDuringTransfer));
+    }
+
+    #[test]
+    fn test_chunk_hashes_with_file_hash() {
+        let fixture = SourceFixture::new("rdrop_chunk_hashes_with_file_hash", CHUNK_SIZE * 2 + 100);
+        let file = File::open(&fixture.source).unwrap();
+
+        let (hash, hashes) = ChunkHashes::with_file_hash(&file, CHUNK_SIZE).unwrap();
+        assert_eq!(hash, fixture.hash);
+        assert_eq!(hashes, ChunkHashes::from_file(&fixture.source, CHUNK_SIZE).unwrap());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_chunk_buffer_pool_speed() {
+        let chunk_count = 200;
+        let fixture = SourceFixture::new("rdrop_buffer_pool_speed", CHUNK_SIZE * chunk_count);
+        let SourceFixture { source, hash, .. } = &fixture;
+
+        let start_time = Instant::now();
+        for pos in 1..=chunk_count as u64 {
+            create_data_vec(&source, pos, &hash).unwrap();
+        }
+        let unpooled = start_time.elapsed();
+
+        let mut pool = ChunkBufferPool::default();
+        let start_time = Instant::now();
+        let mut chunk_source = ChunkSource::open(&source).unwrap();
+        for pos in 1..=chunk_count as u64 {
+            let msg = create_data_vec_pooled(&mut chunk_source, pos, &hash, &Hash::SIPHASH24, &mut pool).unwrap();
+            pool.put(msg);
+        }
+        let pooled = start_time.elapsed();
+
+        println!(
+            "unpooled: {} microseconds, {} buffer allocations",
+            unpooled.as_micros() / chunk_count as u128,
+            chunk_count
+        );
+        println!(
+            "pooled: {} microseconds, {} buffer allocations",
+            pooled.as_micros() / chunk_count as u128,
+            pool.allocations()
+        );
+        assert_eq!(pool.allocations(), 1);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_create_data_vec_speed() {
+        let chunk_count = 200;
+        let fixture = SourceFixture::new("rdrop_data_vec_speed", CHUNK_SIZE * chunk_count);
+        let SourceFixture { source, content, hash, .. } = &fixture;
+
+        let start_time = Instant::now();
+        for pos in 1..=chunk_count as u64 {
+            create_data_vec(&source, pos, &hash).unwrap();
+        }
+        let reopen = start_time.elapsed();
+
+        let start_time = Instant::now();
+        let mut file = File::open(&source).unwrap();
+        let mut buf_reader = BufReader::with_capacity(BUFFER_SIZE, &mut file);
+        for pos in 1..=chunk_count as u64 {
+            create_data_vec_from_reader(
+                &mut buf_reader,
+                content.len() as u64,
+                pos,
+                &hash,
+                &Hash::SIPHASH24,
+                &Some(CHUNK_HASH_TYPE),
+            )
+            .unwrap();
+        }
+        let persistent = start_time.elapsed();
+
+        println!("reopen per chunk: {} microseconds", reopen.as_micros() / chunk_count as u128);
+        println!("persistent handle: {} microseconds", persistent.as_micros() / chunk_count as u128);
+    }
+
+    #[test]
+    fn test_interrupted_transfer_leaves_temp_file() {
+        let fixture = SourceFixture::new("rdrop_temp_file", CHUNK_SIZE * 2 + 100);
+        let SourceFixture { dir, content, hash, .. } = &fixture;
+        let output = fixture.path("output.bin");
+
+        let temp = get_temp_path(&output, None);
+        assert_eq!(temp, dir.join("output.bin.rdroppart").to_string_lossy());
+
+        let write_chunk = |pos: u64| {
+            let (header_data, data) = fixture.chunk(pos);
+            write_data_vec(&header_data, &data, &temp).unwrap()
+        };
+
+        // interrupted after the first chunk
+        let log_path = write_chunk(1);
+        assert_eq!(log_path, format!("{}.rdroplog", temp));
+        assert!(Path::new(&temp).exists());
+        assert!(!Path::new(&output).exists());
+        assert!(finish_temp_file(&temp, &output, &hash, &Hash::SIPHASH24).is_err());
+        assert!(!Path::new(&output).exists());
+
+        write_chunk(2);
+        write_chunk(3);
+        finish_temp_file(&temp, &output, &hash, &Hash::SIPHASH24).unwrap();
+
+        assert_eq!(fs::read(&output).unwrap(), *content);
+        assert!(!Path::new(&temp).exists());
+        assert!(!Path::new(&log_path).exists());
+
+        let other_dir = dir.join("temp");
+        assert_eq!(
+            get_temp_path(&output, Some(&other_dir.to_string_lossy())),
+            other_dir.join("output.bin.rdroppart").to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn test_move_temp_files() {
+        let dir = test_dir("rdrop_move_temp_files");
+
+        let paths: Vec<(String, String)> = (0..3)
+            .map(|i| {
+                let output = dir.join(format!("output{}.bin", i)).to_string_lossy().to_string();
+                let temp = get_temp_path(&output, None);
+                fs::write(&temp, format!("content {}", i)).unwrap();
+                fs::write(format!("{}.{}", temp, LOGFILE_EXTENSION), b"log").unwrap();
+                (temp, output)
+            })
+            .collect();
+        let mut files: Vec<(&str, &str)> = paths
+            .iter()
+            .map(|(temp, output)| (temp.as_str(), output.as_str()))
+            .collect();
+
+        // the last file can't be moved, the files moved before are moved back
+        let missing_dir = dir.join("missing").join("output2.bin").to_string_lossy().to_string();
+        files[2].1 = &missing_dir;
+        assert!(move_temp_files(&files).is_err());
+        for (temp, output) in &paths {
+            assert!(Path::new(temp).exists());
+            assert!(Path::new(&format!("{}.{}", temp, LOGFILE_EXTENSION)).exists());
+            assert!(!Path::new(output).exists());
+        }
+
+        files[2].1 = &paths[2].1;
+        move_temp_files(&files).unwrap();
+        for (i, (temp, output)) in paths.iter().enumerate() {
+            assert_eq!(fs::read_to_string(output).unwrap(), format!("content {}", i));
+            assert!(!Path::new(temp).exists());
+            assert!(!Path::new(&format!("{}.{}", temp, LOGFILE_EXTENSION)).exists());
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_move_temp_files_keeps_existing_targets() {
+        let dir = test_dir("rdrop_move_temp_files_existing");
+
+        let paths: Vec<(String, String)> = (0..2)
+            .map(|i| {
+                let output = dir.join(format!("output{}.bin", i)).to_string_lossy().to_string();
+                let temp = get_temp_path(&output, None);
+                fs::write(&temp, format!("content {}", i)).unwrap();
+                fs::write(&output, format!("original {}", i)).unwrap();
+                (temp, output)
+            })
+            .collect();
+        let mut files: Vec<(&str, &str)> = paths
+            .iter()
+            .map(|(temp, output)| (temp.as_str(), output.as_str()))
+            .collect();
+
+        // the second file can't be moved, the first target gets its original content back
+        let missing_temp = dir.join("missing.rdroppart").to_string_lossy().to_string();
+        files[1].0 = &missing_temp;
+        assert!(move_temp_files(&files).is_err());
+        for (i, (temp, output)) in paths.iter().enumerate() {
+            assert_eq!(fs::read_to_string(temp).unwrap(), format!("content {}", i));
+            assert_eq!(fs::read_to_string(output).unwrap(), format!("original {}", i));
+            assert!(!Path::new(&format!("{}.{}", output, BACKUP_FILE_EXTENSION)).exists());
+        }
+
+        files[1].0 = &paths[1].0;
+        move_temp_files(&files).unwrap();
+        for (i, (temp, output)) in paths.iter().enumerate() {
+            assert_eq!(fs::read_to_string(output).unwrap(), format!("content {}", i));
+            assert!(!Path::new(temp).exists());
+            assert!(!Path::new(&format!("{}.{}", output, BACKUP_FILE_EXTENSION)).exists());
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_transfer_with_chunk_size() {
+        let fixture = SourceFixture::new("rdrop_chunk_size", MIN_CHUNK_SIZE * 3 + 100);
+        let SourceFixture { source, content, hash, .. } = &fixture;
+        let output = fixture.path("output.bin");
+        let temp = get_temp_path(&output, None);
+
+        let hashes = ChunkHashes::from_file(&source, MIN_CHUNK_SIZE).unwrap();
+        let mut chunk_source = ChunkSource::open(&source).unwrap();
+        chunk_source.set_chunk_size(MIN_CHUNK_SIZE);
+        let mut pool = ChunkBufferPool::new(1);
+        let mut writer = BatchedChunkWriter::new(&temp, WriteBatching::default());
+        writer.set_chunk_size(MIN_CHUNK_SIZE);
+
+        for pos in [4, 2, 1] {
+            let msg = create_data_vec_verified(&mut chunk_source, pos, &hash, &Hash::SIPHASH24, &hashes, &mut pool).unwrap();
+            let (header, data) = separate_header(&msg).unwrap();
+            let header_data = read_send_header(&header).unwrap();
+            assert_eq!(header_data.chunk_max, 4);
+            writer.write(&header_data, &data).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let mut offer = Offer::new("output.bin", &content.len().to_string(), "SIPHASH24", &hash).unwrap();
+        offer.chunk_size = MIN_CHUNK_SIZE;
+        write_offer_file(&temp, &offer).unwrap();
+
+        // the chunks are placed and resumed by the size of the offer
+        let (resumed, missing) = resume_from_disk(&temp).unwrap();
+        assert_eq!(resumed.chunk_size, MIN_CHUNK_SIZE);
+        assert_eq!(missing, vec![3]);
+        let written = fs::read(&temp).unwrap();
+        assert_eq!(written[..MIN_CHUNK_SIZE * 2], content[..MIN_CHUNK_SIZE * 2]);
+        assert_eq!(written[MIN_CHUNK_SIZE * 3..], content[MIN_CHUNK_SIZE * 3..]);
+    }
+
+    #[test]
+    fn test_batched_chunk_writer() {
+        let fixture = SourceFixture::new("rdrop_batched_writer", CHUNK_SIZE * 5 + 100);
+        let SourceFixture { source, content, hash, .. } = &fixture;
+        let output = fixture.path("output.bin");
+        let temp = get_temp_path(&output, None);
+        let log_path = format!("{}.{}", temp, LOGFILE_EXTENSION);
+
+        let batching = WriteBatching {
+            max_chunks: 4,
+            interval: Duration::from_secs(60),
+        };
+        let mut writer = BatchedChunkWriter::new(&temp, batching);
+        let mut write_chunk = |pos: u64| {
+            let (header_data, data) = fixture.chunk(pos);
+            writer.write(&header_data, &data)
+        };
+
+        for pos in [2, 1, 3] {
+            write_chunk(pos).unwrap();
+        }
+        assert!(!Path::new(&log_path).exists());
+
+        // the full batch is adjacent, so its data is written at once
+        assert_eq!(write_chunk(4).unwrap(), log_path);
+        assert_eq!(fs::read_to_string(&log_path).unwrap().lines().count(), 4);
+        assert_eq!(fs::read(&temp).unwrap(), content[..CHUNK_SIZE * 4]);
+
+        // the log only lists chunks whose data was written
+        write_chunk(6).unwrap();
+        assert_eq!(fs::read_to_string(&log_path).unwrap().lines().count(), 4);
+        assert_eq!(fs::metadata(&temp).unwrap().len(), CHUNK_SIZE as u64 * 4);
+
+        write_chunk(5).unwrap();
+        let msg = create_data_vec(&source, 1, &hash).unwrap();
+        let (header, mut data) = separate_header(&msg).unwrap();
+        data[0] ^= 0xff;
+        assert!(writer.write(&read_send_header(&header).unwrap(), &data).is_err());
+
+        writer.flush().unwrap();
+        drop(writer);
+
+        finish_temp_file(&temp, &output, &hash, &Hash::SIPHASH24).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), *content);
+    }
+
+    #[test]
+    fn test_batched_chunk_writer_compact_log() {
+        let fixture = SourceFixture::new("rdrop_batched_compact", CHUNK_SIZE * 5 + 100);
+        let output = fixture.path("output.bin");
+        let log_path = format!("{}.{}", output, LOGFILE_EXTENSION);
+
+        let batching = WriteBatching {
+            max_chunks: 3,
+            interval: Duration::from_secs(60),
+        };
+        let mut writer = BatchedChunkWriter::new(&output, batching);
+        writer.set_compact_log(true);
+        for pos in [3, 1, 2, 5, 4] {
+            let (header_data, data) = fixture.chunk(pos);
+            writer.write(&header_data, &data).unwrap();
+        }
+
+        // the open run is only written on close
+        assert!(!Path::new(&log_path).exists());
+        writer.close().unwrap();
+        // the sorted batches form a single run, the last chunk was never written
+        assert_eq!(fs::read_to_string(&log_path).unwrap().lines().count(), 1);
+        assert_eq!(validate_file(&log_path, &fixture.hash).unwrap(), (6, 6));
+        assert_eq!(fs::read(&output).unwrap(), fixture.content[..CHUNK_SIZE * 5]);
+    }
+
+    /// Returns the number of write syscalls this process made so far, see proc(5).
+    #[cfg(target_os = "linux")]
+    fn write_syscalls() -> u64 {
+        let io = fs::read_to_string("/proc/self/io").unwrap();
+        return io
+            .lines()
+            .find_map(|line| line.strip_prefix("syscw:"))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+    }
+
+    #[test]
+    #[ignore]
+    #[cfg(target_os = "linux")]
+    fn test_batched_chunk_writer_speed() {
+        let chunk_count = 200;
+        let fixture = SourceFixture::new("rdrop_batched_writer_speed", CHUNK_SIZE * chunk_count);
+        let SourceFixture { dir, content, .. } = &fixture;
+        let chunks: Vec<(HeaderData, Vec<u8>)> = (1..=chunk_count as u64).map(|pos| fixture.chunk(pos)).collect();
+
+        let unbatched_path = dir.join("unbatched.bin").to_string_lossy().to_string();
+        let start_time = Instant::now();
+        let start_syscalls = write_syscalls();
+        for (header_data, data) in &chunks {
+            write_data_vec(header_data, data, &unbatched_path).unwrap();
+        }
+        let unbatched_syscalls = write_syscalls() - start_syscalls;
+        let unbatched = start_time.elapsed();
+
+        let batched_path = dir.join("batched.bin").to_string_lossy().to_string();
+        let batching = WriteBatching {
+            max_chunks: 16,
+            interval: Duration::from_millis(100),
+        };
+        let mut writer = BatchedChunkWriter::new(&batched_path, batching);
+        let start_time = Instant::now();
+        let start_syscalls = write_syscalls();
+        for (header_data, data) in &chunks {
+            writer.write(header_data, data).unwrap();
+        }
+        writer.flush().unwrap();
+        let batched_syscalls = write_syscalls() - start_syscalls;
+        let batched = start_time.elapsed();
+
+        println!(
+            "unbatched: {} microseconds, {} write syscalls",
+            unbatched.as_micros() / chunk_count as u128,
+            unbatched_syscalls
+        );
+        println!(
+            "batched: {} microseconds, {} write syscalls",
+            batched.as_micros() / chunk_count as u128,
+            batched_syscalls
+        );
+        assert!(batched_syscalls < unbatched_syscalls / 4);
+        assert_eq!(fs::read(&batched_path).unwrap(), *content);
+
+        drop(writer);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_preallocated_out_of_order_writes() {
+        let fixture = SourceFixture::new("rdrop_preallocate", CHUNK_SIZE * 3 + 1234);
+        let content = &fixture.content;
+        let output = fixture.path("output.bin");
+
+        preallocate_file(&output, content.len() as u64).unwrap();
+        assert_eq!(metadata(&output).unwrap().len(), content.len() as u64);
+
+        for pos in [4, 2, 1, 3] {
+            let (header_data, data) = fixture.chunk(pos);
+            write_data_vec(&header_data, &data, &output).unwrap();
+            assert_eq!(metadata(&output).unwrap().len(), content.len() as u64);
+        }
+
+        assert_eq!(fs::read(&output).unwrap(), *content);
+
+        // without preallocation the gap before a later chunk is filled with zeros
+        let unallocated = fixture.path("unallocated.bin");
+        merge_file_on_path(&unallocated, &vec![1, 2, 3], 2, 4).unwrap();
+        assert_eq!(fs::read(&unallocated).unwrap(), vec![0, 0, 0, 0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_resume_from_disk() {
+        let fixture = SourceFixture::new("rdrop_resume_from_disk", CHUNK_SIZE * 2 + 100);
+        let SourceFixture { content, hash, .. } = &fixture;
+        let output = fixture.path("output.bin");
+
+        for pos in [1, 3] {
+            let (header_data, data) = fixture.chunk(pos);
+            write_data_vec(&header_data, &data, &output).unwrap();
+        }
+
+        let offer = Offer {
+            name: "source.bin".to_string(),
+            size: content.len() as u64,
+            hash_type: Hash::SIPHASH24,
+            file_hash: hash.clone(),
+            note: "resume".to_string(),
+            extension: "bin".to_string(),
+            mime: String::new(),
+            merkle_root: String::new(),
+            range: None,
+            group: None,
+            chunk_size: CHUNK_SIZE,
+            streaming: false,
+        };
+        write_offer_file(&output, &offer).unwrap();
+
+        // simulated restart: only the files on disk are left
+        let (resumed, missing) = resume_from_disk(&output).unwrap();
+        assert_eq!(resumed.file_hash, *hash);
+        assert_eq!(resumed.size, content.len() as u64);
+        assert_eq!(resumed.note, "resume");
+        assert_eq!(missing, vec![2]);
+
+        let mut order = create_order_byte_vec(missing[0], missing[0], &resumed.file_hash).unwrap();
+        let order = read_order(&mut order).unwrap();
+        assert_eq!((order.start_num, order.end_num), (2, 2));
+    }
+
+    #[test]
+    fn test_resume_rejects_oversized_offer() {
+        let dir = test_dir("rdrop_resume_oversized");
+
+        let output = dir.join("output.bin").to_string_lossy().to_string();
+        let offer = Offer {
+            name: "output.bin".to_string(),
+            size: u64::MAX,
+            hash_type: Hash::SIZE,
+            file_hash: format!("{:016x}", u64::MAX),
+            note: String::new(),
+            extension: "bin".to_string(),
+            mime: String::new(),
+            merkle_root: String::new(),
+            range: None,
+            group: None,
+            chunk_size: CHUNK_SIZE,
+            streaming: false,
+        };
+        write_offer_file(&output, &offer).unwrap();
+
+        let err = resume_from_disk(&output).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::LimitExceeded));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_size_only_transfer() {
+        let dir = test_dir("rdrop_size_only_transfer");
+
+        let source = dir.join("source.bin").to_string_lossy().to_string();
+        let output = dir.join("output.bin").to_string_lossy().to_string();
+        let content: Vec<u8> = (0..CHUNK_SIZE + 10).map(|i| (i % 7) as u8).collect();
+        fs::write(&source, &content).unwrap();
+
+        let msg = create_size_only_offer_byte_msg(content.len() as u64, &source, "").unwrap();
+        let offer = read_offer_vec(&msg).unwrap();
+        assert_eq!(offer.hash_type.to_string(), "SIZE");
+        assert_eq!(
+            offer.file_hash,
+            get_file_hash(&File::open(&source).unwrap(), BUFFER_SIZE, &Hash::SIZE, 0).unwrap()
+        );
+
+        for pos in 1..=2 {
+            let msg = create_data_vec_with_chunk_hash(&source, pos, &offer.file_hash, &offer.hash_type, &None).unwrap();
+            let (header, data) = separate_header(&msg).unwrap();
+            let header_data = read_send_header(&header).unwrap();
+            assert!(header_data.chunk_hash.is_none());
+            assert_eq!(header_data.file_hash, offer.file_hash);
+            write_data_vec(&header_data, &data, &output).unwrap();
+        }
+
+        assert_eq!(fs::read(&output).unwrap(), content);
+
+        // the receiver logs the offered type, whichever type the header names
+        let received = dir.join("received.bin").to_string_lossy().to_string();
+        let mut writer = BatchedChunkWriter::new(&received, WriteBatching::default());
+        writer.set_file_hash_alg(offer.hash_type);
+        for pos in 1..=2 {
+            let msg = create_data_vec_with_chunk_hash(&source, pos, &offer.file_hash, &offer.hash_type, &None).unwrap();
+            let (header, data) = separate_header(&msg).unwrap();
+            writer.write(&read_send_header(&header).unwrap(), &data).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let log = fs::read_to_string(format!("{}.{}", received, LOGFILE_EXTENSION)).unwrap();
+        assert!(log.lines().all(|line| line.contains("[SIZE]")));
+        let report = verify_from_disk(&received).unwrap();
+        assert_eq!(report.hash_type, Hash::SIZE);
+        assert!(report.is_complete());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune_logs() {
+        let dir = test_dir("rdrop_prune_logs");
+        let log_path = |name: &str| {
+            dir.join(format!("{}.{}", name, LOGFILE_EXTENSION))
+                .to_string_lossy()
+                .to_string()
+        };
+        let write_old = |path: &str, parts: &[u64]| {
+            let lines: Vec<String> = parts
+                .iter()
+                .map(|part| {
+                    format!(
+                        "[01.01.2020 - 00:00:00.000] - [{}] - [SIPHASH24] - [0123456789abcdef] - [{}] - [2] - [10 bytes]",
+                        USER_HASH, part
+                    )
+                })
+                .collect();
+            fs::write(path, lines.join("\n") + "\n").unwrap();
+        };
+
+        let old_complete = log_path("old_complete");
+        write_old(&old_complete, &[1, 2]);
+        let old_incomplete = log_path("old_incomplete");
+        write_old(&old_incomplete, &[1]);
+        let recent_complete = log_path("recent_complete");
+        for part in 1..=2 {
+            write_to_log_file(&recent_complete, USER_HASH, &Hash::SIPHASH24, part, 2, 10, "0123456789abcdef", &None, &None)
+                .unwrap();
+        }
+        let empty = log_path("empty");
+        fs::write(&empty, "").unwrap();
+        fs::write(dir.join("data.bin"), b"data").unwrap();
+
+        assert_eq!(list_logs(dir.to_str().unwrap()).unwrap().len(), 4);
+
+        let pruned = prune_logs(dir.to_str().unwrap(), Duration::from_secs(24 * 60 * 60)).unwrap();
+        assert_eq!(pruned, vec![old_complete.clone()]);
+        assert!(!Path::new(&old_complete).exists());
+        assert!(Path::new(&old_incomplete).exists());
+        assert!(Path::new(&recent_complete).exists());
+        assert!(Path::new(&empty).exists());
+        assert!(dir.join("data.bin").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+use crate::file::file::create_data_vec;
+use crate::general::general::{read_send_header, separate_header, HeaderData};
+use crate::hash::hash::get_hash_from_file;
+
+/// Creates an empty directory in the temporary directory of the system. An existing directory of the name is removed.
+///
+/// # Arguments
+///
+/// * name - The name of the directory.
+///
+/// # Returns
+///
+/// The function returns the path of the directory.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    return dir;
+}
+
+/// A source file with a known content in its own temporary directory, used by the transfer tests.
+/// The directory is removed when the fixture is dropped.
+pub struct SourceFixture {
+    /// The directory of the source file. Received files are written to it as well.
+    pub dir: PathBuf,
+    /// The path of the source file.
+    pub source: String,
+    /// The content of the source file.
+    pub content: Vec<u8>,
+    /// The SIPHASH24 hash of the source file.
+    pub hash: String,
+}
+
+impl SourceFixture {
+    /// Creates the directory and writes a source file of the given size to it.
+    ///
+    /// # Arguments
+    ///
+    /// * name - The name of the directory, see `test_dir`.
+    /// * size - The size of the source file in bytes.
+    pub fn new(name: &str, size: usize) -> SourceFixture {
+        let dir = test_dir(name);
+        let source = dir.join("source.bin").to_string_lossy().to_string();
+        let content: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        fs::write(&source, &content).unwrap();
+        let hash = get_hash_from_file(&File::open(&source).unwrap()).unwrap();
+
+        return SourceFixture {
+            dir,
+            source,
+            content,
+            hash,
+        };
+    }
+
+    /// Returns the path of a file in the directory of the fixture.
+    pub fn path(&self, name: &str) -> String {
+        return self.dir.join(name).to_string_lossy().to_string();
+    }
+
+    /// Reads a chunk of the source file and returns it as the receiver reads it.
+    ///
+    /// # Arguments
+    ///
+    /// * pos - The position of the chunk, starting at 1.
+    ///
+    /// # Returns
+    ///
+    /// The function returns the header of the chunk and its data.
+    pub fn chunk(&self, pos: u64) -> (HeaderData, Vec<u8>) {
+        let msg = create_data_vec(&self.source, pos, &self.hash).unwrap();
+        let (header, data) = separate_header(&msg).unwrap();
+
+        return (read_send_header(&header).unwrap(), data);
+    }
+}
+
+impl Drop for SourceFixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
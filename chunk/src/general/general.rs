@@ -6,7 +6,7 @@ use std::{
     path::Path,
 };
 
-use chrono::Utc;
+use chrono::{NaiveDateTime, Utc};
 
 use crate::error::error::RError;
 use crate::{
@@ -17,16 +17,47 @@ use crate::{
 pub const USER_HASH: &str = "0123456789abcdef";
 pub const CHUNK_HASH_TYPE: Hash = Hash::SIPHASH24;
 pub const CHUNK_SIZE: usize = 1024 * 300;
+/// The smallest chunk size an offer may announce, see `Offer::chunk_size`. Offers announce at most `CHUNK_SIZE`.
+pub const MIN_CHUNK_SIZE: usize = 1024 * 16;
+/// The chunk sizes probed when the chunk size is tuned to a connection, in increasing order.
+pub const CHUNK_SIZE_CANDIDATES: [usize; 4] = [MIN_CHUNK_SIZE, 1024 * 64, 1024 * 128, CHUNK_SIZE];
+/// The longest data vector of a chunk, a header followed by a complete chunk.
+/// The header length is stored in a single byte.
+pub const MAX_DATA_VEC_LEN: usize = CHUNK_SIZE + u8::MAX as usize;
 pub const BUFFER_SIZE: usize = 1024 * 300;
-pub const LOGGER_REGEX: &str = r"\[(\d{2}\.\d{2}\.\d{4} \- \d{2}:\d{2}:\d{2}\.\d{3})\][\t\f\v ]*-[\t\f\v ]*\[([a-fA-F0-9]+)\][\t\f\v ]*-[\t\f\v ]*\[(SHA256|SHA512|MD5|SIPHASH24)\][\t\f\v ]*-[\t\f\v ]*\[([a-fA-F0-9]+)\][\t\f\v ]*-[\t\f\v ]*\[(\d+)\][\t\f\v ]*-[\t\f\v ]*\[(\d+)\][\t\f\v ]*-[\t\f\v ]*\[(\d+) bytes\][\t\f\v ]*(-[\t\f\v ]*\[(SHA256|SHA512|MD5|SIPHASH24)\][\t\f\v ]*-[\t\f\v ]*\[([a-fA-F0-9]+)\])?";
+/// Largest file size accepted from a peer by default (1 TiB).
+pub const MAX_OFFER_SIZE: u64 = 1024 * 1024 * 1024 * 1024;
+/// Largest chunk count accepted from a peer or a logfile by default.
+pub const MAX_CHUNK_COUNT: u64 = (MAX_OFFER_SIZE + CHUNK_SIZE as u64 - 1) / CHUNK_SIZE as u64;
+pub const LOGGER_REGEX: &str = r"\[(\d{2}\.\d{2}\.\d{4} \- \d{2}:\d{2}:\d{2}\.\d{3})\][\t\f\v ]*-[\t\f\v ]*\[([a-fA-F0-9]+)\][\t\f\v ]*-[\t\f\v ]*\[(SHA1|SHA256|SHA512|MD5|SIPHASH24|SIZE)\][\t\f\v ]*-[\t\f\v ]*\[([a-fA-F0-9]+)\][\t\f\v ]*-[\t\f\v ]*\[(\d+)\][\t\f\v ]*-[\t\f\v ]*\[(\d+)\][\t\f\v ]*-[\t\f\v ]*\[(\d+) bytes\][\t\f\v ]*(-[\t\f\v ]*\[(SHA1|SHA256|SHA512|MD5|SIPHASH24|SIZE)\][\t\f\v ]*-[\t\f\v ]*\[([a-fA-F0-9]+)\])?";
+/// Format of a compacted logfile entry, a run of contiguous chunks `[first..last]` of the same size without chunk hashes.
+/// Read by `read_log_file` in addition to the given regex, see `FileTransferLog::compacted`.
+pub const LOG_RANGE_REGEX: &str = r"\[(\d{2}\.\d{2}\.\d{4} \- \d{2}:\d{2}:\d{2}\.\d{3})\][\t\f\v ]*-[\t\f\v ]*\[([a-fA-F0-9]+)\][\t\f\v ]*-[\t\f\v ]*\[(SHA1|SHA256|SHA512|MD5|SIPHASH24|SIZE)\][\t\f\v ]*-[\t\f\v ]*\[([a-fA-F0-9]+)\][\t\f\v ]*-[\t\f\v ]*\[(\d+)\.\.(\d+)\][\t\f\v ]*-[\t\f\v ]*\[(\d+)\][\t\f\v ]*-[\t\f\v ]*\[(\d+) bytes\]";
+/// Format of the timestamp of a logfile entry, in UTC.
+pub const LOG_TIMESTAMP_FORMAT: &str = "%d.%m.%Y - %H:%M:%S%.3f";
 pub const STOP_REGEX: &str = r"\[([a-fA-F0-9]+)\]";
-
-#[derive(Debug)]
+/// Format of a completion message, the offered hash followed by the hash of the sent file.
+pub const COMPLETE_REGEX: &str = r"\[([a-fA-F0-9]+)\]\[([a-fA-F0-9]+)\]";
+/// Version of the data header layout. Peers reject headers of other versions.
+/// Version 2 added the file hash algorithm byte.
+pub const HEADER_VERSION: u8 = 2;
+/// Largest chunk count the header can encode, its chunk count and position fields have at most 4 bytes.
+pub const MAX_HEADER_CHUNK_COUNT: u64 = u32::MAX as u64;
+/// Size of the header part in front of the variable-length fields:
+/// type byte, header length, flag byte, version, user hash and file hash algorithm.
+const FIXED_HEADER_SIZE: usize = 13;
+/// Position of the file hash algorithm in the header, see `Hash::from_code`.
+const FILE_HASH_ALG_POS: usize = 12;
+
+#[derive(Debug, Clone)]
 pub struct LogEntry {
     string_ts: String,
     user_hash: String,
     pub file_hash: String,
     pub file_hash_alg: Hash,
+    /// The first chunk of the entry. Entries of compacted logs cover the chunks `first_part..=chunk_part`,
+    /// all other entries a single chunk.
+    pub first_part: u64,
     pub chunk_part: u64,
     pub max_part: u64,
     pub chunk_size: u64,
@@ -51,6 +82,7 @@ impl LogEntry {
             user_hash,
             file_hash,
             file_hash_alg,
+            first_part: chunk_part,
             chunk_part,
             chunk_size,
             max_part,
@@ -58,6 +90,256 @@ impl LogEntry {
             chunk_hash_alg,
         }
     }
+
+    /// Creates the entry of a received chunk, stamped with the current time.
+    ///
+    /// # Arguments
+    ///
+    /// * `header_data` - The header data of the chunk.
+    pub fn from_header_data(header_data: &HeaderData) -> Self {
+        return Self {
+            string_ts: Utc::now().format(LOG_TIMESTAMP_FORMAT).to_string(),
+            user_hash: header_data.user_hash.clone(),
+            file_hash: header_data.file_hash.clone(),
+            file_hash_alg: header_data.file_hash_alg,
+            first_part: header_data.chunk_pos,
+            chunk_part: header_data.chunk_pos,
+            max_part: header_data.chunk_max,
+            chunk_size: header_data.chunk_length as u64,
+            chunk_hash: header_data.chunk_hash.clone(),
+            chunk_hash_alg: header_data.chunk_hash_alg,
+        };
+    }
+
+    /// Returns the time the entry was written as milliseconds since the Unix epoch,
+    /// or None if the timestamp can't be parsed.
+    pub fn timestamp_millis(&self) -> Option<i64> {
+        return NaiveDateTime::parse_from_str(&self.string_ts, LOG_TIMESTAMP_FORMAT)
+            .ok()
+            .map(|time| time.timestamp_millis());
+    }
+
+    /// Formats the entry as a line of the logfile, without the line break.
+    /// An entry of several chunks is formatted as a compacted entry, see `LOG_RANGE_REGEX`.
+    pub fn to_log_line(&self) -> String {
+        if self.first_part != self.chunk_part {
+            return log_range_line(self);
+        }
+
+        return log_line(
+            &self.string_ts,
+            &self.user_hash,
+            &self.file_hash_alg,
+            self.chunk_part,
+            self.max_part,
+            self.chunk_size,
+            &self.file_hash,
+            &self.chunk_hash_alg,
+            &self.chunk_hash,
+        );
+    }
+}
+
+/// Target the progress of a received file is logged to.
+/// Every received chunk is recorded, the entries tell which chunks are still missing, see `validate_transfer_log`.
+pub trait TransferLog {
+    /// Records a received chunk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry can't be stored. The chunk has to be requested again in this case.
+    fn record(&mut self, entry: &LogEntry) -> Result<(), RError>;
+
+    /// Records several received chunks, by default one after another with `record`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an entry can't be stored. The entries in front of it may be recorded.
+    fn record_all(&mut self, entries: &[LogEntry]) -> Result<(), RError> {
+        for entry in entries {
+            self.record(entry)?;
+        }
+
+        return Ok(());
+    }
+
+    /// Returns the recorded entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the recorded entries can't be read.
+    fn entries(&self) -> Result<Vec<LogEntry>, RError>;
+}
+
+/// The default `TransferLog`, a logfile next to the received file.
+/// Survives restarts, so interrupted transfers can be resumed.
+/// Not `Clone`, every log writes its open run when it is dropped.
+#[derive(Debug)]
+pub struct FileTransferLog {
+    path: String,
+    compact: bool,
+    /// The entry of the run which is not written yet, only used by compacted logs.
+    run: Option<LogEntry>,
+}
+
+impl FileTransferLog {
+    /// Creates a log which appends to the logfile at `path`.
+    pub fn new(path: &str) -> Self {
+        return Self {
+            path: path.to_string(),
+            compact: false,
+            run: None,
+        };
+    }
+
+    /// Creates a log which appends runs of contiguous chunks to the logfile at `path`, one line per run.
+    /// Chunk hashes are not logged. A run is written when it is interrupted, when it reaches the last chunk,
+    /// on `flush` and when the log is dropped. If the process crashes, the chunks of the open run are requested again.
+    pub fn compacted(path: &str) -> Self {
+        return Self {
+            path: path.to_string(),
+            compact: true,
+            run: None,
+        };
+    }
+
+    /// Returns the path of the logfile.
+    pub fn path(&self) -> &str {
+        return &self.path;
+    }
+
+    /// Writes the open run of a compacted log.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the logfile can't be written. The run stays open in this case.
+    pub fn flush(&mut self) -> Result<(), RError> {
+        if let Some(run) = &self.run {
+            self.append(&log_range_line(run))?;
+            self.run = None;
+        }
+
+        return Ok(());
+    }
+
+    /// Appends the given lines to the logfile with a single write.
+    fn append(&self, lines: &str) -> Result<(), RError> {
+        return OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{}", lines))
+            .map_err(|err| {
+                RError::new(
+                    RErrorKind::InputOutputError,
+                    &format!("Can't write to log {}: {}", self.path, err),
+                )
+            });
+    }
+}
+
+impl TransferLog for FileTransferLog {
+    fn record(&mut self, entry: &LogEntry) -> Result<(), RError> {
+        if !self.compact {
+            return self.append(&entry.to_log_line());
+        }
+
+        let extends_run = match &self.run {
+            Some(run) => {
+                run.chunk_part + 1 == entry.first_part
+                    && run.max_part == entry.max_part
+                    && run.chunk_size == entry.chunk_size
+                    && run.file_hash == entry.file_hash
+            }
+            None => false,
+        };
+
+        let first_part = match &self.run {
+            Some(run) if extends_run => run.first_part,
+            _ => {
+                self.flush()?;
+                entry.first_part
+            }
+        };
+        self.run = Some(LogEntry {
+            first_part,
+            chunk_hash: None,
+            chunk_hash_alg: None,
+            ..entry.clone()
+        });
+
+        if entry.chunk_part >= entry.max_part {
+            self.flush()?;
+        }
+
+        return Ok(());
+    }
+
+    fn record_all(&mut self, entries: &[LogEntry]) -> Result<(), RError> {
+        if self.compact {
+            for entry in entries {
+                self.record(entry)?;
+            }
+            return Ok(());
+        }
+
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let lines: Vec<String> = entries.iter().map(|entry| entry.to_log_line()).collect();
+        return self.append(&lines.join("\n"));
+    }
+
+    fn entries(&self) -> Result<Vec<LogEntry>, RError> {
+        // a missing logfile means nothing was received yet
+        let mut entries = if Path::new(&self.path).exists() {
+            read_log_file(&self.path, BUFFER_SIZE, LOGGER_REGEX)
+                .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?
+        } else {
+            Vec::new()
+        };
+
+        if let Some(run) = &self.run {
+            entries.push(run.clone());
+        }
+
+        return Ok(entries);
+    }
+}
+
+impl Drop for FileTransferLog {
+    fn drop(&mut self) {
+        // the chunks of a run which is not logged are requested again
+        if let Err(err) = self.flush() {
+            println!("[FILE] {}", err);
+        }
+    }
+}
+
+/// A `TransferLog` kept in memory, for tests and receivers which don't want logfiles on disk.
+/// Interrupted transfers can't be resumed with it.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTransferLog {
+    entries: Vec<LogEntry>,
+}
+
+impl MemoryTransferLog {
+    /// Creates an empty log.
+    pub fn new() -> Self {
+        return Self::default();
+    }
+}
+
+impl TransferLog for MemoryTransferLog {
+    fn record(&mut self, entry: &LogEntry) -> Result<(), RError> {
+        self.entries.push(entry.clone());
+        return Ok(());
+    }
+
+    fn entries(&self) -> Result<Vec<LogEntry>, RError> {
+        return Ok(self.entries.clone());
+    }
 }
 
 #[repr(u8)]
@@ -182,7 +464,11 @@ pub struct FileData {
     path: String,
     pub name: String,
     pub size: u64,
-    extension: FileType,
+    file_type: FileType,
+    /// Extension of the file name without the dot, see `get_extension`.
+    pub extension: Option<String>,
+    /// MIME type derived from the extension, see `get_mime_hint`.
+    pub mime: Option<String>,
     pub file_hash: Option<String>,
 }
 
@@ -191,19 +477,97 @@ impl FileData {
         path: &str,
         name: String,
         size: u64,
-        extension: FileType,
+        file_type: FileType,
         file_hash: Option<String>,
     ) -> Self {
+        let extension = get_extension(&name);
+        let mime = extension
+            .as_deref()
+            .and_then(get_mime_hint)
+            .map(|mime| mime.to_string());
+
         Self {
             path: path.to_string(),
             name: name.to_string(),
             size: size,
-            extension: extension,
+            file_type: file_type,
+            extension,
+            mime,
             file_hash: file_hash,
         }
     }
 }
 
+/// Returns the extension of a file name without the dot.
+/// Only extensions of up to 16 ASCII letters and digits are returned, so they can be transmitted in an offer.
+///
+/// # Arguments
+///
+/// * `name` - The name or path of the file.
+///
+/// # Returns
+///
+/// The function returns the extension, or `None` if the name has no valid extension.
+///
+pub fn get_extension(name: &str) -> Option<String> {
+    let extension = Path::new(name).extension()?.to_str()?;
+
+    if extension.is_empty()
+        || extension.len() > 16
+        || !extension.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        return None;
+    }
+
+    return Some(extension.to_string());
+}
+
+/// Returns the MIME type usually used for files with the given extension.
+///
+/// # Arguments
+///
+/// * `extension` - The extension without the dot. The case is ignored.
+///
+/// # Returns
+///
+/// The function returns the MIME type, or `None` if the extension is unknown.
+///
+pub fn get_mime_hint(extension: &str) -> Option<&'static str> {
+    let mime = match extension.to_ascii_lowercase().as_str() {
+        "txt" | "log" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "7z" => "application/x-7z-compressed",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        _ => return None,
+    };
+
+    return Some(mime);
+}
+
 #[derive(Debug)]
 pub struct HeaderData {
     pub user_hash: String,
@@ -221,6 +585,7 @@ impl HeaderData {
     pub fn new(
         user_hash: String,
         file_hash: String,
+        file_hash_alg: Hash,
         chunk_hash: String,
         chunk_length: usize,
         chunk_pos: u64,
@@ -229,6 +594,7 @@ impl HeaderData {
         let user_hash_alg = match user_hash.len() {
             16 => Hash::SIPHASH24,
             32 => Hash::MD5,
+            40 => Hash::SHA1,
             64 => Hash::SHA256,
             128 => Hash::SHA512,
             _ => {
@@ -238,22 +604,17 @@ impl HeaderData {
                 ))
             }
         };
-        let file_hash_alg = match file_hash.len() {
-            16 => Hash::SIPHASH24,
-            32 => Hash::MD5,
-            64 => Hash::SHA256,
-            128 => Hash::SHA512,
-            _ => {
-                return Err(RError::new(
-                    RErrorKind::ConvertionError,
-                    &format!("Invalid user-hash length: {}", user_hash.len()),
-                ))
-            }
-        };
+        if file_hash.len() != header_hash_len(&file_hash_alg) * 2 {
+            return Err(RError::new(
+                RErrorKind::ConvertionError,
+                &format!("Invalid file-hash length: {}", file_hash.len()),
+            ));
+        }
 
         let chunk_hash_alg = match chunk_hash.len() {
             16 => Some(Hash::SIPHASH24),
             32 => Some(Hash::MD5),
+            40 => Some(Hash::SHA1),
             64 => Some(Hash::SHA256),
             128 => Some(Hash::SHA512),
             _ => None,
@@ -337,7 +698,22 @@ pub fn get_file_data(filepath: &str) -> Result<(File, String, u64), Error> {
 /// The function returns the number of chunks required to split the file.
 ///
 pub fn get_chunk_count(file_size: u64) -> u64 {
-    let chunk_size = CHUNK_SIZE as u64;
+    return get_chunk_count_with_size(file_size, CHUNK_SIZE);
+}
+
+/// Calculates the number of chunks of a file like `get_chunk_count`, for chunks of the given size.
+///
+/// # Arguments
+///
+/// * `file_size` - The size of the file in bytes.
+/// * `chunk_size` - The size of a chunk, see `Offer::chunk_size`.
+///
+/// # Returns
+///
+/// The function returns the number of chunks required to split the file.
+///
+pub fn get_chunk_count_with_size(file_size: u64, chunk_size: usize) -> u64 {
+    let chunk_size = chunk_size as u64;
 
     let mut full_val = file_size / chunk_size;
 
@@ -348,6 +724,126 @@ pub fn get_chunk_count(file_size: u64) -> u64 {
     return full_val;
 }
 
+/// Calculates the size of a chunk of a file. Only the last chunk may be shorter than `CHUNK_SIZE`.
+///
+/// # Arguments
+///
+/// * `file_size` - The size of the file in bytes.
+/// * `chunk_pos` - The position of the chunk, starting at 1.
+///
+/// # Returns
+///
+/// The function returns the size of the chunk in bytes, 0 if the file has no such chunk.
+///
+pub fn get_chunk_len(file_size: u64, chunk_pos: u64) -> u64 {
+    return get_chunk_len_with_size(file_size, chunk_pos, CHUNK_SIZE);
+}
+
+/// Calculates the size of a chunk of a file like `get_chunk_len`, for chunks of the given size.
+///
+/// # Arguments
+///
+/// * `file_size` - The size of the file in bytes.
+/// * `chunk_pos` - The position of the chunk, starting at 1.
+/// * `chunk_size` - The size of a chunk, see `Offer::chunk_size`.
+///
+/// # Returns
+///
+/// The function returns the size of the chunk in bytes, 0 if the file has no such chunk.
+///
+pub fn get_chunk_len_with_size(file_size: u64, chunk_pos: u64, chunk_size: usize) -> u64 {
+    let chunk_count = get_chunk_count_with_size(file_size, chunk_size);
+    let chunk_size = chunk_size as u64;
+
+    if chunk_pos == 0 || chunk_pos > chunk_count {
+        return 0;
+    }
+
+    if chunk_pos == chunk_count && file_size % chunk_size != 0 {
+        return file_size % chunk_size;
+    }
+
+    return chunk_size;
+}
+
+/// Calculates the number of bytes of the first chunks of a file.
+///
+/// # Arguments
+///
+/// * `file_size` - The size of the file in bytes.
+/// * `chunks` - The number of chunks from the start of the file.
+///
+/// # Returns
+///
+/// The function returns the number of bytes, at most `file_size`.
+///
+pub fn get_chunks_len(file_size: u64, chunks: u64) -> u64 {
+    return get_chunks_len_with_size(file_size, chunks, CHUNK_SIZE);
+}
+
+/// Calculates the number of bytes of the first chunks of a file like `get_chunks_len`, for chunks of the given size.
+///
+/// # Arguments
+///
+/// * `file_size` - The size of the file in bytes.
+/// * `chunks` - The number of chunks from the start of the file.
+/// * `chunk_size` - The size of a chunk, see `Offer::chunk_size`.
+///
+/// # Returns
+///
+/// The function returns the number of bytes, at most `file_size`.
+///
+pub fn get_chunks_len_with_size(file_size: u64, chunks: u64, chunk_size: usize) -> u64 {
+    let chunks = chunks.min(get_chunk_count_with_size(file_size, chunk_size));
+
+    if chunks == 0 {
+        return 0;
+    }
+
+    return (chunks - 1) * chunk_size as u64 + get_chunk_len_with_size(file_size, chunks, chunk_size);
+}
+
+/// Checks that a chunk size is between `MIN_CHUNK_SIZE` and `CHUNK_SIZE`, so a chunk fits into `MAX_DATA_VEC_LEN`.
+///
+/// # Arguments
+///
+/// * `chunk_size` - The size of a chunk, e.g. announced in an offer.
+///
+/// # Errors
+///
+/// The function returns an `InputOutputError` if the chunk size is out of bounds.
+///
+pub fn check_chunk_size(chunk_size: usize) -> Result<(), RError> {
+    if chunk_size < MIN_CHUNK_SIZE || chunk_size > CHUNK_SIZE {
+        return Err(RError::new(
+            RErrorKind::InputOutputError,
+            &format!("Chunk size {} is not between {} and {}.", chunk_size, MIN_CHUNK_SIZE, CHUNK_SIZE),
+        ));
+    }
+
+    return Ok(());
+}
+
+/// Returns the chunk size a file is offered with. Smaller chunks mean more chunks,
+/// so `CHUNK_SIZE` is used instead if the file would exceed `MAX_CHUNK_COUNT` chunks of the preferred size.
+///
+/// # Arguments
+///
+/// * `file_size` - The size of the file in bytes.
+/// * `chunk_size` - The preferred chunk size, e.g. tuned to the connection.
+///
+/// # Returns
+///
+/// The function returns the preferred chunk size if it is valid and the file stays within `MAX_CHUNK_COUNT` chunks, otherwise `CHUNK_SIZE`.
+///
+pub fn fit_chunk_size(file_size: u64, chunk_size: usize) -> usize {
+    if check_chunk_size(chunk_size).is_err() || get_chunk_count_with_size(file_size, chunk_size) > MAX_CHUNK_COUNT {
+        return CHUNK_SIZE;
+    }
+
+    return chunk_size;
+}
+
 
 /// Appends a header byte to the beginning of a byte vector.
 ///
@@ -401,6 +897,47 @@ pub fn calc_chunk_count(chunk_size: usize, file_size: u64) -> Result<u64, RError
 
 
 
+/// Returns the byte at the given position of a header.
+///
+/// # Errors
+///
+/// Returns `RErrorKind::ReadHeaderError` if the header is too short, so malformed headers from the network can't cause a panic.
+///
+fn header_byte(header_vec: &[u8], pos: usize) -> Result<u8, RError> {
+    return header_vec.get(pos).copied().ok_or_else(|| {
+        RError::new(
+            RErrorKind::ReadHeaderError,
+            &format!("Header is too short to contain byte {}.", pos),
+        )
+    });
+}
+
+/// Returns the number of bytes a file hash of the given algorithm takes in the header.
+/// A `SIZE` hash is the size as 8 bytes.
+fn header_hash_len(hash: &Hash) -> usize {
+    match hash {
+        Hash::SIZE => 8,
+        hash => *hash as usize / 2,
+    }
+}
+
+/// Reads the file hash algorithm of a header.
+///
+/// # Errors
+///
+/// Returns `RErrorKind::ReadHeaderError` if the header is too short or the algorithm is unknown.
+///
+fn read_file_hash_alg(header_vec: &[u8]) -> Result<Hash, RError> {
+    let code = header_byte(header_vec, FILE_HASH_ALG_POS)?;
+
+    return Hash::from_code(code).ok_or_else(|| {
+        RError::new(
+            RErrorKind::ReadHeaderError,
+            &format!("Unknown file hash algorithm {} in header.", code),
+        )
+    });
+}
+
 /// Reads the header information from a byte vector and constructs a `Header` struct.
 ///
 /// # Arguments
@@ -415,15 +952,14 @@ pub fn calc_chunk_count(chunk_size: usize, file_size: u64) -> Result<u64, RError
 pub fn read_header(header_vec: &Vec<u8>) -> Result<Header, RError> {
     //bitmasks for length of different data in header
     let bitmask_chunk_size: u8 = 0b10000000;
-    let bitmask_file_hash = 0b00011000;
     let bitmask_chunk_count: u8 = 0b01100000;
     let bitmask_chunk_hash: u8 = 0b00000111;
     let mut header = Header::new(
         0,
         0,
-        3,
-        10,
+        4,
         11,
+        12,
         0,
         0,
         0,
@@ -436,11 +972,36 @@ pub fn read_header(header_vec: &Vec<u8>) -> Result<Header, RError> {
         Vec::new(),
     );
 
-    header.header_length = header_vec.get(1).unwrap().clone() as usize;
+    if header_vec.len() < FIXED_HEADER_SIZE {
+        return Err(RError::new(
+            RErrorKind::ReadHeaderError,
+            "Header is too short.",
+        ));
+    }
 
-    header.third_byte = header_vec.get(2).unwrap().clone() as usize;
+    let version = header_byte(header_vec, 3)?;
+    if version != HEADER_VERSION {
+        return Err(RError::new(
+            RErrorKind::ReadHeaderError,
+            &format!(
+                "Header version {} is not supported, expected version {}.",
+                version, HEADER_VERSION
+            ),
+        ));
+    }
+
+    header.header_length = header_byte(header_vec, 1)? as usize;
+
+    if header_vec.len() < header.header_length {
+        return Err(RError::new(
+            RErrorKind::ReadHeaderError,
+            "Header is shorter than its declared length.",
+        ));
+    }
+
+    header.third_byte = header_byte(header_vec, 2)? as usize;
 
-    let mut length = 11;
+    let mut length = FIXED_HEADER_SIZE;
 
     header.chunk_length_pos_s = length;
     match header.third_byte as u8 & bitmask_chunk_size {
@@ -461,31 +1022,9 @@ pub fn read_header(header_vec: &Vec<u8>) -> Result<Header, RError> {
     };
     header.chunk_length_pos_e = length - 1;
 
+    // bits 4 and 5 are unused, the file hash algorithm has its own byte
     header.file_hash_pos_s = length;
-    match header.third_byte as u8 & bitmask_file_hash {
-        0b00000000 => {
-            // Bits 4 and 5 are 00
-            length = length + 8;
-        }
-        0b00001000 => {
-            // Bits 4 and 5 are 01
-            length = length + 16;
-        }
-        0b00010000 => {
-            // Bits 4 and 5 are 10
-            length = length + 32;
-        }
-        0b00011000 => {
-            // Bits 4 and 5 are 11
-            length = length + 64;
-        }
-        _ => {
-            return Err(RError::new(
-                RErrorKind::ReadHeaderError,
-                "Can't map headerbit in bit 3 for ReadData - Calc file_hash.",
-            ));
-        }
-    };
+    length = length + header_hash_len(&read_file_hash_alg(header_vec)?);
     header.file_hash_pos_e = length - 1;
 
     header.chunk_max_pos_s = length;
@@ -527,6 +1066,11 @@ pub fn read_header(header_vec: &Vec<u8>) -> Result<Header, RError> {
             header.chunk_hash_pos_s = None;
             header.chunk_hash_pos_e = None;
         }
+        0b00000001 => {
+            // Bits 6, 7 and 8 are 001
+            length = length + 20;
+            header.chunk_hash_pos_e = Some(length - 1);
+        }
         0b00000100 => {
             // Bits 6, 7 and 8 are 100
             length = length + 8;
@@ -589,39 +1133,40 @@ pub fn extract_header_data(header: &Header) -> Result<HeaderData, RError> {
     let mut chunk_hash: String = "".to_string();
 
     for i in header.user_pos_s..=header.user_pos_e {
-        let val = header.fix_header[i];
+        let val = header_byte(&header.fix_header, i)?;
         user_hash = format!("{}{:02X}", user_hash, val);
     }
 
     for i in header.chunk_length_pos_s..=header.chunk_length_pos_e {
-        let val = header.fix_header[i] as u32;
+        let val = header_byte(&header.fix_header, i)? as u32;
         chunk_length = (chunk_length << 8) | (val)
     }
 
     for i in header.file_hash_pos_s..=header.file_hash_pos_e {
-        let val = header.fix_header[i];
+        let val = header_byte(&header.fix_header, i)?;
         file_hash = format!("{}{:02X}", file_hash, val);
     }
 
     for i in header.chunk_max_pos_s..=header.chunk_max_pos_e {
-        let val = header.fix_header[i] as u32;
+        let val = header_byte(&header.fix_header, i)? as u32;
         chunk_max = (chunk_max << 8) | (val)
     }
 
     for i in header.chunk_pos_s..=header.chunk_pos_e {
-        let val = header.fix_header[i] as u32;
+        let val = header_byte(&header.fix_header, i)? as u32;
         chunk_pos = (chunk_pos << 8) | (val)
     }
 
-    if header.chunk_hash_pos_s.is_some() && header.chunk_hash_pos_e.is_some() {
-        for i in header.chunk_hash_pos_s.unwrap()..=header.chunk_hash_pos_e.unwrap() {
-            let val = header.fix_header[i];
+    if let (Some(start), Some(end)) = (header.chunk_hash_pos_s, header.chunk_hash_pos_e) {
+        for i in start..=end {
+            let val = header_byte(&header.fix_header, i)?;
             chunk_hash = format!("{}{:02X}", chunk_hash, val);
         }
     }
     return HeaderData::new(
         user_hash,
         file_hash.to_lowercase(),
+        read_file_hash_alg(&header.fix_header)?,
         chunk_hash.to_lowercase(),
         chunk_length as usize,
         chunk_pos as u64,
@@ -644,19 +1189,25 @@ pub fn extract_header_data(header: &Header) -> Result<HeaderData, RError> {
 /// # Returns
 ///
 /// The function returns a `Header` struct representing the created header.
+/// The file hash algorithm is written as its own byte, so `SIZE` and `SIPHASH24` hashes of the same length can be told apart.
+/// `SIZE` sends no chunk hashes.
+///
+/// # Errors
+///
+/// The function returns a `LimitExceeded` error if `chunk_count` exceeds `MAX_HEADER_CHUNK_COUNT`.
 ///
 pub fn create_header(
     file_length: u64,
     chunk_count: u64,
     file_hash_type: &Hash,
     chunk_hash_type: &Option<Hash>,
-) -> Header {
+) -> Result<Header, RError> {
     let mut header = Header::new(
         0,
         0,
-        3,
-        10,
+        4,
         11,
+        12,
         0,
         0,
         0,
@@ -669,7 +1220,7 @@ pub fn create_header(
         Vec::new(),
     );
 
-    let mut length: usize = 11;
+    let mut length: usize = FIXED_HEADER_SIZE;
 
     let mut third_byte = [0; 8];
 
@@ -684,29 +1235,7 @@ pub fn create_header(
     header.chunk_length_pos_e = length - 1;
 
     header.file_hash_pos_s = length;
-    // set bits for file_hash_size
-    match file_hash_type {
-        Hash::SIPHASH24 => {
-            third_byte[3] = 0;
-            third_byte[4] = 0;
-            length = length + 8;
-        }
-        Hash::MD5 => {
-            third_byte[3] = 0;
-            third_byte[4] = 1;
-            length = length + 16;
-        }
-        Hash::SHA256 => {
-            third_byte[3] = 1;
-            third_byte[4] = 0;
-            length = length + 32;
-        }
-        Hash::SHA512 => {
-            third_byte[3] = 1;
-            third_byte[4] = 1;
-            length = length + 64;
-        }
-    }
+    length = length + header_hash_len(file_hash_type);
     header.file_hash_pos_e = length - 1;
 
     header.chunk_max_pos_s = length;
@@ -723,10 +1252,18 @@ pub fn create_header(
         third_byte[1] = 1;
         third_byte[2] = 0;
         length = length + 3;
-    } else if chunk_count < 2u64.pow(32) {
+    } else if chunk_count <= MAX_HEADER_CHUNK_COUNT {
         third_byte[1] = 1;
         third_byte[2] = 1;
         length = length + 4;
+    } else {
+        return Err(RError::new(
+            RErrorKind::LimitExceeded,
+            &format!(
+                "Chunk count {} exceeds the header limit of {}.",
+                chunk_count, MAX_HEADER_CHUNK_COUNT
+            ),
+        ));
     }
     header.chunk_max_pos_e = length - 1;
 
@@ -762,7 +1299,13 @@ pub fn create_header(
             third_byte[7] = 1;
             length = length + 64;
         }
-        None => {
+        Some(Hash::SHA1) => {
+            third_byte[5] = 0;
+            third_byte[6] = 0;
+            third_byte[7] = 1;
+            length = length + 20;
+        }
+        Some(Hash::SIZE) | None => {
             third_byte[5] = 0;
             third_byte[6] = 0;
             third_byte[7] = 0;
@@ -788,13 +1331,15 @@ pub fn create_header(
     fix_header[0] = 0 as u8;
     fix_header[1] = length as u8;
     fix_header[2] = byte as u8;
+    fix_header[3] = HEADER_VERSION;
+    fix_header[FILE_HASH_ALG_POS] = *file_hash_type as u8;
 
     header.fix_header = fix_header;
 
     header.header_length = length;
     header.third_byte = byte as usize;
 
-    return header;
+    return Ok(header);
 }
 
 
@@ -872,7 +1417,18 @@ pub fn write_hex_in_header(
 ///
 /// Returns `Result<(Vec<u8>, Vec<u8>), RError>` containing the separated header and data vectors, or an error.
 ///
+/// # Errors
+///
+/// Returns `RErrorKind::InputOutputError` if the vector is shorter than two bytes or shorter than the declared header length.
+///
 pub fn separate_header(data: &Vec<u8>) -> Result<(Vec<u8>, Vec<u8>), RError> {
+    if data.len() < 2 {
+        return Err(RError::new(
+            RErrorKind::InputOutputError,
+            "Message is too short to contain a header.",
+        ));
+    }
+
     let first_byte = data[0];
 
     if first_byte != 0 {
@@ -884,6 +1440,13 @@ pub fn separate_header(data: &Vec<u8>) -> Result<(Vec<u8>, Vec<u8>), RError> {
 
     let second_byte = data[1];
 
+    if data.len() < second_byte as usize {
+        return Err(RError::new(
+            RErrorKind::InputOutputError,
+            "Message is shorter than the declared header length.",
+        ));
+    }
+
     let (header, data) = data.split_at(second_byte as usize);
 
     let header = header.to_vec();
@@ -959,6 +1522,10 @@ pub fn check_chunk_hash(
 ///
 /// Returns the path to the log file if successful.
 ///
+/// # Errors
+///
+/// Returns an error if the log file cannot be opened or written.
+///
 pub fn write_to_log_file(
     path: &str,
     user_hash: &str,
@@ -970,8 +1537,62 @@ pub fn write_to_log_file(
     part_hash_alg: &Option<Hash>,
     part_hash: &Option<String>,
 ) -> Result<String, Error> {
-    let time = Utc::now().format("%d.%m.%Y - %H:%M:%S%.3f").to_string();
+    let log_line = format_log_line(
+        user_hash,
+        parent_hash_alg,
+        part_num,
+        max_num,
+        part_size,
+        parent_hash,
+        part_hash_alg,
+        part_hash,
+    );
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", log_line)?;
+
+    Ok(path.to_string())
+}
 
+/// Formats a log entry as written by `write_to_log_file`, without the line break.
+/// The arguments are the ones of `write_to_log_file`; the entry is stamped with the current time.
+pub fn format_log_line(
+    user_hash: &str,
+    parent_hash_alg: &Hash,
+    part_num: u64,
+    max_num: u64,
+    part_size: u64,
+    parent_hash: &str,
+    part_hash_alg: &Option<Hash>,
+    part_hash: &Option<String>,
+) -> String {
+    let time = Utc::now().format(LOG_TIMESTAMP_FORMAT).to_string();
+
+    return log_line(
+        &time,
+        user_hash,
+        parent_hash_alg,
+        part_num,
+        max_num,
+        part_size,
+        parent_hash,
+        part_hash_alg,
+        part_hash,
+    );
+}
+
+/// Formats a log entry with the given timestamp, see `format_log_line`.
+fn log_line(
+    time: &str,
+    user_hash: &str,
+    parent_hash_alg: &Hash,
+    part_num: u64,
+    max_num: u64,
+    part_size: u64,
+    parent_hash: &str,
+    part_hash_alg: &Option<Hash>,
+    part_hash: &Option<String>,
+) -> String {
     let opt_hash = match (part_hash_alg, part_hash) {
         (Some(alg), Some(hash)) => {
             format!(" - [{}] - [{}]", alg.to_string(), hash)
@@ -991,14 +1612,22 @@ pub fn write_to_log_file(
         opt_hash
     );
 
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(path)
-        .unwrap();
-    writeln!(file, "{}", log_line).unwrap();
+    return log_line;
+}
 
-    Ok(path.to_string())
+/// Formats the run of contiguous chunks of an entry as a compacted log entry, see `LOG_RANGE_REGEX`.
+fn log_range_line(run: &LogEntry) -> String {
+    return format!(
+        "[{}] - [{}] - [{}] - [{}] - [{}..{}] - [{}] - [{} bytes]",
+        run.string_ts,
+        run.user_hash,
+        run.file_hash_alg.to_string(),
+        run.file_hash,
+        run.first_part,
+        run.chunk_part,
+        run.max_part,
+        run.chunk_size
+    );
 }
 
 
@@ -1014,30 +1643,54 @@ pub fn write_to_log_file(
 /// Returns a tuple indicating the range of missing chunk parts. The first value represents the lowest missing chunk part,
 /// and the second value represents the highest missing chunk part. If no missing chunk parts are found, both values will be zero.
 ///
-pub fn validate_log_file(vec: &Vec<LogEntry>) -> (u64, u64) {
-    let mut missing_vec = Vec::new();
-    let max_count = vec[0].max_part;
-    let mut found_values = vec![false; max_count as usize];
-
-    for entry in vec {
-        if entry.chunk_part <= max_count {
-            found_values[entry.chunk_part as usize - 1] = true;
+/// # Errors
+///
+/// The function returns an error if there are no log entries or the logged chunk count exceeds `MAX_CHUNK_COUNT`.
+///
+pub fn validate_log_file(vec: &Vec<LogEntry>) -> Result<(u64, u64), RError> {
+    let max_count = match vec.first() {
+        Some(entry) => entry.max_part,
+        None => {
+            return Err(RError::new(
+                RErrorKind::InputOutputError,
+                "The transfer log has no entries.",
+            ))
         }
+    };
+    if max_count > MAX_CHUNK_COUNT {
+        return Err(RError::new(
+            RErrorKind::LimitExceeded,
+            &format!("Logged chunk count {} exceeds the limit of {}.", max_count, MAX_CHUNK_COUNT),
+        ));
     }
 
-    for (i, &found) in found_values.iter().enumerate() {
-        if !found {
-            missing_vec.push(i as u64 + 1);
+    // the logged runs are walked in order, so the chunks are never expanded
+    let mut ranges: Vec<(u64, u64)> = vec
+        .iter()
+        .map(|entry| (entry.first_part.max(1), entry.chunk_part.min(max_count)))
+        .filter(|(first, last)| first <= last)
+        .collect();
+    ranges.sort_unstable();
+
+    let mut lowest_missing = None;
+    let mut highest_missing = 0;
+    // the first chunk which is not covered by the runs walked so far
+    let mut next = 1;
+    for (first, last) in ranges {
+        if first > next {
+            lowest_missing.get_or_insert(next);
+            highest_missing = first - 1;
         }
+        next = next.max(last + 1);
+    }
+    if next <= max_count {
+        lowest_missing.get_or_insert(next);
+        highest_missing = max_count;
     }
 
-    match missing_vec.len() {
-        0 => return (0, 0),
-        1 => return (missing_vec[0], missing_vec[0]),
-        _ => {
-            let x = missing_vec.len() - 1;
-            return (missing_vec[0], missing_vec[x]);
-        }
+    match lowest_missing {
+        Some(lowest_missing) => return Ok((lowest_missing, highest_missing)),
+        None => return Ok((0, 0)),
     }
 }
 
@@ -1098,6 +1751,64 @@ pub fn create_stop(hash: &str) -> Result<Vec<u8>, Error> {
     return Ok(vec);
 }
 
+/// Creates a completion message, sent after the last chunk of a file.
+/// The sender hashes the file again, so the receiver can compare the received file against the data which was
+/// actually sent instead of only the offer.
+///
+/// # Arguments
+///
+/// * `hash` - The hash of the offered file.
+/// * `file_hash` - The `Hash::SIPHASH24` hash of the sent file.
+///
+/// # Returns
+///
+/// Returns a byte vector representing the completion message if successful.
+///
+/// # Errors
+///
+/// The function can return an error if there is an issue with writing the hashes to the byte vector.
+///
+pub fn create_complete(hash: &str, file_hash: &str) -> Result<Vec<u8>, Error> {
+    let mut vec = Vec::new();
+    vec.push(4);
+    write!(vec, "[{}][{}]", hash, file_hash)?;
+
+    return Ok(vec);
+}
+
+/// Reads a completion message, see `create_complete`.
+///
+/// # Arguments
+///
+/// * `byte_vec` - The byte vector containing the completion message.
+///
+/// # Returns
+///
+/// Returns the hash of the offered file and the hash of the sent file.
+///
+/// # Errors
+///
+/// The function returns an error if the byte vector is no completion message.
+///
+pub fn read_complete(byte_vec: &[u8]) -> Result<(String, String), RError> {
+    let complete = String::from_utf8_lossy(byte_vec).into_owned();
+
+    let regex = Regex::new(COMPLETE_REGEX)
+        .map_err(|err| RError::new(RErrorKind::RegexError, &err.to_string()))?;
+
+    if let Some(captures) = regex.captures(&complete) {
+        let hash = captures.get(1).map_or("", |m| m.as_str()).to_string();
+        let file_hash = captures.get(2).map_or("", |m| m.as_str()).to_string();
+
+        return Ok((hash, file_hash));
+    }
+
+    return Err(RError::new(
+        RErrorKind::InputOutputError,
+        "Can't read completion message.",
+    ));
+}
+
 
 
 /// Reads a log file at the specified path and extracts log entries based on the provided regular expression.
@@ -1111,6 +1822,7 @@ pub fn create_stop(hash: &str) -> Result<Vec<u8>, Error> {
 /// # Returns
 ///
 /// Returns a vector of `LogEntry` structures representing the extracted log entries if successful.
+/// The runs of a compacted logfile are returned as one entry each, see `LogEntry::first_part`.
 ///
 /// # Errors
 ///
@@ -1132,6 +1844,7 @@ pub fn read_log_file(path: &str, buffer_size: usize, regex: &str) -> Result<Vec<
     let mut result = HashMap::new();
 
     let regex = Regex::new(regex).unwrap();
+    let range_regex = Regex::new(LOG_RANGE_REGEX).unwrap();
 
     for entry in buf_reader.lines() {
         let line = match entry {
@@ -1139,13 +1852,54 @@ pub fn read_log_file(path: &str, buffer_size: usize, regex: &str) -> Result<Vec<
             Err(_) => continue,
         };
 
-        if let Some(captures) = regex.captures(&line) {
+        if let Some(captures) = range_regex.captures(&line) {
+            let file_hash_alg = match captures[3].parse::<Hash>() {
+                Ok(hash) => hash,
+                Err(_) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "Hash-Algorithm not found",
+                    ))
+                }
+            };
+
+            let (first, last, max_part, chunk_size) = match (
+                captures[5].parse::<u64>(),
+                captures[6].parse::<u64>(),
+                captures[7].parse::<u64>(),
+                captures[8].parse::<u64>(),
+            ) {
+                (Ok(first), Ok(last), Ok(max_part), Ok(chunk_size)) => (first, last, max_part, chunk_size),
+                _ => continue,
+            };
+
+            // a run outside of the chunks of the file is skipped
+            if first == 0 || first > last || last > max_part || max_part > MAX_CHUNK_COUNT {
+                continue;
+            }
+
+            let log_entry = LogEntry {
+                string_ts: captures[1].to_owned(),
+                user_hash: captures[2].to_owned(),
+                file_hash_alg,
+                file_hash: captures[4].to_owned(),
+                first_part: first,
+                chunk_part: last,
+                max_part,
+                chunk_size,
+                chunk_hash_alg: None,
+                chunk_hash: None,
+            };
+            result.insert((first, last), log_entry);
+        } else if let Some(captures) = regex.captures(&line) {
             let chunk_hash_alg = if captures.get(8).is_some() && captures.get(9).is_some() {
                 let chunk_hash_alg_tmp = match &captures[9] {
                     "SIPHASH24" => Hash::SIPHASH24,
                     "SHA512" => Hash::SHA512,
                     "MD5" => Hash::MD5,
+                    "SHA1" => Hash::SHA1,
                     "SHA256" => Hash::SHA256,
+                    "SIZE" => Hash::SIZE,
                     _ => {
                         return Err(Error::new(
                             ErrorKind::InvalidInput,
@@ -1168,7 +1922,9 @@ pub fn read_log_file(path: &str, buffer_size: usize, regex: &str) -> Result<Vec<
                 "SIPHASH24" => Hash::SIPHASH24,
                 "SHA512" => Hash::SHA512,
                 "MD5" => Hash::MD5,
+                "SHA1" => Hash::SHA1,
                 "SHA256" => Hash::SHA256,
+                "SIZE" => Hash::SIZE,
                 _ => {
                     return Err(Error::new(
                         ErrorKind::InvalidInput,
@@ -1182,13 +1938,14 @@ pub fn read_log_file(path: &str, buffer_size: usize, regex: &str) -> Result<Vec<
                 user_hash: captures[2].to_owned(),
                 file_hash_alg: file_hash_alg,
                 file_hash: captures[4].to_owned(),
+                first_part: captures[5].parse::<u64>().unwrap(),
                 chunk_part: captures[5].parse::<u64>().unwrap(),
                 max_part: captures[6].parse::<u64>().unwrap(),
                 chunk_size: captures[7].parse::<u64>().unwrap(),
                 chunk_hash_alg: chunk_hash_alg,
                 chunk_hash: chunk_hash,
             };
-            result.insert(log_entry.chunk_part, log_entry);
+            result.insert((log_entry.first_part, log_entry.chunk_part), log_entry);
         }
     }
     let vec = result.into_iter().map(|(_, le)| le).collect();
@@ -1214,13 +1971,218 @@ pub fn read_log_file(path: &str, buffer_size: usize, regex: &str) -> Result<Vec<
 ///
 /// The function can return an error if there is an issue reading the log file.
 pub fn validate_file(output_dir: &str, _file_hash: &str) -> Result<(u64, u64), RError> {
+    return validate_transfer_log(&FileTransferLog::new(output_dir));
+}
+
+/// Validates the entries of a transfer log like `validate_log_file`.
+///
+/// # Arguments
+///
+/// * `log` - The log of the received file.
+///
+/// # Returns
+///
+/// The function returns a tuple containing the lowest and the highest missing chunk part, both zero if no part is missing.
+///
+/// # Errors
+///
+/// The function returns an error if the log can't be read, has no entries or the logged chunk count exceeds `MAX_CHUNK_COUNT`.
+pub fn validate_transfer_log<L: TransferLog + ?Sized>(log: &L) -> Result<(u64, u64), RError> {
+    return validate_log_file(&log.entries()?);
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut log_entry_vec = read_log_file(&output_dir, BUFFER_SIZE, LOGGER_REGEX)
-        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+    #[test]
+    fn test_complete() {
+        let msg = create_complete("00ff", "a1b2").unwrap();
+        assert_eq!(msg[0], 4);
+        assert_eq!(read_complete(&msg).unwrap(), ("00ff".to_string(), "a1b2".to_string()));
 
-    let (startpos, endpos) = validate_log_file(&mut log_entry_vec);
+        assert!(read_complete(&create_stop("00ff").unwrap()).is_err());
+    }
 
-    return Ok((startpos, endpos));
-}
+    #[test]
+    fn test_chunk_len() {
+        let chunk_size = CHUNK_SIZE as u64;
+        let file_size = chunk_size * 2 + 7;
+
+        assert_eq!(get_chunk_len(file_size, 0), 0);
+        assert_eq!(get_chunk_len(file_size, 1), chunk_size);
+        assert_eq!(get_chunk_len(file_size, 3), 7);
+        assert_eq!(get_chunk_len(file_size, 4), 0);
+        assert_eq!(get_chunk_len(chunk_size * 2, 2), chunk_size);
+
+        assert_eq!(get_chunks_len(file_size, 0), 0);
+        assert_eq!(get_chunks_len(file_size, 2), chunk_size * 2);
+        assert_eq!(get_chunks_len(file_size, 3), file_size);
+        assert_eq!(get_chunks_len(file_size, 10), file_size);
+        assert_eq!(get_chunks_len(0, 1), 0);
+
+        let small = MIN_CHUNK_SIZE as u64;
+        assert_eq!(get_chunk_count_with_size(file_size, MIN_CHUNK_SIZE), (file_size + small - 1) / small);
+        assert_eq!(get_chunk_len_with_size(small * 2 + 7, 3, MIN_CHUNK_SIZE), 7);
+        assert_eq!(get_chunks_len_with_size(small * 2 + 7, 2, MIN_CHUNK_SIZE), small * 2);
+    }
+
+    #[test]
+    fn test_chunk_size_bounds() {
+        assert!(check_chunk_size(MIN_CHUNK_SIZE).is_ok());
+        assert!(check_chunk_size(CHUNK_SIZE).is_ok());
+        assert!(check_chunk_size(MIN_CHUNK_SIZE - 1).is_err());
+        assert!(check_chunk_size(CHUNK_SIZE + 1).is_err());
+        assert!(CHUNK_SIZE_CANDIDATES.iter().all(|size| check_chunk_size(*size).is_ok()));
+
+        assert_eq!(fit_chunk_size(1000, MIN_CHUNK_SIZE), MIN_CHUNK_SIZE);
+        assert_eq!(fit_chunk_size(1000, 1), CHUNK_SIZE);
+        // a file of the largest size would have too many small chunks
+        assert_eq!(fit_chunk_size(MAX_OFFER_SIZE, MIN_CHUNK_SIZE), CHUNK_SIZE);
+    }
 
+    #[test]
+    fn test_separate_header_empty() {
+        let err = separate_header(&vec![]).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::InputOutputError));
+    }
+
+    #[test]
+    fn test_separate_header_one_byte() {
+        let err = separate_header(&vec![0]).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::InputOutputError));
+    }
+
+    #[test]
+    fn test_separate_header_longer_than_buffer() {
+        let err = separate_header(&vec![0, 10, 1, 2]).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::InputOutputError));
+    }
+
+    #[test]
+    fn test_read_header_version() {
+        let header = create_header(16, 1, &Hash::SIPHASH24, &Some(Hash::SIPHASH24)).unwrap();
+        assert_eq!(header.fix_header[3], HEADER_VERSION);
+        assert!(read_header(&header.fix_header).is_ok());
+
+        let mut unknown_version = header.fix_header.clone();
+        unknown_version[3] = HEADER_VERSION + 1;
+        let err = read_header(&unknown_version).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::ReadHeaderError));
+    }
+
+    #[test]
+    fn test_read_header_file_hash_alg() {
+        for (hash, len) in [(Hash::SIZE, 8), (Hash::SIPHASH24, 8), (Hash::SHA1, 20), (Hash::SHA512, 64)] {
+            let header = create_header(16, 1, &hash, &None).unwrap();
+            let read = read_header(&header.fix_header).unwrap();
+            assert_eq!(read_file_hash_alg(&read.fix_header).unwrap(), hash);
+            assert_eq!(read.file_hash_pos_e - read.file_hash_pos_s + 1, len);
+        }
+
+        let mut unknown_alg = create_header(16, 1, &Hash::SIPHASH24, &None).unwrap().fix_header;
+        unknown_alg[FILE_HASH_ALG_POS] = 1;
+        let err = read_header(&unknown_alg).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::ReadHeaderError));
+    }
+
+    #[test]
+    fn test_create_header_chunk_count_limit() {
+        let header = create_header(16, MAX_HEADER_CHUNK_COUNT, &Hash::SIPHASH24, &None).unwrap();
+        assert_eq!(header.chunk_max_pos_e - header.chunk_max_pos_s + 1, 4);
+
+        let err = create_header(16, MAX_HEADER_CHUNK_COUNT + 1, &Hash::SIPHASH24, &None).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::LimitExceeded));
+        let err = create_header(16, 2u64.pow(40), &Hash::SIPHASH24, &None).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::LimitExceeded));
+    }
+
+    #[test]
+    fn test_read_header_too_short() {
+        let err = read_header(&vec![0, 12, 0]).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::ReadHeaderError));
+    }
+
+    #[test]
+    fn test_extract_header_data_truncated() {
+        let mut header = create_header(16, 1, &Hash::SIPHASH24, &Some(Hash::SIPHASH24)).unwrap();
+        header.fix_header.truncate(20);
+        let err = extract_header_data(&header).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::ReadHeaderError));
+    }
+
+    #[test]
+    fn test_parse_arbitrary_bytes() {
+        // seeded generator, so a failing input can be reproduced
+        let mut seed: u64 = 7;
+        let mut next = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as u32
+        };
+
+        let valid = create_header(16, 1, &Hash::SHA256, &Some(Hash::MD5)).unwrap().fix_header;
+
+        for round in 0..20000 {
+            let bytes: Vec<u8> = match round % 2 {
+                // random bytes of random length
+                0 => (0..next() % 160).map(|_| next() as u8).collect(),
+                // a valid header with a few random mutations, which reaches the deeper branches
+                _ => {
+                    let mut bytes = valid.clone();
+                    for _ in 0..1 + next() % 3 {
+                        let pos = next() as usize % bytes.len();
+                        bytes[pos] = next() as u8;
+                    }
+                    bytes.truncate(next() as usize % (bytes.len() + 1) + bytes.len() / 2);
+                    bytes
+                }
+            };
+
+            // none of the parsers may panic, errors are expected
+            if let Ok((header, _)) = separate_header(&bytes) {
+                let _ = read_send_header(&header);
+            }
+            if let Ok(header) = read_header(&bytes) {
+                let _ = extract_header_data(&header);
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_log_file_limits() {
+        let entry = |part: u64, max: u64| {
+            LogEntry::new(String::new(), USER_HASH.to_string(), "00ff".to_string(), Hash::SIPHASH24, part, max, 1, None, None)
+        };
+
+        // an empty log must be an error, not a panic
+        assert!(validate_log_file(&Vec::new()).is_err());
+
+        let err = validate_log_file(&vec![entry(1, MAX_CHUNK_COUNT + 1)]).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::LimitExceeded));
+
+        assert_eq!(validate_log_file(&vec![entry(1, 3), entry(0, 3)]).unwrap(), (2, 3));
+        assert_eq!(validate_log_file(&vec![entry(1, 2), entry(2, 2)]).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_validate_log_file_ranges() {
+        let range = |first: u64, last: u64| {
+            let entry = LogEntry::new(String::new(), USER_HASH.to_string(), "00ff".to_string(), Hash::SIPHASH24, last, MAX_CHUNK_COUNT, 1, None, None);
+            LogEntry { first_part: first, ..entry }
+        };
+
+        // overlapping and unordered runs up to the largest chunk count
+        let entries = vec![range(30, MAX_CHUNK_COUNT), range(5, 20), range(25, 25), range(1, 10)];
+        assert_eq!(validate_log_file(&entries).unwrap(), (21, 29));
+        assert_eq!(validate_log_file(&vec![range(2, MAX_CHUNK_COUNT)]).unwrap(), (1, 1));
+        assert_eq!(validate_log_file(&vec![range(1, 7)]).unwrap(), (8, MAX_CHUNK_COUNT));
+        assert_eq!(validate_log_file(&vec![range(1, 7), range(8, MAX_CHUNK_COUNT)]).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_separate_header() {
+        let (header, data) = separate_header(&vec![0, 3, 7, 1, 2]).unwrap();
+        assert_eq!(header, vec![0, 3, 7]);
+        assert_eq!(data, vec![1, 2]);
+    }
+}
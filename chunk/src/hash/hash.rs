@@ -1,23 +1,35 @@
 use std::{
-    collections::hash_map::DefaultHasher,
-    fs::File,
+    fs::{self, File},
     hash::Hasher,
     io::Error,
     io::{BufReader, Read, Seek, SeekFrom},
+    str::FromStr,
 };
 
 use md5::Md5;
+use sha1::Sha1;
 use sha2::{Digest, Sha256, Sha512};
+use siphasher::sip::SipHasher13;
+
+use crate::error::error::{RError, RErrorKind};
 
 pub const BUFFER_HASH_SIZE: usize = 1024 * 1024 * 250;
 
+/// Keys of the `Hash::SIPHASH24` hashes. The keys are fixed, so every build and every peer calculates the same hash.
+/// They are zero, like the keys of the standard library hasher earlier versions used, so their hashes stay valid.
+pub const SIPHASH_KEYS: (u64, u64) = (0, 0);
+
 //Enum with string-len of hash
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Hash {
     SIPHASH24 = 16,
     MD5 = 32,
+    SHA1 = 40,
     SHA256 = 64,
     SHA512 = 128,
+    /// No hash at all, data is identified by its size only.
+    /// The "hash" is the size as 16 hex digits, no chunk hashes are sent.
+    SIZE = 0,
 }
 
 impl Hash {
@@ -25,8 +37,51 @@ impl Hash {
         match &self {
             Hash::SIPHASH24 => "SIPHASH24".to_string(),
             Hash::MD5 => "MD5".to_string(),
+            Hash::SHA1 => "SHA1".to_string(),
             Hash::SHA256 => "SHA256".to_string(),
             Hash::SHA512 => "SHA512".to_string(),
+            Hash::SIZE => "SIZE".to_string(),
+        }
+    }
+
+    /// Returns the algorithm of a code written in the data header, see `create_header`.
+    /// The code of an algorithm is its value as `u8`.
+    pub fn from_code(code: u8) -> Option<Hash> {
+        match code {
+            16 => Some(Hash::SIPHASH24),
+            32 => Some(Hash::MD5),
+            40 => Some(Hash::SHA1),
+            64 => Some(Hash::SHA256),
+            128 => Some(Hash::SHA512),
+            0 => Some(Hash::SIZE),
+            _ => None,
+        }
+    }
+}
+
+/// Names accepted by `Hash::from_str`.
+pub const HASH_NAMES: [&str; 6] = ["siphash24", "md5", "sha1", "sha256", "sha512", "size"];
+
+impl FromStr for Hash {
+    type Err = RError;
+
+    /// Parses a hash algorithm name, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "siphash24" => Ok(Hash::SIPHASH24),
+            "md5" => Ok(Hash::MD5),
+            "sha1" => Ok(Hash::SHA1),
+            "sha256" => Ok(Hash::SHA256),
+            "sha512" => Ok(Hash::SHA512),
+            "size" => Ok(Hash::SIZE),
+            _ => Err(RError::new(
+                RErrorKind::ConvertionError,
+                &format!(
+                    "Unknown hash algorithm '{}', valid choices are: {}.",
+                    s,
+                    HASH_NAMES.join(", ")
+                ),
+            )),
         }
     }
 }
@@ -35,6 +90,79 @@ pub fn get_hash_from_file(file: &File) -> Result<String, Error> {
     return get_file_hash(file, BUFFER_HASH_SIZE, &Hash::SIPHASH24, 0);
 }
 
+/// Returns the hash of a file for an offer, like `get_hash_from_file`, but uses a precomputed hash if one is supplied,
+/// e.g. a hash cached from a previous session. Hashing large files delays the offer, so the file is only read
+/// if no hash is supplied or the supplied hash is verified.
+///
+/// # Arguments
+///
+/// * file - The file to hash.
+/// * supplied - A precomputed `Hash::SIPHASH24` hash of the file.
+/// * verify - Whether a supplied hash is checked against the content of the file.
+///
+/// # Returns
+///
+/// The function returns the hash of the file.
+///
+/// # Errors
+///
+/// The function returns an error of kind InvalidData if the supplied hash is not a hash of its algorithm
+/// or if it is verified and does not match the file, or an error if the file cannot be read.
+pub fn resolve_file_hash(file: &File, supplied: Option<&str>, verify: bool) -> Result<String, Error> {
+    return resolve_file_hash_with_type(file, supplied, verify, &Hash::SIPHASH24);
+}
+
+/// Returns the hash of a file for an offer like `resolve_file_hash`, with the given algorithm.
+///
+/// # Arguments
+///
+/// * file - The file to hash.
+/// * supplied - A precomputed hash of the file of the algorithm `hash_type`.
+/// * verify - Whether a supplied hash is checked against the content of the file.
+/// * hash_type - The algorithm of the hash, see `Offer::hash_type`.
+///
+/// # Returns
+///
+/// The function returns the hash of the file.
+///
+/// # Errors
+///
+/// The function returns the errors of `resolve_file_hash`.
+pub fn resolve_file_hash_with_type(
+    file: &File,
+    supplied: Option<&str>,
+    verify: bool,
+    hash_type: &Hash,
+) -> Result<String, Error> {
+    let supplied = match supplied {
+        Some(supplied) => supplied.to_lowercase(),
+        None => return get_file_hash(file, BUFFER_HASH_SIZE, hash_type, 0),
+    };
+
+    // a size "hash" is 16 hex digits, see `Hash::SIZE`
+    let len = match hash_type {
+        Hash::SIZE => 16,
+        _ => *hash_type as usize,
+    };
+    let valid = supplied.len() == len
+        && supplied.chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        return Err(Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Supplied hash is not a valid hash",
+        ));
+    }
+
+    if verify && get_file_hash(file, BUFFER_HASH_SIZE, hash_type, 0)? != supplied {
+        return Err(Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Supplied hash does not match the file",
+        ));
+    }
+
+    return Ok(supplied);
+}
+
 /// Calculates the hash value of a data vector using the specified hash algorithm.
 ///
 /// # Arguments
@@ -46,18 +174,13 @@ pub fn get_hash_from_file(file: &File) -> Result<String, Error> {
 ///
 /// The function returns the hash value as a hexadecimal string.
 /// 
-pub fn get_hash(byte_vec: &Vec<u8>, hash_algorithm: &Hash) -> String {
+pub fn get_hash(byte_vec: &[u8], hash_algorithm: &Hash) -> String {
     match hash_algorithm {
         Hash::SIPHASH24 => {
-            let mut hasher = DefaultHasher::new();
+            let mut hasher = new_siphasher();
 
             hasher.write(&byte_vec);
-            let mut return_val = format!("{:x}", hasher.finish());
-
-            if return_val.len() < 16 {
-                return_val = format!("{:0<16}", return_val);
-            }
-            return return_val;
+            return format_siphash(hasher.finish());
         }
         Hash::MD5 => {
             let mut hasher = Md5::new();
@@ -66,6 +189,13 @@ pub fn get_hash(byte_vec: &Vec<u8>, hash_algorithm: &Hash) -> String {
             let return_val = format!("{:x}", hasher.finalize());
             return return_val;
         }
+        Hash::SHA1 => {
+            let mut hasher = Sha1::new();
+
+            hasher.update(&byte_vec);
+            let return_val = format!("{:x}", hasher.finalize());
+            return return_val;
+        }
         Hash::SHA256 => {
             let mut hasher = Sha256::new();
 
@@ -80,6 +210,9 @@ pub fn get_hash(byte_vec: &Vec<u8>, hash_algorithm: &Hash) -> String {
             let return_val = format!("{:x}", hasher.finalize());
             return return_val;
         }
+        Hash::SIZE => {
+            return format!("{:016x}", byte_vec.len());
+        }
     }
 }
 
@@ -118,8 +251,12 @@ pub fn get_file_hash(
 
     // build hashes
     match file_hash {
+        Hash::SIZE => {
+            let size = file.metadata()?.len().saturating_sub(length);
+            return Ok(format!("{:016x}", size));
+        }
         Hash::SIPHASH24 => {
-            let mut hasher = DefaultHasher::new();
+            let mut hasher = new_siphasher();
 
             loop {
                 let bytes = buf_reader.read(&mut buffer)?;
@@ -128,16 +265,25 @@ pub fn get_file_hash(
                 }
                 hasher.write(&buffer[0..bytes]);
             }
-            let mut return_val = format!("{:x}", hasher.finish());
 
-            if return_val.len() < 16 {
-                return_val = format!("{:0<16}", return_val);
+            return Ok(format_siphash(hasher.finish()));
+        }
+        Hash::MD5 => {
+            let mut hasher = Md5::new();
+
+            loop {
+                let bytes = buf_reader.read(&mut buffer)?;
+                if bytes == 0 {
+                    break;
+                }
+                hasher.update(&buffer[0..bytes]);
             }
 
+            let return_val = format!("{:x}", hasher.finalize());
             return Ok(return_val);
         }
-        Hash::MD5 => {
-            let mut hasher = Md5::new();
+        Hash::SHA1 => {
+            let mut hasher = Sha1::new();
 
             loop {
                 let bytes = buf_reader.read(&mut buffer)?;
@@ -181,6 +327,141 @@ pub fn get_file_hash(
     }
 }
 
+/// Creates the hasher of `Hash::SIPHASH24`, keyed with `SIPHASH_KEYS`.
+pub fn new_siphasher() -> SipHasher13 {
+    return SipHasher13::new_with_keys(SIPHASH_KEYS.0, SIPHASH_KEYS.1);
+}
+
+/// Formats a `Hash::SIPHASH24` value as hex string of 16 digits.
+/// Shorter values are padded at the end, as hashes have always been.
+pub fn format_siphash(value: u64) -> String {
+    let mut return_val = format!("{:x}", value);
+    if return_val.len() < 16 {
+        return_val = format!("{:0<16}", return_val);
+    }
+    return return_val;
+}
+
+/// Intermediate state of a whole-file hash. The state can be saved next to the logfile,
+/// so an interrupted transfer continues hashing where it stopped instead of reading the whole file again.
+///
+/// Only SIPHASH24 and SIZE support saving the state. The hash crates used for the other algorithms
+/// don't expose their state.
+#[derive(Debug, Clone)]
+pub struct HashState {
+    hash: Hash,
+    length: u64,
+    hasher: SipHasher13,
+}
+
+impl HashState {
+    /// Creates the state of an empty input.
+    ///
+    /// # Arguments
+    ///
+    /// * hash - The hash algorithm.
+    ///
+    /// # Errors
+    ///
+    /// The function returns a ConvertionError if the state of the algorithm can't be saved.
+    pub fn new(hash: &Hash) -> Result<HashState, RError> {
+        match hash {
+            Hash::SIPHASH24 | Hash::SIZE => {}
+            _ => {
+                return Err(RError::new(
+                    RErrorKind::ConvertionError,
+                    &format!("The state of {} can't be saved.", hash.to_string()),
+                ))
+            }
+        }
+
+        return Ok(HashState {
+            hash: *hash,
+            length: 0,
+            hasher: new_siphasher(),
+        });
+    }
+
+    /// Adds data to the hash.
+    /// Hashing data in several parts gives the same hash as hashing it at once.
+    pub fn update(&mut self, data: &[u8]) {
+        self.length += data.len() as u64;
+        if self.hash == Hash::SIZE {
+            return;
+        }
+
+        self.hasher.write(data);
+    }
+
+    /// Returns the number of bytes hashed so far.
+    pub fn hashed_len(&self) -> u64 {
+        return self.length;
+    }
+
+    /// Finishes the hash. The state is not changed, so more data can still be added.
+    ///
+    /// # Returns
+    ///
+    /// The function returns the same hash as `get_hash` for all data added so far.
+    pub fn finish(&self) -> String {
+        if self.hash == Hash::SIZE {
+            return format!("{:016x}", self.length);
+        }
+
+        return format_siphash(self.hasher.finish());
+    }
+
+    /// Saves the state to a file, replacing a previously saved state.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path of the file, e.g. the logfile path with a `.hashstate` extension.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an InputOutputError if the file can't be written.
+    pub fn save(&self, path: &str) -> Result<(), RError> {
+        let hasher = serde_json::to_string(&self.hasher)
+            .map_err(|err| RError::new(RErrorKind::ConvertionError, &err.to_string()))?;
+        let line = format!("[{}] - [{}] - {}", self.hash.to_string(), self.length, hasher);
+
+        return fs::write(path, line)
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()));
+    }
+
+    /// Loads a state saved with `save`.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path of the file.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an InputOutputError if the file can't be read,
+    /// or a ConvertionError if it holds no valid state.
+    pub fn load(path: &str) -> Result<HashState, RError> {
+        let line = fs::read_to_string(path)
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+        let invalid = || RError::new(RErrorKind::ConvertionError, "Invalid hash state.");
+
+        let fields: Vec<&str> = line.trim().splitn(3, " - ").collect();
+        if fields.len() != 3 {
+            return Err(invalid());
+        }
+        let field = |field: &str| field.trim_start_matches('[').trim_end_matches(']').to_string();
+
+        let mut state = HashState::new(&Hash::from_str(&field(fields[0]))?)?;
+        state.length = field(fields[1]).parse().map_err(|_| invalid())?;
+        let hasher: SipHasher13 = serde_json::from_str(fields[2]).map_err(|_| invalid())?;
+        // a state of other keys would give hashes no peer can check
+        if hasher.keys() != SIPHASH_KEYS {
+            return Err(invalid());
+        }
+        state.hasher = hasher;
+
+        return Ok(state);
+    }
+}
 
 
 //hash-test-speed
@@ -192,7 +473,76 @@ mod tests{
     use std::time::Instant;
 
     use crate::general::general::AppSettings;
-    use crate::hash::hash::{Hash, get_file_hash};
+    use crate::hash::hash::{Hash, HashState, get_file_hash, get_hash};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_hash_from_str() {
+        assert_eq!(Hash::from_str("sha256").unwrap().to_string(), "SHA256");
+        assert_eq!(Hash::from_str("SIPHASH24").unwrap().to_string(), "SIPHASH24");
+
+        let err = Hash::from_str("crc32").unwrap_err().to_string();
+        assert!(err.contains("'crc32'"));
+        assert!(err.contains("siphash24, md5, sha1, sha256, sha512, size"));
+    }
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(
+            get_hash(&b"abc".to_vec(), &Hash::SHA1),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            get_hash(&Vec::new(), &Hash::SHA1),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert_eq!(get_hash(&vec![0u8; 300], &Hash::SIZE), "000000000000012c");
+    }
+
+    #[test]
+    fn test_siphash_is_stable() {
+        // hashes of earlier versions, made with the standard library hasher
+        assert_eq!(get_hash(b"abc", &Hash::SIPHASH24), "c03bc3a0042630f2");
+        assert_eq!(get_hash(b"", &Hash::SIPHASH24), "d1fba762150c532c");
+        assert_eq!(get_hash(&[7u8; 1000], &Hash::SIPHASH24), "a0cedb22bd4f68c0");
+    }
+
+    #[test]
+    fn test_hash_state_matches_one_shot() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 256) as u8).collect();
+
+        for split in [0, 1, 3, 8, 13, 500, 999, 1000] {
+            let mut state = HashState::new(&Hash::SIPHASH24).unwrap();
+            state.update(&data[..split]);
+            state.update(&data[split..]);
+            assert_eq!(state.finish(), get_hash(&data, &Hash::SIPHASH24));
+        }
+
+        let mut state = HashState::new(&Hash::SIZE).unwrap();
+        state.update(&data);
+        assert_eq!(state.finish(), get_hash(&data, &Hash::SIZE));
+
+        assert!(HashState::new(&Hash::SHA256).is_err());
+    }
+
+    #[test]
+    fn test_hash_state_save_load() {
+        let data: Vec<u8> = (0..4099u32).map(|i| (i % 253) as u8).collect();
+        let path = std::env::temp_dir().join(format!("rdrop_hash_state_{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut state = HashState::new(&Hash::SIPHASH24).unwrap();
+        state.update(&data[..data.len() / 2]);
+        state.save(path).unwrap();
+
+        let mut loaded = HashState::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.finish(), state.finish());
+        assert_eq!(loaded.hashed_len(), data.len() as u64 / 2);
+
+        loaded.update(&data[data.len() / 2..]);
+        assert_eq!(loaded.finish(), get_hash(&data, &Hash::SIPHASH24));
+    }
 
     #[test]
     #[ignore]
@@ -226,6 +576,8 @@ mod tests{
                     &Hash::SIPHASH24 => Hash::SIPHASH24,
                     &Hash::SHA256 => Hash::SHA256,
                     &Hash::SHA512 => Hash::SHA512,
+                    &Hash::SHA1 => Hash::SHA1,
+                    &Hash::SIZE => Hash::SIZE,
                 };
 
                 let start_time = Instant::now();
@@ -247,6 +599,8 @@ mod tests{
                 &Hash::SIPHASH24 => "Hash::SIPHASH24",
                 &Hash::SHA256 => "Hash::SHA256",
                 &Hash::SHA512 => "Hash::SHA512",
+                &Hash::SHA1 => "Hash::SHA1",
+                &Hash::SIZE => "Hash::SIZE",
             };
 
             println!("{}: {} microseconds", hash, avg_duration);
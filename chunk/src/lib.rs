@@ -1,6 +1,10 @@
 pub mod error;
 pub mod file;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixture;
 pub mod general;
 pub mod hash;
+pub mod merkle;
 pub mod offer;
 pub mod order;
+pub mod stream;
@@ -0,0 +1,502 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+};
+
+use crate::{
+    error::error::{RError, RErrorKind},
+    general::general::{calc_chunk_count, BUFFER_SIZE, CHUNK_SIZE, MAX_CHUNK_COUNT},
+    hash::hash::{get_hash, Hash},
+};
+
+/// Hash algorithm of the tree used by `MerkleTree::from_file`.
+pub const MERKLE_HASH_TYPE: Hash = Hash::SHA256;
+
+// inner nodes are prefixed, so a node can't be passed off as a chunk hash
+const NODE_PREFIX: u8 = 0x01;
+
+/// A Merkle tree over the chunk hashes of a file.
+/// The leaves are the hashes of the chunks in order, every inner node is the hash of its two children.
+/// A node without a sibling is moved up unchanged. The root identifies the whole file.
+#[derive(Debug)]
+pub struct MerkleTree {
+    hash_type: Hash,
+    /// The levels of the tree, starting with the leaves and ending with the root.
+    levels: Vec<Vec<Vec<u8>>>,
+}
+
+/// Proves that a chunk is part of the file identified by a root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleProof {
+    /// The position of the chunk, starting at 1.
+    pub chunk_pos: u64,
+    /// The hashes of the siblings from the leaf up to the root as hex strings.
+    /// Levels on which the node has no sibling are skipped.
+    /// Whether a sibling is the left or the right child follows from `chunk_pos` and the size of the tree.
+    pub path: Vec<String>,
+}
+
+impl MerkleTree {
+    /// Builds a tree from the chunk hashes of a file.
+    ///
+    /// # Arguments
+    ///
+    /// * leaves - The chunk hashes as hex strings, in the order of the chunks.
+    /// * hash_type - The algorithm the chunk hashes were made with, also used for the inner nodes.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if there are no leaves, a leaf is no valid hex string or the algorithm is `SIZE`.
+    pub fn from_leaves(leaves: &[String], hash_type: Hash) -> Result<MerkleTree, RError> {
+        if hash_type == Hash::SIZE {
+            return Err(RError::new(
+                RErrorKind::ConvertionError,
+                "A Merkle tree can't be built without a hash algorithm.",
+            ));
+        }
+        if leaves.is_empty() {
+            return Err(RError::new(
+                RErrorKind::InputOutputError,
+                "A Merkle tree needs at least one chunk.",
+            ));
+        }
+
+        let leaves = leaves
+            .iter()
+            .map(|leaf| decode_hex(leaf))
+            .collect::<Result<Vec<Vec<u8>>, RError>>()?;
+
+        let mut levels = vec![leaves];
+        while levels[levels.len() - 1].len() > 1 {
+            let level = &levels[levels.len() - 1];
+            let parents = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_node(left, right, &hash_type),
+                    [single] => single.clone(),
+                    _ => unreachable!("chunks(2) yields one or two nodes"),
+                })
+                .collect();
+            levels.push(parents);
+        }
+
+        return Ok(MerkleTree { hash_type, levels });
+    }
+
+    /// Builds a tree from a file, hashing every chunk of `CHUNK_SIZE` bytes with `MERKLE_HASH_TYPE`.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path of the file.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the file can't be read or is empty.
+    pub fn from_file(path: &str) -> Result<MerkleTree, RError> {
+        return MerkleTree::from_file_with_chunk_size(path, CHUNK_SIZE);
+    }
+
+    /// Builds a tree from a file like `from_file`, for a file offered with another chunk size.
+    ///
+    /// # Arguments
+    ///
+    /// * path - The path of the file.
+    /// * chunk_size - The chunk size the file is offered with, see `Offer::chunk_size`.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the file can't be read or is empty.
+    pub fn from_file_with_chunk_size(path: &str, chunk_size: usize) -> Result<MerkleTree, RError> {
+        let file = File::open(path)
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+        let file_size = file
+            .metadata()
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?
+            .len();
+        let chunk_count = calc_chunk_count(chunk_size, file_size)?;
+
+        let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
+        let mut buffer = vec![0; chunk_size];
+        let mut leaves = Vec::with_capacity(chunk_count as usize);
+        for pos in 1..=chunk_count {
+            let chunk_len = match pos == chunk_count {
+                true => (file_size - (pos - 1) * chunk_size as u64) as usize,
+                false => chunk_size,
+            };
+            reader
+                .read_exact(&mut buffer[..chunk_len])
+                .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+            leaves.push(get_hash(&buffer[..chunk_len], &MERKLE_HASH_TYPE));
+        }
+
+        return MerkleTree::from_leaves(&leaves, MERKLE_HASH_TYPE);
+    }
+
+    /// Returns the root of the tree as hex string.
+    pub fn root(&self) -> String {
+        return encode_hex(&self.levels[self.levels.len() - 1][0]);
+    }
+
+    /// Returns the hash algorithm of the tree.
+    pub fn hash_type(&self) -> Hash {
+        return self.hash_type;
+    }
+
+    /// Returns the number of chunks of the tree.
+    pub fn chunk_count(&self) -> u64 {
+        return self.levels[0].len() as u64;
+    }
+
+    /// Creates the inclusion proof of a chunk.
+    ///
+    /// # Arguments
+    ///
+    /// * chunk_pos - The position of the chunk, starting at 1.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the position is outside of the tree.
+    pub fn proof(&self, chunk_pos: u64) -> Result<MerkleProof, RError> {
+        if chunk_pos < 1 || chunk_pos > self.chunk_count() {
+            return Err(RError::new(
+                RErrorKind::InputOutputError,
+                &format!("Chunk {} is not part of the tree.", chunk_pos),
+            ));
+        }
+
+        let mut index = (chunk_pos - 1) as usize;
+        let mut path = Vec::new();
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = index ^ 1;
+            if let Some(hash) = level.get(sibling) {
+                path.push(encode_hex(hash));
+            }
+            index /= 2;
+        }
+
+        return Ok(MerkleProof { chunk_pos, path });
+    }
+}
+
+/// Checks that a chunk is part of the file identified by the root.
+///
+/// # Arguments
+///
+/// * root - The root of the file as hex string, e.g. from the offer.
+/// * chunk - The data of the chunk.
+/// * proof - The inclusion proof of the chunk.
+/// * chunk_count - The number of chunks of the file.
+/// * hash_type - The hash algorithm of the tree.
+///
+/// # Returns
+///
+/// The function returns true if the chunk and the proof lead to the root.
+pub fn verify_chunk(
+    root: &str,
+    chunk: &[u8],
+    proof: &MerkleProof,
+    chunk_count: u64,
+    hash_type: &Hash,
+) -> bool {
+    let leaf = get_hash(chunk, hash_type);
+
+    return verify_leaf(root, &leaf, proof, chunk_count, hash_type);
+}
+
+/// Checks that a chunk hash is part of the file identified by the root, see `verify_chunk`.
+/// The position of every sibling is derived from `chunk_pos` and `chunk_count`,
+/// so a proof only verifies for the position it was created for.
+pub fn verify_leaf(
+    root: &str,
+    leaf: &str,
+    proof: &MerkleProof,
+    chunk_count: u64,
+    hash_type: &Hash,
+) -> bool {
+    if proof.chunk_pos < 1 || proof.chunk_pos > chunk_count {
+        return false;
+    }
+
+    let mut node = match decode_hex(leaf) {
+        Ok(node) => node,
+        Err(_) => return false,
+    };
+
+    let mut steps = proof.path.iter();
+    let mut index = proof.chunk_pos - 1;
+    let mut level_len = chunk_count;
+    while level_len > 1 {
+        let sibling_index = index ^ 1;
+        if sibling_index < level_len {
+            let sibling = match steps.next().map(|hash| decode_hex(hash)) {
+                Some(Ok(sibling)) => sibling,
+                _ => return false,
+            };
+            node = match sibling_index < index {
+                true => hash_node(&sibling, &node, hash_type),
+                false => hash_node(&node, &sibling, hash_type),
+            };
+        }
+        index /= 2;
+        level_len = (level_len + 1) / 2;
+    }
+
+    // a longer path belongs to another tree
+    if steps.next().is_some() {
+        return false;
+    }
+
+    return encode_hex(&node).eq_ignore_ascii_case(root);
+}
+
+/// Verifies the chunks of a transfer against the root of the offer while they arrive.
+#[derive(Debug, Clone)]
+pub struct MerkleVerifier {
+    root: String,
+    hash_type: Hash,
+    leaves: Vec<Option<String>>,
+}
+
+impl MerkleVerifier {
+    /// Creates a verifier for a file.
+    ///
+    /// # Arguments
+    ///
+    /// * root - The expected root as hex string.
+    /// * hash_type - The hash algorithm of the tree.
+    /// * chunk_count - The number of chunks of the file.
+    ///
+    /// # Errors
+    ///
+    /// The function returns a `LimitExceeded` error if `chunk_count` exceeds `MAX_CHUNK_COUNT`,
+    /// before anything is allocated for the chunks.
+    pub fn new(root: &str, hash_type: Hash, chunk_count: u64) -> Result<MerkleVerifier, RError> {
+        if chunk_count > MAX_CHUNK_COUNT {
+            return Err(RError::new(
+                RErrorKind::LimitExceeded,
+                &format!(
+                    "A file of {} chunks exceeds the limit of {} chunks.",
+                    chunk_count, MAX_CHUNK_COUNT
+                ),
+            ));
+        }
+
+        return Ok(MerkleVerifier {
+            root: root.to_lowercase(),
+            hash_type,
+            leaves: vec![None; chunk_count as usize],
+        });
+    }
+
+    /// Verifies a chunk against the root and remembers its hash.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the position is outside of the file or the proof does not lead to the root.
+    pub fn add_chunk(&mut self, chunk: &[u8], proof: &MerkleProof) -> Result<(), RError> {
+        let index = proof.chunk_pos.checked_sub(1).unwrap_or(u64::MAX) as usize;
+        if index >= self.leaves.len() {
+            return Err(RError::new(
+                RErrorKind::InputOutputError,
+                &format!("Chunk {} is not part of the file.", proof.chunk_pos),
+            ));
+        }
+
+        let leaf = get_hash(chunk, &self.hash_type);
+        let chunk_count = self.leaves.len() as u64;
+        if !verify_leaf(&self.root, &leaf, proof, chunk_count, &self.hash_type) {
+            return Err(RError::new(
+                RErrorKind::InputOutputError,
+                &format!("Chunk {} does not match the Merkle root.", proof.chunk_pos),
+            ));
+        }
+
+        self.leaves[index] = Some(leaf);
+
+        return Ok(());
+    }
+
+    /// Remembers the hash of a chunk received without a proof.
+    /// The chunk is only checked against the root by `is_complete`, once all chunks arrived.
+    ///
+    /// # Arguments
+    ///
+    /// * chunk_pos - The position of the chunk, starting at 1.
+    /// * chunk - The data of the chunk.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the position is outside of the file.
+    pub fn add_unproven_chunk(&mut self, chunk_pos: u64, chunk: &[u8]) -> Result<(), RError> {
+        let index = chunk_pos.checked_sub(1).unwrap_or(u64::MAX) as usize;
+        if index >= self.leaves.len() {
+            return Err(RError::new(
+                RErrorKind::InputOutputError,
+                &format!("Chunk {} is not part of the file.", chunk_pos),
+            ));
+        }
+
+        self.leaves[index] = Some(get_hash(chunk, &self.hash_type));
+
+        return Ok(());
+    }
+
+    /// Returns the positions of the chunks which have not been verified yet.
+    pub fn missing(&self) -> Vec<u64> {
+        return (1..=self.leaves.len() as u64)
+            .filter(|pos| self.leaves[(*pos - 1) as usize].is_none())
+            .collect();
+    }
+
+    /// Confirms the complete file by rebuilding the root from all verified chunks.
+    ///
+    /// # Returns
+    ///
+    /// The function returns true if all chunks were verified and their tree has the expected root.
+    pub fn is_complete(&self) -> bool {
+        let leaves = match self.leaves.iter().cloned().collect::<Option<Vec<String>>>() {
+            Some(leaves) => leaves,
+            None => return false,
+        };
+
+        return match MerkleTree::from_leaves(&leaves, self.hash_type) {
+            Ok(tree) => tree.root() == self.root,
+            Err(_) => false,
+        };
+    }
+}
+
+fn hash_node(left: &[u8], right: &[u8], hash_type: &Hash) -> Vec<u8> {
+    let mut data = Vec::with_capacity(1 + left.len() + right.len());
+    data.push(NODE_PREFIX);
+    data.extend_from_slice(left);
+    data.extend_from_slice(right);
+
+    // get_hash only returns hex digits
+    return decode_hex(&get_hash(&data, hash_type)).unwrap_or_default();
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, RError> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err(RError::new(RErrorKind::ConvertionError, "Failed to parse hex string."));
+    }
+
+    return (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| RError::new(RErrorKind::ConvertionError, "Failed to parse hex string."))
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks() -> Vec<Vec<u8>> {
+        return (0..5u8).map(|i| vec![i; 100]).collect();
+    }
+
+    fn tree() -> MerkleTree {
+        let leaves: Vec<String> = chunks().iter().map(|c| get_hash(c, &Hash::SHA256)).collect();
+        return MerkleTree::from_leaves(&leaves, Hash::SHA256).unwrap();
+    }
+
+    #[test]
+    fn test_verify_chunk() {
+        let tree = tree();
+        let root = tree.root();
+        assert_eq!(root.len(), 64);
+
+        for (i, chunk) in chunks().iter().enumerate() {
+            let proof = tree.proof(i as u64 + 1).unwrap();
+            assert!(verify_chunk(&root, chunk, &proof, 5, &Hash::SHA256));
+        }
+
+        // the last chunk has no sibling on the first level
+        assert_eq!(tree.proof(5).unwrap().path.len(), 1);
+        assert!(tree.proof(0).is_err());
+        assert!(tree.proof(6).is_err());
+    }
+
+    #[test]
+    fn test_detect_tampered_chunk() {
+        let tree = tree();
+        let root = tree.root();
+        let proof = tree.proof(2).unwrap();
+
+        let mut tampered = chunks()[1].clone();
+        tampered[0] ^= 1;
+        assert!(!verify_chunk(&root, &tampered, &proof, 5, &Hash::SHA256));
+
+        // a valid chunk at a wrong position
+        assert!(!verify_chunk(&root, &chunks()[0], &proof, 5, &Hash::SHA256));
+
+        // the proof of chunk 2 claimed for chunk 1, with the sides of the siblings swapped
+        let moved = MerkleProof {
+            chunk_pos: 1,
+            path: proof.path.clone(),
+        };
+        assert!(!verify_chunk(&root, &chunks()[1], &moved, 5, &Hash::SHA256));
+        assert!(!verify_chunk(&root, &chunks()[1], &proof, 4, &Hash::SHA256));
+
+        let mut padded = proof.clone();
+        padded.path.push(root.clone());
+        assert!(!verify_chunk(&root, &chunks()[1], &padded, 5, &Hash::SHA256));
+
+        let mut verifier = MerkleVerifier::new(&root, Hash::SHA256, 5).unwrap();
+        assert!(verifier.add_chunk(&tampered, &proof).is_err());
+        assert_eq!(verifier.missing(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_merkle_verifier() {
+        let tree = tree();
+        let mut verifier =
+            MerkleVerifier::new(&tree.root(), Hash::SHA256, tree.chunk_count()).unwrap();
+
+        for pos in [4, 1, 5, 2] {
+            let chunk = &chunks()[pos as usize - 1];
+            verifier.add_chunk(chunk, &tree.proof(pos).unwrap()).unwrap();
+        }
+        assert_eq!(verifier.missing(), vec![3]);
+        assert!(!verifier.is_complete());
+
+        verifier.add_chunk(&chunks()[2], &tree.proof(3).unwrap()).unwrap();
+        assert!(verifier.is_complete());
+
+        let err = MerkleVerifier::new(&tree.root(), Hash::SHA256, MAX_CHUNK_COUNT + 1).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::LimitExceeded));
+    }
+
+    #[test]
+    fn test_unproven_chunks() {
+        let tree = tree();
+        let mut verifier = MerkleVerifier::new(&tree.root(), Hash::SHA256, 5).unwrap();
+
+        for (i, chunk) in chunks().iter().enumerate() {
+            verifier.add_unproven_chunk(i as u64 + 1, chunk).unwrap();
+        }
+        assert!(verifier.is_complete());
+        assert!(verifier.add_unproven_chunk(6, &[0]).is_err());
+
+        verifier.add_unproven_chunk(3, &[0; 100]).unwrap();
+        assert!(!verifier.is_complete());
+    }
+
+    #[test]
+    fn test_single_chunk() {
+        let leaf = get_hash(&[1, 2, 3], &Hash::SHA256);
+        let tree = MerkleTree::from_leaves(std::slice::from_ref(&leaf), Hash::SHA256).unwrap();
+        assert_eq!(tree.root(), leaf);
+        assert!(tree.proof(1).unwrap().path.is_empty());
+
+        assert!(MerkleTree::from_leaves(&[], Hash::SHA256).is_err());
+        assert!(MerkleTree::from_leaves(&[leaf], Hash::SIZE).is_err());
+    }
+}
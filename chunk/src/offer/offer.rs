@@ -1,15 +1,29 @@
-use std::fs::metadata;
-use std::io::{Error, ErrorKind, Write};
-use std::path::Path;
+use std::fs::{metadata, read, read_dir, write};
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path};
+use std::str::FromStr;
 
 use regex::Regex;
 
 use crate::error::error::{RError, RErrorKind};
-use crate::general::general::{append_header, HeaderByte};
-use crate::hash::hash::Hash;
+use crate::general::general::{
+    append_header, calc_chunk_count, check_chunk_size, get_extension, get_file_data, get_mime_hint, HeaderByte,
+    BUFFER_SIZE, CHUNK_SIZE, MAX_CHUNK_COUNT, MAX_OFFER_SIZE,
+};
+use crate::hash::hash::{get_file_hash, Hash};
+use crate::merkle::merkle::MerkleTree;
 
+/// Matches an offer. The optional groups hold the extension of the file with a leading dot,
+/// a MIME hint, the Merkle root of the file prefixed with `M:`, the offered chunk range prefixed with `R:`,
+/// the id and file count of an offer group prefixed with `G:`, the chunk size prefixed with `C:`,
+/// the flag `S` of a streamed offer and the length in bytes of the note, which directly follows the match.
+/// Offers without extension, MIME hint, Merkle root, range, group, stream flag or note omit the respective group,
+/// offers with chunks of `CHUNK_SIZE` bytes omit the chunk size.
 pub const OFFER_REGEX: &str =
-    r"\[(.+)\] - \[(\d+)\] - \[(SHA256|SHA512|MD5|SIPHASH24)\] - \[([0-9a-fA-F]+)\]";
+    r"\[(.+?)\] - \[(\d+)\] - \[(SHA1|SHA256|SHA512|MD5|SIPHASH24|SIZE)\] - \[([0-9a-fA-F]+)\](?: - \[\.([A-Za-z0-9]{1,16})\](?: - \[([A-Za-z0-9.+-]+/[A-Za-z0-9.+-]+)\])?)?(?: - \[M:([0-9a-fA-F]+)\])?(?: - \[R:(\d+)-(\d+)\])?(?: - \[G:([0-9a-fA-F]+)/(\d+)\])?(?: - \[C:(\d+)\])?(?: - \[(S)\])?(?: - \[(\d+)\])?";
+
+/// Extension of the file an accepted offer is stored in next to the received data, see `write_offer_file`.
+pub const OFFER_FILE_EXTENSION: &str = "rdropoffer";
 
 #[derive(Debug)]
 pub struct Offer {
@@ -17,29 +31,225 @@ pub struct Offer {
     pub size: u64,
     pub hash_type: Hash,
     pub file_hash: String,
+    pub note: String,
+    /// Extension of the offered file without the dot. Empty if the file has none.
+    pub extension: String,
+    /// MIME type derived from the extension. Empty if it is unknown.
+    pub mime: String,
+    /// Root of the Merkle tree over the chunks, see `merkle::MerkleTree`. Empty if the sender built no tree.
+    pub merkle_root: String,
+    /// First and last position of the offered chunks, both inclusive. `None` if the whole file is offered.
+    /// Size, hash and chunk positions still refer to the whole file.
+    pub range: Option<(u64, u64)>,
+    /// Id of the group the file belongs to and the number of files in the group. `None` if the file is offered alone.
+    /// The files of a group are only kept if every file of the group was received, see `file::move_temp_files`.
+    pub group: Option<(String, u64)>,
+    /// Size of the chunks the file is split into, between `MIN_CHUNK_SIZE` and `CHUNK_SIZE`.
+    /// Chunk positions, offsets, logfiles and the Merkle tree of the file refer to chunks of this size.
+    pub chunk_size: usize,
+    /// Whether the source is streamed, so its size is unknown when it is offered, see `stream::send_from_reader`.
+    /// The size of a streamed offer is 0 and its hash only identifies the stream.
+    pub streaming: bool,
+}
+
+/// Upper bounds for offers received from a peer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OfferLimits {
+    pub max_size: u64,
+    pub max_chunk_count: u64,
+}
+
+impl Default for OfferLimits {
+    fn default() -> Self {
+        Self {
+            max_size: MAX_OFFER_SIZE,
+            max_chunk_count: MAX_CHUNK_COUNT,
+        }
+    }
+}
+
+impl OfferLimits {
+    /// Checks an offer against the limits.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `LimitExceeded` error if the declared size or the resulting chunk count is too large.
+    pub fn check(&self, offer: &Offer) -> Result<(), RError> {
+        if offer.size > self.max_size {
+            return Err(RError::new(
+                RErrorKind::LimitExceeded,
+                &format!("Offered size {} exceeds the limit of {}.", offer.size, self.max_size),
+            ));
+        }
+
+        let chunk_count = calc_chunk_count(offer.chunk_size, offer.size)?;
+        if chunk_count > self.max_chunk_count {
+            return Err(RError::new(
+                RErrorKind::LimitExceeded,
+                &format!("Offered chunk count {} exceeds the limit of {}.", chunk_count, self.max_chunk_count),
+            ));
+        }
+
+        return Ok(());
+    }
+}
+
+/// Checks an offer received from a peer before any state is allocated for it.
+/// The name must not leave the output directory, the chunk size must be valid, the size and chunk count must be within `OfferLimits::default`,
+/// the file hash must be a hex hash of the declared algorithm, which must be usable for whole files,
+/// and the offered range and group must be valid.
+///
+/// # Arguments
+///
+/// * offer - The received offer.
+///
+/// # Errors
+///
+/// The function returns a `LimitExceeded` error if the offer exceeds the limits
+/// and an `InputOutputError` for an unsafe name, an unsupported or malformed hash, range or group.
+///
+pub fn validate_offer(offer: &Offer) -> Result<(), RError> {
+    let name = offer.name.replace('\\', "/");
+    let is_drive = name.len() >= 2 && name.as_bytes()[1] == b':' && name.as_bytes()[0].is_ascii_alphabetic();
+    let traverses = Path::new(&name)
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+    if name.trim().is_empty() || is_drive || traverses || name.chars().any(|c| c.is_control()) {
+        return Err(RError::new(
+            RErrorKind::InputOutputError,
+            &format!("Offered name {:?} is not a safe file name.", offer.name),
+        ));
+    }
+
+    check_chunk_size(offer.chunk_size)?;
+    OfferLimits::default().check(offer)?;
+
+    let hash_len = match offer.hash_type {
+        Hash::SIZE => 16,
+        hash_type => hash_type as usize,
+    };
+    if offer.file_hash.len() != hash_len || !offer.file_hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(RError::new(
+            RErrorKind::InputOutputError,
+            &format!("Offered hash is no {} hash.", offer.hash_type.to_string()),
+        ));
+    }
+
+    offer.chunk_range()?;
+
+    if offer.streaming && (offer.size != 0 || offer.range.is_some()) {
+        return Err(RError::new(
+            RErrorKind::InputOutputError,
+            "Streamed offer has a size or a range.",
+        ));
+    }
+
+    if let Some((_, 0)) = offer.group {
+        return Err(RError::new(RErrorKind::InputOutputError, "Offer group is empty."));
+    }
+
+    return Ok(());
 }
 
 impl Offer {
     pub fn new(name: &str, size: &str, hash_type: &str, file_hash: &str) -> Result<Self, Error> {
-        let hash = match hash_type {
-            "SIPHASH24" => Hash::SIPHASH24,
-            "MD5" => Hash::MD5,
-            "SHA256" => Hash::SHA256,
-            "SHA512" => Hash::SHA512,
-            _ => {
-                return Err(Error::new(
-                    ErrorKind::InvalidInput,
-                    "Hash-Algorithm not found, bad input.",
-                ));
-            }
-        };
+        let hash = Hash::from_str(hash_type)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))?;
         Ok(Self {
             name: name.to_string(),
-            size: size.parse::<u64>().unwrap(),
+            size: size
+                .parse::<u64>()
+                .map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))?,
             hash_type: hash,
             file_hash: file_hash.to_string(),
+            extension: String::new(),
+            mime: String::new(),
+            note: String::new(),
+            merkle_root: String::new(),
+            range: None,
+            group: None,
+            chunk_size: CHUNK_SIZE,
+            streaming: false,
         })
     }
+
+    /// Returns the first and last position of the offered chunks, both inclusive.
+    /// An offer without range covers all chunks of the file.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an `InputOutputError` if the range is not part of the file.
+    pub fn chunk_range(&self) -> Result<(u64, u64), RError> {
+        let chunk_count = calc_chunk_count(self.chunk_size, self.size)?;
+
+        return match self.range {
+            None => Ok((1, chunk_count)),
+            Some((start, end)) if 1 <= start && start <= end && end <= chunk_count => Ok((start, end)),
+            Some((start, end)) => Err(RError::new(
+                RErrorKind::InputOutputError,
+                &format!("Range {}-{} is not part of a file with {} chunks.", start, end, chunk_count),
+            )),
+        };
+    }
+
+    /// Checks if a chunk is part of the offer.
+    pub fn contains_chunk(&self, chunk_pos: u64) -> bool {
+        match self.chunk_range() {
+            Ok((start, end)) => start <= chunk_pos && chunk_pos <= end,
+            Err(_) => false,
+        }
+    }
+
+    /// Sets the extension and the MIME hint from the name of the offered file.
+    pub fn set_extension_from_name(&mut self) {
+        self.extension = get_extension(&self.name).unwrap_or_default();
+        self.mime = get_mime_hint(&self.extension).unwrap_or_default().to_string();
+    }
+
+    /// Formats the offer as it is transmitted, without the header byte.
+    pub fn to_offer_string(&self) -> String {
+        let mut offer = format!(
+            "[{}] - [{}] - [{}] - [{}]",
+            self.name,
+            self.size,
+            self.hash_type.to_string(),
+            self.file_hash
+        );
+
+        if !self.extension.is_empty() {
+            offer.push_str(&format!(" - [.{}]", self.extension));
+
+            if !self.mime.is_empty() {
+                offer.push_str(&format!(" - [{}]", self.mime));
+            }
+        }
+
+        if !self.merkle_root.is_empty() {
+            offer.push_str(&format!(" - [M:{}]", self.merkle_root));
+        }
+
+        if let Some((start, end)) = self.range {
+            offer.push_str(&format!(" - [R:{}-{}]", start, end));
+        }
+
+        if let Some((id, count)) = &self.group {
+            offer.push_str(&format!(" - [G:{}/{}]", id, count));
+        }
+
+        if self.chunk_size != CHUNK_SIZE {
+            offer.push_str(&format!(" - [C:{}]", self.chunk_size));
+        }
+
+        if self.streaming {
+            offer.push_str(" - [S]");
+        }
+
+        if !self.note.is_empty() {
+            offer.push_str(&format!(" - [{}]{}", self.note.len(), self.note));
+        }
+
+        return offer;
+    }
 }
 
 /// Creates an offer message as a byte vector.
@@ -60,6 +270,225 @@ impl Offer {
 /// The Error type contains details about the error.
 /// 
 pub fn create_offer_byte_msg(hash: &str, size: u64, path: &str) -> Result<Vec<u8>, Error> {
+    return create_offer_byte_msg_with_note(hash, size, path, "");
+}
+
+/// Creates an offer message with an attached note as a byte vector.
+///
+/// # Arguments
+///
+/// * hash - The hash value of the file.
+/// * size - The size of the file in bytes.
+/// * path - The path of the file.
+/// * note - A human-readable note for the receiver. An empty note is not transmitted.
+///
+/// # Returns
+///
+/// The function returns a Result containing the offer message as a byte vector if successful.
+///
+/// # Errors
+///
+/// The function can return an error if the path does not point to a valid file or directory, or if there is an error while constructing the offer message.
+/// The Error type contains details about the error.
+/// 
+pub fn create_offer_byte_msg_with_note(
+    hash: &str,
+    size: u64,
+    path: &str,
+    note: &str,
+) -> Result<Vec<u8>, Error> {
+    return create_offer_byte_msg_with_hash_type(hash, Hash::SIPHASH24, size, path, note, "", None, None);
+}
+
+/// Creates an offer message for a part of a file, e.g. the chunks a peer is missing of an interrupted download.
+/// Size and hash still describe the whole file, so the receiver writes each chunk at its position in the file.
+///
+/// # Arguments
+///
+/// * hash - The hash value of the file.
+/// * size - The size of the file in bytes.
+/// * path - The path of the file.
+/// * note - A human-readable note for the receiver. An empty note is not transmitted.
+/// * range - The first and last position of the offered chunks, both inclusive. Positions start at 1.
+///
+/// # Returns
+///
+/// The function returns a Result containing the offer message as a byte vector if successful.
+///
+/// # Errors
+///
+/// The function can return an error if the path does not point to a valid file or if the range is not part of the file.
+///
+pub fn create_range_offer_byte_msg(
+    hash: &str,
+    size: u64,
+    path: &str,
+    note: &str,
+    range: (u64, u64),
+) -> Result<Vec<u8>, Error> {
+    return create_offer_byte_msg_with_hash_type(hash, Hash::SIPHASH24, size, path, note, "", Some(range), None);
+}
+
+/// Creates an offer message for a file which is part of an offer group.
+/// The receiver only keeps the files of a group if all of them were received and verified.
+///
+/// # Arguments
+///
+/// * hash - The hash value of the file.
+/// * size - The size of the file in bytes.
+/// * path - The path of the file.
+/// * note - A human-readable note for the receiver. An empty note is not transmitted.
+/// * group - The hex id of the group and the number of files in it.
+///
+/// # Returns
+///
+/// The function returns a Result containing the offer message as a byte vector if successful.
+///
+/// # Errors
+///
+/// The function can return an error if the path does not point to a valid file or if the group is invalid.
+///
+pub fn create_group_offer_byte_msg(
+    hash: &str,
+    size: u64,
+    path: &str,
+    note: &str,
+    group: (&str, u64),
+) -> Result<Vec<u8>, Error> {
+    return create_offer_byte_msg_with_root(hash, size, path, note, "", Some(group));
+}
+
+/// Creates an offer message carrying a Merkle root computed before, e.g. by `MerkleTree::from_file` when the file was offered.
+/// The file can be part of an offer group, see `create_group_offer_byte_msg`.
+///
+/// # Arguments
+///
+/// * hash - The hash value of the file.
+/// * size - The size of the file in bytes.
+/// * path - The path of the file.
+/// * note - A human-readable note for the receiver. An empty note is not transmitted.
+/// * merkle_root - The Merkle root of the file as hex string. An empty root is not transmitted.
+/// * group - The hex id of the group and the number of files in it, if the file is part of a group.
+///
+/// # Returns
+///
+/// The function returns a Result containing the offer message as a byte vector if successful.
+///
+/// # Errors
+///
+/// The function can return an error if the path does not point to a valid file or if the group is invalid.
+///
+pub fn create_offer_byte_msg_with_root(
+    hash: &str,
+    size: u64,
+    path: &str,
+    note: &str,
+    merkle_root: &str,
+    group: Option<(&str, u64)>,
+) -> Result<Vec<u8>, Error> {
+    if let Some((id, count)) = group {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) || count == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Invalid offer group"));
+        }
+    }
+
+    return create_offer_byte_msg_with_hash_type(hash, Hash::SIPHASH24, size, path, note, merkle_root, None, group);
+}
+
+/// Creates an offer message like `create_offer_byte_msg_with_note`, which additionally carries the Merkle root of the file.
+/// The receiver can verify chunks against the root while they arrive, see `merkle::MerkleVerifier`.
+///
+/// # Arguments
+///
+/// * hash - The hash value of the file.
+/// * size - The size of the file in bytes.
+/// * path - The path of the file.
+/// * note - A human-readable note for the receiver. An empty note is not transmitted.
+///
+/// # Returns
+///
+/// The function returns a Result containing the offer message as a byte vector if successful.
+///
+/// # Errors
+///
+/// The function can return an error if the path does not point to a valid, non-empty file.
+///
+pub fn create_merkle_offer_byte_msg(
+    hash: &str,
+    size: u64,
+    path: &str,
+    note: &str,
+) -> Result<Vec<u8>, Error> {
+    let tree = MerkleTree::from_file(path)
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))?;
+
+    return create_offer_byte_msg_with_hash_type(hash, Hash::SIPHASH24, size, path, note, &tree.root(), None, None);
+}
+
+/// Creates an offer message which identifies the file by its size only.
+/// The file hash of the offer is the size as 16 hex digits, as returned by `get_hash` with `Hash::SIZE`.
+/// The data of such a transfer should be sent without chunk hashes, see `create_data_vec_with_chunk_hash`.
+///
+/// # Arguments
+///
+/// * size - The size of the file in bytes.
+/// * path - The path of the file.
+/// * note - A human-readable note for the receiver. An empty note is not transmitted.
+///
+/// # Returns
+///
+/// The function returns a Result containing the offer message as a byte vector if successful.
+///
+/// # Errors
+///
+/// The function can return an error if the path does not point to a valid file.
+/// 
+pub fn create_size_only_offer_byte_msg(size: u64, path: &str, note: &str) -> Result<Vec<u8>, Error> {
+    let hash = format!("{:016x}", size);
+
+    return create_offer_byte_msg_with_hash_type(&hash, Hash::SIZE, size, path, note, "", None, None);
+}
+
+/// Creates an offer message for a source of unknown size, like standard input, which is sent with `stream::send_from_reader`.
+/// The offer carries no size. Its hash identifies the stream until the hash of the data is known at the end of the source.
+///
+/// # Arguments
+///
+/// * id - The id of the stream, a SIPHASH24 hash as hex string.
+/// * name - The name the receiver shows for the stream.
+/// * note - A human-readable note for the receiver. An empty note is not transmitted.
+///
+/// # Returns
+///
+/// The function returns a Result containing the offer message as a byte vector if successful.
+///
+/// # Errors
+///
+/// The function can return an error if the id is no SIPHASH24 hash.
+///
+pub fn create_stream_offer_byte_msg(id: &str, name: &str, note: &str) -> Result<Vec<u8>, Error> {
+    if id.len() != Hash::SIPHASH24 as usize || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::new(ErrorKind::InvalidInput, "Invalid stream id"));
+    }
+
+    let mut offer = Offer::new(name, "0", &Hash::SIPHASH24.to_string(), id)?;
+    offer.note = note.to_string();
+    offer.streaming = true;
+    offer.set_extension_from_name();
+
+    return Ok(append_header(offer.to_offer_string().into_bytes(), HeaderByte::SendOffer));
+}
+
+fn create_offer_byte_msg_with_hash_type(
+    hash: &str,
+    hash_type: Hash,
+    size: u64,
+    path: &str,
+    note: &str,
+    merkle_root: &str,
+    range: Option<(u64, u64)>,
+    group: Option<(&str, u64)>,
+) -> Result<Vec<u8>, Error> {
     let metadata = match metadata(path) {
         Ok(metadata) => metadata,
         Err(_) => {
@@ -84,20 +513,26 @@ pub fn create_offer_byte_msg(hash: &str, size: u64, path: &str) -> Result<Vec<u8
         .to_string_lossy()
         .to_string();
 
-    let mut offer = Vec::new();
-
-    write!(
-        offer,
-        "[{}] - [{}] - [{}] - [{}]",
+    let mut offer = Offer {
         name,
         size,
-        Hash::SIPHASH24.to_string(),
-        &hash
-    )?;
-
-    offer = append_header(offer, HeaderByte::SendOffer);
+        hash_type,
+        file_hash: hash.to_string(),
+        note: note.to_string(),
+        extension: String::new(),
+        mime: String::new(),
+        merkle_root: merkle_root.to_string(),
+        range,
+        group: group.map(|(id, count)| (id.to_string(), count)),
+        chunk_size: CHUNK_SIZE,
+        streaming: false,
+    };
+    offer.set_extension_from_name();
+    offer
+        .chunk_range()
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))?;
 
-    return Ok(offer);
+    return Ok(append_header(offer.to_offer_string().into_bytes(), HeaderByte::SendOffer));
 }
 
 
@@ -129,8 +564,79 @@ pub fn read_offer(offer_regex: &str, offer: &str) -> Result<Offer, RError> {
         let hash_type = captures.get(3).map_or("", |m| m.as_str());
         let file_hash = captures.get(4).map_or("", |m| m.as_str());
 
-        let offer = Offer::new(name, size, hash_type, file_hash)
+        let extension = captures.get(5).map_or("", |m| m.as_str());
+        let mime = captures.get(6).map_or("", |m| m.as_str());
+        let merkle_root = captures.get(7).map_or("", |m| m.as_str());
+        let range = match (captures.get(8), captures.get(9)) {
+            (Some(start), Some(end)) => {
+                let start = start
+                    .as_str()
+                    .parse::<u64>()
+                    .map_err(|err| RError::new(RErrorKind::ConvertionError, &err.to_string()))?;
+                let end = end
+                    .as_str()
+                    .parse::<u64>()
+                    .map_err(|err| RError::new(RErrorKind::ConvertionError, &err.to_string()))?;
+                Some((start, end))
+            }
+            _ => None,
+        };
+        let group = match (captures.get(10), captures.get(11)) {
+            (Some(id), Some(count)) => {
+                let count = count
+                    .as_str()
+                    .parse::<u64>()
+                    .map_err(|err| RError::new(RErrorKind::ConvertionError, &err.to_string()))?;
+                if count == 0 {
+                    return Err(RError::new(RErrorKind::InputOutputError, "Offer group is empty."));
+                }
+                Some((id.as_str().to_lowercase(), count))
+            }
+            _ => None,
+        };
+
+        let chunk_size = match captures.get(12) {
+            Some(chunk_size) => {
+                let chunk_size = chunk_size
+                    .as_str()
+                    .parse::<usize>()
+                    .map_err(|err| RError::new(RErrorKind::ConvertionError, &err.to_string()))?;
+                check_chunk_size(chunk_size)?;
+                chunk_size
+            }
+            None => CHUNK_SIZE,
+        };
+        let streaming = captures.get(13).is_some();
+
+        let note = match captures.get(14) {
+            Some(note_len) => {
+                let note_len = note_len
+                    .as_str()
+                    .parse::<usize>()
+                    .map_err(|err| RError::new(RErrorKind::ConvertionError, &err.to_string()))?;
+                let note_start = captures.get(0).map_or(0, |m| m.end());
+
+                note_start
+                    .checked_add(note_len)
+                    .and_then(|note_end| offer.get(note_start..note_end))
+                    .ok_or_else(|| {
+                        RError::new(RErrorKind::InputOutputError, "Can't read note of Offer.")
+                    })?
+            }
+            None => "",
+        };
+
+        let mut offer = Offer::new(name, size, hash_type, file_hash)
             .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+        offer.note = note.to_string();
+        offer.extension = extension.to_string();
+        offer.mime = mime.to_string();
+        offer.merkle_root = merkle_root.to_lowercase();
+        offer.range = range;
+        offer.group = group;
+        offer.chunk_size = chunk_size;
+        offer.streaming = streaming;
+        offer.chunk_range()?;
 
         return Ok(offer);
     }
@@ -159,9 +665,455 @@ pub fn read_offer(offer_regex: &str, offer: &str) -> Result<Offer, RError> {
 /// The RError type contains details about the error.
 /// 
 pub fn read_offer_vec(byte_vec: &Vec<u8>) -> Result<Offer, RError> {
+    return read_offer_vec_with_limits(byte_vec, &OfferLimits::default());
+}
+
+/// Reads and parses an offer message from a byte vector and rejects it if it exceeds the given limits.
+///
+/// # Arguments
+///
+/// * byte_vec - The byte vector containing the offer message.
+/// * limits - The maximum size and chunk count accepted.
+///
+/// # Returns
+///
+/// The function returns a Result containing the parsed offer if successful.
+///
+/// # Errors
+///
+/// The function can return an error if the offer cannot be parsed or if it exceeds the limits.
+/// The RError type contains details about the error.
+/// 
+pub fn read_offer_vec_with_limits(byte_vec: &Vec<u8>, limits: &OfferLimits) -> Result<Offer, RError> {
     let offer_as_string = String::from_utf8_lossy(&byte_vec).into_owned();
     let offer = read_offer(OFFER_REGEX, &offer_as_string)?;
+    limits.check(&offer)?;
 
     return Ok(offer);
 }
 
+
+/// Stores an accepted offer next to the output file, so the transfer can be resumed after a restart.
+///
+/// # Arguments
+///
+/// * output_path - The path the received file is written to.
+/// * offer - The accepted offer.
+///
+/// # Returns
+///
+/// The function returns a Result containing the path of the offer file if successful.
+///
+/// # Errors
+///
+/// The function can return an error if the offer file cannot be written.
+/// 
+pub fn write_offer_file(output_path: &str, offer: &Offer) -> Result<String, Error> {
+    let offer_path = format!("{}.{}", output_path, OFFER_FILE_EXTENSION);
+    write(&offer_path, offer.to_offer_string())?;
+
+    return Ok(offer_path);
+}
+
+/// Reads the offer stored next to an output file by `write_offer_file`.
+///
+/// # Arguments
+///
+/// * output_path - The path the received file is written to.
+///
+/// # Returns
+///
+/// The function returns a Result containing the stored offer if successful.
+///
+/// # Errors
+///
+/// The function can return an error if the offer file cannot be read or parsed.
+/// The RError type contains details about the error.
+/// 
+pub fn read_offer_file(output_path: &str) -> Result<Offer, RError> {
+    let offer_path = format!("{}.{}", output_path, OFFER_FILE_EXTENSION);
+    let offer = read(&offer_path)
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+
+    return read_offer_vec(&offer);
+}
+
+/// Lists the files of a directory.
+/// Entries that cannot be read are skipped with a warning.
+///
+/// # Arguments
+///
+/// * input_dir - The directory to scan.
+/// * recursive - Whether files in subdirectories are listed as well.
+///
+/// # Returns
+///
+/// The function returns a Result containing the paths of the files, sorted by path.
+///
+/// # Errors
+///
+/// The function returns an `InputOutputError` if `input_dir` cannot be read.
+///
+pub fn list_directory_files(input_dir: &str, recursive: bool) -> Result<Vec<String>, RError> {
+    let entries = read_dir(input_dir)
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        // the type of the entry itself, so a symlink to a parent directory is not followed
+        let (path, is_dir) = match entry.and_then(|entry| Ok((entry.path(), entry.file_type()?.is_dir()))) {
+            Ok(entry) => entry,
+            Err(err) => {
+                println!("[OFFER] skipping unreadable entry in {}: {}", input_dir, err);
+                continue;
+            }
+        };
+
+        if is_dir {
+            if !recursive {
+                continue;
+            }
+
+            match list_directory_files(&path.to_string_lossy(), recursive) {
+                Ok(sub_files) => files.extend(sub_files),
+                Err(err) => println!("[OFFER] skipping unreadable directory {}: {}", path.display(), err),
+            }
+        } else if !path.is_dir() {
+            files.push(path.to_string_lossy().to_string());
+        }
+    }
+
+    files.sort();
+    return Ok(files);
+}
+
+/// Creates offers for all files in a directory.
+/// Files that cannot be read are skipped with a warning.
+///
+/// # Arguments
+///
+/// * input_dir - The directory to scan.
+/// * recursive - Whether files in subdirectories are offered as well.
+/// * hash_type - The algorithm the files are hashed with.
+///
+/// # Returns
+///
+/// The function returns a Result containing an offer for each file, sorted by path.
+///
+/// # Errors
+///
+/// The function returns an `InputOutputError` if `input_dir` cannot be read.
+///
+pub fn offer_directory(input_dir: &str, recursive: bool, hash_type: &Hash) -> Result<Vec<Offer>, RError> {
+    let mut offers = Vec::new();
+
+    for path in list_directory_files(input_dir, recursive)? {
+        let offer = get_file_data(&path).and_then(|(file, name, size)| {
+            let file_hash = get_file_hash(&file, BUFFER_SIZE, hash_type, 0)?;
+            let mut offer = Offer {
+                name,
+                size,
+                hash_type: *hash_type,
+                file_hash,
+                note: String::new(),
+                extension: String::new(),
+                mime: String::new(),
+                merkle_root: String::new(),
+                range: None,
+                group: None,
+                chunk_size: CHUNK_SIZE,
+                streaming: false,
+            };
+            offer.set_extension_from_name();
+            Ok(offer)
+        });
+
+        match offer {
+            Ok(offer) => offers.push(offer),
+            Err(err) => println!("[OFFER] skipping unreadable file {}: {}", path, err),
+        }
+    }
+
+    return Ok(offers);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::fixture::test_dir;
+    use crate::general::general::MIN_CHUNK_SIZE;
+    use crate::hash::hash::{get_hash_from_file, resolve_file_hash};
+    use std::fs;
+
+    fn prepare_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, b"rdrop offer test").unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_offer_without_note() {
+        let path = prepare_file("rdrop_offer_without_note.txt");
+        let msg = create_offer_byte_msg("ab12", 16, &path).unwrap();
+        let offer = read_offer_vec(&msg).unwrap();
+
+        assert_eq!(offer.name, "rdrop_offer_without_note.txt");
+        assert_eq!(offer.size, 16);
+        assert_eq!(offer.file_hash, "ab12");
+        assert_eq!(offer.note, "");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_offer_with_supplied_hash() {
+        let path = prepare_file("rdrop_offer_supplied_hash.txt");
+        let file = std::fs::File::open(&path).unwrap();
+        let computed = get_hash_from_file(&file).unwrap();
+        let computed_msg = create_offer_byte_msg(&computed, 16, &path).unwrap();
+
+        let supplied = resolve_file_hash(&file, Some(&computed.to_uppercase()), false).unwrap();
+        assert_eq!(create_offer_byte_msg(&supplied, 16, &path).unwrap(), computed_msg);
+        let verified = resolve_file_hash(&file, Some(&computed), true).unwrap();
+        assert_eq!(verified, computed);
+
+        let wrong = "0123456789abcdef";
+        assert_eq!(resolve_file_hash(&file, Some(wrong), false).unwrap(), wrong);
+        let err = resolve_file_hash(&file, Some(wrong), true).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = resolve_file_hash(&file, Some("not a hash"), false).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_offer_with_note() {
+        let path = prepare_file("rdrop_offer_with_note.txt");
+        let note = "Q3 report, please review] - [äöü]";
+        let msg = create_offer_byte_msg_with_note("ab12", 16, &path, note).unwrap();
+        let offer = read_offer_vec(&msg).unwrap();
+
+        assert_eq!(offer.name, "rdrop_offer_with_note.txt");
+        assert_eq!(offer.size, 16);
+        assert_eq!(offer.file_hash, "ab12");
+        assert_eq!(offer.note, note);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_offer_with_truncated_note() {
+        let offer = "[a.txt] - [16] - [SIPHASH24] - [ab12] - [10]short";
+        assert!(read_offer(OFFER_REGEX, offer).is_err());
+    }
+
+    #[test]
+    fn test_offer_exceeding_limits() {
+        let msg = append_header(
+            format!("[huge.bin] - [{}] - [SIPHASH24] - [ab12]", u64::MAX).into_bytes(),
+            HeaderByte::SendOffer,
+        );
+        assert!(read_offer_vec(&msg).is_err());
+
+        let overflowing = b"[huge.bin] - [99999999999999999999999] - [SIPHASH24] - [ab12]".to_vec();
+        assert!(read_offer_vec(&overflowing).is_err());
+
+        let limits = OfferLimits {
+            max_size: u64::MAX,
+            max_chunk_count: 10,
+        };
+        let msg = format!("[big.bin] - [{}] - [SIPHASH24] - [ab12]", CHUNK_SIZE * 11).into_bytes();
+        assert!(read_offer_vec_with_limits(&msg, &limits).is_err());
+
+        let msg = format!("[ok.bin] - [{}] - [SIPHASH24] - [ab12]", CHUNK_SIZE * 10).into_bytes();
+        assert_eq!(read_offer_vec_with_limits(&msg, &limits).unwrap().size, CHUNK_SIZE as u64 * 10);
+    }
+
+    #[test]
+    fn test_validate_offer() {
+        let hash = "0123456789abcdef";
+        let offer = |name: &str, size: &str, hash_type: &str, hash: &str| {
+            read_offer(OFFER_REGEX, &format!("[{}] - [{}] - [{}] - [{}]", name, size, hash_type, hash)).unwrap()
+        };
+
+        assert!(validate_offer(&offer("a.txt", "16", "SIPHASH24", hash)).is_ok());
+        assert!(validate_offer(&offer("a.txt", "16", "SIZE", "0000000000000010")).is_ok());
+
+        // names which would leave the output directory
+        for name in ["../a.txt", "dir/../../a.txt", "/etc/passwd", "C:\\a.txt", "..\\a.txt", " "] {
+            let err = validate_offer(&offer(name, "16", "SIPHASH24", hash)).unwrap_err();
+            assert!(matches!(err.kind(), RErrorKind::InputOutputError), "{}", name);
+        }
+
+        // declared sizes above the limit
+        let err = validate_offer(&offer("a.txt", &(MAX_OFFER_SIZE + 1).to_string(), "SIPHASH24", hash)).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::LimitExceeded));
+        let err = validate_offer(&offer("a.txt", &u64::MAX.to_string(), "SIPHASH24", hash)).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::LimitExceeded));
+
+        // unknown algorithms are not parsed at all
+        assert!(read_offer(OFFER_REGEX, &format!("[a.txt] - [16] - [CRC32] - [{}]", hash)).is_err());
+        let sha1 = "0123456789abcdef0123456789abcdef01234567";
+        assert!(validate_offer(&offer("a.txt", "16", "SHA1", sha1)).is_ok());
+        assert!(validate_offer(&offer("a.txt", "16", "SHA1", hash)).is_err());
+
+        // hashes which don't match the algorithm
+        assert!(validate_offer(&offer("a.txt", "16", "SIPHASH24", "ab12")).is_err());
+        assert!(validate_offer(&offer("a.txt", "16", "MD5", hash)).is_err());
+    }
+
+    #[test]
+    fn test_offer_directory() {
+        let dir = test_dir("rdrop_offer_directory");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), b"first").unwrap();
+        fs::write(dir.join("b.txt"), b"second file").unwrap();
+        fs::write(dir.join("sub").join("c.txt"), b"nested").unwrap();
+        let dir_path = dir.to_string_lossy().to_string();
+
+        let offers = offer_directory(&dir_path, false, &Hash::SIPHASH24).unwrap();
+        let names: Vec<&str> = offers.iter().map(|offer| offer.name.as_str()).collect();
+        assert_eq!(names, vec!["a.txt", "b.txt"]);
+        assert_eq!(offers[1].size, 11);
+
+        // symlinked directories are not followed, a link to a parent would never end
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&dir, dir.join("sub").join("parent")).unwrap();
+        let offers = offer_directory(&dir_path, true, &Hash::SHA256).unwrap();
+        let names: Vec<&str> = offers.iter().map(|offer| offer.name.as_str()).collect();
+        assert_eq!(names, vec!["a.txt", "b.txt", "c.txt"]);
+        assert!(offers.iter().all(|offer| offer.hash_type == Hash::SHA256 && offer.file_hash.len() == 64));
+
+        assert!(offer_directory(&dir.join("missing").to_string_lossy(), false, &Hash::SIPHASH24).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_offer_extension_and_mime() {
+        let path = prepare_file("report.pdf");
+        let msg = create_offer_byte_msg_with_note("ab12", 16, &path, "[draft]").unwrap();
+        let offer = read_offer_vec(&msg).unwrap();
+
+        assert_eq!(offer.name, "report.pdf");
+        assert_eq!(offer.extension, "pdf");
+        assert_eq!(offer.mime, "application/pdf");
+        assert_eq!(offer.note, "[draft]");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_offer_with_merkle_root() {
+        let path = prepare_file("rdrop_offer_merkle.txt");
+        let msg = create_merkle_offer_byte_msg("ab12", 16, &path, "[signed]").unwrap();
+        let offer = read_offer_vec(&msg).unwrap();
+
+        assert_eq!(offer.merkle_root, MerkleTree::from_file(&path).unwrap().root());
+        assert_eq!(offer.extension, "txt");
+        assert_eq!(offer.note, "[signed]");
+
+        let offer = read_offer_vec(&create_offer_byte_msg("ab12", 16, &path).unwrap()).unwrap();
+        assert_eq!(offer.merkle_root, "");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_offer_with_range() {
+        let size = CHUNK_SIZE * 30;
+        let offer = format!("[a.bin] - [{}] - [SIPHASH24] - [ab12] - [.bin] - [R:10-20] - [4]note", size);
+        let offer = read_offer(OFFER_REGEX, &offer).unwrap();
+
+        assert_eq!(offer.range, Some((10, 20)));
+        assert_eq!(offer.note, "note");
+        assert!(offer.contains_chunk(10));
+        assert!(offer.contains_chunk(20));
+        assert!(!offer.contains_chunk(21));
+        assert_eq!(read_offer(OFFER_REGEX, &offer.to_offer_string()).unwrap().range, Some((10, 20)));
+
+        for range in ["0-5", "20-10", "25-31"] {
+            let offer = format!("[a.bin] - [{}] - [SIPHASH24] - [ab12] - [R:{}]", size, range);
+            assert!(read_offer(OFFER_REGEX, &offer).is_err());
+        }
+
+        let offer = read_offer(OFFER_REGEX, &format!("[a.bin] - [{}] - [SIPHASH24] - [ab12]", size)).unwrap();
+        assert_eq!(offer.chunk_range().unwrap(), (1, 30));
+    }
+
+    #[test]
+    fn test_offer_with_group() {
+        let path = prepare_file("rdrop_offer_group.txt");
+        let msg = create_group_offer_byte_msg("ab12", 16, &path, "note", ("C0FFEE", 3)).unwrap();
+        let offer = read_offer_vec(&msg).unwrap();
+
+        assert_eq!(offer.group, Some(("c0ffee".to_string(), 3)));
+        assert_eq!(offer.note, "note");
+        assert_eq!(read_offer(OFFER_REGEX, &offer.to_offer_string()).unwrap().group, offer.group);
+
+        assert!(create_group_offer_byte_msg("ab12", 16, &path, "", ("xyz", 3)).is_err());
+        assert!(create_group_offer_byte_msg("ab12", 16, &path, "", ("c0ffee", 0)).is_err());
+
+        let root = MerkleTree::from_file(&path).unwrap().root();
+        let msg = create_offer_byte_msg_with_root("ab12", 16, &path, "", &root, Some(("c0ffee", 3))).unwrap();
+        let offer = read_offer_vec(&msg).unwrap();
+        assert_eq!(offer.merkle_root, root);
+        assert_eq!(offer.group, Some(("c0ffee".to_string(), 3)));
+
+        assert!(read_offer(OFFER_REGEX, "[a.txt] - [16] - [SIPHASH24] - [ab12] - [G:c0ffee/0]").is_err());
+        assert_eq!(read_offer(OFFER_REGEX, "[a.txt] - [16] - [SIPHASH24] - [ab12]").unwrap().group, None);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_offer_with_chunk_size() {
+        let mut offer = Offer::new("a.bin", &(MIN_CHUNK_SIZE * 3).to_string(), "SIPHASH24", "00ff00ff00ff00ff").unwrap();
+        assert!(!offer.to_offer_string().contains("[C:"));
+
+        offer.chunk_size = MIN_CHUNK_SIZE;
+        offer.range = Some((2, 3));
+        let read = read_offer(OFFER_REGEX, &offer.to_offer_string()).unwrap();
+        assert_eq!(read.chunk_size, MIN_CHUNK_SIZE);
+        assert_eq!(read.chunk_range().unwrap(), (2, 3));
+        assert!(validate_offer(&read).is_ok());
+
+        // the range is checked against the chunks of the announced size
+        offer.range = Some((2, 4));
+        assert!(read_offer(OFFER_REGEX, &offer.to_offer_string()).is_err());
+
+        assert!(read_offer(OFFER_REGEX, "[a] - [16] - [SIPHASH24] - [00ff00ff00ff00ff] - [C:1024]").is_err());
+        assert!(read_offer(OFFER_REGEX, &format!("[a] - [16] - [SIPHASH24] - [ab12] - [C:{}]", CHUNK_SIZE + 1)).is_err());
+
+        // smaller chunks count against the chunk limit
+        let limits = OfferLimits { max_size: MAX_OFFER_SIZE, max_chunk_count: 2 };
+        offer.range = None;
+        assert!(limits.check(&offer).is_err());
+        offer.chunk_size = CHUNK_SIZE;
+        assert!(limits.check(&offer).is_ok());
+    }
+
+    #[test]
+    fn test_stream_offer() {
+        let msg = create_stream_offer_byte_msg("00ff00ff00ff00ff", "stdin.txt", "piped").unwrap();
+        let offer = read_offer_vec(&msg).unwrap();
+
+        assert!(offer.streaming);
+        assert_eq!(offer.size, 0);
+        assert_eq!(offer.file_hash, "00ff00ff00ff00ff");
+        assert_eq!(offer.extension, "txt");
+        assert_eq!(offer.note, "piped");
+        assert!(validate_offer(&offer).is_ok());
+        assert!(read_offer(OFFER_REGEX, &offer.to_offer_string()).unwrap().streaming);
+
+        assert!(create_stream_offer_byte_msg("c0ffee", "stdin", "").is_err());
+        assert!(!read_offer(OFFER_REGEX, "[a.txt] - [16] - [SIPHASH24] - [ab12]").unwrap().streaming);
+
+        let sized = read_offer(OFFER_REGEX, "[a] - [16] - [SIPHASH24] - [00ff00ff00ff00ff] - [S]").unwrap();
+        assert!(sized.streaming);
+        assert!(validate_offer(&sized).is_err());
+    }
+
+    #[test]
+    fn test_offer_without_extension() {
+        let offer = read_offer(OFFER_REGEX, "[README] - [16] - [SIPHASH24] - [ab12]").unwrap();
+
+        assert_eq!(offer.extension, "");
+        assert_eq!(offer.mime, "");
+    }
+}
@@ -14,7 +14,7 @@ use crate::{
 
 };
 
-pub const ORDER_REGEX: &str = r"\[(\d+)\]\s-\s\[(SHA256|SHA512|MD5|SIPHASH24)\]\s-\s\[([a-fA-F0-9]+)\]\s-\s\[(.*)\]\s-\s\[(\d+)\]\s-\s\[(\d+)\](\s-\s\[(SHA256|SHA512|MD5|SIPHASH24)\])?";
+pub const ORDER_REGEX: &str = r"\[(\d+)\]\s-\s\[(SHA1|SHA256|SHA512|MD5|SIPHASH24|SIZE)\]\s-\s\[([a-fA-F0-9]+)\]\s-\s\[(.*)\]\s-\s\[(\d+)\]\s-\s\[(\d+)\](\s-\s\[(SHA1|SHA256|SHA512|MD5|SIPHASH24|SIZE)\])?(?:\s-\s\{((?:\d+-\d+,?)*)\})?";
 
 #[derive(Debug)]
 pub struct Order {
@@ -24,6 +24,8 @@ pub struct Order {
     pub file_name: String,
     pub start_num: u64,
     pub end_num: u64,
+    /// All ordered ranges of chunk positions, starting with `(start_num, end_num)`.
+    pub ranges: Vec<(u64, u64)>,
 }
 
 impl Order {
@@ -38,7 +40,9 @@ impl Order {
         let file_hash_type = match file_hash_type {
             "SIPHASH24" => Hash::SIPHASH24,
             "MD5" => Hash::MD5,
+            "SHA1" => Hash::SHA1,
             "SHA256" => Hash::SHA256,
+            "SIZE" => Hash::SIZE,
             "SHA512" => Hash::SHA512,
             _ => {
                 return Err(Error::new(
@@ -61,6 +65,7 @@ impl Order {
             file_name,
             start_num,
             end_num,
+            ranges: vec![(start_num, end_num)],
         })
     }
 }
@@ -83,6 +88,36 @@ impl Order {
 /// The function can return an error if there is an error while creating the order or appending the header. The Error type contains details about the error.
 /// 
 pub fn create_order_byte_vec(start: u64, end: u64, file_hash: &str) -> Result<Vec<u8>, Error> {
+    return create_order_from_ranges(&[(start, end)], file_hash)
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()));
+}
+
+/// Creates an order byte vector which requests several ranges of chunks at once.
+///
+/// # Arguments
+///
+/// * ranges - The ranges of chunk positions to order. Start and end of each range are inclusive.
+/// * file_hash - The hash of the file.
+///
+/// # Returns
+///
+/// The function returns a Result containing the order byte vector if successful.
+///
+/// # Errors
+///
+/// The function returns an `InputOutputError` if `ranges` is empty or the order cannot be created.
+///
+pub fn create_order_from_ranges(ranges: &[(u64, u64)], file_hash: &str) -> Result<Vec<u8>, RError> {
+    let (start, end) = match ranges.first() {
+        Some(range) => *range,
+        None => {
+            return Err(RError::new(
+                RErrorKind::InputOutputError,
+                "An order needs at least one range.",
+            ))
+        }
+    };
+
     let mut order_byte_vec = create_order(
         start,
         end,
@@ -91,13 +126,45 @@ pub fn create_order_byte_vec(start: u64, end: u64, file_hash: &str) -> Result<Ve
         file_hash,
         "",
         &Some(CHUNK_HASH_TYPE),
-    )?;
+    )
+    .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+
+    if ranges.len() > 1 {
+        let additional: Vec<String> = ranges[1..]
+            .iter()
+            .map(|(start, end)| format!("{}-{}", start, end))
+            .collect();
+        order_byte_vec.extend_from_slice(format!(" - {{{}}}", additional.join(",")).as_bytes());
+    }
 
     order_byte_vec = append_header(order_byte_vec, HeaderByte::SendOrder);
 
     return Ok(order_byte_vec);
 }
 
+/// Groups chunk positions into the ranges of an order, see `create_order_from_ranges`.
+///
+/// # Arguments
+///
+/// * positions - The chunk positions in ascending order, e.g. the missing chunks of `file::resume_from_disk`.
+///
+/// # Returns
+///
+/// The function returns one range per run of consecutive positions. Start and end of each range are inclusive.
+///
+pub fn ranges_from_positions(positions: &[u64]) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+
+    for &pos in positions {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == pos => *end = pos,
+            _ => ranges.push((pos, pos)),
+        }
+    }
+
+    return ranges;
+}
+
 /// Reads an order from a byte vector.
 ///
 /// # Arguments
@@ -128,7 +195,7 @@ pub fn read_order(byte_vec: &mut Vec<u8>) -> Result<Order, RError> {
         let start_number = captures.get(5).map_or("", |m| m.as_str());
         let end_number = captures.get(6).map_or("", |m| m.as_str());
 
-        let order = Order::new(
+        let mut order = Order::new(
             chunk_size,
             file_hash_type,
             file_hash,
@@ -136,9 +203,24 @@ pub fn read_order(byte_vec: &mut Vec<u8>) -> Result<Order, RError> {
             start_number,
             end_number,
         )
-        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()));
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
 
-        return order;
+        if let Some(additional) = captures.get(9) {
+            for range in additional.as_str().split(',').filter(|range| !range.is_empty()) {
+                let (start, end) = range.split_once('-').ok_or_else(|| {
+                    RError::new(RErrorKind::InputOutputError, "Can't read order range.")
+                })?;
+                let start = start
+                    .parse::<u64>()
+                    .map_err(|err| RError::new(RErrorKind::ConvertionError, &err.to_string()))?;
+                let end = end
+                    .parse::<u64>()
+                    .map_err(|err| RError::new(RErrorKind::ConvertionError, &err.to_string()))?;
+                order.ranges.push((start, end));
+            }
+        }
+
+        return Ok(order);
     }
     return Err(RError::new(
         RErrorKind::InputOutputError,
@@ -191,3 +273,37 @@ pub fn create_order(
     return Ok(byte_vec);
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_from_ranges() {
+        let mut order = create_order_from_ranges(&[(1, 3), (7, 9)], "ab12").unwrap();
+        let order = read_order(&mut order).unwrap();
+
+        assert_eq!(order.file_hash, "ab12");
+        assert_eq!(order.start_num, 1);
+        assert_eq!(order.end_num, 3);
+        assert_eq!(order.ranges, vec![(1, 3), (7, 9)]);
+    }
+
+    #[test]
+    fn test_order_single_range() {
+        let mut order = create_order_byte_vec(4, 5, "ab12").unwrap();
+        let order = read_order(&mut order).unwrap();
+
+        assert_eq!(order.ranges, vec![(4, 5)]);
+    }
+
+    #[test]
+    fn test_ranges_from_positions() {
+        assert_eq!(ranges_from_positions(&[2, 3, 4, 7, 9, 10]), vec![(2, 4), (7, 7), (9, 10)]);
+        assert!(ranges_from_positions(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_order_without_ranges() {
+        assert!(create_order_from_ranges(&[], "ab12").is_err());
+    }
+}
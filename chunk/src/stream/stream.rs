@@ -0,0 +1,402 @@
+use std::{
+    collections::BTreeMap,
+    hash::Hasher,
+    io::{ErrorKind, Read, Write},
+    sync::mpsc::{channel, Receiver, SendError, Sender},
+};
+
+use crate::error::error::{RError, RErrorKind};
+use crate::hash::hash::{format_siphash, new_siphasher};
+
+/// Default upper bound for the bytes a `ChunkReorderer` buffers, 64 MiB.
+pub const DEFAULT_MAX_PENDING_BYTES: usize = 64 * 1024 * 1024;
+
+/// Buffers chunks which arrive out of order until all chunks before them arrived.
+#[derive(Debug)]
+pub struct ChunkReorderer {
+    next_pos: u64,
+    pending: BTreeMap<u64, Vec<u8>>,
+    pending_bytes: usize,
+    max_pending_bytes: usize,
+}
+
+impl ChunkReorderer {
+    /// Creates a reorderer which starts with the chunk at `first_pos` and buffers up to `DEFAULT_MAX_PENDING_BYTES`.
+    ///
+    /// # Arguments
+    ///
+    /// * first_pos - The position of the first chunk, chunk positions start at 1.
+    pub fn new(first_pos: u64) -> ChunkReorderer {
+        return ChunkReorderer::with_limit(first_pos, DEFAULT_MAX_PENDING_BYTES);
+    }
+
+    /// Creates a reorderer which starts with the chunk at `first_pos`.
+    ///
+    /// # Arguments
+    ///
+    /// * first_pos - The position of the first chunk, chunk positions start at 1.
+    /// * max_pending_bytes - The number of bytes of the chunks buffered at most.
+    ///   The next chunk in order is always accepted, as it is delivered right away.
+    pub fn with_limit(first_pos: u64, max_pending_bytes: usize) -> ChunkReorderer {
+        return ChunkReorderer {
+            next_pos: first_pos,
+            pending: BTreeMap::new(),
+            pending_bytes: 0,
+            max_pending_bytes,
+        };
+    }
+
+    /// Adds a received chunk.
+    ///
+    /// # Returns
+    ///
+    /// The function returns false if the chunk was already delivered or is already buffered. The chunk is dropped in this case.
+    ///
+    /// # Errors
+    ///
+    /// The function returns a `LimitExceeded` error if buffering the chunk exceeds the limit of the reorderer.
+    /// The chunk is dropped, so the caller has to request it again once the chunks before it were delivered.
+    pub fn push(&mut self, chunk_pos: u64, chunk: Vec<u8>) -> Result<bool, RError> {
+        if chunk_pos < self.next_pos || self.pending.contains_key(&chunk_pos) {
+            return Ok(false);
+        }
+
+        if chunk_pos != self.next_pos && self.pending_bytes + chunk.len() > self.max_pending_bytes {
+            return Err(RError::new(
+                RErrorKind::LimitExceeded,
+                &format!(
+                    "Chunk {} exceeds the limit of {} buffered bytes.",
+                    chunk_pos, self.max_pending_bytes
+                ),
+            ));
+        }
+
+        self.pending_bytes += chunk.len();
+        self.pending.insert(chunk_pos, chunk);
+        return Ok(true);
+    }
+
+    /// Takes the next chunk in order, if it arrived.
+    pub fn pop_ready(&mut self) -> Option<(u64, Vec<u8>)> {
+        let chunk = self.pending.remove(&self.next_pos)?;
+        let chunk_pos = self.next_pos;
+        self.next_pos += 1;
+        self.pending_bytes -= chunk.len();
+
+        return Some((chunk_pos, chunk));
+    }
+
+    /// Returns the position of the next chunk to deliver.
+    pub fn next_pos(&self) -> u64 {
+        return self.next_pos;
+    }
+
+    /// Returns the number of buffered chunks, which wait for a missing chunk before them.
+    pub fn pending(&self) -> usize {
+        return self.pending.len();
+    }
+
+    /// Returns the number of bytes of the buffered chunks.
+    pub fn pending_bytes(&self) -> usize {
+        return self.pending_bytes;
+    }
+}
+
+/// Sending half of an ordered chunk stream, see `ordered_channel`.
+/// Chunks are sent as they are validated, in any order.
+#[derive(Debug, Clone)]
+pub struct ChunkSender {
+    sender: Sender<(u64, Vec<u8>)>,
+}
+
+impl ChunkSender {
+    /// Sends a validated chunk.
+    ///
+    /// # Errors
+    ///
+    /// The function returns an error if the receiver was dropped.
+    pub fn send(&self, chunk_pos: u64, chunk: Vec<u8>) -> Result<(), SendError<(u64, Vec<u8>)>> {
+        return self.sender.send((chunk_pos, chunk));
+    }
+}
+
+/// Receiving half of an ordered chunk stream, see `ordered_channel`.
+#[derive(Debug)]
+pub struct OrderedChunkReceiver {
+    receiver: Receiver<(u64, Vec<u8>)>,
+    reorderer: ChunkReorderer,
+}
+
+impl OrderedChunkReceiver {
+    /// Returns an iterator over the received chunks in increasing position order.
+    /// The iterator blocks until the next chunk in order arrived and ends once all senders were dropped.
+    /// Chunks after a chunk which never arrived are not yielded,
+    /// neither are chunks which arrived while `DEFAULT_MAX_PENDING_BYTES` were buffered.
+    pub fn ordered_chunks(&mut self) -> OrderedChunks<'_> {
+        return OrderedChunks { receiver: self };
+    }
+
+    /// Returns the position of the next chunk to deliver.
+    pub fn next_pos(&self) -> u64 {
+        return self.reorderer.next_pos();
+    }
+}
+
+/// Iterator returned by `OrderedChunkReceiver::ordered_chunks`.
+#[derive(Debug)]
+pub struct OrderedChunks<'a> {
+    receiver: &'a mut OrderedChunkReceiver,
+}
+
+impl Iterator for OrderedChunks<'_> {
+    type Item = (u64, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(chunk) = self.receiver.reorderer.pop_ready() {
+                return Some(chunk);
+            }
+
+            let (chunk_pos, chunk) = self.receiver.receiver.recv().ok()?;
+            // a chunk beyond the limit is dropped, the stream ends at the gap it leaves
+            let _ = self.receiver.reorderer.push(chunk_pos, chunk);
+        }
+    }
+}
+
+/// Creates a stream which delivers chunks in order of their position, while they may be sent in any order.
+/// This allows processing a received file while it is still transferred.
+///
+/// # Arguments
+///
+/// * first_pos - The position of the first chunk, chunk positions start at 1.
+///
+/// # Returns
+///
+/// The function returns the sending and the receiving half of the stream.
+pub fn ordered_channel(first_pos: u64) -> (ChunkSender, OrderedChunkReceiver) {
+    let (sender, receiver) = channel();
+
+    return (
+        ChunkSender { sender },
+        OrderedChunkReceiver {
+            receiver,
+            reorderer: ChunkReorderer::new(first_pos),
+        },
+    );
+}
+
+/// Result of streaming a source of unknown size with `send_from_reader`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamedSource {
+    /// The number of bytes read from the source.
+    pub size: u64,
+    /// The number of chunks sent.
+    pub chunk_count: u64,
+    /// The SIPHASH24 hash of the whole source, calculated while it was read.
+    pub hash: String,
+}
+
+/// Reads a source of unknown size, like standard input, and sends it as chunks of `chunk_size` bytes.
+/// The source is read until its end, so it does not have to be seekable,
+/// and the hash is calculated while reading, so the source is only read once.
+///
+/// # Arguments
+///
+/// * reader - The source of the data.
+/// * sender - The stream the chunks are sent to, starting at position 1.
+/// * chunk_size - The size of a chunk. Only the last chunk may be smaller.
+///
+/// # Returns
+///
+/// The function returns the size, chunk count and hash of the source.
+/// The hash equals `get_hash(data, &Hash::SIPHASH24)` of the whole source.
+///
+/// # Errors
+///
+/// The function returns an InputOutputError if reading the source fails or the receiver was dropped.
+pub fn send_from_reader<R: Read>(
+    reader: &mut R,
+    sender: &ChunkSender,
+    chunk_size: usize,
+) -> Result<StreamedSource, RError> {
+    let mut hasher = new_siphasher();
+    let mut size = 0u64;
+    let mut chunk_count = 0u64;
+
+    loop {
+        let chunk = read_chunk(reader, chunk_size)?;
+        if chunk.is_empty() {
+            break;
+        }
+
+        hasher.write(&chunk);
+        size += chunk.len() as u64;
+        chunk_count += 1;
+
+        sender
+            .send(chunk_count, chunk)
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+    }
+
+    return Ok(StreamedSource {
+        size,
+        chunk_count,
+        hash: format_siphash(hasher.finish()),
+    });
+}
+
+/// Reads up to `chunk_size` bytes. The chunk is only shorter at the end of the source.
+fn read_chunk<R: Read>(reader: &mut R, chunk_size: usize) -> Result<Vec<u8>, RError> {
+    let mut chunk = vec![0u8; chunk_size];
+    let mut filled = 0;
+
+    while filled < chunk_size {
+        match reader.read(&mut chunk[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(RError::new(RErrorKind::InputOutputError, &err.to_string())),
+        }
+    }
+
+    chunk.truncate(filled);
+    return Ok(chunk);
+}
+
+/// Writes the received chunks in order to a sink, like standard output.
+/// Blocks until all senders were dropped.
+///
+/// # Arguments
+///
+/// * receiver - The stream the chunks are received from.
+/// * writer - The sink the chunks are written to. It is flushed after the last chunk.
+///
+/// # Returns
+///
+/// The function returns the number of bytes written.
+///
+/// # Errors
+///
+/// The function returns an InputOutputError if writing to the sink fails.
+pub fn write_ordered<W: Write>(receiver: &mut OrderedChunkReceiver, writer: &mut W) -> Result<u64, RError> {
+    let mut written = 0u64;
+
+    for (_, chunk) in receiver.ordered_chunks() {
+        writer
+            .write_all(&chunk)
+            .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+        written += chunk.len() as u64;
+    }
+
+    writer
+        .flush()
+        .map_err(|err| RError::new(RErrorKind::InputOutputError, &err.to_string()))?;
+    return Ok(written);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::hash::hash::{get_hash, Hash};
+
+    #[test]
+    fn test_reorderer() {
+        let mut reorderer = ChunkReorderer::new(1);
+
+        assert!(reorderer.push(2, vec![2]).unwrap());
+        assert!(reorderer.pop_ready().is_none());
+        assert!(!reorderer.push(2, vec![2]).unwrap());
+        assert_eq!(reorderer.pending(), 1);
+
+        assert!(reorderer.push(1, vec![1]).unwrap());
+        assert_eq!(reorderer.pop_ready(), Some((1, vec![1])));
+        assert_eq!(reorderer.pop_ready(), Some((2, vec![2])));
+        assert!(reorderer.pop_ready().is_none());
+        assert!(!reorderer.push(1, vec![1]).unwrap());
+        assert_eq!(reorderer.next_pos(), 3);
+    }
+
+    #[test]
+    fn test_reorderer_limit() {
+        let mut reorderer = ChunkReorderer::with_limit(1, 8);
+
+        assert!(reorderer.push(2, vec![2; 4]).unwrap());
+        assert!(reorderer.push(3, vec![3; 4]).unwrap());
+        let err = reorderer.push(4, vec![4; 4]).unwrap_err();
+        assert!(matches!(err.kind(), RErrorKind::LimitExceeded));
+        assert_eq!(reorderer.pending_bytes(), 8);
+
+        // the next chunk in order is always accepted
+        assert!(reorderer.push(1, vec![1; 4]).unwrap());
+        assert_eq!(reorderer.pop_ready(), Some((1, vec![1; 4])));
+        assert_eq!(reorderer.pop_ready(), Some((2, vec![2; 4])));
+        assert_eq!(reorderer.pending_bytes(), 4);
+        assert!(reorderer.push(4, vec![4; 4]).unwrap());
+    }
+
+    #[test]
+    fn test_ordered_chunks() {
+        let (sender, mut receiver) = ordered_channel(1);
+        let order = [3u64, 1, 5, 2, 4, 2, 8, 7, 6];
+
+        let sender_thread = thread::spawn(move || {
+            for chunk_pos in order {
+                sender.send(chunk_pos, vec![chunk_pos as u8]).unwrap();
+            }
+        });
+
+        let chunks: Vec<(u64, Vec<u8>)> = receiver.ordered_chunks().collect();
+        sender_thread.join().unwrap();
+
+        let positions: Vec<u64> = chunks.iter().map(|(chunk_pos, _)| *chunk_pos).collect();
+        assert_eq!(positions, (1..=8).collect::<Vec<u64>>());
+        assert!(chunks.iter().all(|(chunk_pos, chunk)| chunk == &vec![*chunk_pos as u8]));
+        assert_eq!(receiver.next_pos(), 9);
+    }
+
+    #[test]
+    fn test_ordered_chunks_stop_at_gap() {
+        let (sender, mut receiver) = ordered_channel(1);
+        sender.send(1, vec![1]).unwrap();
+        sender.send(3, vec![3]).unwrap();
+        drop(sender);
+
+        let chunks: Vec<(u64, Vec<u8>)> = receiver.ordered_chunks().collect();
+        assert_eq!(chunks, vec![(1, vec![1])]);
+    }
+
+    #[test]
+    fn test_pipe_reader_to_writer() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let (sender, mut receiver) = ordered_channel(1);
+
+        let source = data.clone();
+        let sender_thread = thread::spawn(move || {
+            return send_from_reader(&mut source.as_slice(), &sender, 1024).unwrap();
+        });
+
+        let mut output = Vec::new();
+        let written = write_ordered(&mut receiver, &mut output).unwrap();
+        let streamed = sender_thread.join().unwrap();
+
+        assert_eq!(output, data);
+        assert_eq!(written, data.len() as u64);
+        assert_eq!(streamed.size, data.len() as u64);
+        assert_eq!(streamed.chunk_count, 10);
+        assert_eq!(streamed.hash, get_hash(&data, &Hash::SIPHASH24));
+    }
+
+    #[test]
+    fn test_send_from_empty_reader() {
+        let (sender, mut receiver) = ordered_channel(1);
+        let streamed = send_from_reader(&mut [0u8; 0].as_slice(), &sender, 1024).unwrap();
+        drop(sender);
+
+        assert_eq!(streamed.size, 0);
+        assert_eq!(streamed.chunk_count, 0);
+        assert_eq!(receiver.ordered_chunks().count(), 0);
+    }
+}
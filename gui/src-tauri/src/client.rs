@@ -1,46 +1,273 @@
+use std::collections::hash_map::{DefaultHasher, Entry, RandomState};
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hash as _, Hasher};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
-use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::sync::{mpsc, Arc, Mutex, PoisonError, RwLock};
 use std::thread;
 use std::thread::{sleep, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
 use tauri::{AppHandle, Wry};
 
-use chunk::file::file::{create_data_vec, write_data_vec};
+use chunk::error::error::{RError, RErrorKind};
+use chunk::file::file::{
+    create_data_vec_pooled, create_data_vec_verified, ensure_extension, get_temp_path, move_temp_files, preallocate_file,
+    release_output_path, resolve_output_path, resume_from_disk, sanitize_output_name, templated_output_path, verify_temp_file, write_manifest_file,
+    BatchedChunkWriter, ChunkBufferPool, ChunkHashes, ChunkSource, OverwritePolicy, WriteBatching, DEFAULT_NAME_TEMPLATE,
+    LOGFILE_EXTENSION,
+};
 use chunk::general::general::{
-    create_stop, get_chunk_count, read_send_header, read_stop, separate_header, validate_file,
+    append_header, check_chunk_hash, check_chunk_size, create_complete, create_stop, fit_chunk_size,
+    get_chunk_count_with_size, get_chunk_len_with_size, get_chunks_len_with_size, read_complete,
+    read_send_header, read_stop, separate_header, validate_file, HeaderByte, HeaderData, BUFFER_SIZE,
+    CHUNK_SIZE,
+};
+use chunk::hash::hash::{get_file_hash, get_hash_from_file, Hash};
+use chunk::merkle::merkle::{MerkleTree, MerkleVerifier, MERKLE_HASH_TYPE};
+use chunk::offer::offer::{
+    read_offer, write_offer_file, validate_offer, Offer, OFFER_REGEX,
 };
-use chunk::offer::offer::{create_offer_byte_msg, read_offer_vec};
-use chunk::order::order::{create_order_byte_vec, read_order};
-use p2p::client::{ClientReader, ClientWriter};
-use p2p::error::ErrorKind;
+use chunk::order::order::{
+    create_order_byte_vec, create_order_from_ranges, ranges_from_positions, read_order,
+};
+use p2p::client::{ClientReader, ClientWriter, Priority, ProtocolKind};
+use p2p::error::{DisconnectReason, ErrorKind};
 
 use crate::error::{ClientError, ClientErrorKind};
-use crate::events::{send_disconnect, send_file_state, FileState};
+use crate::events::{
+    send_batch_summary, send_disconnect, send_file_state, send_transfer_summary, FileState,
+};
 
 const READ_TIMEOUT: Duration = Duration::from_millis(1);
+/// Time after which an offer the peer did not request is reported as unanswered.
+const OFFER_TIMEOUT: Duration = Duration::from_secs(60);
+/// Number of times a corrupt chunk is requested again before its file is given up.
+const MAX_CHUNK_RETRIES: u32 = 5;
+/// Number of chunks of a file which are read and queued into the transport at once by default.
+const DEFAULT_PIPELINE_DEPTH: usize = 1;
+/// Upper limit for the pipeline depth, bounds the memory used for chunks read ahead.
+const MAX_PIPELINE_DEPTH: usize = 64;
+/// Minimum time between two progress events of a file. State changes are always sent right away.
+const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_millis(100);
+/// Time between two summaries of all transfers.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(1);
+/// Time a received file waits for the sender to confirm its hash once all chunks arrived.
+/// Senders which never confirm a file are trusted with the hash of their offer afterwards.
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time held files of an offer group wait once no other file of the group is transferred or pending.
+/// The held files are discarded afterwards, as the group can't complete anymore.
+const GROUP_TIMEOUT: Duration = Duration::from_secs(60);
 
 
 /// Wrapper for a file.
 #[derive(Clone)]
 pub struct File {
     pub(crate) hash: String,
+    /// Algorithm of `hash`, see `Offer::hash_type`.
+    pub(crate) hash_type: Hash,
     pub(crate) path: String,
     pub(crate) size: u64,
     pub(crate) name: String,
+    pub(crate) note: String,
+    /// Extension of the offered file without the dot. Empty if unknown.
+    pub(crate) extension: String,
+    /// MIME type of the offered file. Empty if unknown.
+    pub(crate) mime: String,
+    /// Id of the offer group of the file and the number of files in it, see `Client::offer_group`.
+    pub(crate) group: Option<(String, u64)>,
+    /// Merkle root over the chunks of the file, see `Client::set_merkle_roots`. Empty if the offer carries no root.
+    pub(crate) merkle_root: String,
+    /// Size of the chunks the file is sent in, see `Offer::chunk_size`.
+    pub(crate) chunk_size: usize,
 }
 
 impl File {
     fn new(hash: String, path: String, name: String, size: u64) -> Self {
         File {
             hash,
+            hash_type: Hash::SIPHASH24,
             path,
             name,
             size,
+            note: String::new(),
+            extension: String::new(),
+            mime: String::new(),
+            group: None,
+            merkle_root: String::new(),
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+
+    /// Returns the offer describing the file.
+    fn to_offer(&self) -> Offer {
+        Offer {
+            name: self.name.clone(),
+            size: self.size,
+            hash_type: self.hash_type,
+            file_hash: self.hash.clone(),
+            note: self.note.clone(),
+            extension: self.extension.clone(),
+            mime: self.mime.clone(),
+            merkle_root: self.merkle_root.clone(),
+            range: None,
+            group: self.group.clone(),
+            chunk_size: self.chunk_size,
+            streaming: false,
+        }
+    }
+}
+
+/// Hash of an offered file and the state of the file when it was hashed.
+struct CachedHash {
+    modified: SystemTime,
+    size: u64,
+    hash_type: Hash,
+    hash: String,
+}
+
+/// Hashes of offered files keyed by their path, so a file which is offered again is not hashed again,
+/// e.g. after the peer denied it. An entry is only used while the modification time and the size of the file are unchanged.
+#[derive(Default)]
+struct HashCache {
+    entries: HashMap<String, CachedHash>,
+}
+
+impl HashCache {
+    /// Returns the hash of an opened file, computed with `compute` unless the file is unchanged since it was hashed last.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path the file was opened from.
+    /// * `file` - The opened file.
+    /// * `hash_type` - The algorithm `compute` hashes with. A cached hash of another algorithm is not used.
+    /// * `compute` - Hashes the file, e.g. `get_hash_from_file`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the hash or an `Err` containing a `ClientError` if the file can't be read.
+    fn get_or_compute<F>(
+        &mut self,
+        path: &str,
+        file: &std::fs::File,
+        hash_type: Hash,
+        compute: F,
+    ) -> Result<String, ClientError>
+    where
+        F: FnOnce(&std::fs::File) -> io::Result<String>,
+    {
+        let metadata = file.metadata()?;
+        let modified = match metadata.modified() {
+            Ok(modified) => modified,
+            // without a modification time a change can't be noticed
+            Err(_) => return Ok(compute(file)?),
+        };
+
+        if let Some(cached) = self.entries.get(path) {
+            if cached.modified == modified && cached.size == metadata.len() && cached.hash_type == hash_type {
+                println!("[OFFER] cached hash of {}", path);
+                return Ok(cached.hash.clone());
+            }
+        }
+
+        let hash = compute(file)?;
+        self.entries.insert(
+            path.to_string(),
+            CachedHash {
+                modified,
+                size: metadata.len(),
+                hash_type,
+                hash: hash.clone(),
+            },
+        );
+        Ok(hash)
+    }
+}
+
+/// How a client prepares the files it offers, see `Client::set_verify_source` and `Client::set_chunk_size`.
+#[derive(Debug, Clone, Copy)]
+struct OfferSettings {
+    /// Whether the chunks are hashed when the file is offered.
+    verify_source: bool,
+    /// The preferred size of the chunks, see `fit_chunk_size`.
+    chunk_size: usize,
+}
+
+impl Default for OfferSettings {
+    fn default() -> Self {
+        OfferSettings {
+            verify_source: false,
+            chunk_size: CHUNK_SIZE,
         }
     }
 }
 
+/// Reads a file which is offered to the peer.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file.
+/// * `note` - A note for the peer.
+/// * `hash` - A precomputed hash of the file. If `None` is passed, the file is hashed unless it is in `hash_cache`.
+/// * `verify_hash` - Whether a precomputed hash is checked against the file.
+/// * `hash_type` - The algorithm of the hash of the file.
+/// * `hash_cache` - The hashes of files offered before.
+/// * `settings` - Whether the chunks are hashed and their preferred size.
+///
+/// # Returns
+///
+/// Returns the `File` and the hashes of its chunks if `settings.verify_source` is set,
+/// or an `Err` containing a `ClientError` if the file can't be read.
+fn prepare_offer(
+    path: String,
+    note: String,
+    hash: Option<String>,
+    verify_hash: bool,
+    hash_type: Hash,
+    hash_cache: &mut HashCache,
+    settings: OfferSettings,
+) -> Result<(File, Option<Arc<ChunkHashes>>), ClientError> {
+    let (file, file_name, file_size) = chunk::general::general::get_file_data(&path)?;
+    let verify_source = settings.verify_source;
+    let chunk_size = fit_chunk_size(file_size, settings.chunk_size);
+    let mut source_hashes = None;
+    let file_hash = match hash {
+        // the chunks are hashed in the same pass as the file
+        None if verify_source && hash_type == Hash::SIPHASH24 => hash_cache.get_or_compute(&path, &file, hash_type, |file| {
+            let (hash, hashes) = ChunkHashes::with_file_hash(file, chunk_size)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+            source_hashes = Some(hashes);
+            Ok(hash)
+        })?,
+        None if hash_type == Hash::SIPHASH24 => hash_cache.get_or_compute(&path, &file, hash_type, get_hash_from_file)?,
+        None => hash_cache.get_or_compute(&path, &file, hash_type, |file| {
+            get_file_hash(file, BUFFER_SIZE, &hash_type, 0)
+        })?,
+        Some(hash) => chunk::hash::hash::resolve_file_hash_with_type(&file, Some(&hash), verify_hash, &hash_type)?,
+    };
+
+    // the chunks are hashed before the offer is sent, so later edits of the file are noticed
+    if verify_source && source_hashes.is_none() {
+        source_hashes = Some(
+            ChunkHashes::from_file(&path, chunk_size).map_err(|_| ClientError::new(ClientErrorKind::IOError))?,
+        );
+    }
+
+    let extension = chunk::general::general::get_extension(&file_name).unwrap_or_default();
+    let mut new_file = File::new(file_hash, path, file_name, file_size);
+    new_file.hash_type = hash_type;
+    new_file.chunk_size = chunk_size;
+    new_file.note = note;
+    new_file.mime = chunk::general::general::get_mime_hint(&extension)
+        .unwrap_or_default()
+        .to_string();
+    new_file.extension = extension;
+
+    Ok((new_file, source_hashes.map(Arc::new)))
+}
+
 /// A Client for communicating with a peer.
 pub struct Client<W: ClientWriter + Send, R: ClientReader + Send> {
     app_handle: AppHandle<Wry>,
@@ -56,6 +283,15 @@ pub struct Client<W: ClientWriter + Send, R: ClientReader + Send> {
     writer_thread: Option<JoinHandle<Result<(), ClientError>>>,
     read_command: Sender<ReadCommand>,
     write_command: Sender<WriteCommand>,
+    transfers: Transfers,
+    protocol_kind: ProtocolKind,
+    /// Whether sent chunks are checked against the hashes taken when the file was offered, and the preferred chunk size.
+    offer_settings: OfferSettings,
+    /// Whether offers carry the Merkle root of the file.
+    merkle_roots: bool,
+    hash_cache: HashCache,
+    /// Number of groups offered so far, part of the id of the next group.
+    offered_groups: u64,
 }
 
 
@@ -80,6 +316,7 @@ impl<W: ClientWriter + Send + 'static, R: ClientReader + Send + 'static> Client<
         port: u16,
     ) -> Self {
         let drop_threads = Arc::new(RwLock::new(false));
+        let protocol_kind = writer.protocol_kind();
         let reader = Arc::new(Mutex::new(reader));
         let writer = Arc::new(Mutex::new(writer));
 
@@ -95,43 +332,49 @@ impl<W: ClientWriter + Send + 'static, R: ClientReader + Send + 'static> Client<
 
         let write_command_clone = write_command.clone();
 
-        let reader_thread = thread::spawn(move || {
-            let app_handle_clone_3 = app_handle_clone_1.clone();
-            let read = read_thread(
-                drop_threads_clone_1,
-                reader_clone,
-                app_handle_clone_1,
-                read_command_receiver,
-                write_command_clone,
-            );
-            match read {
-                Ok(_) => println!("[CLIENT]: Read thread exited successfully"),
-                Err(e) => {
-                    println!("[CLIENT]: Read thread exited with error {}", e);
-                    send_disconnect(&app_handle_clone_3)
-                        .map_err(|_| ClientError::new(ClientErrorKind::MpscSendError))?;
-                }
-            };
-            Ok(())
-        });
-        let writer_thread = thread::spawn(move || {
-            let app_handle_clone_3 = app_handle_clone_2.clone();
-            let write = write_thread(
-                drop_threads_clone_2,
-                app_handle_clone_2,
-                writer_clone,
-                write_command_receiver,
-            );
-            match write {
-                Ok(_) => println!("[CLIENT]: Write thread exited successfully"),
-                Err(e) => {
-                    println!("[CLIENT]: Write thread exited with error {}", e);
-                    send_disconnect(&app_handle_clone_3)
-                        .map_err(|_| ClientError::new(ClientErrorKind::MpscSendError))?;
-                }
-            };
-            Ok(())
-        });
+        let transfers = Transfers::default();
+        let transfers_clone_1 = transfers.clone();
+        let transfers_clone_2 = transfers.clone();
+
+        let thread_status = ThreadStatus::default();
+        let thread_status_clone_1 = thread_status.clone();
+        let thread_status_clone_2 = thread_status.clone();
+
+        let app_handle_clone_3 = app_handle_clone_1.clone();
+        let reader_thread = spawn_client_thread(
+            "Read",
+            thread_status.clone(),
+            transfers.clone(),
+            move || {
+                read_thread(
+                    drop_threads_clone_1,
+                    reader_clone,
+                    app_handle_clone_1,
+                    read_command_receiver,
+                    write_command_clone,
+                    transfers_clone_1,
+                    thread_status_clone_1,
+                )
+            },
+            move |reason| send_disconnect(&app_handle_clone_3, reason),
+        );
+        let app_handle_clone_4 = app_handle_clone_2.clone();
+        let writer_thread = spawn_client_thread(
+            "Write",
+            thread_status,
+            transfers.clone(),
+            move || {
+                write_thread(
+                    drop_threads_clone_2,
+                    app_handle_clone_2,
+                    writer_clone,
+                    write_command_receiver,
+                    transfers_clone_2,
+                    thread_status_clone_2,
+                )
+            },
+            move |reason| send_disconnect(&app_handle_clone_4, reason),
+        );
 
         Client {
             read_command,
@@ -143,6 +386,12 @@ impl<W: ClientWriter + Send + 'static, R: ClientReader + Send + 'static> Client<
             port,
             reader_thread : Some(reader_thread),
             writer_thread: Some(writer_thread),
+            transfers,
+            protocol_kind,
+            offer_settings: OfferSettings::default(),
+            merkle_roots: false,
+            hash_cache: HashCache::default(),
+            offered_groups: 0,
         }
     }
 
@@ -154,24 +403,91 @@ impl<W: ClientWriter + Send + 'static, R: ClientReader + Send + 'static> Client<
         self.port
     }
 
+    /// Gets the transport protocol the client communicates over.
+    ///
+    /// Returns the `ProtocolKind` of the connection.
+    pub fn protocol_kind(&self) -> ProtocolKind {
+        self.protocol_kind
+    }
+
     /// Offers sending a file.
     ///
     /// # Arguments
     ///
-    /// * `hash` - The hash of the file to accept.
     /// * `path` - The path where the file is saved.
+    /// * `note` - A note for the peer, which is shown next to the offer.
+    /// * `hash` - A precomputed hash of the file. If `None` is passed, the file is hashed unless it is unchanged since an earlier offer.
+    /// * `verify_hash` - Whether a precomputed hash is checked against the file.
+    /// * `hash_type` - The algorithm of the hash of the file, which the peer checks the received file with.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the command was transmitted successfully,
-    /// or an `Err` containing a `ClientError`.
-    pub fn offer_file(&mut self, path: String) -> Result<(), ClientError> {
-        let (file, file_name, file_size) = chunk::general::general::get_file_data(&path)?;
-        let file_hash = chunk::hash::hash::get_hash_from_file(&file)?;
+    /// or an `Err` containing a `ClientError`, e.g. if a verified hash does not match the file.
+    pub fn offer_file(
+        &mut self,
+        path: String,
+        note: String,
+        hash: Option<String>,
+        verify_hash: bool,
+        hash_type: Hash,
+    ) -> Result<(), ClientError> {
+        let (new_file, source_hashes) =
+            prepare_offer(path, note, hash, verify_hash, hash_type, &mut self.hash_cache, self.offer_settings)?;
+        self.offer(new_file, source_hashes)
+    }
+
+    /// Offers several files as a group, which the peer only keeps if it received every file of the group.
+    /// If a file of the group fails or is denied, none of the files are moved to their paths.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - The paths of the files.
+    /// * `note` - A note attached to each offer.
+    ///
+    /// # Returns
+    ///
+    /// Returns the id of the group,
+    /// or an `Err` containing a `ClientError` if a file can't be read. No file is offered in this case.
+    pub fn offer_group(&mut self, paths: Vec<String>, note: String) -> Result<String, ClientError> {
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            files.push(prepare_offer(
+                path,
+                note.clone(),
+                None,
+                false,
+                Hash::SIPHASH24,
+                &mut self.hash_cache,
+                self.offer_settings,
+            )?);
+        }
+
+        if files.is_empty() {
+            return Err(ClientError::new(ClientErrorKind::IOError));
+        }
+
+        // the counter keeps ids unique for this client, the random nonce across clients and restarts
+        let nonce = RandomState::new().build_hasher().finish();
+        let group = (format!("{:016x}-{}", nonce, self.offered_groups), files.len() as u64);
+        self.offered_groups += 1;
+
+        for (mut file, source_hashes) in files {
+            file.group = Some(group.clone());
+            self.offer(file, source_hashes)?;
+        }
+
+        Ok(group.0)
+    }
 
-        let new_file = File::new(file_hash, path, file_name, file_size);
+    fn offer(&mut self, mut new_file: File, source_hashes: Option<Arc<ChunkHashes>>) -> Result<(), ClientError> {
+        if self.merkle_roots {
+            new_file.merkle_root = MerkleTree::from_file_with_chunk_size(&new_file.path, new_file.chunk_size)
+                .map_err(|_| ClientError::new(ClientErrorKind::IOError))?
+                .root();
+        }
 
-        send_file_state(
+        self.transfers.publish(
             &self.app_handle,
             new_file.clone(),
             FileState::Pending,
@@ -179,25 +495,157 @@ impl<W: ClientWriter + Send + 'static, R: ClientReader + Send + 'static> Client<
             true,
         )?;
 
-        self.write_command.send(WriteCommand::Offer(new_file))?;
+        self.write_command.send(WriteCommand::Offer(new_file, source_hashes))?;
         Ok(())
     }
 
+    /// Offers all files of a directory.
+    /// Files that cannot be offered are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the directory.
+    /// * `note` - A note attached to each offer.
+    /// * `recursive` - Whether files in subdirectories are offered as well.
+    ///
+    /// # Returns
+    ///
+    /// Returns the number of offered files,
+    /// or an `Err` containing a `ClientError` if the directory cannot be read.
+    pub fn offer_directory(
+        &mut self,
+        path: String,
+        note: String,
+        recursive: bool,
+    ) -> Result<usize, ClientError> {
+        let files = chunk::offer::offer::list_directory_files(&path, recursive)
+            .map_err(|_| ClientError::new(ClientErrorKind::IOError))?;
+
+        let mut offered = 0;
+        for file in files {
+            match self.offer_file(file.clone(), note.clone(), None, false, Hash::SIPHASH24) {
+                Ok(()) => offered += 1,
+                Err(err) => println!("[CLIENT]: skipping {}: {}", file, err),
+            }
+        }
+
+        Ok(offered)
+    }
+
     /// Accepts receiving a file.
     ///
     /// # Arguments
     ///
     /// * `hash` - The hash of the file to accept.
-    /// * `path` - The path to save the file to.
+    /// * `path` - The path to save the file to. If it is a directory, the file is saved in it
+    ///   under the sanitized name offered by the peer.
+    /// * `policy` - What to do if a file already exists at the path the file is saved to.
+    /// * `temp_dir` - The directory the file is written to until it is complete.
+    ///   If `None` is passed, the directory of `path` is used.
+    /// * `manifest` - Whether a manifest with the hash of the file is written next to it once it is complete.
     ///
     /// # Returns
     ///
     /// Returns `Ok(())` if the command was transmitted successfully,
     /// or an `Err` containing a `ClientError`.
-    pub fn accept_file(&mut self, hash: String, path: String) -> Result<(), ClientError> {
+    /// If the file exists and `policy` is `Skip`, the transfer is declined and stopped.
+    pub fn accept_file(
+        &mut self,
+        hash: String,
+        path: String,
+        policy: OverwritePolicy,
+        temp_dir: Option<String>,
+        manifest: bool,
+    ) -> Result<(), ClientError> {
+        self.accept(hash, path, policy, temp_dir, manifest, 0)
+    }
+
+    /// Accepts receiving several files as a batch.
+    /// Once every file of the batch completed or failed, a `BatchSummary` is sent to the front end.
+    /// A file failing its verification is received again from the start up to `max_retries` times,
+    /// before it counts as failed.
+    ///
+    /// # Arguments
+    ///
+    /// * `hashes` - The hashes of the files to accept.
+    /// * `path` - The directory the files are saved in, see `accept_file`.
+    /// * `policy` - What to do if a file already exists.
+    /// * `temp_dir` - The directory the files are written to until they are complete.
+    /// * `max_retries` - How often a failed file is received again.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the commands were transmitted successfully,
+    /// or an `Err` containing a `ClientError`.
+    /// Files which can't be accepted, e.g. because they exist and `policy` is `Skip`, count as failed,
+    /// as do files which are not offered. A disconnect ends the batch.
+    pub fn accept_batch(
+        &mut self,
+        hashes: Vec<String>,
+        path: String,
+        policy: OverwritePolicy,
+        temp_dir: Option<String>,
+        max_retries: u8,
+    ) -> Result<(), ClientError> {
+        // subscribed first, so no result of the batch is missed
+        let results = self.transfer_results();
+        let mut batch = BatchTransfer::new(hashes.clone());
+        let app_handle = self.app_handle.clone();
+
+        // a file which is not offered never reports a result
+        let offered: HashSet<String> = self
+            .transfers
+            .snapshot()
+            .into_iter()
+            .filter(|transfer| !transfer.is_sender && transfer.state == FileState::Pending)
+            .map(|transfer| transfer.hash)
+            .collect();
+        let (hashes, unknown): (Vec<String>, Vec<String>) =
+            hashes.into_iter().partition(|hash| offered.contains(hash));
+        for hash in unknown {
+            println!("[BATCH] {} is not offered", hash);
+            batch.finish_file(&hash, TransferOutcome::Cancelled);
+        }
+
+        thread::spawn(move || loop {
+            let summary = match batch.summary() {
+                Some(summary) => summary,
+                None => match results.recv() {
+                    Ok(result) => {
+                        batch.record(&result);
+                        continue;
+                    }
+                    // the client is gone, the remaining files never finish
+                    Err(_) => batch.finish(TransferOutcome::Disconnected),
+                },
+            };
+
+            if let Err(err) = send_batch_summary(&app_handle, &summary) {
+                println!("[BATCH] can't send summary {}", err);
+            }
+            return;
+        });
+
+        // a declined file is stopped, which ends it in the batch
+        for hash in hashes {
+            self.accept(hash, path.clone(), policy, temp_dir.clone(), false, max_retries)?;
+        }
+        Ok(())
+    }
+
+    fn accept(
+        &mut self,
+        hash: String,
+        path: String,
+        policy: OverwritePolicy,
+        temp_dir: Option<String>,
+        manifest: bool,
+        restarts: u8,
+    ) -> Result<(), ClientError> {
         let file = File::new(hash, path, "".to_string(), 0);
 
-        self.read_command.send(ReadCommand::Receive(file))?;
+        self.read_command
+            .send(ReadCommand::Receive(file, policy, temp_dir, manifest, restarts))?;
         Ok(())
     }
 
@@ -231,6 +679,21 @@ impl<W: ClientWriter + Send + 'static, R: ClientReader + Send + 'static> Client<
         Ok(())
     }
 
+    /// Withdraws an offer the peer has not requested yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The hash of the offered file.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the command was transmitted successfully,
+    /// or an `Err` containing a `ClientError`.
+    pub fn rescind_offer(&mut self, hash: String) -> Result<(), ClientError> {
+        self.write_command.send(WriteCommand::Rescind(hash))?;
+        Ok(())
+    }
+
     /// Pause receiving a file.
     ///
     /// # Arguments
@@ -245,106 +708,968 @@ impl<W: ClientWriter + Send + 'static, R: ClientReader + Send + 'static> Client<
         self.read_command.send(ReadCommand::Pause(hash))?;
         Ok(())
     }
+
+    /// Lists all files which are currently offered or transferred.
+    ///
+    /// # Returns
+    ///
+    /// Returns the hash, the state and the progress of each transfer.
+    pub fn active_transfers(&self) -> Vec<(String, FileState, f32)> {
+        self.transfers
+            .snapshot()
+            .into_iter()
+            .map(|transfer| (transfer.hash, transfer.state, transfer.percent))
+            .collect()
+    }
+
+    /// Counts the bytes of a file which were transferred so far.
+    /// Unlike the progress of `active_transfers`, the count is exact, the last chunk of a file may be shorter.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The hash of the file.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the file is not offered or transferred.
+    pub fn bytes_transferred(&self, hash: &str) -> Option<u64> {
+        self.transfers
+            .snapshot()
+            .into_iter()
+            .find(|transfer| transfer.hash == hash)
+            .map(|transfer| transfer.transferred)
+    }
+
+    /// Counts the bytes of a file which are still missing.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The hash of the file.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the file is not offered or transferred.
+    pub fn bytes_remaining(&self, hash: &str) -> Option<u64> {
+        self.transfers
+            .snapshot()
+            .into_iter()
+            .find(|transfer| transfer.hash == hash)
+            .map(|transfer| transfer.size.saturating_sub(transfer.transferred))
+    }
+
+    /// Stops all offered and transferred files.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the commands were transmitted successfully,
+    /// or an `Err` containing a `ClientError`.
+    pub fn cancel_all(&mut self) -> Result<(), ClientError> {
+        cancel_transfers(&self.transfers, &self.read_command, &self.write_command)
+    }
+
+    /// Returns a receiver for the results of all transfers which finish from now on.
+    /// Each finished transfer yields exactly one `TransferResult`.
+    /// Used by callers without a front end, which can't listen to the file events.
+    pub fn transfer_results(&self) -> mpsc::Receiver<TransferResult> {
+        self.transfers.subscribe()
+    }
+
+    /// Stops sending data for all files, while keeping the connection alive.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the command was transmitted successfully,
+    /// or an `Err` containing a `ClientError`.
+    pub fn suspend(&mut self) -> Result<(), ClientError> {
+        self.write_command.send(WriteCommand::Suspend)?;
+        Ok(())
+    }
+
+    /// Continues sending data after `suspend`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the command was transmitted successfully,
+    /// or an `Err` containing a `ClientError`.
+    pub fn resume(&mut self) -> Result<(), ClientError> {
+        self.write_command.send(WriteCommand::Resume)?;
+        Ok(())
+    }
+
+    /// Sets the number of chunks of a file which are read ahead and queued into the transport at once.
+    /// A larger depth keeps links with a high bandwidth-delay product busy.
+    ///
+    /// # Arguments
+    ///
+    /// * `depth` - The number of chunks, limited to `1..=MAX_PIPELINE_DEPTH`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the command was transmitted successfully,
+    /// or an `Err` containing a `ClientError`.
+    pub fn set_pipeline_depth(&mut self, depth: usize) -> Result<(), ClientError> {
+        let depth = depth.clamp(1, MAX_PIPELINE_DEPTH);
+        self.write_command.send(WriteCommand::PipelineDepth(depth))?;
+        Ok(())
+    }
+
+    /// Sets whether every sent chunk is checked against a hash taken when the file was offered.
+    /// A file which is edited while it is sent is stopped and reported as `FileState::Changed`,
+    /// instead of failing at the receiver without a clear cause. Hashing the chunks reads the file once more per offer.
+    /// The setting applies to files offered afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether sent chunks are verified.
+    pub fn set_verify_source(&mut self, enabled: bool) {
+        self.offer_settings.verify_source = enabled;
+    }
+
+    /// Sets the size of the chunks files are sent in, e.g. tuned to the connection with `tune_chunk_size`.
+    /// Each offer announces its chunk size, so the peer does not need the same setting.
+    /// Files which would have more than `MAX_CHUNK_COUNT` chunks are sent in chunks of `CHUNK_SIZE` bytes.
+    /// The setting applies to files offered afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk_size` - The chunk size, between `MIN_CHUNK_SIZE` and `CHUNK_SIZE`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the chunk size was set, or an `Err` containing a `ClientError` of kind `InvalidArgument` if it is out of bounds.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) -> Result<(), ClientError> {
+        check_chunk_size(chunk_size).map_err(|_| ClientError::new(ClientErrorKind::InvalidArgument))?;
+        self.offer_settings.chunk_size = chunk_size;
+        Ok(())
+    }
+
+    /// Sets whether offers carry the Merkle root over the chunks of the file.
+    /// The receiver checks the received chunks against the root before it keeps the file.
+    /// Building the tree reads the file once more per offer. The setting applies to files offered afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether Merkle roots are sent.
+    pub fn set_merkle_roots(&mut self, enabled: bool) {
+        self.merkle_roots = enabled;
+    }
+
+    /// Sets how many received chunks are collected before they are written to disk.
+    /// Batching reduces the number of writes on fast links. A file is always written completely before it is verified.
+    /// The batching applies to files whose first chunk arrives afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_chunks` - The number of chunks of a batch, at least 1. 1 writes every chunk immediately.
+    /// * `interval` - The time after which a batch is written, even if it is not full.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the command was transmitted successfully,
+    /// or an `Err` containing a `ClientError`.
+    pub fn set_write_batching(
+        &mut self,
+        max_chunks: usize,
+        interval: Duration,
+    ) -> Result<(), ClientError> {
+        let batching = WriteBatching {
+            max_chunks: max_chunks.max(1),
+            interval,
+        };
+        self.read_command.send(ReadCommand::WriteBatching(batching))?;
+        Ok(())
+    }
+
+    /// Sets the template for the names of received files, e.g. `{date}_{name}`.
+    /// The template applies to files accepted into a directory afterwards. Files accepted
+    /// under a chosen path keep that path.
+    ///
+    /// # Arguments
+    ///
+    /// * `template` - The template, see `chunk::file::file::render_output_name` for the placeholders.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the command was transmitted successfully,
+    /// or an `Err` containing a `ClientError`.
+    pub fn set_name_template(&mut self, template: &str) -> Result<(), ClientError> {
+        self.read_command
+            .send(ReadCommand::NameTemplate(template.to_string()))?;
+        Ok(())
+    }
 }
 
-impl<W: ClientWriter + Send, R: ClientReader + Send> Drop for Client<W, R> {
-    fn drop(&mut self) {
-        //should panic if this fails. It could be that one of the threads is already dead and there would be no way to stop the other one.
-        {
-            let mut dropper = self.drop_threads.write().unwrap();
-            *dropper = true;
-        }
+/// A transfer as published by the read and write threads.
+#[derive(Clone)]
+struct Transfer {
+    hash: String,
+    size: u64,
+    state: FileState,
+    percent: f32,
+    /// Bytes of the chunks which were transferred, see `ActiveFile::bytes_transferred`.
+    transferred: u64,
+    is_sender: bool,
+    /// Time the transfer left `FileState::Pending`, so the wait for the peer to accept is not counted.
+    started: Option<Instant>,
+    /// Time the last event of the transfer was sent to the front end.
+    last_event: Instant,
+}
 
-        if let Some(thread) = self.writer_thread.take() {
-            if thread.join().is_err() {
-                println!("[CLIENT]: Failed to join writer thread");
-            }
-        }
+/// How a transfer ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferOutcome {
+    Completed,
+    /// The file could not be verified.
+    Corrupted,
+    /// The transfer was stopped by either peer.
+    Cancelled,
+    /// The connection ended before the transfer finished.
+    Disconnected,
+    /// The sent file changed on disk during the transfer.
+    SourceChanged,
+}
 
-        if let Some(thread) = self.reader_thread.take() {
-            if thread.join().is_err() {
-                println!("[CLIENT]: Failed to join reader thread");
-            }
+/// Summary of a finished transfer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferResult {
+    pub hash: String,
+    /// Whether the file was sent or received.
+    pub is_sender: bool,
+    /// Number of bytes transferred.
+    pub bytes: u64,
+    /// Time since the transfer started, without the wait for the peer to accept it.
+    pub duration: Duration,
+    /// Average speed in bytes per second.
+    pub avg_speed: f64,
+    pub outcome: TransferOutcome,
+}
+
+impl TransferResult {
+    fn new(transfer: &Transfer, outcome: TransferOutcome) -> Self {
+        let bytes = match outcome {
+            TransferOutcome::Completed => transfer.size,
+            _ => transfer.transferred.min(transfer.size),
+        };
+        let duration = transfer.started.map_or(Duration::ZERO, |started| started.elapsed());
+        let avg_speed = match duration.as_secs_f64() {
+            secs if secs > 0.0 => bytes as f64 / secs,
+            _ => 0.0,
+        };
+
+        TransferResult {
+            hash: transfer.hash.clone(),
+            is_sender: transfer.is_sender,
+            bytes,
+            duration,
+            avg_speed,
+            outcome,
         }
     }
 }
 
-/// Commands to send to the read thread.
-enum ReadCommand {
-    /// Activate receiving for a file.
-    Receive(File),
-    /// Pause receiving for a file. Contains the file hash.
-    Pause(String),
+/// Aggregate progress of all transfers, sent to the front end as `app://transfer-summary`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct TransferSummary {
+    /// Combined speed of all running transfers in bytes per second.
+    pub bytes_per_second: f64,
+    /// Number of files which were transferred completely.
+    pub completed: usize,
+    /// Number of files which are offered, running or completed.
+    pub total: usize,
+}
 
-    /// Resume receiving for a file. Contains the file hash.
-    // TODO implement resume
-    #[allow(dead_code)]
-    Resume(String),
-    /// Stop receiving a file. Contains the file hash.
-    Stop(String),
+/// Outcome of a batch of received files, sent to the front end as `app://batch-summary`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct BatchSummary {
+    /// Hashes of the files which were received completely.
+    pub succeeded: Vec<String>,
+    /// Hashes of the files which were corrupted, stopped or cut off by a disconnect.
+    pub failed: Vec<String>,
 }
 
-/// Commands to send to the write thread.
-enum WriteCommand {
-    /// Sends a request for a file.
-    Request(ActiveFile),
-    /// Sends a offer for a file. Contains the file hash.
-    Offer(File),
-    /// Stop sending a file. Contains the file hash.
-    Stop(String),
-    /// Send a stop sending signal to the peer. Contains the file hash.
-    StopSend(String),
-    /// Send a chunk to the peer. Contains the file hash, the chunk start point and endpoint.
-    Send(String, u64, u64),
+/// Tracks the outcome of every file of a batch, see `Client::accept_batch`.
+struct BatchTransfer {
+    /// The hashes of the files of the batch and their outcome, once they finished.
+    files: Vec<(String, Option<TransferOutcome>)>,
 }
 
-/// Function in charge of handling all incoming messages.
-///
-/// # Arguments
-/// * `dropper` - A boolean that indicates if the thread should stop.
+impl BatchTransfer {
+    fn new(hashes: Vec<String>) -> Self {
+        BatchTransfer {
+            files: hashes.into_iter().map(|hash| (hash, None)).collect(),
+        }
+    }
+
+    /// Records the result of a finished transfer. Results of sent files and of files outside the batch are ignored.
+    /// A disconnect ends every file of the batch, also if it is reported for a sent file.
+    ///
+    /// # Returns
+    ///
+    /// Returns the summary once every file of the batch finished, `None` before.
+    fn record(&mut self, result: &TransferResult) -> Option<BatchSummary> {
+        if result.outcome == TransferOutcome::Disconnected {
+            return Some(self.finish(TransferOutcome::Disconnected));
+        }
+        if !result.is_sender {
+            self.finish_file(&result.hash, result.outcome);
+        }
+
+        self.summary()
+    }
+
+    /// Records the outcome of a file of the batch, unless it finished before.
+    fn finish_file(&mut self, hash: &str, outcome: TransferOutcome) {
+        if let Some((_, finished)) = self
+            .files
+            .iter_mut()
+            .find(|(file_hash, finished)| file_hash == hash && finished.is_none())
+        {
+            *finished = Some(outcome);
+        }
+    }
+
+    /// Ends every file of the batch which did not finish yet with `outcome`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the summary of the batch.
+    fn finish(&mut self, outcome: TransferOutcome) -> BatchSummary {
+        for (_, finished) in self.files.iter_mut().filter(|(_, finished)| finished.is_none()) {
+            *finished = Some(outcome);
+        }
+
+        self.summary().expect("every file of the batch finished")
+    }
+
+    /// Returns the summary once every file of the batch finished, `None` before.
+    fn summary(&self) -> Option<BatchSummary> {
+        if self.files.iter().any(|(_, outcome)| outcome.is_none()) {
+            return None;
+        }
+
+        let (succeeded, failed) = self
+            .files
+            .iter()
+            .partition::<Vec<_>, _>(|(_, outcome)| *outcome == Some(TransferOutcome::Completed));
+        let hashes = |files: Vec<&(String, Option<TransferOutcome>)>| {
+            files.into_iter().map(|(hash, _)| hash.clone()).collect()
+        };
+
+        Some(BatchSummary {
+            succeeded: hashes(succeeded),
+            failed: hashes(failed),
+        })
+    }
+}
+
+/// Measures the speed of the running transfers between two samples.
+struct TransferRates {
+    /// Transferred bytes per transfer at the last sample, keyed by hash and direction.
+    bytes: HashMap<(String, bool), u64>,
+    sampled: Instant,
+}
+
+impl TransferRates {
+    fn new(now: Instant) -> Self {
+        TransferRates {
+            bytes: HashMap::new(),
+            sampled: now,
+        }
+    }
+
+    /// Samples the transferred bytes of all running transfers.
+    ///
+    /// # Returns
+    ///
+    /// Returns the sum of the speeds of all running transfers since the last sample, in bytes per second.
+    /// A transfer which was not running at the last sample only counts from this sample on.
+    fn sample(&mut self, transfers: &[Transfer], now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.sampled).as_secs_f64();
+        let mut bytes = HashMap::new();
+        let mut total_rate = 0.0;
+
+        for transfer in transfers {
+            if transfer.state != FileState::Transferring {
+                continue;
+            }
+
+            let key = (transfer.hash.clone(), transfer.is_sender);
+            let transferred = transfer.transferred;
+            if let Some(previous) = self.bytes.get(&key) {
+                if elapsed > 0.0 {
+                    total_rate += transferred.saturating_sub(*previous) as f64 / elapsed;
+                }
+            }
+            bytes.insert(key, transferred);
+        }
+
+        self.bytes = bytes;
+        self.sampled = now;
+        total_rate
+    }
+}
+
+/// Shared between the read and the write thread, so the surviving thread stops once its sibling exited.
+#[derive(Clone, Default)]
+struct ThreadStatus {
+    /// Set when one of the threads exited, also if it panicked.
+    exited: Arc<AtomicBool>,
+    /// Set once the disconnect was reported to the front end.
+    disconnect_reported: Arc<AtomicBool>,
+}
+
+/// Sets `ThreadStatus::exited` when the owning thread ends.
+struct ExitGuard(Arc<AtomicBool>);
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+impl ThreadStatus {
+    /// Returns a guard which marks the calling thread as exited once it is dropped.
+    fn guard(&self) -> ExitGuard {
+        ExitGuard(self.exited.clone())
+    }
+
+    /// Checks if the sibling thread exited.
+    /// Only the sibling can have set the flag, since the guard of the calling thread is still alive.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ClientError` of kind `Disconnected` if the sibling exited.
+    fn check_sibling(&self) -> Result<(), ClientError> {
+        match self.exited.load(Ordering::SeqCst) {
+            true => Err(ClientError::new(ClientErrorKind::Disconnected(
+                DisconnectReason::SocketError,
+            ))),
+            false => Ok(()),
+        }
+    }
+
+    /// Returns `true` for the first caller only, so the disconnect is reported once.
+    fn take_disconnect(&self) -> bool {
+        !self.disconnect_reported.swap(true, Ordering::SeqCst)
+    }
+}
+
+/// Spawns a client thread running `body`.
+/// If `body` fails, all transfers are reported as disconnected and `on_disconnect` is called,
+/// unless the other thread already reported the disconnect.
+///
+/// # Arguments
+///
+/// * `name` - The name of the thread used for logging.
+/// * `status` - The status shared with the other client thread.
+/// * `transfers` - The snapshot of all transfers.
+/// * `body` - The function of the thread.
+/// * `on_disconnect` - Reports the disconnect to the front end.
+fn spawn_client_thread<F, D>(
+    name: &'static str,
+    status: ThreadStatus,
+    transfers: Transfers,
+    body: F,
+    on_disconnect: D,
+) -> JoinHandle<Result<(), ClientError>>
+where
+    F: FnOnce() -> Result<(), ClientError> + Send + 'static,
+    D: FnOnce(DisconnectReason) -> Result<(), ClientError> + Send + 'static,
+{
+    thread::spawn(move || {
+        let _guard = status.guard();
+
+        match body() {
+            Ok(_) => println!("[CLIENT]: {} thread exited successfully", name),
+            Err(e) => {
+                println!("[CLIENT]: {} thread exited with error {}", name, e);
+                transfers.disconnect();
+                if status.take_disconnect() {
+                    on_disconnect(e.disconnect_reason())
+                        .map_err(|_| ClientError::new(ClientErrorKind::MpscSendError))?;
+                }
+            }
+        };
+        Ok(())
+    })
+}
+
+/// Decides if an update of a file is sent to the front end.
+/// Progress updates are coalesced to at most one per `PROGRESS_EVENT_INTERVAL`, so small chunks don't flood the front end.
+/// New files and state changes are always sent.
+///
+/// # Arguments
+///
+/// * `previous` - The state of the file and the time its last event was sent. `None` if the file is new.
+/// * `state` - The new state of the file.
+/// * `now` - The time of the update.
+fn should_emit(previous: Option<(&FileState, Instant)>, state: &FileState, now: Instant) -> bool {
+    match previous {
+        Some((previous_state, last_event)) if previous_state == state => {
+            now.saturating_duration_since(last_event) >= PROGRESS_EVENT_INTERVAL
+        }
+        _ => true,
+    }
+}
+
+/// Snapshot of all transfers which are not finished yet, shared between the client and its threads.
+#[derive(Clone, Default)]
+struct Transfers {
+    inner: Arc<Mutex<Vec<Transfer>>>,
+    /// Receivers of the results of finished transfers.
+    subscribers: Arc<Mutex<Vec<Sender<TransferResult>>>>,
+    /// Number of transfers which completed.
+    completed: Arc<AtomicUsize>,
+}
+
+impl Transfers {
+    /// Stores the state of a file and sends it to the front end.
+    /// Finished files are removed from the snapshot.
+    fn publish(
+        &self,
+        handle: &AppHandle<Wry>,
+        file: File,
+        file_state: FileState,
+        percent: f32,
+        is_sender: bool,
+    ) -> Result<(), ClientError> {
+        if !self.update(&file.hash, file.size, file_state.clone(), percent, is_sender) {
+            return Ok(());
+        }
+        send_file_state(handle, file, file_state, percent, is_sender)
+    }
+
+    /// Stores the state of a file.
+    /// Finished files are removed and their result is sent to all subscribers.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the update has to be sent to the front end, see `should_emit`.
+    fn update(&self, hash: &str, size: u64, state: FileState, percent: f32, is_sender: bool) -> bool {
+        let now = Instant::now();
+        let mut transfers = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let previous = transfers
+            .iter()
+            .position(|transfer| transfer.hash == hash && transfer.is_sender == is_sender)
+            .map(|index| transfers.swap_remove(index));
+
+        let emit = should_emit(
+            previous.as_ref().map(|previous| (&previous.state, previous.last_event)),
+            &state,
+            now,
+        );
+        let last_event = match (emit, previous.as_ref()) {
+            (false, Some(previous)) => previous.last_event,
+            _ => now,
+        };
+
+        let transfer = Transfer {
+            hash: hash.to_string(),
+            // the receiver learns the size with the offer, later states may not carry it
+            size: previous.as_ref().map_or(size, |previous| size.max(previous.size)),
+            state: state.clone(),
+            percent,
+            // a file which is received again from the start counts its bytes again
+            transferred: match percent == 0.0 {
+                true => 0,
+                false => previous.as_ref().map_or(0, |previous| previous.transferred),
+            },
+            is_sender,
+            started: match previous.and_then(|previous| previous.started) {
+                None if state != FileState::Pending => Some(now),
+                started => started,
+            },
+            last_event,
+        };
+
+        let outcome = match state {
+            FileState::Completed => TransferOutcome::Completed,
+            FileState::Corrupted => TransferOutcome::Corrupted,
+            FileState::Stopped => TransferOutcome::Cancelled,
+            FileState::Changed => TransferOutcome::SourceChanged,
+            _ => {
+                transfers.push(transfer);
+                return emit;
+            }
+        };
+        drop(transfers);
+
+        if outcome == TransferOutcome::Completed {
+            self.completed.fetch_add(1, Ordering::SeqCst);
+        }
+        self.report(TransferResult::new(&transfer, outcome));
+        emit
+    }
+
+    /// Stores the number of transferred bytes of a file which is not finished yet.
+    fn record_bytes(&self, hash: &str, is_sender: bool, transferred: u64) {
+        let mut transfers = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(transfer) = transfers
+            .iter_mut()
+            .find(|transfer| transfer.hash == hash && transfer.is_sender == is_sender)
+        {
+            transfer.transferred = transferred;
+        }
+    }
+
+    /// Summarizes all transfers.
+    ///
+    /// # Arguments
+    ///
+    /// * `rates` - The speed measurement, which is sampled.
+    /// * `now` - The time of the summary.
+    fn summary(&self, rates: &mut TransferRates, now: Instant) -> TransferSummary {
+        let transfers = self.snapshot();
+        let completed = self.completed.load(Ordering::SeqCst);
+
+        TransferSummary {
+            bytes_per_second: rates.sample(&transfers, now),
+            completed,
+            total: completed + transfers.len(),
+        }
+    }
+
+    /// Ends all transfers which are not finished yet with the outcome `Disconnected`.
+    fn disconnect(&self) {
+        let transfers: Vec<Transfer> = self
+            .inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain(..)
+            .collect();
+
+        for transfer in transfers {
+            self.report(TransferResult::new(&transfer, TransferOutcome::Disconnected));
+        }
+    }
+
+    /// Sends a result to all subscribers. Subscribers which are gone are removed.
+    fn report(&self, result: TransferResult) {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|subscriber| subscriber.send(result.clone()).is_ok());
+    }
+
+    /// Returns a receiver for the results of all transfers which finish from now on.
+    fn subscribe(&self) -> mpsc::Receiver<TransferResult> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
+
+    fn snapshot(&self) -> Vec<Transfer> {
+        self.inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+/// Sends the commands which stop every transfer in `transfers`.
+/// Offers which were not requested are rescinded.
+fn cancel_transfers(
+    transfers: &Transfers,
+    read_command: &Sender<ReadCommand>,
+    write_command: &Sender<WriteCommand>,
+) -> Result<(), ClientError> {
+    for transfer in transfers.snapshot() {
+        match (transfer.is_sender, transfer.state) {
+            (true, FileState::Pending | FileState::Unanswered) => {
+                write_command.send(WriteCommand::Rescind(transfer.hash))?;
+            }
+            (true, _) => {
+                write_command.send(WriteCommand::StopSend(transfer.hash.clone()))?;
+                write_command.send(WriteCommand::Stop(transfer.hash))?;
+            }
+            (false, _) => {
+                read_command.send(ReadCommand::Stop(transfer.hash))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl<W: ClientWriter + Send, R: ClientReader + Send> Drop for Client<W, R> {
+    fn drop(&mut self) {
+        stop_client_threads(
+            &self.drop_threads,
+            self.writer_thread.take(),
+            self.reader_thread.take(),
+        );
+    }
+}
+
+/// Tells the client threads to stop and waits for them.
+/// Never panics: a poisoned flag or a thread which already exited is logged and skipped.
+///
+/// # Arguments
+///
+/// * `drop_threads` - The flag the threads poll to know when to stop.
+/// * `writer_thread` - The handle of the writer thread, if it was not joined yet.
+/// * `reader_thread` - The handle of the reader thread, if it was not joined yet.
+fn stop_client_threads(
+    drop_threads: &RwLock<bool>,
+    writer_thread: Option<JoinHandle<Result<(), ClientError>>>,
+    reader_thread: Option<JoinHandle<Result<(), ClientError>>>,
+) {
+    // A poisoned lock only means one of the threads already panicked. The flag is still set so the other one stops.
+    match drop_threads.write() {
+        Ok(mut dropper) => *dropper = true,
+        Err(poisoned) => {
+            println!("[CLIENT]: Drop flag poisoned, a thread already exited");
+            *poisoned.into_inner() = true;
+        }
+    }
+
+    if let Some(thread) = writer_thread {
+        if thread.join().is_err() {
+            println!("[CLIENT]: Failed to join writer thread");
+        }
+    }
+
+    if let Some(thread) = reader_thread {
+        if thread.join().is_err() {
+            println!("[CLIENT]: Failed to join reader thread");
+        }
+    }
+}
+
+/// Commands to send to the read thread.
+enum ReadCommand {
+    /// Activate receiving for a file. Contains the file, what happens if its output file exists,
+    /// the directory for the temporary file, whether a manifest is written once the file is complete
+    /// and how often the file is received again if it fails.
+    Receive(File, OverwritePolicy, Option<String>, bool, u8),
+    /// Pause receiving for a file. Contains the file hash.
+    Pause(String),
+
+    /// Resume receiving for a file. Contains the file hash.
+    // TODO implement resume
+    #[allow(dead_code)]
+    Resume(String),
+    /// Stop receiving a file. Contains the file hash.
+    Stop(String),
+    /// Change how received chunks are batched before they are written.
+    WriteBatching(WriteBatching),
+    /// Change the template for the names of received files.
+    NameTemplate(String),
+}
+
+/// Commands to send to the write thread.
+enum WriteCommand {
+    /// Sends a request for a file.
+    Request(ActiveFile),
+    /// Sends a request for several ranges of chunks of a file, e.g. the missing chunks of a resumed file.
+    /// Contains the file hash and the ranges.
+    RequestRanges(String, Vec<(u64, u64)>),
+    /// Sends a offer for a file. Contains the file and the hashes of its chunks if they are verified when sent.
+    Offer(File, Option<Arc<ChunkHashes>>),
+    /// Stop sending a file. Contains the file hash.
+    Stop(String),
+    /// Send a stop sending signal to the peer. Contains the file hash.
+    StopSend(String),
+    /// Send chunks to the peer. Contains the file hash and the ordered ranges of chunks,
+    /// start and end of each range are inclusive.
+    Send(String, Vec<(u64, u64)>),
+    /// Withdraw an offer which was not requested yet. Contains the file hash.
+    Rescind(String),
+    /// Stop sending data, while keeping the connection alive.
+    Suspend,
+    /// Continue sending data after `Suspend`.
+    Resume,
+    /// Set the number of chunks of a file queued at once.
+    PipelineDepth(usize),
+}
+
+/// Function in charge of handling all incoming messages.
+///
+/// # Arguments
+/// * `dropper` - A boolean that indicates if the thread should stop.
 /// * `reader` - The `ClientReader` that is used to read from the peer.
 /// * `app_handle` - A handle to the tauri application.
 /// * `command_receiver` - A receiver for the read commands.
 /// * `command_sender` - A writer for the write commands.
+/// * `transfers` - The snapshot of all transfers, which is updated with every file state.
+/// * `status` - The status shared with the write thread. The thread stops once the write thread exited.
 fn read_thread<R: ClientReader>(
     dropper: Arc<RwLock<bool>>,
     reader: Arc<Mutex<R>>,
     app_handle: AppHandle<Wry>,
     command_receiver: mpsc::Receiver<ReadCommand>,
     command_sender: Sender<WriteCommand>,
+    transfers: Transfers,
+    status: ThreadStatus,
 ) -> Result<(), ClientError> {
     let mut reader = reader.lock()?;
     let mut paused_files: Vec<ActiveFile> = vec![];
     let mut active_files: Vec<ActiveFile> = vec![];
     let mut pending_files: Vec<File> = vec![];
+    let mut write_batching = WriteBatching::default();
+    let mut name_template = DEFAULT_NAME_TEMPLATE.to_string();
+    let mut groups = Groups::default();
+    // writers of the received files, dropping a writer writes its last batch
+    let mut writers: HashMap<String, BatchedChunkWriter> = HashMap::new();
     loop {
         {
             if *dropper.read()? {
                 return Ok(());
             }
         }
+        status.check_sibling()?;
+
+        for writer in writers.values_mut() {
+            writer.flush_if_due()?;
+        }
+
+        // a sender which never confirms its files is trusted with the hash of its offer
+        let mut index = 0;
+        while index < active_files.len() {
+            let file = &mut active_files[index];
+            let unconfirmed = file
+                .awaiting_confirmation
+                .map_or(false, |since| since.elapsed() >= CONFIRMATION_TIMEOUT);
+            if unconfirmed && file.confirmed_hash.is_none() {
+                println!("[READER] : no confirmation of {}, verifying against the offer", file.file.hash);
+                file.confirmed_hash = Some(file.file.hash.clone());
+                if finish_received_file(file, &mut writers, &mut groups, &app_handle, &command_sender, &transfers)? {
+                    active_files.remove(index);
+                    continue;
+                }
+            }
+            index += 1;
+        }
+
+        // held files of a group whose other files are gone are never moved to their paths
+        let live_groups: HashSet<&str> = active_files
+            .iter()
+            .chain(paused_files.iter())
+            .map(|file| &file.file)
+            .chain(pending_files.iter())
+            .filter_map(|file| file.group.as_ref().map(|(id, _)| id.as_str()))
+            .collect();
+        for held in groups.expire(GROUP_TIMEOUT, &live_groups) {
+            println!("[READER] : group of {} can't complete, discarding it", held.file.hash);
+            transfers.publish(&app_handle, held.file, FileState::Stopped, 1.0, false)?;
+        }
 
         match command_receiver.try_recv() {
             Ok(c) => match c {
-                ReadCommand::Receive(file) => {
+                ReadCommand::Receive(file, policy, temp_dir, manifest, restarts) => {
                     match pending_files.iter().position(|wf| wf.hash == file.hash) {
                         None => {
                             println!("[READER] COMMAND : receive not found {}", file.hash);
                         }
                         Some(index) => {
                             let mut new_file = pending_files.swap_remove(index);
-                            new_file.path = file.path;
+                            if groups.has_failed(&new_file) {
+                                println!("[READER] COMMAND : group of {} failed", new_file.hash);
+                                fail_group(&mut groups, &new_file, &app_handle, &transfers)?;
+                                command_sender.send(WriteCommand::Stop(new_file.hash.clone()))?;
+                                transfers.publish(&app_handle, new_file, FileState::Stopped, 0.0, false)?;
+                                continue;
+                            }
+                            let chosen_path = match Path::new(&file.path).is_dir() {
+                                false => file.path,
+                                // the templated name is confined to the chosen directory
+                                true => match templated_output_path(&file.path, &name_template, &new_file.to_offer()) {
+                                    Ok(path) => {
+                                        // a template may place the file in a subdirectory
+                                        if let Some(parent) = path.parent() {
+                                            std::fs::create_dir_all(parent)?;
+                                        }
+                                        path.to_string_lossy().to_string()
+                                    }
+                                    Err(err) => {
+                                        println!("[READER] COMMAND : rejected name {} {}", new_file.hash, err);
+                                        command_sender.send(WriteCommand::Stop(new_file.hash.clone()))?;
+                                        transfers.publish(&app_handle, new_file, FileState::Stopped, 0.0, false)?;
+                                        continue;
+                                    }
+                                },
+                            };
+
+                            let output_path = ensure_extension(&chosen_path, &new_file.extension);
+                            // never replace an existing file because of the added extension
+                            let policy = match output_path != chosen_path {
+                                true => OverwritePolicy::Rename,
+                                false => policy,
+                            };
+                            // a transfer interrupted before, e.g. by a restart, continues in its temporary file
+                            let missing = resume_from_disk(&get_temp_path(&output_path, temp_dir.as_deref()))
+                                .ok()
+                                // chunks logged with another size are at other offsets
+                                .filter(|(offer, _)| {
+                                    offer.file_hash.eq_ignore_ascii_case(&new_file.hash) && offer.chunk_size == new_file.chunk_size
+                                })
+                                .map(|(_, missing)| missing);
+                            // a resumed transfer keeps the path reserved by its first attempt
+                            let resolved = match missing {
+                                Some(_) => Ok(output_path),
+                                None => resolve_output_path(&output_path, policy),
+                            };
+                            new_file.path = match resolved {
+                                Ok(path) => path,
+                                Err(err) => {
+                                    println!("[READER] COMMAND : declined {} {}", new_file.hash, err);
+                                    command_sender.send(WriteCommand::Stop(new_file.hash.clone()))?;
+                                    transfers.publish(&app_handle, new_file, FileState::Stopped, 0.0, false)?;
+                                    continue;
+                                }
+                            };
 
-                            let active_file = ActiveFile::from_file(new_file);
-                            send_file_state(
+                            let mut active_file = ActiveFile::from_file(new_file);
+                            active_file.reserved = policy != OverwritePolicy::Overwrite;
+                            active_file.temp_path =
+                                get_temp_path(&active_file.file.path, temp_dir.as_deref());
+                            active_file.manifest = manifest;
+                            active_file.restarts = restarts;
+                            // chunks received before a resume are not hashed again, so only complete transfers are checked
+                            if missing.is_none() && !active_file.file.merkle_root.is_empty() {
+                                match MerkleVerifier::new(
+                                    &active_file.file.merkle_root,
+                                    MERKLE_HASH_TYPE,
+                                    active_file.stop,
+                                ) {
+                                    Ok(merkle) => active_file.merkle = Some(merkle),
+                                    Err(err) => {
+                                        println!("[READER] COMMAND : can't verify root of {} {}", active_file.file.hash, err)
+                                    }
+                                }
+                            }
+                            let new_file = &active_file.file;
+
+                            let offer = new_file.to_offer();
+                            if let Err(err) = write_offer_file(&active_file.temp_path, &offer) {
+                                println!("[READER] COMMAND : can't store offer {} {}", new_file.hash, err);
+                            }
+                            if let Err(err) = preallocate_file(&active_file.temp_path, new_file.size) {
+                                println!("[READER] COMMAND : can't allocate {} {}", new_file.hash, err);
+                            }
+
+                            transfers.publish(
                                 &app_handle,
                                 active_file.file.clone(),
                                 FileState::Transferring,
                                 0.0,
                                 false,
                             )?;
-                            active_files.push(active_file.clone());
-                            command_sender.send(WriteCommand::Request(active_file))?;
+                            match missing {
+                                Some(missing) => {
+                                    println!("[READER] COMMAND : resume {}", active_file.file.hash);
+                                    let ranges = active_file.resume(&missing);
+                                    let hash = active_file.file.hash.clone();
+                                    active_files.push(active_file);
+                                    command_sender.send(WriteCommand::RequestRanges(hash, ranges))?;
+                                }
+                                None => {
+                                    active_files.push(active_file.clone());
+                                    command_sender.send(WriteCommand::Request(active_file))?;
+                                }
+                            }
                         }
                     }
                 }
@@ -356,6 +1681,7 @@ fn read_thread<R: ClientReader>(
                         Some(index) => {
                             let file = active_files.swap_remove(index);
                             let hash = file.file.hash.clone();
+                            writers.remove(&hash);
                             command_sender.send(WriteCommand::Stop(hash))?;
                             paused_files.push(file);
                         }
@@ -374,23 +1700,44 @@ fn read_thread<R: ClientReader>(
                     }
                 }
                 ReadCommand::Stop(hash) => {
-                    match active_files.iter().position(|wf| wf.file.hash == hash) {
+                    let active = active_files.iter().position(|wf| wf.file.hash == hash);
+                    let paused = paused_files.iter().position(|wf| wf.file.hash == hash);
+                    let pending = pending_files.iter().position(|pf| pf.hash == hash);
+
+                    let active_file = match (active, paused) {
+                        (Some(index), _) => Some(active_files.swap_remove(index)),
+                        (_, Some(index)) => Some(paused_files.swap_remove(index)),
+                        _ => None,
+                    };
+                    if let Some(active_file) = active_file.as_ref().filter(|file| file.reserved) {
+                        release_output_path(&active_file.file.path);
+                    }
+
+                    let file = match (active_file, pending) {
+                        (Some(active_file), _) => Some(active_file.file),
+                        (_, Some(index)) => Some(pending_files.swap_remove(index)),
+                        _ => None,
+                    };
+
+                    match file {
                         None => {
                             println!("[READER] COMMAND : stop not found {}", hash);
                         }
-                        Some(index) => {
-                            send_file_state(
-                                &app_handle,
-                                active_files[index].file.clone(),
-                                FileState::Stopped,
-                                0.0,
-                                false,
-                            )?;
-                            active_files.swap_remove(index);
+                        Some(file) => {
+                            // a denied file fails its whole group
+                            fail_group(&mut groups, &file, &app_handle, &transfers)?;
+                            transfers.publish(&app_handle, file, FileState::Stopped, 0.0, false)?;
                         }
                     }
+                    writers.remove(&hash);
                     command_sender.send(WriteCommand::Stop(hash))?;
                 }
+                ReadCommand::WriteBatching(batching) => {
+                    write_batching = batching;
+                }
+                ReadCommand::NameTemplate(template) => {
+                    name_template = template;
+                }
             },
             Err(_) => {}
         }
@@ -403,13 +1750,20 @@ fn read_thread<R: ClientReader>(
                 },
                 _ => {
                     println!("[READER] : error reading {}", _err);
-                    return Err(ClientError::new(ClientErrorKind::SocketClosed));
+                    return Err(ClientError::new(ClientErrorKind::Disconnected(
+                        _err.kind().disconnect_reason(),
+                    )));
                 }
             },
         };
 
         //println!("[READER] : msg {}", msg[0]);
 
+        if msg.is_empty() {
+            println!("[READER] : received empty message");
+            continue;
+        }
+
         match msg[0] {
             0x02 => {
                 //request file
@@ -419,24 +1773,46 @@ fn read_thread<R: ClientReader>(
                 })?;
                 println!("[READER] : request {}", order.file_hash);
 
-                command_sender.send(WriteCommand::Send(
-                    order.file_hash,
-                    order.start_num,
-                    order.end_num,
-                ))?;
+                // all ranges are sent at once, so the file can't complete before its last range is known
+                command_sender.send(WriteCommand::Send(order.file_hash, order.ranges))?;
             }
             0x01 => {
                 //offer file
-                let offer = read_offer_vec(&msg)
+                let offer = read_offer(OFFER_REGEX, &String::from_utf8_lossy(&msg))
                     .map_err(|_| ClientError::new(ClientErrorKind::DataCorruptionError))?;
 
+                // malformed or malicious offers are rejected before any state is kept for them.
+                // the name is sanitized whichever path the file is saved to, it is shown and stored from here on
+                let name = match validate_offer(&offer).and_then(|_| sanitize_output_name(&offer.name)) {
+                    Ok(name) => name.to_string_lossy().to_string(),
+                    Err(err) => {
+                        println!("[READER] : rejected offer {} {}", offer.file_hash, err);
+                        command_sender.send(WriteCommand::Stop(offer.file_hash))?;
+                        continue;
+                    }
+                };
+
+                // a streamed source has no size to write a file of, it is written to a sink with `write_ordered`
+                if offer.streaming {
+                    println!("[READER] : rejected streamed offer {}", offer.file_hash);
+                    command_sender.send(WriteCommand::Stop(offer.file_hash))?;
+                    continue;
+                }
+
                 println!("[READER] : offer {}", offer.file_hash);
 
-                let file = File::new(offer.file_hash, "".to_string(), offer.name, offer.size);
+                let mut file = File::new(offer.file_hash, "".to_string(), name, offer.size);
+                file.hash_type = offer.hash_type;
+                file.note = offer.note;
+                file.extension = offer.extension;
+                file.mime = offer.mime;
+                file.group = offer.group;
+                file.merkle_root = offer.merkle_root;
+                file.chunk_size = offer.chunk_size;
                 pending_files.push(file.clone());
 
                 //send_offer(&app_handle, file.path, file.hash, file.size)?;
-                send_file_state(&app_handle, file, FileState::Pending, 0.0, false)?;
+                transfers.publish(&app_handle, file, FileState::Pending, 0.0, false)?;
             }
             0x03 => {
                 //stop send file
@@ -445,8 +1821,37 @@ fn read_thread<R: ClientReader>(
 
                 println!("[READER] : stop {}", hash);
 
+                if let Some(index) = pending_files.iter().position(|pf| pf.hash == hash) {
+                    // the peer rescinded its offer
+                    let file = pending_files.swap_remove(index);
+                    fail_group(&mut groups, &file, &app_handle, &transfers)?;
+                    transfers.publish(&app_handle, file, FileState::Stopped, 0.0, false)?;
+                }
+
                 command_sender.send(WriteCommand::StopSend(hash))?;
             }
+            0x04 => {
+                //sender completed file
+                let (hash, confirmed_hash) = read_complete(&msg)
+                    .map_err(|_| ClientError::new(ClientErrorKind::DataCorruptionError))?;
+
+                println!("[READER] : complete {}", hash);
+
+                match active_files.iter().position(|wf| wf.file.hash == hash) {
+                    None => {
+                        println!("[READER] : unknown file {}", hash);
+                    }
+                    Some(index) => {
+                        let file = &mut active_files[index];
+                        file.confirmed_hash = Some(confirmed_hash);
+
+                        // corrupt chunks requested again may still be missing
+                        if finish_received_file(file, &mut writers, &mut groups, &app_handle, &command_sender, &transfers)? {
+                            active_files.remove(index);
+                        }
+                    }
+                }
+            }
             0x00 => {
                 //file data
                 let (header_vector, data_vector) = separate_header(&msg)
@@ -466,9 +1871,20 @@ fn read_thread<R: ClientReader>(
 
                         //println!("[READER] : file {}", header_data.file_hash);
 
+                        // the other files of a failed group are not received any further
+                        if groups.has_failed(&file.file) {
+                            println!("[READER] : group of {} failed", file.file.hash);
+                            fail_group(&mut groups, &file.file, &app_handle, &transfers)?;
+                            command_sender.send(WriteCommand::Stop(file.file.hash.clone()))?;
+                            transfers.publish(&app_handle, file.file.clone(), FileState::Stopped, 0.0, false)?;
+                            writers.remove(&header_data.file_hash);
+                            active_files.remove(index);
+                            continue;
+                        }
+
                         // send file status to front end
                         let percent = file.current as f32 / file.stop as f32;
-                        send_file_state(
+                        transfers.publish(
                             &app_handle,
                             file.file.clone(),
                             FileState::Transferring,
@@ -476,36 +1892,62 @@ fn read_thread<R: ClientReader>(
                             false,
                         )?;
 
-                        let log_path = write_data_vec(&header_data, &data_vector, &file.file.path)?;
-
-                        let act_num = header_data.chunk_pos;
-
-                        file.current = act_num;
-
-                        if file.current == file.stop {
-                            match validate_file(&log_path, &file.file.hash) {
-                                Ok((start, end)) => {
-                                    if start == end && start == 0 {
-                                        send_file_state(
-                                            &app_handle,
-                                            file.file.clone(),
-                                            FileState::Completed,
-                                            1.0,
-                                            false,
-                                        )?;
-                                        active_files.remove(index);
-                                    }
-                                }
-                                Err(_err) => {
-                                    send_file_state(
+                        let writer = writers
+                            .entry(file.file.hash.clone())
+                            .or_insert_with(|| {
+                                let mut writer = BatchedChunkWriter::new(&file.temp_path, write_batching);
+                                writer.set_file_hash_alg(file.file.hash_type);
+                                writer.set_chunk_size(file.file.chunk_size);
+                                writer.set_compact_log(true);
+                                writer
+                            });
+
+                        match receive_chunk(&mut file, writer, &header_data, &data_vector)? {
+                            ChunkOutcome::Written(_) => {
+                                transfers.record_bytes(&file.file.hash, false, file.bytes_transferred());
+                            }
+                            ChunkOutcome::Rerequest(chunk_num) => {
+                                println!(
+                                    "[READER] : corrupt chunk {} of {}",
+                                    chunk_num, file.file.hash
+                                );
+                                let mut retry = file.clone();
+                                retry.start = chunk_num;
+                                retry.stop = chunk_num;
+                                command_sender.send(WriteCommand::Request(retry))?;
+                                continue;
+                            }
+                            ChunkOutcome::Corrupted => {
+                                println!("[READER] : too many corrupt chunks {}", file.file.hash);
+                                if file.restart() {
+                                    println!("[READER] : retrying {}", file.file.hash);
+                                    transfers.publish(
                                         &app_handle,
                                         file.file.clone(),
-                                        FileState::Corrupted,
-                                        1.0,
+                                        FileState::Transferring,
+                                        0.0,
                                         false,
                                     )?;
+                                    command_sender.send(WriteCommand::Request(file.clone()))?;
+                                    continue;
                                 }
-                            };
+                                transfers.publish(
+                                    &app_handle,
+                                    file.file.clone(),
+                                    FileState::Corrupted,
+                                    percent,
+                                    false,
+                                )?;
+                                fail_group(&mut groups, &file.file, &app_handle, &transfers)?;
+                                command_sender.send(WriteCommand::Stop(file.file.hash.clone()))?;
+                                writers.remove(&file.file.hash);
+                                active_files.remove(index);
+                                continue;
+                            }
+                        };
+
+                        if finish_received_file(file, &mut writers, &mut groups, &app_handle, &command_sender, &transfers)? {
+                            active_files.remove(index);
                         }
                     }
                 }
@@ -525,37 +1967,663 @@ struct ActiveFile {
     start: u64,
     stop: u64,
     current: u64,
+    /// The file received data is written to until the file is complete. Empty for sent files.
+    temp_path: String,
+    /// Chunks which have to be transmitted again.
+    retransmit: Vec<u64>,
+    /// Failed hash checks of the chunks which were not written yet, by chunk number.
+    retries: HashMap<u64, u32>,
+    /// Whether a manifest is written once the received file is complete.
+    manifest: bool,
+    /// How often the received file is received again from the start if it fails.
+    restarts: u8,
+    /// Hash of the file the sender confirmed after its last chunk, see `create_complete`.
+    confirmed_hash: Option<String>,
+    /// Time since all chunks of the received file arrived without a confirmation, see `CONFIRMATION_TIMEOUT`.
+    awaiting_confirmation: Option<Instant>,
+    /// Hashes of the chunks of a sent file taken when it was offered, see `Client::set_verify_source`.
+    source_hashes: Option<Arc<ChunkHashes>>,
+    /// Whether the output path of a received file was reserved with an empty file, see `resolve_output_path`.
+    reserved: bool,
+    /// Collects the chunk hashes of a received file whose offer carries a Merkle root.
+    merkle: Option<MerkleVerifier>,
 }
 
 impl ActiveFile {
     fn from_file(file: File) -> Self {
-        let stop = get_chunk_count(file.size);
+        let stop = get_chunk_count_with_size(file.size, file.chunk_size);
+        let temp_path = get_temp_path(&file.path, None);
         Self {
             file,
             start: 1,
             stop,
             current: 1,
+            temp_path,
+            retransmit: vec![],
+            retries: HashMap::new(),
+            manifest: false,
+            restarts: 0,
+            confirmed_hash: None,
+            awaiting_confirmation: None,
+            source_hashes: None,
+            reserved: false,
+            merkle: None,
         }
     }
-}
 
-/// Function which handles writing to the peer.
-///
-/// # Arguments
-///
-/// * `dropper` - A boolean which is used to signal the thread to stop.
-/// * `app_handle` - A handle to the tauri application.
-/// * `writer` - A `ClientWriter` which is used to write to the peer.
-/// * `command_receiver` - A receiver for the commands to handle.
-fn write_thread<W: ClientWriter>(
+    /// Counts the bytes of the chunks which were transferred.
+    /// Chunks which have to be transmitted again are not counted, the last chunk may be shorter than the others.
+    fn bytes_transferred(&self) -> u64 {
+        let chunks = match self.temp_path.is_empty() {
+            // a sent file points at the next chunk to send
+            true => self.current.saturating_sub(1),
+            // a received file points at the highest received chunk
+            false => self.current,
+        }
+        .min(self.stop);
+
+        let retransmit: u64 = self
+            .retransmit
+            .iter()
+            .filter(|chunk_num| **chunk_num <= chunks)
+            .map(|chunk_num| get_chunk_len_with_size(self.file.size, *chunk_num, self.file.chunk_size))
+            .sum();
+
+        get_chunks_len_with_size(self.file.size, chunks, self.file.chunk_size).saturating_sub(retransmit)
+    }
+
+    /// Continues receiving a file of which some chunks are on disk already, see `resume_from_disk`.
+    ///
+    /// # Arguments
+    ///
+    /// * `missing` - The positions of the chunks which were not received yet, in ascending order.
+    ///
+    /// # Returns
+    ///
+    /// Returns the ranges of chunks to request. If no chunk is missing, the last chunk is requested again,
+    /// because the sender confirms the file only after it sent a chunk.
+    fn resume(&mut self, missing: &[u64]) -> Vec<(u64, u64)> {
+        let mut highest = self.stop;
+        for pos in missing.iter().rev() {
+            if *pos != highest {
+                break;
+            }
+            highest -= 1;
+        }
+
+        self.current = highest;
+        self.retransmit = missing.to_vec();
+        if self.retransmit.is_empty() {
+            self.retransmit.push(self.stop);
+        }
+
+        ranges_from_positions(&self.retransmit)
+    }
+
+    /// Receives the file again from its first chunk, if restarts are left.
+    ///
+    /// # Returns
+    ///
+    /// Returns `false` if no restart is left.
+    fn restart(&mut self) -> bool {
+        if self.restarts == 0 {
+            return false;
+        }
+
+        self.restarts -= 1;
+        self.current = self.start;
+        self.retransmit.clear();
+        self.retries.clear();
+        self.awaiting_confirmation = None;
+        return true;
+    }
+}
+
+/// Result of receiving a single chunk.
+#[derive(Debug, PartialEq)]
+enum ChunkOutcome {
+    /// The chunk was written. Contains the path of the log file.
+    Written(String),
+    /// The chunk failed its hash check and has to be requested again.
+    Rerequest(u64),
+    /// The file received too many corrupt chunks.
+    Corrupted,
+}
+
+/// Verifies a received chunk against its chunk hash and writes it to the file.
+///
+/// # Arguments
+///
+/// * `file` - The file the chunk belongs to.
+/// * `writer` - The writer of the file.
+/// * `header_data` - The header of the chunk.
+/// * `data_vector` - The data of the chunk.
+///
+/// # Returns
+///
+/// Returns the `ChunkOutcome` or an `Err` containing a `ClientError` if the chunk can't be written.
+fn receive_chunk(
+    file: &mut ActiveFile,
+    writer: &mut BatchedChunkWriter,
+    header_data: &HeaderData,
+    data_vector: &Vec<u8>,
+) -> Result<ChunkOutcome, ClientError> {
+    let chunk_num = header_data.chunk_pos;
+
+    if !check_chunk_hash(&header_data.chunk_hash, &header_data.chunk_hash_alg, data_vector) {
+        let retries = file.retries.entry(chunk_num).or_insert(0);
+        if *retries >= MAX_CHUNK_RETRIES {
+            return Ok(ChunkOutcome::Corrupted);
+        }
+        *retries += 1;
+        if !file.retransmit.contains(&chunk_num) {
+            file.retransmit.push(chunk_num);
+        }
+        return Ok(ChunkOutcome::Rerequest(chunk_num));
+    }
+
+    let log_path = writer.write_verified(header_data, data_vector)?;
+    if let Some(merkle) = file.merkle.as_mut() {
+        if merkle.add_unproven_chunk(chunk_num, data_vector).is_err() {
+            return Ok(ChunkOutcome::Corrupted);
+        }
+    }
+
+    file.retransmit.retain(|num| *num != chunk_num);
+    file.retries.remove(&chunk_num);
+    if chunk_num > file.current {
+        file.current = chunk_num;
+    }
+
+    return Ok(ChunkOutcome::Written(log_path));
+}
+
+/// Result of checking a completely received file, see `verify_received_file`.
+#[derive(Debug, PartialEq)]
+enum Verification {
+    /// Chunks of the file are still missing.
+    Incomplete,
+    /// The file matches the confirmed hash, it can be moved to its path.
+    Verified,
+    /// The received data does not match the confirmed hash, receiving it again may help.
+    Mismatch,
+    /// The sender confirmed a hash other than the offered one, the data it sent is not the offered file.
+    Unconfirmed,
+}
+
+/// Checks a received file against the hash the sender confirmed after its last chunk.
+/// The file is hashed again, so a file is only accepted if it matches the data the sender actually read,
+/// not only the hash of the offer.
+///
+/// # Arguments
+///
+/// * `file` - The received file. Its last batch has to be written.
+/// * `confirmed_hash` - The hash of the sent file, see `create_complete`.
+///
+/// # Returns
+///
+/// Returns the `Verification` of the file.
+fn verify_received_file(file: &ActiveFile, confirmed_hash: &str) -> Verification {
+    if !confirmed_hash.eq_ignore_ascii_case(&file.file.hash) {
+        return Verification::Unconfirmed;
+    }
+
+    let log_path = format!("{}.{}", file.temp_path, LOGFILE_EXTENSION);
+    match validate_file(&log_path, &file.file.hash) {
+        Ok((0, 0)) => {}
+        Ok(_) => return Verification::Incomplete,
+        Err(_) => return Verification::Mismatch,
+    }
+
+    if let Some(merkle) = &file.merkle {
+        if !merkle.is_complete() {
+            return Verification::Mismatch;
+        }
+    }
+
+    match verify_temp_file(&file.temp_path, confirmed_hash, &file.file.hash_type) {
+        Ok(_) => Verification::Verified,
+        Err(_) => Verification::Mismatch,
+    }
+}
+
+/// Progress of an offer group, see `Groups::hold`.
+enum GroupProgress {
+    /// Files of the group are still missing.
+    Waiting,
+    /// Every file of the group is verified, the files can be moved to their paths.
+    Complete(Vec<ActiveFile>),
+    /// A file of the group failed before, the files are never moved to their paths.
+    Failed,
+}
+
+/// Received files of offer groups, which are held until every file of their group is verified.
+#[derive(Default)]
+struct Groups {
+    /// Verified files of each group which are not moved to their paths yet, with the time the last one was held.
+    held: HashMap<String, (Instant, Vec<ActiveFile>)>,
+    /// Groups of which a file failed or was stopped, with the number of their files which did not end yet.
+    /// A group is forgotten once all of its files ended.
+    failed: HashMap<String, u64>,
+}
+
+impl Groups {
+    /// Holds a verified file until every file of its group is verified.
+    /// A file without group is complete on its own.
+    fn hold(&mut self, file: ActiveFile) -> GroupProgress {
+        let (id, count) = match file.file.group.clone() {
+            Some(group) => group,
+            None => return GroupProgress::Complete(vec![file]),
+        };
+
+        if self.failed.contains_key(&id) {
+            self.end_failed(&id);
+            return GroupProgress::Failed;
+        }
+
+        let (since, held) = self.held.entry(id.clone()).or_insert_with(|| (Instant::now(), vec![]));
+        *since = Instant::now();
+        held.push(file);
+        if (held.len() as u64) < count {
+            return GroupProgress::Waiting;
+        }
+
+        GroupProgress::Complete(self.held.remove(&id).map(|(_, held)| held).unwrap_or_default())
+    }
+
+    /// Marks the group of a file as failed. The file ends with this call.
+    ///
+    /// # Returns
+    ///
+    /// Returns the held files of the group, which are not moved to their paths.
+    fn fail(&mut self, file: &File) -> Vec<ActiveFile> {
+        let (id, count) = match &file.group {
+            Some(group) => group,
+            None => return vec![],
+        };
+
+        if self.failed.contains_key(id) {
+            self.end_failed(id);
+            return vec![];
+        }
+
+        let held = self.held.remove(id).map(|(_, held)| held).unwrap_or_default();
+        let remaining = count.saturating_sub(held.len() as u64 + 1);
+        if remaining > 0 {
+            self.failed.insert(id.clone(), remaining);
+        }
+        held
+    }
+
+    /// Fails the groups which held files for at least `timeout` while none of their other files is left.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The time since the last file of a group was held.
+    /// * `live` - The ids of the groups with files which are still transferred or pending.
+    ///
+    /// # Returns
+    ///
+    /// Returns the held files of the failed groups, which are not moved to their paths.
+    fn expire(&mut self, timeout: Duration, live: &HashSet<&str>) -> Vec<ActiveFile> {
+        let expired: Vec<String> = self
+            .held
+            .iter()
+            .filter(|(id, (since, _))| since.elapsed() >= timeout && !live.contains(id.as_str()))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        let mut files = vec![];
+        for id in expired {
+            let held = self.held.remove(&id).map(|(_, held)| held).unwrap_or_default();
+            // files of the group which were never offered may still arrive
+            let count = held.first().and_then(|file| file.file.group.as_ref()).map_or(0, |(_, count)| *count);
+            let remaining = count.saturating_sub(held.len() as u64);
+            if remaining > 0 {
+                self.failed.insert(id, remaining);
+            }
+            files.extend(held);
+        }
+        files
+    }
+
+    /// Checks if the group of a file failed.
+    fn has_failed(&self, file: &File) -> bool {
+        match &file.group {
+            Some((id, _)) => self.failed.contains_key(id),
+            None => false,
+        }
+    }
+
+    /// Counts a file of a failed group as ended and forgets the group once all of its files ended.
+    fn end_failed(&mut self, id: &str) {
+        if let Some(remaining) = self.failed.get_mut(id) {
+            *remaining -= 1;
+            if *remaining == 0 {
+                self.failed.remove(id);
+            }
+        }
+    }
+}
+
+/// Fails the group of a file, the held files of the group are reported as stopped.
+fn fail_group(
+    groups: &mut Groups,
+    file: &File,
+    app_handle: &AppHandle<Wry>,
+    transfers: &Transfers,
+) -> Result<(), ClientError> {
+    for held in groups.fail(file) {
+        println!("[READER] : discarding {} of failed group", held.file.hash);
+        transfers.publish(app_handle, held.file, FileState::Stopped, 1.0, false)?;
+    }
+    Ok(())
+}
+
+/// Moves verified received files to their paths, all or none of them.
+fn commit_files(files: &[ActiveFile]) -> Result<(), RError> {
+    let paths: Vec<(&str, &str)> = files
+        .iter()
+        .map(|file| (file.temp_path.as_str(), file.file.path.as_str()))
+        .collect();
+    move_temp_files(&paths)
+}
+
+/// Finishes a received file once all chunks arrived and the sender confirmed the hash of the file.
+///
+/// # Arguments
+///
+/// * `file` - The received file.
+/// * `writers` - The writers of the received files. The writer of the file is flushed and removed.
+/// * `groups` - The offer groups. A file of a group is held until the whole group is verified.
+/// * `app_handle` - A handle to the tauri application.
+/// * `command_sender` - A writer for the write commands, used to receive the file again.
+/// * `transfers` - The snapshot of all transfers.
+///
+/// # Returns
+///
+/// Returns `true` if the file completed, failed or is held and has to be removed from the active files.
+fn finish_received_file(
+    file: &mut ActiveFile,
+    writers: &mut HashMap<String, BatchedChunkWriter>,
+    groups: &mut Groups,
+    app_handle: &AppHandle<Wry>,
+    command_sender: &Sender<WriteCommand>,
+    transfers: &Transfers,
+) -> Result<bool, ClientError> {
+    if file.current != file.stop || !file.retransmit.is_empty() {
+        return Ok(false);
+    }
+
+    let confirmed_hash = match file.confirmed_hash.clone() {
+        Some(confirmed_hash) => confirmed_hash,
+        None => {
+            println!("[READER] : awaiting confirmation {}", file.file.hash);
+            file.awaiting_confirmation.get_or_insert_with(Instant::now);
+            return Ok(false);
+        }
+    };
+
+    // the file is verified from disk, so its last batch and log run are written first
+    if let Some(writer) = writers.remove(&file.file.hash) {
+        writer.close()?;
+    }
+
+    match verify_received_file(file, &confirmed_hash) {
+        Verification::Incomplete => return Ok(false),
+        Verification::Verified => {
+            let files = match groups.hold(file.clone()) {
+                GroupProgress::Complete(files) => files,
+                GroupProgress::Waiting => {
+                    println!("[READER] : holding {} until its group is complete", file.file.hash);
+                    return Ok(true);
+                }
+                GroupProgress::Failed => {
+                    println!("[READER] : discarding {} of failed group", file.file.hash);
+                    transfers.publish(app_handle, file.file.clone(), FileState::Stopped, 1.0, false)?;
+                    return Ok(true);
+                }
+            };
+
+            if let Err(err) = commit_files(&files) {
+                println!("[READER] : can't move {} {}", file.file.hash, err);
+                for failed in files {
+                    transfers.publish(app_handle, failed.file, FileState::Corrupted, 1.0, false)?;
+                }
+                return Ok(true);
+            }
+
+            for completed in files {
+                if completed.manifest {
+                    if let Err(err) = write_manifest_file(&completed.file.path, &completed.file.to_offer()) {
+                        println!("[READER] : can't write manifest {} {}", completed.file.hash, err);
+                    }
+                }
+                transfers.publish(app_handle, completed.file, FileState::Completed, 1.0, false)?;
+            }
+            return Ok(true);
+        }
+        Verification::Mismatch => {
+            if file.restart() {
+                println!("[READER] : retrying {}", file.file.hash);
+                transfers.publish(
+                    app_handle,
+                    file.file.clone(),
+                    FileState::Transferring,
+                    0.0,
+                    false,
+                )?;
+                command_sender.send(WriteCommand::Request(file.clone()))?;
+                return Ok(false);
+            }
+        }
+        Verification::Unconfirmed => {
+            println!(
+                "[READER] : confirmed hash {} does not match offer {}",
+                confirmed_hash, file.file.hash
+            );
+        }
+    }
+
+    transfers.publish(app_handle, file.file.clone(), FileState::Corrupted, 1.0, false)?;
+    fail_group(groups, &file.file, app_handle, transfers)?;
+    return Ok(true);
+}
+
+/// An offer sent to the peer which was not requested yet.
+struct PendingOffer {
+    file: File,
+    sent: Instant,
+    unanswered: bool,
+    source_hashes: Option<Arc<ChunkHashes>>,
+}
+
+impl PendingOffer {
+    fn new(file: File) -> Self {
+        Self {
+            file,
+            sent: Instant::now(),
+            unanswered: false,
+            source_hashes: None,
+        }
+    }
+}
+
+/// Marks all offers which were sent longer than `timeout` ago as unanswered.
+///
+/// # Returns
+///
+/// Returns the files of the offers which became unanswered with this call.
+fn mark_unanswered_offers(offers: &mut Vec<PendingOffer>, now: Instant, timeout: Duration) -> Vec<File> {
+    let mut unanswered = Vec::new();
+
+    for offer in offers.iter_mut() {
+        if !offer.unanswered && now.duration_since(offer.sent) >= timeout {
+            offer.unanswered = true;
+            unanswered.push(offer.file.clone());
+        }
+    }
+
+    unanswered
+}
+
+/// Removes the offer with the given hash.
+///
+/// # Returns
+///
+/// Returns the removed offer or `None` if there is no offer with this hash.
+fn remove_offer(offers: &mut Vec<PendingOffer>, hash: &str) -> Option<PendingOffer> {
+    offers
+        .iter()
+        .position(|of| of.file.hash == hash)
+        .map(|index| offers.swap_remove(index))
+}
+
+/// Takes the next chunks of a sent file, chunks which have to be transmitted again first.
+///
+/// # Arguments
+///
+/// * `file` - The sent file. `current` is advanced past the returned new chunks.
+/// * `depth` - The maximum number of chunks.
+///
+/// # Returns
+///
+/// Returns the numbers of the chunks to send in order.
+fn next_chunks(file: &mut ActiveFile, depth: usize) -> Vec<u64> {
+    let mut chunks = Vec::with_capacity(depth);
+
+    while chunks.len() < depth {
+        match file.retransmit.pop() {
+            Some(chunk_num) => chunks.push(chunk_num),
+            None if file.current > file.stop => break,
+            None => {
+                chunks.push(file.current);
+                file.current += 1;
+            }
+        }
+    }
+
+    chunks
+}
+
+/// Derives the tag the chunks of a sent file are written with, see `ClientWriter::write_tagged`.
+///
+/// # Arguments
+///
+/// * `hash` - The hash of the file.
+///
+/// # Returns
+///
+/// Returns the tag, equal for equal hashes.
+fn transfer_tag(hash: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Reads chunks of a sent file, checking them against the hashes taken when it was offered if there are any.
+///
+/// # Arguments
+///
+/// * `file` - The sent file.
+/// * `source` - The sent file, kept open for the whole transfer.
+/// * `chunks` - The numbers of the chunks to read.
+/// * `pool` - The pool the buffers are taken from.
+///
+/// # Returns
+///
+/// Returns the data vectors of the chunks in the order of `chunks`,
+/// or an `Err` of kind `FileChangedDuringTransfer` if a chunk changed since the offer. No buffer is kept in this case.
+fn read_chunks(
+    file: &ActiveFile,
+    source: &mut ChunkSource,
+    chunks: &[u64],
+    pool: &mut ChunkBufferPool,
+) -> Result<Vec<Vec<u8>>, ClientError> {
+    let mut data_vecs = Vec::with_capacity(chunks.len());
+    for chunk_num in chunks.iter() {
+        let result = match &file.source_hashes {
+            Some(hashes) => create_data_vec_verified(
+                source,
+                *chunk_num,
+                &file.file.hash,
+                &file.file.hash_type,
+                hashes,
+                pool,
+            ),
+            None => create_data_vec_pooled(source, *chunk_num, &file.file.hash, &file.file.hash_type, pool),
+        };
+
+        match result {
+            Ok(data_vec) => data_vecs.push(data_vec),
+            Err(err) => {
+                for data_vec in data_vecs {
+                    pool.put(data_vec);
+                }
+                return Err(match err.kind() {
+                    RErrorKind::FileChangedDuringTransfer => {
+                        ClientError::new(ClientErrorKind::FileChangedDuringTransfer)
+                    }
+                    _ => ClientError::new(ClientErrorKind::IOError),
+                });
+            }
+        }
+    }
+
+    Ok(data_vecs)
+}
+
+/// Hashes a completely sent file on its own thread, so the other transfers go on meanwhile.
+/// The file is hashed again with the algorithm of its offer, so the receiver checks the data which was actually sent.
+///
+/// # Arguments
+///
+/// * `file` - The sent file.
+/// * `confirmations` - Receives the hash of the offer and the hash of the sent file, `None` if the file can't be read.
+fn confirm_sent_file(file: &File, confirmations: &Sender<(String, Option<String>)>) {
+    let hash = file.hash.clone();
+    let path = file.path.clone();
+    let hash_type = file.hash_type;
+    let confirmations = confirmations.clone();
+
+    thread::spawn(move || {
+        let file_hash = std::fs::File::open(&path)
+            .and_then(|source| get_file_hash(&source, BUFFER_SIZE, &hash_type, 0))
+            .ok();
+        // the write thread may have exited meanwhile
+        let _ = confirmations.send((hash, file_hash));
+    });
+}
+
+/// Function which handles writing to the peer.
+///
+/// # Arguments
+///
+/// * `dropper` - A boolean which is used to signal the thread to stop.
+/// * `app_handle` - A handle to the tauri application.
+/// * `writer` - A `ClientWriter` which is used to write to the peer.
+/// * `command_receiver` - A receiver for the commands to handle.
+/// * `transfers` - The snapshot of all transfers, which is updated with every file state.
+/// * `status` - The status shared with the read thread. The thread stops once the read thread exited.
+fn write_thread<W: ClientWriter>(
     dropper: Arc<RwLock<bool>>,
     app_handle: AppHandle<Wry>,
     writer: Arc<Mutex<W>>,
     command_receiver: mpsc::Receiver<WriteCommand>,
+    transfers: Transfers,
+    status: ThreadStatus,
 ) -> Result<(), ClientError> {
     let mut writer = writer.lock()?;
     let mut files = Vec::<ActiveFile>::new();
-    let mut offers = Vec::<File>::new();
+    let mut offers = Vec::<PendingOffer>::new();
+    let mut suspended = false;
+    let mut pipeline_depth = DEFAULT_PIPELINE_DEPTH;
+    let mut buffer_pool = ChunkBufferPool::new(MAX_PIPELINE_DEPTH);
+    // the sent files stay open until they are done, by hash
+    let mut sources = HashMap::<String, ChunkSource>::new();
+    let mut rates = TransferRates::new(Instant::now());
+    let mut last_summary: Option<TransferSummary> = None;
+    let (confirm_sender, confirm_receiver) = mpsc::channel::<(String, Option<String>)>();
 
     loop {
         {
@@ -564,19 +2632,31 @@ fn write_thread<W: ClientWriter>(
                 return Ok(());
             }
         }
+        status.check_sibling()?;
 
-        match command_receiver.try_recv() {
-            Ok(c) => match c {
+        // all pending commands are handled first, so a stop takes effect before more chunks are queued.
+        // control messages are written with priority and overtake the chunks already queued by the transport
+        while let Ok(c) = command_receiver.try_recv() {
+            match c {
                 WriteCommand::Request(file) => {
                     let vec = create_order_byte_vec(file.start, file.stop, &file.file.hash)?;
                     println!("[WRITER] SENT: request {}", file.file.hash);
-                    writer.write(&vec)?;
+                    writer.write_priority(&vec, Priority::High)?;
                 }
-                WriteCommand::Offer(file) => {
+                WriteCommand::RequestRanges(hash, ranges) => {
+                    let vec = create_order_from_ranges(&ranges, &hash)
+                        .map_err(|_| ClientError::new(ClientErrorKind::DataCorruptionError))?;
+                    println!("[WRITER] SENT: request {} ranges of {}", ranges.len(), hash);
+                    writer.write_priority(&vec, Priority::High)?;
+                }
+                WriteCommand::Offer(file, source_hashes) => {
                     println!("[WRITER] SENT: offer {}", file.hash);
-                    let vec = create_offer_byte_msg(&file.hash, file.size, &file.path)?;
-                    offers.push(file);
-                    writer.write(&vec)?;
+                    // the offer is built from the file, so it names the algorithm of its hash
+                    let vec = append_header(file.to_offer().to_offer_string().into_bytes(), HeaderByte::SendOffer);
+                    let mut offer = PendingOffer::new(file);
+                    offer.source_hashes = source_hashes;
+                    offers.push(offer);
+                    writer.write_priority(&vec, Priority::High)?;
                 }
                 WriteCommand::StopSend(hash) => {
                     match files.iter().position(|wf| wf.file.hash == hash) {
@@ -585,32 +2665,72 @@ fn write_thread<W: ClientWriter>(
                         }
                         Some(index) => {
                             println!("[WRITER]   OP: stop send {}", hash);
-                            files.swap_remove(index);
+                            if writer.discard_tagged(transfer_tag(&hash)) {
+                                println!("[WRITER]   OP: discard queued {}", hash);
+                            }
+                            let file = files.swap_remove(index);
+                            if file.current <= file.stop {
+                                transfers.publish(
+                                    &app_handle,
+                                    file.file,
+                                    FileState::Stopped,
+                                    0.0,
+                                    true,
+                                )?;
+                            }
                         }
                     }
                 }
-                WriteCommand::Send(hash, start, stop) => {
-                    match offers.iter().position(|of| of.hash == hash) {
-                        None => {
-                            println!("[WRITER]   OP: send unknown {}", hash);
-                        }
+                WriteCommand::Send(hash, ranges) => {
+                    let (start, stop) = match ranges.first() {
+                        Some(range) => *range,
+                        None => continue,
+                    };
+                    match offers.iter().position(|of| of.file.hash == hash) {
+                        None => match files.iter_mut().find(|wf| wf.file.hash == hash) {
+                            None => {
+                                println!("[WRITER]   OP: send unknown {}", hash);
+                            }
+                            Some(file) => {
+                                // the peer requests chunks of an active file again
+                                println!("[WRITER]   OP: resend {} with {:?}", hash, ranges);
+                                for (start, stop) in ranges.iter().rev() {
+                                    file.retransmit.extend((*start..=*stop).rev());
+                                }
+                            }
+                        },
                         Some(index) => {
-                            println!("[WRITER]   OP: send {} with {} : {}", hash, start, stop);
+                            println!("[WRITER]   OP: send {} with {:?}", hash, ranges);
                             if stop != 0 {
-                                let file = offers.swap_remove(index);
-                                send_file_state(
+                                let offer = offers.swap_remove(index);
+                                let file = offer.file;
+                                transfers.publish(
                                     &app_handle,
                                     file.clone(),
                                     FileState::Transferring,
                                     0.0,
                                     true,
                                 )?;
-                                let active_file = ActiveFile {
+                                let mut active_file = ActiveFile {
                                     file,
                                     stop,
                                     start,
                                     current: 0,
+                                    temp_path: String::new(),
+                                    retransmit: vec![],
+                                    retries: HashMap::new(),
+                                    manifest: false,
+                                    restarts: 0,
+                                    confirmed_hash: None,
+                                    awaiting_confirmation: None,
+                                    source_hashes: offer.source_hashes,
+                                    reserved: false,
+                                    merkle: None,
                                 };
+                                // further ranges, e.g. of a resumed file, are sent like chunks requested again
+                                for (start, stop) in ranges[1..].iter().rev() {
+                                    active_file.retransmit.extend((*start..=*stop).rev());
+                                }
                                 files.push(active_file);
                             }
                         }
@@ -619,64 +2739,1207 @@ fn write_thread<W: ClientWriter>(
                 WriteCommand::Stop(hash) => {
                     let vec = create_stop(&hash)?;
                     println!("[WRITER] SENT: stop {}", hash);
-                    writer.write(&vec)?;
+                    writer.write_priority(&vec, Priority::High)?;
                 }
-            },
-            Err(_) => {}
-        };
+                WriteCommand::Rescind(hash) => match remove_offer(&mut offers, &hash) {
+                    None => {
+                        println!("[WRITER]   OP: rescind unknown {}", hash);
+                    }
+                    Some(offer) => {
+                        let vec = create_stop(&hash)?;
+                        println!("[WRITER] SENT: rescind {}", hash);
+                        writer.write_priority(&vec, Priority::High)?;
+                        transfers.publish(&app_handle, offer.file, FileState::Stopped, 0.0, true)?;
+                    }
+                },
+                WriteCommand::Suspend => {
+                    println!("[WRITER]   OP: suspend");
+                    suspended = true;
+                    writer.suspend();
+                }
+                WriteCommand::Resume => {
+                    println!("[WRITER]   OP: resume");
+                    suspended = false;
+                    writer.resume();
+                }
+                WriteCommand::PipelineDepth(depth) => {
+                    println!("[WRITER]   OP: pipeline depth {}", depth);
+                    pipeline_depth = depth;
+                }
+            }
+        }
 
-        let mut marked_for_remove = Vec::<String>::new();
+        for file in mark_unanswered_offers(&mut offers, Instant::now(), OFFER_TIMEOUT) {
+            println!("[WRITER]   OP: offer unanswered {}", file.hash);
+            transfers.publish(&app_handle, file, FileState::Unanswered, 0.0, true)?;
+        }
 
-        for i in 0..files.len() {
-            if i >= files.len() {
-                break;
+        if rates.sampled.elapsed() >= SUMMARY_INTERVAL {
+            let summary = transfers.summary(&mut rates, Instant::now());
+            // an idle client does not repeat the same summary
+            if last_summary.as_ref() != Some(&summary) {
+                send_transfer_summary(&app_handle, &summary)?;
+                last_summary = Some(summary);
             }
+        }
+
+        if suspended {
+            // no data is queued, the transport would only buffer it
+            sleep(Duration::from_millis(5));
+            continue;
+        }
+
+        let mut sent_data = false;
+        let mut changed = Vec::new();
+
+        while let Ok((hash, file_hash)) = confirm_receiver.try_recv() {
+            // the file may have been stopped while it was hashed
+            let file = match files.iter().find(|wf| wf.file.hash == hash) {
+                Some(file) => file,
+                None => continue,
+            };
 
-            let mut file = &mut files[i];
+            match file_hash {
+                Some(file_hash) => {
+                    let vec = create_complete(&hash, &file_hash)?;
+                    println!("[WRITER] SENT: complete {}", hash);
+                    // the completion must not overtake the chunks it confirms
+                    writer.write(&vec)?;
+                    transfers.publish(&app_handle, file.file.clone(), FileState::Completed, 1.0, true)?;
+                }
+                None => {
+                    // the source was moved or can't be read any more, only this file fails
+                    println!("[WRITER]   OP: can't confirm {}", hash);
+                    changed.push(hash);
+                }
+            }
+        }
 
+        // completed files are kept until the peer stops them, so corrupt chunks can be sent again
+        for file in files.iter_mut() {
             if file.current < 1 {
                 file.current = 1;
             }
 
-            let data_vec = create_data_vec(&file.file.path, file.current, &file.file.hash)
-                .map_err(|_| ClientError::new(ClientErrorKind::IOError))?;
+            let first_new = file.current;
+            let chunks = next_chunks(file, pipeline_depth);
+            if chunks.is_empty() {
+                continue;
+            }
 
-            match writer.write(&data_vec) {
-                Ok(_) => {
-                    //println!("[WRITER] SENT: data {}", file.file.hash);
-                    let percent = file.current as f32 / file.stop as f32;
-                    send_file_state(
-                        &app_handle,
-                        file.file.clone(),
-                        FileState::Transferring,
-                        percent,
-                        true,
-                    )?;
-                }
-                Err(_err) => {
-                    send_disconnect(&app_handle)?;
-                    return Err(ClientError::new(ClientErrorKind::SocketClosed));
+            let source = match sources.entry(file.file.hash.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match ChunkSource::open(&file.file.path) {
+                    Ok(mut source) => {
+                        source.set_chunk_size(file.file.chunk_size);
+                        entry.insert(source)
+                    }
+                    Err(err) => {
+                        // the source was moved or can't be read any more, only this file fails
+                        println!("[WRITER]   OP: can't open {} {}", file.file.hash, err);
+                        changed.push(file.file.hash.clone());
+                        continue;
+                    }
+                },
+            };
+
+            // all chunks are read first, so reading the disk does not wait for the transport
+            let data_vecs = match read_chunks(file, source, &chunks, &mut buffer_pool) {
+                Ok(data_vecs) => data_vecs,
+                Err(err) if matches!(err.kind(), ClientErrorKind::FileChangedDuringTransfer) => {
+                    println!("[WRITER]   OP: source changed {}", file.file.hash);
+                    changed.push(file.file.hash.clone());
+                    continue;
                 }
+                Err(err) => return Err(err),
             };
 
-            if file.current == file.stop {
-                send_file_state(
-                    &app_handle,
-                    file.file.clone(),
-                    FileState::Completed,
-                    1.0,
-                    true,
-                )?;
-                marked_for_remove.push(file.file.hash.clone());
-            } else {
-                file.current += 1;
+            for (chunk_num, data_vec) in chunks.iter().zip(data_vecs) {
+                let result = writer.write_tagged(&data_vec, transfer_tag(&file.file.hash));
+                buffer_pool.put(data_vec);
+                if let Err(_err) = result {
+                    return Err(ClientError::new(ClientErrorKind::Disconnected(
+                        _err.kind().disconnect_reason(),
+                    )));
+                }
+
+                if *chunk_num < first_new {
+                    println!("[WRITER] SENT: resend {} chunk {}", file.file.hash, chunk_num);
+                }
+            }
+            sent_data = true;
+
+            if file.current == first_new {
+                continue;
+            }
+
+            //println!("[WRITER] SENT: data {}", file.file.hash);
+            let sent = file.current - 1;
+            let percent = sent as f32 / file.stop as f32;
+            transfers.publish(
+                &app_handle,
+                file.file.clone(),
+                FileState::Transferring,
+                percent,
+                true,
+            )?;
+            transfers.record_bytes(&file.file.hash, true, file.bytes_transferred());
+
+            if sent == file.stop {
+                confirm_sent_file(&file.file, &confirm_sender);
+            }
+        }
+
+        // a changed file can't be completed any more, the peer stops receiving it
+        for hash in changed {
+            if let Some(index) = files.iter().position(|wf| wf.file.hash == hash) {
+                let file = files.swap_remove(index);
+                writer.discard_tagged(transfer_tag(&hash));
+                writer.write_priority(&create_stop(&hash)?, Priority::High)?;
+                println!("[WRITER] SENT: stop changed {}", hash);
+                transfers.publish(&app_handle, file.file, FileState::Changed, 0.0, true)?;
             }
         }
 
-        if files.len() == 0 {
+        sources.retain(|hash, _| files.iter().any(|wf| wf.file.hash == *hash));
+
+        if !sent_data {
             sleep(Duration::from_millis(5));
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chunk::file::file::create_data_vec;
+    use chunk::fixture::fixture::{test_dir, SourceFixture};
+    use chunk::general::general::CHUNK_SIZE;
+    use p2p::protocol::{Connection, Waiting};
+    use std::net::Ipv6Addr;
+
+    fn offer(hash: &str, sent: Instant) -> PendingOffer {
+        let mut offer = PendingOffer::new(File::new(
+            hash.to_string(),
+            "".to_string(),
+            "file.txt".to_string(),
+            1,
+        ));
+        offer.sent = sent;
+        offer
+    }
+
+    #[test]
+    fn test_unanswered_offers_are_reported_once() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(60);
+        let mut offers = vec![
+            offer("aa", now - Duration::from_secs(61)),
+            offer("bb", now - Duration::from_secs(10)),
+        ];
+
+        let unanswered = mark_unanswered_offers(&mut offers, now, timeout);
+        assert_eq!(unanswered.len(), 1);
+        assert_eq!(unanswered[0].hash, "aa");
+
+        assert!(mark_unanswered_offers(&mut offers, now, timeout).is_empty());
+
+        let later = now + Duration::from_secs(55);
+        let unanswered = mark_unanswered_offers(&mut offers, later, timeout);
+        assert_eq!(unanswered.len(), 1);
+        assert_eq!(unanswered[0].hash, "bb");
+    }
+
+    #[test]
+    fn test_rescind_offer() {
+        let now = Instant::now();
+        let mut offers = vec![offer("aa", now), offer("bb", now)];
+
+        assert!(remove_offer(&mut offers, "cc").is_none());
+        assert_eq!(remove_offer(&mut offers, "aa").unwrap().file.hash, "aa");
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].file.hash, "bb");
+        assert!(remove_offer(&mut offers, "aa").is_none());
+    }
+
+    fn transfer(hash: &str, transferred: u64) -> Transfer {
+        Transfer {
+            hash: hash.to_string(),
+            size: 1000,
+            state: FileState::Transferring,
+            percent: transferred as f32 / 1000.0,
+            transferred,
+            is_sender: true,
+            started: Some(Instant::now()),
+            last_event: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_transfer_rates_are_summed() {
+        let start = Instant::now();
+        let mut rates = TransferRates::new(start);
+
+        // transfers seen for the first time only seed the measurement
+        let transfers = [transfer("aa", 100), transfer("bb", 200), transfer("cc", 300)];
+        assert_eq!(rates.sample(&transfers, start + Duration::from_secs(1)), 0.0);
+
+        // 100, 200 and 300 bytes within one second
+        let transfers = [transfer("aa", 200), transfer("bb", 400), transfer("cc", 600)];
+        let total = rates.sample(&transfers, start + Duration::from_secs(2));
+        assert!((total - 600.0).abs() < 1.0);
+
+        // half a second later only one transfer progressed, a resumed transfer starts at its offset
+        let transfers = [transfer("aa", 200), transfer("bb", 400), transfer("cc", 750), transfer("dd", 900)];
+        let total = rates.sample(&transfers, start + Duration::from_millis(2500));
+        assert!((total - 300.0).abs() < 1.0);
+
+        // a restarted transfer does not count negative progress
+        let transfers = [transfer("aa", 200), transfer("bb", 0), transfer("cc", 750), transfer("dd", 900)];
+        assert_eq!(rates.sample(&transfers, start + Duration::from_secs(3)), 0.0);
+
+        let transfers = Transfers::default();
+        transfers.update("aa", 1000, FileState::Transferring, 0.5, false);
+        transfers.record_bytes("aa", false, 500);
+        transfers.update("aa", 1000, FileState::Transferring, 0.0, false);
+        assert_eq!(transfers.snapshot().pop().unwrap().transferred, 0);
+    }
+
+    #[test]
+    fn test_transfer_summary_counts_files() {
+        let transfers = Transfers::default();
+        let mut rates = TransferRates::new(Instant::now());
+
+        transfers.update("aa", 1000, FileState::Transferring, 0.5, true);
+        transfers.update("bb", 1000, FileState::Pending, 0.0, false);
+        transfers.update("cc", 1000, FileState::Transferring, 0.5, false);
+        transfers.update("cc", 1000, FileState::Completed, 1.0, false);
+        transfers.update("dd", 1000, FileState::Stopped, 0.0, true);
+
+        let summary = transfers.summary(&mut rates, Instant::now());
+        assert_eq!(summary.completed, 1);
+        assert_eq!(summary.total, 3);
+    }
+
+    #[test]
+    fn test_progress_events_are_coalesced() {
+        let start = Instant::now();
+        let transferring = FileState::Transferring;
+
+        assert!(should_emit(None, &transferring, start));
+
+        // rapid progress updates are dropped until the interval passed
+        let mut emitted = 0;
+        let mut last_event = start;
+        for ms in 1..=250 {
+            let now = start + Duration::from_millis(ms);
+            if should_emit(Some((&transferring, last_event)), &transferring, now) {
+                emitted += 1;
+                last_event = now;
+            }
+        }
+        assert_eq!(emitted, 2);
+
+        // a state change is never dropped
+        let just_sent = start + Duration::from_millis(1);
+        assert!(should_emit(Some((&transferring, start)), &FileState::Completed, just_sent));
+        assert!(should_emit(Some((&FileState::Pending, start)), &transferring, just_sent));
+    }
+
+    #[test]
+    fn test_transfers_coalesce_progress() {
+        let transfers = Transfers::default();
+
+        assert!(transfers.update("aa", 10, FileState::Transferring, 0.1, true));
+        assert!(!transfers.update("aa", 10, FileState::Transferring, 0.2, true));
+        assert!(transfers.update("aa", 10, FileState::Unanswered, 0.2, true));
+        assert!(transfers.update("aa", 10, FileState::Completed, 1.0, true));
+    }
+
+    #[test]
+    fn test_next_chunks_pipeline() {
+        let mut file = ActiveFile {
+            file: File::new("aa".to_string(), "".to_string(), "file.txt".to_string(), 1),
+            start: 1,
+            stop: 10,
+            current: 1,
+            temp_path: String::new(),
+            retransmit: vec![],
+            retries: HashMap::new(),
+            manifest: false,
+            restarts: 0,
+            confirmed_hash: None,
+            awaiting_confirmation: None,
+            source_hashes: None,
+            reserved: false,
+            merkle: None,
+        };
+
+        // several chunks are queued before the peer answered the first one
+        assert_eq!(next_chunks(&mut file, 4), vec![1, 2, 3, 4]);
+        assert_eq!(file.current, 5);
+
+        file.retransmit.extend([3, 2]);
+        assert_eq!(next_chunks(&mut file, 4), vec![2, 3, 5, 6]);
+        assert_eq!(file.current, 7);
+
+        assert_eq!(next_chunks(&mut file, DEFAULT_PIPELINE_DEPTH), vec![7]);
+        assert_eq!(next_chunks(&mut file, MAX_PIPELINE_DEPTH), vec![8, 9, 10]);
+        assert!(next_chunks(&mut file, MAX_PIPELINE_DEPTH).is_empty());
+        assert_eq!(file.current, 11);
+    }
+
+    #[test]
+    fn test_bytes_transferred() {
+        let chunk_size = CHUNK_SIZE as u64;
+        let size = chunk_size * 2 + 123;
+        let mut file = ActiveFile {
+            file: File::new("aa".to_string(), "".to_string(), "file.txt".to_string(), size),
+            start: 1,
+            stop: get_chunk_count_with_size(size, CHUNK_SIZE),
+            current: 1,
+            temp_path: String::new(),
+            retransmit: vec![],
+            retries: HashMap::new(),
+            manifest: false,
+            restarts: 0,
+            confirmed_hash: None,
+            awaiting_confirmation: None,
+            source_hashes: None,
+            reserved: false,
+            merkle: None,
+        };
+        assert_eq!(file.bytes_transferred(), 0);
+
+        next_chunks(&mut file, 2);
+        assert_eq!(file.bytes_transferred(), chunk_size * 2);
+
+        // a chunk which is sent again is missing until then
+        file.retransmit.push(1);
+        assert_eq!(file.bytes_transferred(), chunk_size);
+        next_chunks(&mut file, 1);
+
+        // the last chunk only counts its real size
+        next_chunks(&mut file, 1);
+        assert_eq!(file.bytes_transferred(), size);
+
+        let transfers = Transfers::default();
+        transfers.update("aa", size, FileState::Transferring, 0.5, true);
+        transfers.record_bytes("aa", true, chunk_size);
+        let transfer = transfers.snapshot().pop().unwrap();
+        assert_eq!(transfer.transferred, chunk_size);
+        assert_eq!(transfer.size - transfer.transferred, chunk_size + 123);
+
+        // later states keep the count
+        transfers.update("aa", size, FileState::Transferring, 0.9, true);
+        assert_eq!(transfers.snapshot().pop().unwrap().transferred, chunk_size);
+        transfers.record_bytes("aa", true, file.bytes_transferred());
+        assert_eq!(transfers.snapshot().pop().unwrap().transferred, size);
+    }
+
+    #[test]
+    fn test_corrupt_chunk_is_rerequested() {
+        let fixture = SourceFixture::new("rdrop_corrupt_chunk", 1000);
+        let SourceFixture { content, hash, .. } = &fixture;
+        let output = fixture.path("output.bin");
+
+        let mut file = ActiveFile::from_file(File::new(
+            hash.clone(),
+            output.clone(),
+            "source.bin".to_string(),
+            content.len() as u64,
+        ));
+        let mut writer = BatchedChunkWriter::new(&file.temp_path, WriteBatching::default());
+
+        let (header_data, data) = fixture.chunk(1);
+
+        let mut corrupt = data.clone();
+        corrupt[10] ^= 0xff;
+        assert_eq!(
+            receive_chunk(&mut file, &mut writer, &header_data, &corrupt).unwrap(),
+            ChunkOutcome::Rerequest(1)
+        );
+        assert_eq!(file.retransmit, vec![1]);
+        assert!(!std::path::Path::new(&file.temp_path).exists());
+
+        let mut order = create_order_byte_vec(1, 1, &hash).unwrap();
+        let order = read_order(&mut order).unwrap();
+        assert_eq!((order.start_num, order.end_num), (1, 1));
+
+        let (header_data, data) = fixture.chunk(order.start_num);
+        match receive_chunk(&mut file, &mut writer, &header_data, &data).unwrap() {
+            ChunkOutcome::Written(_) => {}
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        }
+        assert!(file.retransmit.is_empty());
+        assert_eq!(file.current, file.stop);
+        assert_eq!(std::fs::read(&file.temp_path).unwrap(), *content);
+    }
+
+    #[test]
+    fn test_received_file_is_verified_against_confirmation() {
+        let fixture = SourceFixture::new("rdrop_confirmation", 1000);
+        let SourceFixture { dir, content, hash, .. } = &fixture;
+
+        let receive = |name: &str, offered_hash: &str| {
+            let output = dir.join(name).to_string_lossy().to_string();
+            let mut file = ActiveFile::from_file(File::new(
+                offered_hash.to_string(),
+                output,
+                "source.bin".to_string(),
+                content.len() as u64,
+            ));
+            let mut writer = BatchedChunkWriter::new(&file.temp_path, WriteBatching::default());
+            let (header_data, data) = fixture.chunk(1);
+            receive_chunk(&mut file, &mut writer, &header_data, &data).unwrap();
+            writer.flush().unwrap();
+            file
+        };
+
+        // the offer declared another hash than the sender confirms for the data it sent
+        let mut tampered = hash.clone().into_bytes();
+        tampered[0] = if tampered[0] == b'0' { b'1' } else { b'0' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        let file = receive("tampered.bin", &tampered);
+        assert_eq!(verify_received_file(&file, &hash), Verification::Unconfirmed);
+        assert!(!std::path::Path::new(&file.file.path).exists());
+
+        // the received data does not match the confirmed hash
+        let file = receive("corrupt.bin", &hash);
+        let mut corrupt = content.clone();
+        corrupt[10] ^= 0xff;
+        std::fs::write(&file.temp_path, &corrupt).unwrap();
+        assert_eq!(verify_received_file(&file, &hash), Verification::Mismatch);
+        assert!(!std::path::Path::new(&file.file.path).exists());
+
+        let file = receive("output.bin", &hash);
+        let message = create_complete(&hash, &hash).unwrap();
+        let (offered_hash, confirmed_hash) = read_complete(&message).unwrap();
+        assert_eq!(offered_hash, *hash);
+        assert_eq!(verify_received_file(&file, &confirmed_hash), Verification::Verified);
+        assert!(!std::path::Path::new(&file.file.path).exists());
+        commit_files(std::slice::from_ref(&file)).unwrap();
+        assert_eq!(std::fs::read(&file.file.path).unwrap(), *content);
+
+        // the file is checked with the algorithm of its offer
+        let size_hash = format!("{:016x}", content.len());
+        let mut file = receive("size.bin", &size_hash);
+        file.file.hash_type = Hash::SIZE;
+        assert_eq!(verify_received_file(&file, &size_hash), Verification::Verified);
+    }
+
+    #[test]
+    fn test_received_file_is_verified_against_merkle_root() {
+        let fixture = SourceFixture::new("rdrop_merkle_root", 1000);
+        let SourceFixture { source, content, hash, .. } = &fixture;
+        let root = MerkleTree::from_file(source).unwrap().root();
+
+        let receive = |name: &str, root: &str| {
+            let mut offered = File::new(hash.clone(), fixture.path(name), "source.bin".to_string(), content.len() as u64);
+            offered.merkle_root = root.to_string();
+            assert_eq!(offered.to_offer().merkle_root, root);
+
+            let mut file = ActiveFile::from_file(offered);
+            file.merkle = Some(MerkleVerifier::new(root, MERKLE_HASH_TYPE, file.stop).unwrap());
+            let mut writer = BatchedChunkWriter::new(&file.temp_path, WriteBatching::default());
+            let (header_data, data) = fixture.chunk(1);
+            receive_chunk(&mut file, &mut writer, &header_data, &data).unwrap();
+            writer.flush().unwrap();
+            file
+        };
+
+        let file = receive("output.bin", &root);
+        assert_eq!(verify_received_file(&file, hash), Verification::Verified);
+
+        // the data matches the file hash, but not the root the sender offered
+        let other = MerkleTree::from_leaves(&[hash.repeat(4)], MERKLE_HASH_TYPE).unwrap().root();
+        let file = receive("other.bin", &other);
+        assert_eq!(verify_received_file(&file, hash), Verification::Mismatch);
+    }
+
+    #[test]
+    fn test_confirm_sent_file() {
+        let dir = test_dir("rdrop_confirm_sent");
+
+        let source = dir.join("source.bin").to_string_lossy().to_string();
+        std::fs::write(&source, vec![7u8; 1000]).unwrap();
+        let hash = get_file_hash(&std::fs::File::open(&source).unwrap(), BUFFER_SIZE, &Hash::SHA256, 0).unwrap();
+
+        let mut file = File::new(hash.clone(), source.clone(), "source.bin".to_string(), 1000);
+        file.hash_type = Hash::SHA256;
+        assert_eq!(file.to_offer().hash_type, Hash::SHA256);
+
+        // the sent file is hashed with the algorithm of its offer
+        let (sender, receiver) = mpsc::channel();
+        confirm_sent_file(&file, &sender);
+        let confirmation = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(confirmation, (hash.clone(), Some(hash.clone())));
+
+        // a moved source only fails the confirmation of this file
+        std::fs::remove_file(&source).unwrap();
+        confirm_sent_file(&file, &sender);
+        let confirmation = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(confirmation, (hash, None));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_group_expires_without_live_files() {
+        let group = (String::from("group"), 2);
+        let mut offered = File::new(String::from("hash"), String::from("out.bin"), String::from("out.bin"), 1);
+        offered.group = Some(group.clone());
+        let mut groups = Groups::default();
+        assert!(matches!(groups.hold(ActiveFile::from_file(offered.clone())), GroupProgress::Waiting));
+
+        // the other file of the group is still transferred
+        let live = HashSet::from([group.0.as_str()]);
+        assert!(groups.expire(Duration::ZERO, &live).is_empty());
+
+        assert_eq!(groups.expire(Duration::ZERO, &HashSet::new()).len(), 1);
+        assert!(groups.has_failed(&offered));
+        assert!(matches!(groups.hold(ActiveFile::from_file(offered.clone())), GroupProgress::Failed));
+        assert!(!groups.has_failed(&offered));
+    }
+
+    #[test]
+    fn test_group_is_kept_all_or_nothing() {
+        let dir = test_dir("rdrop_group");
+
+        let group = ("00ff00ff00ff00ff".to_string(), 3);
+        let receive = |name: &str, seed: u8| {
+            let source = dir.join(format!("source_{}", name)).to_string_lossy().to_string();
+            let content: Vec<u8> = (0..500).map(|i| (i as u8).wrapping_add(seed)).collect();
+            std::fs::write(&source, &content).unwrap();
+            let hash = get_hash_from_file(&std::fs::File::open(&source).unwrap()).unwrap();
+
+            let output = dir.join(name).to_string_lossy().to_string();
+            let mut offered = File::new(hash.clone(), output, name.to_string(), content.len() as u64);
+            offered.group = Some(group.clone());
+            let mut file = ActiveFile::from_file(offered);
+            let mut writer = BatchedChunkWriter::new(&file.temp_path, WriteBatching::default());
+            let msg = create_data_vec(&source, 1, &hash).unwrap();
+            let (header, data) = separate_header(&msg).unwrap();
+            let header_data = read_send_header(&header).unwrap();
+            receive_chunk(&mut file, &mut writer, &header_data, &data).unwrap();
+            writer.flush().unwrap();
+            file
+        };
+
+        let mut groups = Groups::default();
+        let first = receive("first.bin", 1);
+        let second = receive("second.bin", 2);
+        let third = receive("third.bin", 3);
+        let outputs: Vec<String> = [&first, &second, &third]
+            .iter()
+            .map(|file| file.file.path.clone())
+            .collect();
+
+        assert_eq!(verify_received_file(&first, &first.file.hash), Verification::Verified);
+        assert!(matches!(groups.hold(first), GroupProgress::Waiting));
+
+        // the second file arrives corrupted and fails the whole group
+        let mut corrupt = std::fs::read(&second.temp_path).unwrap();
+        corrupt[0] ^= 0xff;
+        std::fs::write(&second.temp_path, &corrupt).unwrap();
+        assert_eq!(verify_received_file(&second, &second.file.hash), Verification::Mismatch);
+        assert_eq!(groups.fail(&second.file).len(), 1);
+        assert!(groups.has_failed(&third.file));
+
+        assert_eq!(verify_received_file(&third, &third.file.hash), Verification::Verified);
+        assert!(matches!(groups.hold(third.clone()), GroupProgress::Failed));
+        // every file of the failed group ended, so the group is forgotten
+        assert!(!groups.has_failed(&third.file));
+
+        for output in outputs {
+            assert!(!std::path::Path::new(&output).exists());
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Sends a file the way the writer thread does and receives it the way the reader thread does,
+    /// over any pair of connected writer and reader.
+    fn transfer_file<W: ClientWriter, R: ClientReader>(
+        writer: &mut W,
+        reader: &mut R,
+        source: &str,
+        output: &str,
+    ) -> Verification {
+        let hash = get_hash_from_file(&std::fs::File::open(source).unwrap()).unwrap();
+        let size = std::fs::metadata(source).unwrap().len();
+        let mut file = ActiveFile::from_file(File::new(
+            hash.clone(),
+            output.to_string(),
+            "source.bin".to_string(),
+            size,
+        ));
+        let mut chunk_writer = BatchedChunkWriter::new(&file.temp_path, WriteBatching::default());
+        let timeout = Some(Duration::from_secs(2));
+
+        for chunk in 1..=file.stop {
+            writer.write(&create_data_vec(source, chunk, &hash).unwrap()).unwrap();
+            let msg = reader.read(timeout).unwrap();
+            let (header, data) = separate_header(&msg).unwrap();
+            let header_data = read_send_header(&header).unwrap();
+            receive_chunk(&mut file, &mut chunk_writer, &header_data, &data).unwrap();
+        }
+        chunk_writer.flush().unwrap();
+
+        writer.write(&create_complete(&hash, &hash).unwrap()).unwrap();
+        let (_, confirmed_hash) = read_complete(&reader.read(timeout).unwrap()).unwrap();
+        let verification = verify_received_file(&file, &confirmed_hash);
+        if verification == Verification::Verified {
+            commit_files(&[file]).unwrap();
+        }
+        verification
+    }
+
+    #[test]
+    fn test_transfer_over_plain_connection() {
+        let fixture = SourceFixture::new("rdrop_plain_transfer", CHUNK_SIZE * 2 + 1000);
+        let SourceFixture { source, content, .. } = &fixture;
+        let output = fixture.path("output.bin");
+
+        let timeout = Some(Duration::from_secs(5));
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        let c2 = Connection::<Waiting>::new(None).unwrap();
+        let (p1, p2) = (c1.get_port(), c2.get_port());
+        let ipv6 = Ipv6Addr::LOCALHOST;
+
+        let thread_c2 = thread::spawn(move || {
+            let c2 = c2.connect(ipv6, p1, timeout, timeout).unwrap();
+            c2.transform_to_slide().unwrap()
+        });
+        let c1 = c1.connect(ipv6, p2, timeout, timeout).unwrap();
+        let (mut writer, _c1_reader, _) = c1.transform_to_slide().unwrap();
+        let (_c2_writer, mut reader, _) = thread_c2.join().unwrap();
+
+        assert_eq!(
+            transfer_file(&mut writer, &mut reader, &source, &output),
+            Verification::Verified
+        );
+        assert_eq!(std::fs::read(&output).unwrap(), *content);
+    }
+
+    #[test]
+    fn test_stream_over_plain_connection() {
+        use chunk::offer::offer::{create_stream_offer_byte_msg, read_offer_vec};
+        use chunk::stream::stream::{ordered_channel, send_from_reader, write_ordered};
+
+        let data: Vec<u8> = (0..CHUNK_SIZE as u32 * 2 + 1000).map(|i| (i % 251) as u8).collect();
+        let id = "00ff00ff00ff00ff";
+
+        let timeout = Some(Duration::from_secs(5));
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        let c2 = Connection::<Waiting>::new(None).unwrap();
+        let (p1, p2) = (c1.get_port(), c2.get_port());
+        let ipv6 = Ipv6Addr::LOCALHOST;
+
+        let thread_c2 = thread::spawn(move || {
+            let c2 = c2.connect(ipv6, p1, timeout, timeout).unwrap();
+            c2.transform_to_slide().unwrap()
+        });
+        let c1 = c1.connect(ipv6, p2, timeout, timeout).unwrap();
+        let (mut writer, _c1_reader, _) = c1.transform_to_slide().unwrap();
+        let (_c2_writer, mut reader, _) = thread_c2.join().unwrap();
+
+        // the source is read like standard input, the chunks are sent as their position followed by the data
+        let source = data.clone();
+        let sender_thread = thread::spawn(move || {
+            writer.write(&create_stream_offer_byte_msg(id, "stdin", "").unwrap()).unwrap();
+
+            let (chunk_sender, mut chunks) = ordered_channel(1);
+            let reader_thread =
+                thread::spawn(move || send_from_reader(&mut source.as_slice(), &chunk_sender, CHUNK_SIZE));
+            for (chunk_pos, chunk) in chunks.ordered_chunks() {
+                writer.write(&[chunk_pos.to_be_bytes().to_vec(), chunk].concat()).unwrap();
+            }
+            let streamed = reader_thread.join().unwrap().unwrap();
+
+            // position 0 ends the stream, the hash of the data follows once it is known
+            writer.write(&0u64.to_be_bytes()).unwrap();
+            writer.write(&create_complete(id, &streamed.hash).unwrap()).unwrap();
+            streamed
+        });
+
+        let offer = read_offer_vec(&reader.read(timeout).unwrap()).unwrap();
+        assert!(offer.streaming);
+        assert_eq!(offer.file_hash, id);
+
+        let (chunk_sender, mut chunks) = ordered_channel(1);
+        let writer_thread = thread::spawn(move || {
+            let mut output = Vec::new();
+            write_ordered(&mut chunks, &mut output).unwrap();
+            output
+        });
+        loop {
+            let msg = reader.read(timeout).unwrap();
+            let chunk_pos = u64::from_be_bytes(msg[..8].try_into().unwrap());
+            if chunk_pos == 0 {
+                break;
+            }
+            chunk_sender.send(chunk_pos, msg[8..].to_vec()).unwrap();
+        }
+        drop(chunk_sender);
+        let output = writer_thread.join().unwrap();
+        let (hash, confirmed_hash) = read_complete(&reader.read(timeout).unwrap()).unwrap();
+        let streamed = sender_thread.join().unwrap();
+
+        assert_eq!(output, data);
+        assert_eq!(streamed.size, data.len() as u64);
+        assert_eq!(streamed.chunk_count, 3);
+        assert_eq!(hash, id);
+        assert_eq!(confirmed_hash, chunk::hash::hash::get_hash(&output, &Hash::SIPHASH24));
+    }
+
+    #[test]
+    fn test_hash_cache() {
+        let dir = test_dir("rdrop_hash_cache");
+        let path = dir.join("offer.bin").to_string_lossy().to_string();
+        std::fs::write(&path, b"first content").unwrap();
+
+        let mut cache = HashCache::default();
+        let mut computed = 0;
+        let mut hash = |cache: &mut HashCache| {
+            let file = std::fs::File::open(&path).unwrap();
+            cache
+                .get_or_compute(&path, &file, Hash::SIPHASH24, |file| {
+                    computed += 1;
+                    get_hash_from_file(file)
+                })
+                .unwrap()
+        };
+
+        // the file is offered again unchanged, e.g. after the peer denied it
+        let first = hash(&mut cache);
+        assert_eq!(hash(&mut cache), first);
+
+        // a changed size invalidates the cached hash
+        std::fs::write(&path, b"second content").unwrap();
+        let second = hash(&mut cache);
+        assert_ne!(second, first);
+
+        // so does a changed modification time, even if the size stays the same
+        std::fs::write(&path, b"third content!").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        let third = hash(&mut cache);
+        assert_ne!(third, second);
+        assert_eq!(hash(&mut cache), third);
+
+        assert_eq!(computed, 3);
+        assert_eq!(
+            prepare_offer(path.clone(), String::new(), None, false, Hash::SIPHASH24, &mut cache, OfferSettings::default())
+                .unwrap()
+                .0
+                .hash,
+            third
+        );
+
+        // a hash of another algorithm is not taken from the cache
+        let verified = OfferSettings { verify_source: true, ..OfferSettings::default() };
+        let (offered, _) =
+            prepare_offer(path.clone(), String::new(), None, false, Hash::SHA256, &mut cache, verified).unwrap();
+        let source = std::fs::File::open(&path).unwrap();
+        assert_eq!(offered.hash, get_file_hash(&source, BUFFER_SIZE, &Hash::SHA256, 0).unwrap());
+        // the peer reads the algorithm from the sent offer
+        let offer = read_offer(OFFER_REGEX, &offered.to_offer().to_offer_string()).unwrap();
+        assert_eq!(offer.hash_type, Hash::SHA256);
+        assert!(validate_offer(&offer).is_ok());
+        let supplied = Some(third.clone());
+        assert!(
+            prepare_offer(path.clone(), String::new(), supplied, false, Hash::SHA256, &mut cache, OfferSettings::default())
+                .is_err()
+        );
+
+        // the chunks of a verified source are hashed in the same pass as the file
+        let (offered, source_hashes) =
+            prepare_offer(path.clone(), String::new(), None, false, Hash::SIPHASH24, &mut HashCache::default(), verified)
+                .unwrap();
+        assert_eq!(offered.hash, third);
+        assert_eq!(*source_hashes.unwrap(), ChunkHashes::from_file(&path, CHUNK_SIZE).unwrap());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_transfer_with_offered_chunk_size() {
+        use chunk::general::general::MIN_CHUNK_SIZE;
+
+        let fixture = SourceFixture::new("rdrop_offered_chunk_size", MIN_CHUNK_SIZE * 3 + 100);
+        let SourceFixture { source, content, .. } = &fixture;
+        let output = fixture.path("output.bin");
+
+        // the sender prepares the offer with a tuned chunk size and sends it like the writer thread
+        let settings = OfferSettings { verify_source: true, chunk_size: MIN_CHUNK_SIZE };
+        let (offered, source_hashes) =
+            prepare_offer(source.clone(), String::new(), None, false, Hash::SIPHASH24, &mut HashCache::default(), settings)
+                .unwrap();
+        let offer = read_offer(OFFER_REGEX, &offered.to_offer().to_offer_string()).unwrap();
+        assert_eq!(offer.chunk_size, MIN_CHUNK_SIZE);
+
+        let mut sent = ActiveFile::from_file(offered);
+        sent.source_hashes = source_hashes;
+        assert_eq!(sent.stop, 4);
+        let mut chunk_source = ChunkSource::open(&source).unwrap();
+        chunk_source.set_chunk_size(sent.file.chunk_size);
+        let data_vecs = read_chunks(&sent, &mut chunk_source, &[1, 2, 3, 4], &mut ChunkBufferPool::new(4)).unwrap();
+
+        // the receiver takes the chunk size from the offer
+        let mut received_file = File::new(offer.file_hash, output.clone(), offer.name, offer.size);
+        received_file.chunk_size = offer.chunk_size;
+        let mut received = ActiveFile::from_file(received_file);
+        let mut writer = BatchedChunkWriter::new(&received.temp_path, WriteBatching::default());
+        writer.set_chunk_size(received.file.chunk_size);
+        for msg in data_vecs.iter().rev() {
+            let (header, data) = separate_header(msg).unwrap();
+            let header_data = read_send_header(&header).unwrap();
+            receive_chunk(&mut received, &mut writer, &header_data, &data).unwrap();
+        }
+        writer.flush().unwrap();
+
+        assert_eq!(received.bytes_transferred(), content.len() as u64);
+        assert_eq!(verify_received_file(&received, &received.file.hash), Verification::Verified);
+        assert_eq!(std::fs::read(&received.temp_path).unwrap(), *content);
+    }
+
+    #[test]
+    fn test_changed_source_is_detected() {
+        let fixture = SourceFixture::new("rdrop_changed_source", CHUNK_SIZE * 2 + 1000);
+        let SourceFixture { source, hash, .. } = &fixture;
+        let mut content = fixture.content.clone();
+
+        let mut file = ActiveFile::from_file(File::new(
+            hash.clone(),
+            source.clone(),
+            "source.bin".to_string(),
+            content.len() as u64,
+        ));
+        file.source_hashes = Some(Arc::new(ChunkHashes::from_file(&source, CHUNK_SIZE).unwrap()));
+        let mut pool = ChunkBufferPool::new(MAX_PIPELINE_DEPTH);
+        let mut chunk_source = ChunkSource::open(&source).unwrap();
+
+        let data_vecs = read_chunks(&file, &mut chunk_source, &[1], &mut pool).unwrap();
+        assert_eq!(data_vecs.len(), 1);
+
+        // the user edits the file after the first chunk was sent
+        content[CHUNK_SIZE * 2 + 10] ^= 0xFF;
+        std::fs::write(&source, &content).unwrap();
+
+        let err = read_chunks(&file, &mut chunk_source, &[2, 3], &mut pool).unwrap_err();
+        assert!(matches!(err.kind(), ClientErrorKind::FileChangedDuringTransfer));
+        // the buffers of the failed read are handed back, including the unchanged chunk read before
+        assert_eq!(pool.available(), 2);
+
+        // without hashes the changed data is sent as it is
+        file.source_hashes = None;
+        assert_eq!(read_chunks(&file, &mut chunk_source, &[2, 3], &mut pool).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_corrupt_chunk_retry_limit() {
+        let fixture = SourceFixture::new("rdrop_corrupt_chunk_limit", 100);
+        let mut file = ActiveFile::from_file(File::new(
+            fixture.hash.clone(),
+            fixture.path("output.bin"),
+            "source.bin".to_string(),
+            100,
+        ));
+        let mut writer = BatchedChunkWriter::new(&file.temp_path, WriteBatching::default());
+
+        let (header_data, intact) = fixture.chunk(1);
+        let mut data = intact.clone();
+        data[0] ^= 0xff;
+
+        for _ in 0..MAX_CHUNK_RETRIES {
+            assert_eq!(
+                receive_chunk(&mut file, &mut writer, &header_data, &data).unwrap(),
+                ChunkOutcome::Rerequest(1)
+            );
+        }
+        assert_eq!(
+            receive_chunk(&mut file, &mut writer, &header_data, &data).unwrap(),
+            ChunkOutcome::Corrupted
+        );
+
+        // an intact chunk resets its count
+        file.retries.insert(1, MAX_CHUNK_RETRIES - 1);
+        assert!(matches!(
+            receive_chunk(&mut file, &mut writer, &header_data, &intact).unwrap(),
+            ChunkOutcome::Written(_)
+        ));
+        assert!(file.retries.is_empty());
+        assert!(file.retransmit.is_empty());
+    }
+
+    #[test]
+    fn test_resume_file() {
+        let mut file = ActiveFile::from_file(File::new(
+            "aa".to_string(),
+            "".to_string(),
+            "file.txt".to_string(),
+            CHUNK_SIZE as u64 * 5,
+        ));
+
+        assert_eq!(file.resume(&[2, 3, 5]), vec![(2, 3), (5, 5)]);
+        assert_eq!(file.current, 4);
+        assert_eq!(file.retransmit, vec![2, 3, 5]);
+        assert_eq!(file.bytes_transferred(), CHUNK_SIZE as u64 * 2);
+
+        // the sender has to confirm a completely received file
+        assert_eq!(file.resume(&[]), vec![(5, 5)]);
+        assert_eq!(file.current, file.stop);
+    }
+
+    #[test]
+    fn test_restart_file() {
+        let mut file = ActiveFile::from_file(File::new(
+            "aa".to_string(),
+            "".to_string(),
+            "file.txt".to_string(),
+            100,
+        ));
+        file.restarts = 1;
+        file.current = file.stop;
+        file.retransmit = vec![1];
+        file.retries.insert(1, 2);
+
+        assert!(file.restart());
+        assert_eq!(file.current, file.start);
+        assert!(file.retransmit.is_empty());
+        assert!(file.retries.is_empty());
+        assert!(!file.restart());
+    }
+
+    #[test]
+    fn test_cancel_all_clears_transfers() {
+        let transfers = Transfers::default();
+        transfers.update("aa", 0, FileState::Pending, 0.0, true);
+        transfers.update("bb", 0, FileState::Transferring, 0.5, true);
+        transfers.update("cc", 0, FileState::Transferring, 0.2, false);
+        transfers.update("dd", 0, FileState::Pending, 0.0, false);
+        transfers.update("ee", 0, FileState::Completed, 1.0, false);
+        assert_eq!(transfers.snapshot().len(), 4);
+
+        let (read_command, read_receiver) = mpsc::channel();
+        let (write_command, write_receiver) = mpsc::channel();
+        cancel_transfers(&transfers, &read_command, &write_command).unwrap();
+
+        let mut stopped = Vec::new();
+        for command in read_receiver.try_iter() {
+            match command {
+                ReadCommand::Stop(hash) => stopped.push((hash, false)),
+                _ => panic!("unexpected read command"),
+            }
+        }
+        for command in write_receiver.try_iter() {
+            match command {
+                WriteCommand::Rescind(hash) => stopped.push((hash, true)),
+                WriteCommand::StopSend(hash) => stopped.push((hash, true)),
+                WriteCommand::Stop(_) => {}
+                _ => panic!("unexpected write command"),
+            }
+        }
+        stopped.sort();
+        assert_eq!(
+            stopped,
+            vec![
+                ("aa".to_string(), true),
+                ("bb".to_string(), true),
+                ("cc".to_string(), false),
+                ("dd".to_string(), false),
+            ]
+        );
+
+        // the threads answer every stop with a stopped state
+        for (hash, is_sender) in stopped {
+            transfers.update(&hash, 0, FileState::Stopped, 0.0, is_sender);
+        }
+        assert!(transfers.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_transfer_result_completed() {
+        let transfers = Transfers::default();
+        let results = transfers.subscribe();
+
+        // the wait for the peer to accept the file is not part of the transfer
+        transfers.update("aa", 1000, FileState::Pending, 0.0, false);
+        sleep(Duration::from_millis(50));
+        let accepted = Instant::now();
+        transfers.update("aa", 1000, FileState::Transferring, 0.5, false);
+        assert!(results.try_recv().is_err());
+
+        sleep(Duration::from_millis(10));
+        transfers.update("aa", 1000, FileState::Completed, 1.0, false);
+
+        let result = results.try_recv().unwrap();
+        assert_eq!(result.hash, "aa");
+        assert_eq!(result.outcome, TransferOutcome::Completed);
+        assert_eq!(result.bytes, 1000);
+        assert!(!result.is_sender);
+        assert!(result.duration >= Duration::from_millis(10));
+        assert!(result.duration <= accepted.elapsed());
+        assert!(result.avg_speed > 0.0);
+        assert!(results.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_batch_summary() {
+        let transfers = Transfers::default();
+        let results = transfers.subscribe();
+        let mut batch = BatchTransfer::new(vec!["aa".to_string(), "bb".to_string()]);
+
+        transfers.update("aa", 1000, FileState::Transferring, 0.5, false);
+        transfers.update("bb", 1000, FileState::Transferring, 0.5, false);
+        transfers.update("cc", 1000, FileState::Completed, 1.0, true);
+        transfers.update("aa", 1000, FileState::Completed, 1.0, false);
+        transfers.update("bb", 1000, FileState::Corrupted, 1.0, false);
+
+        let summaries: Vec<BatchSummary> = results
+            .try_iter()
+            .filter_map(|result| batch.record(&result))
+            .collect();
+
+        assert_eq!(
+            summaries,
+            vec![BatchSummary {
+                succeeded: vec!["aa".to_string()],
+                failed: vec!["bb".to_string()],
+            }]
+        );
+
+        // a disconnect ends the files which are still running
+        let mut batch = BatchTransfer::new(vec!["aa".to_string(), "bb".to_string(), "cc".to_string()]);
+        batch.finish_file("cc", TransferOutcome::Cancelled);
+        transfers.update("aa", 1000, FileState::Transferring, 0.5, false);
+        transfers.update("aa", 1000, FileState::Completed, 1.0, false);
+        transfers.update("bb", 1000, FileState::Transferring, 0.5, false);
+        transfers.update("dd", 1000, FileState::Transferring, 0.5, true);
+        transfers.disconnect();
+
+        let summaries: Vec<BatchSummary> = results
+            .try_iter()
+            .filter_map(|result| batch.record(&result))
+            .collect();
+        assert_eq!(
+            summaries[0],
+            BatchSummary {
+                succeeded: vec!["aa".to_string()],
+                failed: vec!["bb".to_string(), "cc".to_string()],
+            }
+        );
+    }
+
+    /// Spawns a read thread that runs until its sibling exits and a write thread running `writer`.
+    /// Returns the receiver of the reported disconnects.
+    fn spawn_threads<F>(writer: F) -> mpsc::Receiver<DisconnectReason>
+    where
+        F: FnOnce() -> Result<(), ClientError> + Send + 'static,
+    {
+        let status = ThreadStatus::default();
+        let transfers = Transfers::default();
+        let (disconnects, disconnect_receiver) = mpsc::channel();
+        let reader_disconnects = disconnects.clone();
+        let reader_status = status.clone();
+
+        spawn_client_thread(
+            "Read",
+            status.clone(),
+            transfers.clone(),
+            move || loop {
+                reader_status.check_sibling()?;
+                sleep(Duration::from_millis(1));
+            },
+            move |reason| Ok(reader_disconnects.send(reason)?),
+        );
+        spawn_client_thread(
+            "Write",
+            status,
+            transfers,
+            writer,
+            move |reason| Ok(disconnects.send(reason)?),
+        );
+
+        disconnect_receiver
+    }
+
+    #[test]
+    fn test_writer_error_disconnects_client() {
+        let disconnects = spawn_threads(|| {
+            sleep(Duration::from_millis(10));
+            Err(ClientError::new(ClientErrorKind::Disconnected(DisconnectReason::Timeout)))
+        });
+
+        // the reason of the failing thread is reported once, the surviving reader stops silently
+        let reason = disconnects.recv_timeout(Duration::from_millis(500)).unwrap();
+        assert_eq!(reason, DisconnectReason::Timeout);
+        assert!(disconnects.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn test_writer_panic_disconnects_client() {
+        let disconnects = spawn_threads(|| panic!("writer thread died"));
+
+        // the reader notices the dead writer and reports the disconnect
+        let reason = disconnects.recv_timeout(Duration::from_millis(500)).unwrap();
+        assert_eq!(reason, DisconnectReason::SocketError);
+    }
+
+    #[test]
+    fn test_stop_threads_after_they_exited() {
+        let drop_threads = Arc::new(RwLock::new(false));
+
+        // poison the flag the way a panicking thread holding it would
+        let poisoner = drop_threads.clone();
+        let _ = thread::spawn(move || {
+            let _dropper = poisoner.write().unwrap();
+            panic!("thread died holding the drop flag");
+        })
+        .join();
+        assert!(drop_threads.is_poisoned());
+
+        let status = ThreadStatus::default();
+        let transfers = Transfers::default();
+        let reader = spawn_client_thread(
+            "Read",
+            status.clone(),
+            transfers.clone(),
+            || Err(ClientError::new(ClientErrorKind::Disconnected(DisconnectReason::SocketError))),
+            |_| Ok(()),
+        );
+        let writer = spawn_client_thread(
+            "Write",
+            status,
+            transfers,
+            || panic!("writer thread died"),
+            |_| Ok(()),
+        );
+        while !reader.is_finished() || !writer.is_finished() {
+            sleep(Duration::from_millis(1));
+        }
+
+        stop_client_threads(&drop_threads, Some(writer), Some(reader));
+        assert!(*drop_threads.read().unwrap_or_else(|poisoned| poisoned.into_inner()));
+    }
+
+    #[test]
+    fn test_transfer_result_disconnected() {
+        let transfers = Transfers::default();
+        let results = transfers.subscribe();
+
+        transfers.update("aa", 1000, FileState::Transferring, 0.25, true);
+        transfers.record_bytes("aa", true, 250);
+        transfers.disconnect();
 
-        files.retain(|file| !marked_for_remove.contains(&file.file.hash));
+        let result = results.try_recv().unwrap();
+        assert_eq!(result.outcome, TransferOutcome::Disconnected);
+        assert_eq!(result.bytes, 250);
+        assert!(transfers.snapshot().is_empty());
     }
 }
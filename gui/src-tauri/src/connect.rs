@@ -1,23 +1,176 @@
 use std::net::Ipv6Addr;
 
-use std::sync::mpsc::Receiver;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
 use tauri::{AppHandle, Wry};
 
+use chunk::general::general::{CHUNK_SIZE, CHUNK_SIZE_CANDIDATES, MAX_DATA_VEC_LEN};
+use p2p::client::{ClientReader, ClientWriter};
 use p2p::error::ErrorKind;
-use p2p::protocol::{Connection, Waiting};
+use p2p::identity::Identity;
+use p2p::protocol::{Active, Connection, HandshakePhase, Plain, Udp, Waiting};
+use p2p::throughput::tune_chunk_size;
 
 use crate::client::Client;
 use crate::error::{ClientError, ClientErrorKind};
-use crate::events::{send_connect_error, send_connect_status, send_connected, Protocol};
+use crate::events::{
+    send_connect_error, send_connect_status, send_connected, send_peer_identity, Protocol,
+};
 use crate::handle::Current;
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
 const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// The time the chunk size may be tuned for at connection setup.
+const TUNE_BUDGET: Duration = Duration::from_secs(2);
+/// The number of probes sent per chunk size candidate while tuning.
+const TUNE_PROBE_COUNT: usize = 8;
+
+/// Options of a connection attempt, see `connect`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectOptions {
+    /// Whether the connection is encrypted. A plain connection skips the handshake and stays on UDP.
+    pub encrypt: bool,
+    /// The number of parallel UDP flows the chunks of a transfer are striped across.
+    /// The smaller number of both peers is used, it only applies to encrypted UDP connections.
+    pub num_flows: usize,
+    /// Whether the chunk size is tuned to the connection before the first transfer, see `tune_chunk_size`.
+    /// Tuning delays the connection by up to `TUNE_BUDGET`.
+    pub tune_chunk_size: bool,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        ConnectOptions {
+            encrypt: true,
+            num_flows: 1,
+            tune_chunk_size: false,
+        }
+    }
+}
+
+/// Returns the status and description shown in the frontend for a handshake phase.
+fn describe_phase(phase: &HandshakePhase) -> (&'static str, String) {
+    match phase {
+        HandshakePhase::RolesNegotiated => ("Encrypting", "Roles negotiated.".to_string()),
+        HandshakePhase::KeysExchanged => ("Encrypting", "Keys exchanged.".to_string()),
+        HandshakePhase::Encrypted => ("Encrypting", "Connection secured.".to_string()),
+        HandshakePhase::Upgrading => ("Upgrading", "Sampling time difference.".to_string()),
+        HandshakePhase::SamplingProgress(done, total) => {
+            ("Upgrading", format!("Sampling time difference ({done}/{total})."))
+        }
+        HandshakePhase::Upgraded => ("Upgrading", "Connected via TCP.".to_string()),
+    }
+}
 
+/// Checks that the peer accepts messages long enough for a chunk.
+/// Chunks have a fixed size, so a peer with a smaller limit could not receive any file.
+///
+/// # Arguments
+///
+/// * `max_msg_len` - The maximum message length agreed with the peer, see `Connection::max_msg_len`.
+fn check_peer_message_limit(max_msg_len: Option<usize>) -> Result<(), ClientError> {
+    match max_msg_len {
+        Some(max_msg_len) if max_msg_len < MAX_DATA_VEC_LEN => {
+            Err(ClientError::new(ClientErrorKind::PeerMessageLimit(max_msg_len)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Creates the client of an established connection.
+/// If enabled in the options, the chunk size is tuned first, while the writer is not yet shared with the client threads.
+///
+/// # Arguments
+///
+/// * `app_handle` - Handle for the tauri application.
+/// * `reader` - The reader of the connection.
+/// * `writer` - The writer of the connection.
+/// * `self_port` - The local port of the connection.
+/// * `options` - Whether the chunk size is tuned.
+///
+/// # Returns
+///
+/// Returns the `Client` of the connection.
+fn create_client<W: ClientWriter + Send + 'static, R: ClientReader + Send + 'static>(
+    app_handle: &AppHandle<Wry>,
+    reader: R,
+    mut writer: W,
+    self_port: u16,
+    options: &ConnectOptions,
+) -> Client<W, R> {
+    let chunk_size = match options.tune_chunk_size {
+        true => tune_chunk_size(&mut writer, &CHUNK_SIZE_CANDIDATES, TUNE_PROBE_COUNT, TUNE_BUDGET, CHUNK_SIZE),
+        false => CHUNK_SIZE,
+    };
+    println!("[CONNECT] : chunk size {chunk_size}");
+
+    let mut client = Client::new(app_handle.clone(), reader, writer, self_port);
+    // the candidates are valid chunk sizes, so this can't fail
+    let _ = client.set_chunk_size(chunk_size);
+    return client;
+}
+
+/// Forwards the handshake phases of a connection to the frontend until the connection drops the sender.
+fn forward_phases(app_handle: AppHandle<Wry>, receiver: mpsc::Receiver<HandshakePhase>) {
+    thread::spawn(move || {
+        for phase in receiver {
+            let (status, description) = describe_phase(&phase);
+            if send_connect_status(&app_handle, status, &description).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Finishes a connection without encryption by creating the sliding window directly over the plain connection.
+/// The application identifier and the identities are still exchanged, see `Connection::transform_to_slide`.
+///
+/// # Arguments
+///
+/// * `app_handle` - Handle for the tauri application.
+/// * `current` - The current state of the client.
+/// * `connection` - The active plain connection.
+/// * `self_port` - The local port of the connection.
+/// * `options` - Whether the chunk size is tuned.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the sliding window is established, or an `Err` containing a `ClientError` otherwise.
+fn connect_plain(
+    app_handle: AppHandle<Wry>,
+    current: Arc<Mutex<Current>>,
+    connection: Connection<Active<Plain<Udp>>>,
+    self_port: u16,
+    options: ConnectOptions,
+) -> Result<(), ClientError> {
+    send_connect_status(&app_handle, "Connecting", "Connection is not encrypted.")?;
+
+    let (writer, reader, peer_identity) = match connection.transform_to_slide() {
+        Ok(slide) => slide,
+        Err(_) => {
+            {
+                let mut write_state = current.lock()?;
+                *write_state = Current::Broken;
+            }
+            send_connect_error(&app_handle, "Failed to connect.", "Could not establish sliding window.")?;
+            return Err(ClientError::new(ClientErrorKind::SocketClosed));
+        }
+    };
+
+    send_peer_identity(&app_handle, &peer_identity)?;
+
+    let client = create_client(&app_handle, reader, writer, self_port, &options);
+    let protocol = Protocol::from(client.protocol_kind());
+
+    let mut write_state = current.lock()?;
+    *write_state = Current::ConnectedPlain(client);
+    send_connected(&app_handle, protocol)?;
+    return Ok(());
+}
 
 /// Establishes a connection with a remote peer.
 ///
@@ -26,9 +179,11 @@ const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 /// * `app_handle` - Handle for the tauri application.
 /// * `current` - The current state of the client.
 /// * `connection` - The waiting connection, which now should be connected.
-/// * `receiver` - A Receiver for receiving termination signals.
+/// * `cancel` - A flag which aborts the connection attempt once it is set.
 /// * `ipv6` - The IPv6 address of the remote server.
 /// * `port` - The port number of the remote server.
+/// * `options` - Whether the connection is encrypted, the number of UDP flows and whether the chunk size is tuned.
+/// * `identity` - The persistent identity presented to the peer, taken by the first connection. `None` presents a new identity.
 ///
 /// # Returns
 ///
@@ -38,14 +193,16 @@ pub fn thread_connect(
     app_handle: AppHandle<Wry>,
     current: Arc<Mutex<Current>>,
     mut connection: Connection<Waiting>,
-    receiver: Receiver<()>,
+    cancel: Arc<AtomicBool>,
     ipv6: Ipv6Addr,
     port: u16,
+    options: ConnectOptions,
+    mut identity: Option<Identity>,
 ) -> Result<(), ClientError> {
     let mut i = 0;
     let mut instant = Instant::now();
     let self_port = connection.get_port();
-    while receiver.try_recv().is_err() {
+    while !cancel.load(Ordering::SeqCst) {
         if instant.elapsed() < Duration::from_millis(50) {
             sleep(Duration::from_millis(51) - instant.elapsed());
         }
@@ -53,10 +210,28 @@ pub fn thread_connect(
         i += 1;
         println!("next {i}");
 
-        match connection.connect(ipv6, port, Some(DEFAULT_TIMEOUT), Some(DISCONNECT_TIMEOUT)) {
-            Ok(active_connection) => {
+        match connection.connect_cancellable(
+            ipv6,
+            port,
+            Some(DEFAULT_TIMEOUT),
+            Some(DISCONNECT_TIMEOUT),
+            cancel.clone(),
+        ) {
+            Ok(mut active_connection) => {
+                if let Some(identity) = identity.take() {
+                    active_connection.set_identity(identity);
+                }
+
+                if !options.encrypt {
+                    return connect_plain(app_handle, current, active_connection, self_port, options);
+                }
+
                 send_connect_status(&app_handle, "Encrypting", "Securing the connection.")?;
 
+                let (phase_sender, phase_receiver) = mpsc::channel();
+                active_connection.set_phase_sender(Some(phase_sender));
+                forward_phases(app_handle.clone(), phase_receiver);
+
                 let active_connection = match active_connection.encrypt() {
                     Ok(connection) => connection,
                     Err(err) => {
@@ -75,9 +250,23 @@ pub fn thread_connect(
                     }
                 };
 
+                send_peer_identity(&app_handle, &active_connection.peer_identity())?;
+
+                if let Err(err) = check_peer_message_limit(active_connection.max_msg_len()) {
+                    send_connect_error(&app_handle, "Peer not supported", &err.kind().message())?;
+
+                    let port = active_connection.get_port();
+                    drop(active_connection);
+                    {
+                        let mut write_state = current.lock()?;
+                        *write_state = Current::try_with_port(port);
+                    }
+                    return Err(err);
+                }
+
                 /// START TCP BLOCKER
 
-                let (writer, reader) = match active_connection.transform_to_slide() {
+                let (writer, reader) = match active_connection.transform_to_slide_flows(options.num_flows) {
                     Ok(wr) => wr,
                     Err(_) => {
                         {
@@ -91,17 +280,12 @@ pub fn thread_connect(
 
                 println!("transformed to slide");
 
-                let client = Client::new(
-                    app_handle.clone(),
-                    reader,
-                    writer,
-                    self_port,
-                );
-
+                let client = create_client(&app_handle, reader, writer, self_port, &options);
+                let protocol = Protocol::from(client.protocol_kind());
 
                 let mut write_state = current.lock()?;
                 *write_state = Current::ConnectedUdp(client);
-                send_connected(&app_handle, Protocol::UDP)?;
+                send_connected(&app_handle, protocol)?;
                 return Ok(());
 
                 /// END TCP BLOCKER
@@ -114,12 +298,8 @@ pub fn thread_connect(
                         let _port = connection.get_port();
 
                         let (writer, reader) = connection.accept();
-                        let client = Client::new(
-                            app_handle.clone(),
-                            reader,
-                            writer,
-                            self_port,
-                        );
+                        let client = create_client(&app_handle, reader, writer, self_port, &options);
+                        let protocol = Protocol::from(client.protocol_kind());
 
 
                         {
@@ -127,7 +307,7 @@ pub fn thread_connect(
                             *write_state = Current::ConnectedTcp(client);
 
                         }
-                        send_connected(&app_handle, Protocol::TCP)?;
+                        send_connected(&app_handle, protocol)?;
                         Ok(())
                     }
                     Err(err) => {
@@ -136,7 +316,7 @@ pub fn thread_connect(
                         println!("{}", err);
 
 
-                        let (writer, reader) = match old_connection.transform_to_slide() {
+                        let (writer, reader) = match old_connection.transform_to_slide_flows(options.num_flows) {
                             Ok(wr) => wr,
                             Err(_) => {
                                 {
@@ -150,17 +330,12 @@ pub fn thread_connect(
 
                         println!("transformed to slide");
 
-                        let client = Client::new(
-                            app_handle.clone(),
-                            reader,
-                            writer,
-                            self_port,
-                        );
-
+                        let client = create_client(&app_handle, reader, writer, self_port, &options);
+                        let protocol = Protocol::from(client.protocol_kind());
 
                         let mut write_state = current.lock()?;
                         *write_state = Current::ConnectedUdp(client);
-                        send_connected(&app_handle, Protocol::UDP)?;
+                        send_connected(&app_handle, protocol)?;
                         Ok(())
                     }
                 };
@@ -175,6 +350,7 @@ pub fn thread_connect(
                             send_connect_error(&app_handle, "Cannot connect to self", "")?;
                             break;
                         }
+                        ErrorKind::Cancelled => break,
                         _ => continue,
                     },
                     None => {}
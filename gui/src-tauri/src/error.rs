@@ -5,6 +5,8 @@ use std::io;
 use std::sync::mpsc::SendError;
 use std::sync::PoisonError;
 
+use chunk::general::general::MAX_DATA_VEC_LEN;
+use p2p::error::DisconnectReason;
 use serde::{Serialize, Serializer};
 
 
@@ -20,6 +22,15 @@ pub enum ClientErrorKind {
     IOError,
     DataCorruptionError,
     CommunicationError,
+    InvalidArgument,
+    /// A sent file changed on disk after it was offered.
+    FileChangedDuringTransfer,
+    /// The connection to the peer ended.
+    Disconnected(DisconnectReason),
+    /// The peer accepts no messages long enough for a chunk, see `Connection::max_msg_len`.
+    PeerMessageLimit(usize),
+    /// The identity key could not be loaded or created, see `Identity::load_or_create`.
+    IdentityUnavailable,
 }
 
 /// Error type for the client.
@@ -33,6 +44,18 @@ impl ClientError {
     pub(crate) fn new(kind: ClientErrorKind) -> Self {
         ClientError { kind, source: None }
     }
+
+    pub(crate) fn kind(&self) -> &ClientErrorKind {
+        &self.kind
+    }
+
+    /// Returns the reason reported to the frontend if this error ends the connection.
+    pub(crate) fn disconnect_reason(&self) -> DisconnectReason {
+        match self.kind {
+            ClientErrorKind::Disconnected(reason) => reason,
+            _ => DisconnectReason::SocketError,
+        }
+    }
 }
 
 impl Error for ClientError {
@@ -91,11 +114,139 @@ impl From<io::Error> for ClientError {
     }
 }
 
+impl ClientErrorKind {
+    /// Returns a stable code identifying the kind in the frontend.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ClientErrorKind::LockPoisoned => "LOCK_POISONED",
+            ClientErrorKind::SocketClosed => "SOCKET_CLOSED",
+            ClientErrorKind::WrongState => "WRONG_STATE",
+            ClientErrorKind::MpscSendError => "CHANNEL_FAILED",
+            ClientErrorKind::Ipv6ParseFailed => "INVALID_ADDRESS",
+            ClientErrorKind::SendToFrontendFailed => "FRONTEND_FAILED",
+            ClientErrorKind::IOError => "IO_ERROR",
+            ClientErrorKind::DataCorruptionError => "DATA_CORRUPTED",
+            ClientErrorKind::CommunicationError => "COMMUNICATION_FAILED",
+            ClientErrorKind::InvalidArgument => "INVALID_ARGUMENT",
+            ClientErrorKind::FileChangedDuringTransfer => "FILE_CHANGED",
+            ClientErrorKind::Disconnected(_) => "DISCONNECTED",
+            ClientErrorKind::PeerMessageLimit(_) => "PEER_MESSAGE_LIMIT",
+            ClientErrorKind::IdentityUnavailable => "IDENTITY_UNAVAILABLE",
+        }
+    }
+
+    /// Returns a message describing the kind, which can be shown to the user.
+    pub fn message(&self) -> String {
+        match self {
+            ClientErrorKind::LockPoisoned => "The application state is broken.".to_string(),
+            ClientErrorKind::SocketClosed => "The socket was closed.".to_string(),
+            ClientErrorKind::WrongState => {
+                "The action is not possible in the current state.".to_string()
+            }
+            ClientErrorKind::MpscSendError => "An internal channel failed.".to_string(),
+            ClientErrorKind::Ipv6ParseFailed => "The address of the peer is invalid.".to_string(),
+            ClientErrorKind::SendToFrontendFailed => {
+                "The frontend could not be notified.".to_string()
+            }
+            ClientErrorKind::IOError => "A file could not be read or written.".to_string(),
+            ClientErrorKind::DataCorruptionError => "The received data is corrupted.".to_string(),
+            ClientErrorKind::CommunicationError => {
+                "The communication with the peer failed.".to_string()
+            }
+            ClientErrorKind::InvalidArgument => "An argument is invalid.".to_string(),
+            ClientErrorKind::FileChangedDuringTransfer => {
+                "The file changed on disk while it was sent.".to_string()
+            }
+            ClientErrorKind::Disconnected(reason) => {
+                format!("The connection to the peer ended ({:?}).", reason)
+            }
+            ClientErrorKind::PeerMessageLimit(max_msg_len) => format!(
+                "The peer accepts messages of at most {} bytes, a chunk needs {} bytes.",
+                max_msg_len, MAX_DATA_VEC_LEN
+            ),
+            ClientErrorKind::IdentityUnavailable => {
+                "The identity key could not be loaded or created.".to_string()
+            }
+        }
+    }
+}
+
+/// Error returned to the frontend by a command.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CommandError {
+    /// Stable code of the error, see `ClientErrorKind::code`.
+    pub code: &'static str,
+    /// Message which can be shown to the user.
+    pub message: String,
+}
+
+impl From<&ClientError> for CommandError {
+    fn from(value: &ClientError) -> Self {
+        let message = match value.source.as_ref() {
+            None => value.kind.message(),
+            Some(src) => format!("{} {}", value.kind.message(), src),
+        };
+
+        CommandError {
+            code: value.kind.code(),
+            message,
+        }
+    }
+}
+
+/// Result of a command. The error is serialized as a `CommandError`.
+pub type CommandResult<T> = Result<T, ClientError>;
+
 impl Serialize for ClientError {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        serializer.serialize_str(format!("{:?}", self.kind).as_str())
+        CommandError::from(self).serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_error_codes_are_distinct() {
+        let kinds = [
+            ClientErrorKind::LockPoisoned,
+            ClientErrorKind::SocketClosed,
+            ClientErrorKind::WrongState,
+            ClientErrorKind::MpscSendError,
+            ClientErrorKind::Ipv6ParseFailed,
+            ClientErrorKind::SendToFrontendFailed,
+            ClientErrorKind::IOError,
+            ClientErrorKind::DataCorruptionError,
+            ClientErrorKind::CommunicationError,
+            ClientErrorKind::InvalidArgument,
+            ClientErrorKind::FileChangedDuringTransfer,
+            ClientErrorKind::Disconnected(DisconnectReason::Timeout),
+            ClientErrorKind::PeerMessageLimit(1024),
+        ];
+
+        let codes: HashSet<&str> = kinds.iter().map(|kind| kind.code()).collect();
+        assert_eq!(codes.len(), kinds.len());
+        assert!(kinds.iter().all(|kind| !kind.message().is_empty()));
+    }
+
+    #[test]
+    fn test_command_error_from_client_error() {
+        let error = ClientError::new(ClientErrorKind::WrongState);
+        let command_error = CommandError::from(&error);
+
+        assert_eq!(command_error.code, "WRONG_STATE");
+        assert_eq!(command_error.message, ClientErrorKind::WrongState.message());
+
+        let error = ClientError::new(ClientErrorKind::Disconnected(DisconnectReason::PeerClosed));
+        let command_error = CommandError::from(&error);
+
+        assert_eq!(command_error.code, "DISCONNECTED");
+        assert!(command_error.message.contains("PeerClosed"));
     }
 }
@@ -1,7 +1,11 @@
-use crate::client::File;
+use crate::client::{BatchSummary, File, TransferSummary};
 use serde::Serialize;
 use tauri::{AppHandle, Manager, Wry};
 
+use p2p::client::ProtocolKind;
+use p2p::error::DisconnectReason;
+use p2p::identity::PeerIdentity;
+
 use crate::error::ClientError;
 
 #[derive(Clone, serde::Serialize)]
@@ -55,6 +59,17 @@ pub fn send_connect_error(
 pub enum Protocol {
     TCP,
     UDP,
+    OTHER,
+}
+
+impl From<ProtocolKind> for Protocol {
+    fn from(kind: ProtocolKind) -> Self {
+        match kind {
+            ProtocolKind::Tcp => Protocol::TCP,
+            ProtocolKind::Udp => Protocol::UDP,
+            ProtocolKind::Other => Protocol::OTHER,
+        }
+    }
 }
 
 pub fn send_connected(handle: &AppHandle<Wry>, protocol: Protocol) -> Result<(), ClientError> {
@@ -63,19 +78,45 @@ pub fn send_connected(handle: &AppHandle<Wry>, protocol: Protocol) -> Result<(),
     Ok(())
 }
 
-pub fn send_disconnect(handle: &AppHandle<Wry>) -> Result<(), ClientError> {
-    handle.emit_all("app://disconnected", ())?;
+/// Shows the identity the peer proved during the handshake, so the user can tell it is the same peer as before.
+pub fn send_peer_identity(handle: &AppHandle<Wry>, identity: &PeerIdentity) -> Result<(), ClientError> {
+    handle.emit_all("app://peer-identity", identity.to_string())?;
 
     Ok(())
 }
 
-#[derive(Serialize, Clone)]
+pub fn send_transfer_summary(
+    handle: &AppHandle<Wry>,
+    summary: &TransferSummary,
+) -> Result<(), ClientError> {
+    handle.emit_all("app://transfer-summary", summary.clone())?;
+
+    Ok(())
+}
+
+pub fn send_batch_summary(handle: &AppHandle<Wry>, summary: &BatchSummary) -> Result<(), ClientError> {
+    handle.emit_all("app://batch-summary", summary.clone())?;
+
+    Ok(())
+}
+
+pub fn send_disconnect(handle: &AppHandle<Wry>, reason: DisconnectReason) -> Result<(), ClientError> {
+    handle.emit_all("app://disconnected", format!("{:?}", reason))?;
+
+    Ok(())
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
 pub enum FileState {
     Transferring,
     Pending,
+    /// An offer the peer did not request in time.
+    Unanswered,
     Completed,
     Stopped,
     Corrupted,
+    /// A sent file changed on disk after it was offered, see `Client::set_verify_source`.
+    Changed,
 }
 
 #[derive(Serialize, Clone)]
@@ -84,6 +125,8 @@ pub struct FileJson {
     path: String,
     size: u64,
     hash: String,
+    note: String,
+    mime: String,
     percent: f32,
     state: FileState,
     is_sender: bool,
@@ -101,6 +144,8 @@ pub fn send_file_state(
         path: file.path,
         size: file.size,
         hash: file.hash,
+        note: file.note,
+        mime: file.mime,
         percent,
         state: file_state,
         is_sender,
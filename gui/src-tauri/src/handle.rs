@@ -1,23 +1,32 @@
-use std::fs::metadata;
+use std::fs::{create_dir_all, metadata};
 use std::mem::replace;
-use std::net::Ipv6Addr;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
 use std::process::Command;
 use std::str::FromStr;
-use std::sync::mpsc::SyncSender;
-use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 use tauri::{AppHandle, State, Wry};
+use chunk::file::file::OverwritePolicy;
+use chunk::hash::hash::Hash;
 use p2p::client::tcp::{TcpClientReader, TcpClientWriter};
 use p2p::client::udp_slide::{UdpClientReader, UdpClientWriter};
-use p2p::client::{EncryptedReader, EncryptedWriter};
+use p2p::client::multi_flow::{MultiFlowReader, MultiFlowWriter};
+use p2p::client::{EncryptedReader, EncryptedWriter, PlainReader, PlainWriter};
+use p2p::identity::Identity;
+use p2p::nat::detect_nat_type_with_defaults;
 use p2p::protocol::{Connection, Waiting};
 
 use crate::client::Client;
-use crate::connect::thread_connect;
-use crate::error::{ClientError, ClientErrorKind};
-use crate::events::{send_bind_port, send_connect_status};
+use crate::connect::{thread_connect, ConnectOptions};
+use crate::error::{ClientError, ClientErrorKind, CommandResult};
+use crate::events::{send_bind_port, send_connect_status, FileState};
+
+/// Name of the file holding the identity key in the app data directory.
+const IDENTITY_FILE: &str = "identity.key";
 
 /// Wrapper for the application state.
 pub struct AppState(Arc<Mutex<Current>>);
@@ -32,13 +41,37 @@ impl AppState {
     }
 }
 
+/// Evaluates an expression with the client of a connected state, whichever transport and encryption it uses.
+/// Every other state evaluates the fallback expression.
+///
+/// # Example
+///
+/// `with_client!(state, client => client.deny_file(hash), _ => Err(...))`
+macro_rules! with_client {
+    ($current:expr, $client:ident => $connected:expr, _ => $otherwise:expr) => {
+        match $current {
+            Current::ConnectedUdp($client) => $connected,
+            Current::ConnectedTcp($client) => $connected,
+            Current::ConnectedPlain($client) => $connected,
+            _ => $otherwise,
+        }
+    };
+}
+
 /// Holds the current state of the application.
 pub enum Current {
     Broken,
     Disconnected(Connection<Waiting>),
-    Connecting(SyncSender<()>),
-    ConnectedUdp(Client<EncryptedWriter<UdpClientWriter>, EncryptedReader<UdpClientReader>>),
+    Connecting(Arc<AtomicBool>),
+    ConnectedUdp(
+        Client<
+            MultiFlowWriter<EncryptedWriter<UdpClientWriter>>,
+            MultiFlowReader<EncryptedReader<UdpClientReader>>,
+        >,
+    ),
     ConnectedTcp(Client<EncryptedWriter<TcpClientWriter>, EncryptedReader<TcpClientReader>>),
+    /// Connected without encryption, see the `encrypt` option of `connect`.
+    ConnectedPlain(Client<PlainWriter<UdpClientWriter>, PlainReader<UdpClientReader>>),
 }
 
 impl Current {
@@ -60,28 +93,123 @@ impl Current {
     }
 }
 
-/// Connects to the peer.
+/// Address family used to reach the peer.
+/// All connections use IPv6 sockets, IPv4 peers are reached by their IPv4-mapped address.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressFamily {
+    /// Accept IPv4 and IPv6 addresses.
+    Auto,
+    /// Only accept IPv4 addresses, including IPv4-mapped IPv6 addresses.
+    V4,
+    /// Only accept native IPv6 addresses.
+    V6,
+}
+
+impl FromStr for AddressFamily {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(AddressFamily::Auto),
+            "v4" => Ok(AddressFamily::V4),
+            "v6" => Ok(AddressFamily::V6),
+            _ => Err(ClientError::new(ClientErrorKind::InvalidArgument)),
+        }
+    }
+}
+
+/// Parses the address of the peer for the given address family.
+///
+/// # Arguments
+///
+/// * `ip` - The address entered by the user.
+/// * `family` - The address family to use.
+///
+/// # Returns
+///
+/// Returns the `Ipv6Addr` to connect to, or an `Err` containing a `ClientError` if the address does not match the family.
+pub fn parse_peer_address(ip: &str, family: AddressFamily) -> Result<Ipv6Addr, ClientError> {
+    let ipv4 = Ipv4Addr::from_str(ip)
+        .ok()
+        .or_else(|| Ipv6Addr::from_str(ip).ok().and_then(|ipv6| ipv6.to_ipv4_mapped()));
+    let ipv6 = Ipv6Addr::from_str(ip)
+        .ok()
+        .filter(|ipv6| ipv6.to_ipv4_mapped().is_none());
+
+    let address = match family {
+        AddressFamily::Auto => ipv4.map(|ipv4| ipv4.to_ipv6_mapped()).or(ipv6),
+        AddressFamily::V4 => ipv4.map(|ipv4| ipv4.to_ipv6_mapped()),
+        AddressFamily::V6 => ipv6,
+    };
+
+    address.ok_or_else(|| ClientError::new(ClientErrorKind::Ipv6ParseFailed))
+}
+
+/// Loads the identity presented to the peer, see `Identity::load_or_create`.
+///
+/// # Arguments
+///
+/// * `app_handle` - Handle for the tauri application.
+/// * `path` - The file of the identity key. `None` keeps it in the app data directory.
+///
+/// # Returns
+///
+/// Returns the `Identity`, or an `Err` containing a `ClientError` if the key can't be loaded or created.
+fn load_identity(app_handle: &AppHandle<Wry>, path: Option<String>) -> Result<Identity, ClientError> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let dir = app_handle
+                .path_resolver()
+                .app_data_dir()
+                .ok_or_else(|| ClientError::new(ClientErrorKind::IdentityUnavailable))?;
+            create_dir_all(&dir)?;
+            dir.join(IDENTITY_FILE)
+        }
+    };
+
+    Identity::load_or_create(&path).map_err(|_| ClientError::new(ClientErrorKind::IdentityUnavailable))
+}
+
+/// Connects to the peer. The connection is encrypted unless `encrypt` is false,
+/// which is only meant for trusted networks and has to be chosen by both peers.
+/// `num_flows` stripes transfers across several UDP flows, the smaller number of both peers is used.
+/// `tune_chunk_size` measures the throughput of the chunk sizes at connection setup and sends files in the fastest one.
+/// The peer recognizes this peer after a reconnect by its identity, which is kept in the file `identity`
+/// or in the app data directory.
 #[tauri::command]
 pub fn connect(
     app_handle: AppHandle<Wry>,
     app_state: State<AppState>,
     ip: String,
     port: u16,
-) -> Result<(), ClientError> {
-    let ipv6 = match Ipv6Addr::from_str(&*ip) {
-        Ok(c) => c,
-        Err(_) => {
-            return Err(ClientError::new(ClientErrorKind::Ipv6ParseFailed));
-        }
+    family: Option<String>,
+    encrypt: Option<bool>,
+    num_flows: Option<usize>,
+    tune_chunk_size: Option<bool>,
+    identity: Option<String>,
+) -> CommandResult<()> {
+    let family = match family {
+        Some(family) => AddressFamily::from_str(&family)?,
+        None => AddressFamily::Auto,
+    };
+    let ipv6 = parse_peer_address(&ip, family)?;
+    let defaults = ConnectOptions::default();
+    let options = ConnectOptions {
+        encrypt: encrypt.unwrap_or(defaults.encrypt),
+        num_flows: num_flows.unwrap_or(defaults.num_flows).max(1),
+        tune_chunk_size: tune_chunk_size.unwrap_or(defaults.tune_chunk_size),
     };
+    let identity = load_identity(&app_handle, identity)?;
 
     let mut unlocked_state = app_state.0.lock().unwrap();
 
-    let (sender, receiver) = mpsc::sync_channel::<()>(0);
+    let cancel = Arc::new(AtomicBool::new(false));
+    let cancel_clone = cancel.clone();
 
     let connection = match unlocked_state.deref() {
         Current::Disconnected(_) => {
-            let prev_state = replace(&mut *unlocked_state, Current::Connecting(sender));
+            let prev_state = replace(&mut *unlocked_state, Current::Connecting(cancel));
 
             match prev_state {
                 Current::Disconnected(connection) => connection,
@@ -100,7 +228,9 @@ pub fn connect(
     send_connect_status(&app_handle, "Connecting", "Waiting for response from peer.")?;
 
     let current: Arc<Mutex<Current>> = app_state.current().clone();
-    thread::spawn(move || thread_connect(app_handle, current, connection, receiver, ipv6, port));
+    thread::spawn(move || {
+        thread_connect(app_handle, current, connection, cancel_clone, ipv6, port, options, Some(identity))
+    });
 
     return Ok(());
 }
@@ -110,14 +240,24 @@ pub fn connect(
 pub fn disconnect(
     app_handle: AppHandle<Wry>,
     app_state: State<AppState>,
-) -> Result<(), ClientError> {
+) -> CommandResult<()> {
     println!("[EVENT] Disconnect");
-    let unlocked_state = (*app_state).0.lock()?;
+    let mut unlocked_state = (*app_state).0.lock()?;
+
+    // best effort to tell the peer about every transfer which ends with the connection
+    let cancelled = with_client!(
+        unlocked_state.deref_mut(),
+        client => client.cancel_all(),
+        _ => Ok(())
+    );
+    if let Err(err) = cancelled {
+        println!("[EVENT] Disconnect : can't cancel transfers {}", err);
+    }
 
     match unlocked_state.deref() {
-        Current::Connecting(sender) => {
+        Current::Connecting(cancel) => {
             println!("Connecting");
-            sender.send(())?;
+            cancel.store(true, Ordering::SeqCst);
             drop(unlocked_state);
             start(app_handle, app_state)
         }
@@ -129,17 +269,69 @@ pub fn disconnect(
     }
 }
 
-/// Offers a file to the peer.
+/// Offers a file to the peer. A precomputed hash skips hashing the file, unless it is verified.
+/// The hash algorithm is named like `Hash::from_str` expects it and defaults to siphash24.
 #[tauri::command]
-pub fn offer_file(app_state: State<AppState>, path: String) -> Result<(), ClientError> {
+pub fn offer_file(
+    app_state: State<AppState>,
+    path: String,
+    note: Option<String>,
+    hash: Option<String>,
+    verify_hash: Option<bool>,
+    hash_type: Option<String>,
+) -> CommandResult<()> {
     println!("[EVENT] offer_file");
+    let hash_type = match hash_type {
+        Some(name) => Hash::from_str(&name).map_err(|_| ClientError::new(ClientErrorKind::InvalidArgument))?,
+        None => Hash::SIPHASH24,
+    };
     let mut unlocked_state = (*app_state).0.lock()?;
+    let note = note.unwrap_or_default();
+    let verify_hash = verify_hash.unwrap_or(false);
+
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.offer_file(path, note, hash, verify_hash, hash_type),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
+}
 
-    match unlocked_state.deref_mut() {
-        &mut Current::ConnectedUdp(ref mut client) => client.offer_file(path),
-        &mut Current::ConnectedTcp(ref mut client) => client.offer_file(path),
-        _ => Err(ClientError::new(ClientErrorKind::WrongState)),
-    }
+/// Offers all files of a directory.
+#[tauri::command]
+pub fn offer_directory(
+    app_state: State<AppState>,
+    path: String,
+    note: Option<String>,
+    recursive: Option<bool>,
+) -> CommandResult<usize> {
+    println!("[EVENT] offer_directory");
+    let mut unlocked_state = (*app_state).0.lock()?;
+    let note = note.unwrap_or_default();
+    let recursive = recursive.unwrap_or(false);
+
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.offer_directory(path, note, recursive),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
+}
+
+/// Offers several files as a group, which the peer only keeps as a whole.
+#[tauri::command]
+pub fn offer_group(
+    app_state: State<AppState>,
+    paths: Vec<String>,
+    note: Option<String>,
+) -> CommandResult<String> {
+    println!("[EVENT] offer_group");
+    let mut unlocked_state = (*app_state).0.lock()?;
+    let note = note.unwrap_or_default();
+
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.offer_group(paths, note),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
 }
 
 /// Accepts the receive of a file.
@@ -148,83 +340,272 @@ pub fn accept_file(
     app_state: State<AppState>,
     hash: String,
     path: String,
-) -> Result<(), ClientError> {
+    overwrite: Option<String>,
+    temp_dir: Option<String>,
+    manifest: Option<bool>,
+) -> CommandResult<()> {
     println!("[EVENT] accept_file");
+    let manifest = manifest.unwrap_or(false);
+    let policy = match overwrite {
+        Some(policy) => OverwritePolicy::from_str(&policy)
+            .map_err(|_| ClientError::new(ClientErrorKind::InvalidArgument))?,
+        None => OverwritePolicy::Overwrite,
+    };
+
     let mut unlocked_state = (*app_state).0.lock()?;
 
-    match unlocked_state.deref_mut() {
-        &mut Current::ConnectedUdp(ref mut client) => client.accept_file(hash, path),
-        &mut Current::ConnectedTcp(ref mut client) => client.accept_file(hash, path),
-        _ => Err(ClientError::new(ClientErrorKind::WrongState)),
-    }
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.accept_file(hash, path, policy, temp_dir, manifest),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
+}
+
+/// Accepts the receive of several files as a batch, which ends with an `app://batch-summary` event.
+#[tauri::command]
+pub fn accept_batch(
+    app_state: State<AppState>,
+    hashes: Vec<String>,
+    path: String,
+    overwrite: Option<String>,
+    temp_dir: Option<String>,
+    max_retries: Option<u8>,
+) -> CommandResult<()> {
+    println!("[EVENT] accept_batch");
+    let max_retries = max_retries.unwrap_or(0);
+    let policy = match overwrite {
+        Some(policy) => OverwritePolicy::from_str(&policy)
+            .map_err(|_| ClientError::new(ClientErrorKind::InvalidArgument))?,
+        None => OverwritePolicy::Overwrite,
+    };
+
+    let mut unlocked_state = (*app_state).0.lock()?;
+
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.accept_batch(hashes, path, policy, temp_dir, max_retries),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
 }
 
 /// Denies the receive of a file.
 #[tauri::command]
-pub fn deny_file(app_state: State<AppState>, hash: String) -> Result<(), ClientError> {
+pub fn deny_file(app_state: State<AppState>, hash: String) -> CommandResult<()> {
     println!("[EVENT] deny_file");
     let mut unlocked_state = (*app_state).0.lock()?;
 
-    match unlocked_state.deref_mut() {
-        &mut Current::ConnectedUdp(ref mut client) => client.deny_file(hash),
-        &mut Current::ConnectedTcp(ref mut client) => client.deny_file(hash),
-        _ => Err(ClientError::new(ClientErrorKind::WrongState)),
-    }
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.deny_file(hash),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
+}
+
+/// Withdraws an offer which was not requested yet.
+#[tauri::command]
+pub fn rescind_offer(app_state: State<AppState>, hash: String) -> CommandResult<()> {
+    println!("[EVENT] rescind_offer");
+    let mut unlocked_state = (*app_state).0.lock()?;
+
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.rescind_offer(hash),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
+}
+
+/// Lists all offered and transferred files.
+#[tauri::command]
+pub fn list_transfers(
+    app_state: State<AppState>,
+) -> CommandResult<Vec<(String, FileState, f32)>> {
+    println!("[EVENT] list_transfers");
+    let unlocked_state = (*app_state).0.lock()?;
+
+    with_client!(
+        unlocked_state.deref(),
+        client => Ok(client.active_transfers()),
+        _ => Ok(vec![])
+    )
+}
+
+/// Counts the transferred and the missing bytes of a file.
+/// Returns `None` if the file is not offered or transferred.
+#[tauri::command]
+pub fn transfer_bytes(
+    app_state: State<AppState>,
+    hash: String,
+) -> CommandResult<Option<(u64, u64)>> {
+    println!("[EVENT] transfer_bytes");
+    let unlocked_state = (*app_state).0.lock()?;
+
+    let bytes = with_client!(
+        unlocked_state.deref(),
+        client => client.bytes_transferred(&hash).zip(client.bytes_remaining(&hash)),
+        _ => None
+    );
+
+    Ok(bytes)
 }
 
 /// Stops the sending of a file.
 #[tauri::command]
-pub fn stop_file(app_state: State<AppState>, hash: String) -> Result<(), ClientError> {
+pub fn stop_file(app_state: State<AppState>, hash: String) -> CommandResult<()> {
     println!("[EVENT] stop_file");
     let mut unlocked_state = (*app_state).0.lock()?;
 
-    match unlocked_state.deref_mut() {
-        &mut Current::ConnectedUdp(ref mut client) => client.stop_sending_file(hash),
-        &mut Current::ConnectedTcp(ref mut client) => client.stop_sending_file(hash),
-        _ => Err(ClientError::new(ClientErrorKind::WrongState)),
-    }
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.stop_sending_file(hash),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
 }
 
 /// Pauses the sending of a file.
 #[tauri::command]
-pub fn pause_file(app_state: State<AppState>, hash: String) -> Result<(), ClientError> {
+pub fn pause_file(app_state: State<AppState>, hash: String) -> CommandResult<()> {
     println!("[EVENT] pause_file");
     let mut unlocked_state = (*app_state).0.lock()?;
 
-    match unlocked_state.deref_mut() {
-        &mut Current::ConnectedUdp(ref mut client) => client.pause_file(hash),
-        &mut Current::ConnectedTcp(ref mut client) => client.pause_file(hash),
-        _ => Err(ClientError::new(ClientErrorKind::WrongState)),
-    }
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.pause_file(hash),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
 }
 
-/// Starts or restarts the client.
+/// Stops sending data for all files, while keeping the connection alive.
 #[tauri::command]
-pub fn start(app_handle: AppHandle<Wry>, app_state: State<AppState>) -> Result<(), ClientError> {
-    println!("[EVENT] start");
+pub fn suspend_connection(app_state: State<AppState>) -> CommandResult<()> {
+    println!("[EVENT] suspend_connection");
     let mut unlocked_state = (*app_state).0.lock()?;
 
-    match unlocked_state.deref() {
-        Current::Disconnected(c) => {
-            send_bind_port(&app_handle, c.get_port())?;
-            println!("port: {}", c.get_port());
-            Ok(())
-        }
-        Current::ConnectedUdp(c) => {
-            println!("ConnectedUdp");
-            let port = c.get_port();
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.suspend(),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
+}
 
-            let old_state = replace(&mut *unlocked_state, Current::Broken);
-            drop(old_state);
+/// Continues sending data after `suspend_connection`.
+#[tauri::command]
+pub fn resume_connection(app_state: State<AppState>) -> CommandResult<()> {
+    println!("[EVENT] resume_connection");
+    let mut unlocked_state = (*app_state).0.lock()?;
 
-            *unlocked_state = Current::try_with_port(port);
-            drop(unlocked_state);
-            start(app_handle, app_state)
-        }
-        Current::ConnectedTcp(c) => {
-            println!("ConnectedTcp");
-            let port = c.get_port();
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.resume(),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
+}
+
+/// Sets the number of chunks of a file which are queued into the transport at once.
+#[tauri::command]
+pub fn set_pipeline_depth(app_state: State<AppState>, depth: usize) -> CommandResult<()> {
+    println!("[EVENT] set_pipeline_depth");
+    let mut unlocked_state = (*app_state).0.lock()?;
+
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.set_pipeline_depth(depth),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
+}
+
+/// Sets whether sent chunks are checked against hashes taken when the file was offered.
+#[tauri::command]
+pub fn set_verify_source(app_state: State<AppState>, enabled: bool) -> CommandResult<()> {
+    println!("[EVENT] set_verify_source");
+    let mut unlocked_state = (*app_state).0.lock()?;
+
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.set_verify_source(enabled),
+        _ => return Err(ClientError::new(ClientErrorKind::WrongState))
+    );
+
+    Ok(())
+}
+
+/// Sets whether offers carry the Merkle root over the chunks of the file.
+#[tauri::command]
+pub fn set_merkle_roots(app_state: State<AppState>, enabled: bool) -> CommandResult<()> {
+    println!("[EVENT] set_merkle_roots");
+    let mut unlocked_state = (*app_state).0.lock()?;
+
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.set_merkle_roots(enabled),
+        _ => return Err(ClientError::new(ClientErrorKind::WrongState))
+    );
 
+    Ok(())
+}
+
+/// Sets how many received chunks are collected before they are written to disk.
+/// `interval_ms` is the time in milliseconds after which a partial batch is written.
+#[tauri::command]
+pub fn set_write_batching(
+    app_state: State<AppState>,
+    max_chunks: usize,
+    interval_ms: u64,
+) -> CommandResult<()> {
+    println!("[EVENT] set_write_batching");
+    let interval = Duration::from_millis(interval_ms);
+    let mut unlocked_state = (*app_state).0.lock()?;
+
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.set_write_batching(max_chunks, interval),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
+}
+
+/// Sets the template for the names of received files, e.g. `{date}_{name}`.
+/// Supported placeholders are `{name}`, `{hash}`, `{date}` and `{ext}`.
+#[tauri::command]
+pub fn set_name_template(app_state: State<AppState>, template: String) -> CommandResult<()> {
+    println!("[EVENT] set_name_template");
+    let mut unlocked_state = (*app_state).0.lock()?;
+
+    with_client!(
+        unlocked_state.deref_mut(),
+        client => client.set_name_template(&template),
+        _ => Err(ClientError::new(ClientErrorKind::WrongState))
+    )
+}
+
+/// Detects the NAT type of the local host, so a doomed direct connection can be warned about.
+/// Returns a description like "symmetric NAT, direct connection unlikely".
+#[tauri::command]
+pub fn nat_type() -> CommandResult<String> {
+    println!("[EVENT] nat_type");
+    let nat_type = detect_nat_type_with_defaults(Duration::from_secs(2))?;
+
+    Ok(nat_type.to_string())
+}
+
+/// Starts or restarts the client.
+#[tauri::command]
+pub fn start(app_handle: AppHandle<Wry>, app_state: State<AppState>) -> CommandResult<()> {
+    println!("[EVENT] start");
+    let mut unlocked_state = (*app_state).0.lock()?;
+
+    if let Current::Disconnected(c) = unlocked_state.deref() {
+        send_bind_port(&app_handle, c.get_port())?;
+        println!("port: {}", c.get_port());
+        return Ok(());
+    }
+
+    let connected_port = with_client!(
+        unlocked_state.deref(),
+        client => Some(client.get_port()),
+        _ => None
+    );
+    match connected_port {
+        Some(port) => {
+            println!("Connected");
             let old_state = replace(&mut *unlocked_state, Current::Broken);
             println!("drop old_state");
             drop(old_state);
@@ -234,7 +615,7 @@ pub fn start(app_handle: AppHandle<Wry>, app_state: State<AppState>) -> Result<(
             drop(unlocked_state);
             start(app_handle, app_state)
         }
-        _ => {
+        None => {
             println!("Other");
             *unlocked_state = Current::new();
             drop(unlocked_state);
@@ -287,3 +668,48 @@ pub fn show_in_folder(path: String) {
         Command::new("open").args(["-R", &path]).spawn().unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_peer_address_auto() {
+        let mapped = Ipv4Addr::new(192, 168, 0, 1).to_ipv6_mapped();
+
+        assert_eq!(parse_peer_address("192.168.0.1", AddressFamily::Auto).unwrap(), mapped);
+        assert_eq!(parse_peer_address("::ffff:192.168.0.1", AddressFamily::Auto).unwrap(), mapped);
+        assert_eq!(
+            parse_peer_address("2001:db8::1", AddressFamily::Auto).unwrap(),
+            Ipv6Addr::from_str("2001:db8::1").unwrap()
+        );
+        assert!(parse_peer_address("localhost", AddressFamily::Auto).is_err());
+    }
+
+    #[test]
+    fn test_parse_peer_address_v4() {
+        let mapped = Ipv4Addr::new(192, 168, 0, 1).to_ipv6_mapped();
+
+        assert_eq!(parse_peer_address("192.168.0.1", AddressFamily::V4).unwrap(), mapped);
+        assert_eq!(parse_peer_address("::ffff:192.168.0.1", AddressFamily::V4).unwrap(), mapped);
+        assert!(parse_peer_address("2001:db8::1", AddressFamily::V4).is_err());
+    }
+
+    #[test]
+    fn test_parse_peer_address_v6() {
+        assert!(parse_peer_address("192.168.0.1", AddressFamily::V6).is_err());
+        assert!(parse_peer_address("::ffff:192.168.0.1", AddressFamily::V6).is_err());
+        assert_eq!(
+            parse_peer_address("2001:db8::1", AddressFamily::V6).unwrap(),
+            Ipv6Addr::from_str("2001:db8::1").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_address_family_from_str() {
+        assert_eq!(AddressFamily::from_str("auto").unwrap(), AddressFamily::Auto);
+        assert_eq!(AddressFamily::from_str("v4").unwrap(), AddressFamily::V4);
+        assert_eq!(AddressFamily::from_str("v6").unwrap(), AddressFamily::V6);
+        assert!(AddressFamily::from_str("ipx").is_err());
+    }
+}
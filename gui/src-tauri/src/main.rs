@@ -35,9 +35,23 @@ fn main() {
             handle::connect,
             handle::disconnect,
             handle::offer_file,
+            handle::offer_directory,
+            handle::offer_group,
             handle::accept_file,
+            handle::accept_batch,
             handle::deny_file,
+            handle::rescind_offer,
+            handle::list_transfers,
+            handle::transfer_bytes,
             handle::pause_file,
+            handle::suspend_connection,
+            handle::resume_connection,
+            handle::set_pipeline_depth,
+            handle::set_verify_source,
+            handle::set_merkle_roots,
+            handle::set_write_batching,
+            handle::set_name_template,
+            handle::nat_type,
             handle::start,
             handle::show_in_folder,
             handle::stop_file
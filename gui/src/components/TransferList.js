@@ -83,6 +83,7 @@ export default function TransferList() {
     const handleCancel = (file) => {
         if (file.state === FileState.PENDING) invoke('deny_file', { hash: file.hash });
         else if (file.state === FileState.TRANSFERING) invoke('stop_file', { hash: file.hash });
+        else if (file.is_sender && file.state === FileState.UNANSWERED) invoke('rescind_offer', { hash: file.hash });
     };
 
     const handleDownload = async (file) => {
@@ -121,9 +122,11 @@ export default function TransferList() {
                             </div>
                             <div className='transfer-list-item-status'>
                                 {file.state === FileState.PENDING && <p className='body-large'>Pending</p>}
+                                {file.state === FileState.UNANSWERED && <p className='body-large'>No answer</p>}
                                 {file.state === FileState.TRANSFERRING && <p className='body-large'>{(file.percent * 100).toFixed(2)}%</p>}
                                 {file.state === FileState.COMPLETED && <p className='body-large'>Completed</p>}
                                 {file.state === FileState.CORRUPTED && <p className='body-large'>Corrupted</p>}
+                                {file.state === FileState.CHANGED && <p className='body-large'>Changed on disk</p>}
                             </div>
                             <div className='transfer-list-item-actions flex'>
                                 {canDownload && (
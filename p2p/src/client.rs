@@ -1,12 +1,70 @@
 use dryoc::dryocstream::{DryocStream, Pull, Push, Tag};
 
 use crate::error::Error as P2pError;
+use crate::error::ErrorKind;
+use socket2::{Domain, SockAddr, Socket, Type};
 
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
+pub mod multi_flow;
 pub mod tcp;
 pub mod udp_slide;
 pub mod udp_send_wait;
 
+/// The transport protocol a Client uses to talk to the peer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProtocolKind {
+    Udp,
+    Tcp,
+    /// A transport provided outside of this crate, see `Connection::from_transport`.
+    Other,
+}
+
+/// Binds a dual-stack UDP socket on all interfaces.
+/// IPv6 only mode is disabled, so IPv4 peers reach the socket by their IPv4-mapped address.
+///
+/// # Arguments
+///
+/// * `port` - The port to bind to. `None` binds to a random port.
+pub(crate) fn bind_dual_stack_udp(port: Option<u16>) -> Result<UdpSocket, P2pError> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, None)?;
+    socket.set_only_v6(false)?;
+
+    let bind_addr = SocketAddr::new(IpAddr::from(Ipv6Addr::UNSPECIFIED), port.unwrap_or(0));
+    socket.bind(&SockAddr::from(bind_addr))?;
+
+    return Ok(socket.into());
+}
+
+/// Maps an address into the IPv6 address space. IPv4 addresses are mapped to `::ffff:a.b.c.d`.
+pub fn to_dual_stack(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ipv4) => ipv4.to_ipv6_mapped(),
+        IpAddr::V6(ipv6) => ipv6,
+    }
+}
+
+/// Returns the address a socket has to use to reach the peer.
+/// A socket bound to an IPv4 address reaches IPv4-mapped peers by their IPv4 address.
+pub(crate) fn peer_socket_addr(socket: &UdpSocket, peer: Ipv6Addr, port: u16) -> SocketAddr {
+    let is_ipv4 = matches!(socket.local_addr(), Ok(SocketAddr::V4(_)));
+
+    match peer.to_ipv4_mapped() {
+        Some(ipv4) if is_ipv4 => SocketAddr::new(IpAddr::from(ipv4), port),
+        _ => SocketAddr::new(IpAddr::from(peer), port),
+    }
+}
+
+/// Priority of a message, see `ClientWriter::write_priority`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    /// Bulk data, sent in the order it was written.
+    Normal,
+    /// Small control messages, sent before any queued `Normal` messages.
+    High,
+}
+
 /// A Client waiting to be connected to a peer.
 /// The Client is already bound to a port.
 pub trait WaitingClient {
@@ -35,40 +93,205 @@ pub trait ClientReader {
     fn try_read(&mut self) -> Result<Vec<u8>, P2pError>;
     /// Read a message from the peer, in a given timeout.
     fn read(&mut self, timeout: Option<Duration>) -> Result<Vec<u8>, P2pError>;
+    /// Check if a message can be read without waiting, without consuming it.
+    /// Readers which cannot tell return `false`.
+    fn has_message(&self) -> bool {
+        false
+    }
+    /// Get the transport protocol the Reader receives over.
+    /// Readers of a transport provided outside of this crate return `ProtocolKind::Other`.
+    fn protocol_kind(&self) -> ProtocolKind {
+        ProtocolKind::Other
+    }
 }
 
 /// Writer part of a Client connected to a peer.
 pub trait ClientWriter {
     /// Write a message to the peer.
     fn write(&mut self, msg: &[u8]) -> Result<(), P2pError>;
+    /// Change the timeout used by subsequent writes. `None` waits without limit.
+    /// Writers without a timeout ignore the call.
+    fn set_write_timeout(&mut self, _timeout: Option<Duration>) {}
+    /// Get the transport protocol the Writer sends over.
+    /// Writers of a transport provided outside of this crate return `ProtocolKind::Other`.
+    fn protocol_kind(&self) -> ProtocolKind {
+        ProtocolKind::Other
+    }
+    /// Stop sending messages while keeping the connection alive.
+    /// Writers which cannot suspend ignore the call.
+    fn suspend(&mut self) {}
+    /// Continue sending messages after `suspend`.
+    fn resume(&mut self) {}
+    /// Write a message which can be dropped with `discard_tagged` while it is queued.
+    /// Writers which cannot discard write it like any other message.
+    fn write_tagged(&mut self, msg: &[u8], _tag: u64) -> Result<(), P2pError> {
+        self.write(msg)
+    }
+    /// Drop all queued messages written with `tag` which were not sent yet.
+    /// Returns `false` if the writer cannot discard messages, e.g. an encrypted writer whose underlying writer
+    /// does not encrypt the blocks itself, so the peer could not decrypt the remaining stream.
+    fn discard_tagged(&mut self, _tag: u64) -> bool {
+        false
+    }
+    /// Write a message with the given priority. `High` messages are sent before queued `Normal` messages,
+    /// the order within each priority is kept.
+    /// Writers which cannot reorder messages write it like any other message.
+    fn write_priority(&mut self, msg: &[u8], _priority: Priority) -> Result<(), P2pError> {
+        self.write(msg)
+    }
+    /// Let the writer encode the blocks of a `PlainWriter` or `EncryptedWriter` right before sending them,
+    /// so queued blocks can still be reordered, e.g. by `write_priority`.
+    /// Returns the encoder if the writer sends every block in the order it was written.
+    fn set_block_encoder(&mut self, encoder: BlockEncoder) -> Result<(), BlockEncoder> {
+        Err(encoder)
+    }
+    /// Set the maximum transmission unit of the path, e.g. a configured or discovered MTU. `None` removes it.
+    /// Writers which do not send datagrams ignore the call.
+    fn set_mtu(&mut self, _mtu: Option<usize>) {}
+    /// Get the largest message which is sent in a single datagram, after all headers and overhead of the writer.
+    /// Writers which do not send datagrams return `usize::MAX`.
+    fn max_payload(&self) -> usize {
+        usize::MAX
+    }
+    /// Send `count` probes of `len` bytes which the peer acknowledges and drops instead of delivering them,
+    /// and wait until they are acknowledged or `timeout` passed, see `throughput::probe_throughput`.
+    /// Returns the number of probe bytes the peer acknowledged.
+    /// Writers which cannot send probes return an error of kind `ProbeUnsupported`.
+    fn probe(&mut self, _len: usize, _count: usize, _timeout: Duration) -> Result<u64, P2pError> {
+        Err(P2pError::new(ErrorKind::ProbeUnsupported))
+    }
+}
+
+/// Encryption block size.
+/// Warning: cannot be too big, as it is limited by the maximum size
+/// of a UDP/TCP packet.
+const BLOCK_SIZE: usize = 1024;
+
+/// Bytes of the header the UDP client adds to every packet.
+pub const UDP_HEADER_LEN: usize = udp_slide::HEADER_SIZE;
+/// Bytes of the checksum the UDP client appends to every packet if checksums are enabled.
+pub const CHECKSUM_LEN: usize = udp_slide::CHECKSUM_SIZE;
+/// Bytes the encryption adds to every encrypted block, a tag and the authentication code.
+pub const CRYPTO_OVERHEAD: usize = 17;
+/// Bytes of the IPv6 and UDP headers of a datagram. IPv4 headers are smaller.
+pub const IP_UDP_HEADER_LEN: usize = 40 + 8;
+/// Largest payload of a UDP datagram over IPv4. IPv6 allows slightly more.
+pub const MAX_UDP_PAYLOAD: usize = 65507;
+
+/// Calculates the UDP payload of a datagram on a path with the given MTU.
+///
+/// # Arguments
+///
+/// * `mtu` - The maximum transmission unit of the path. `None` if it is unknown.
+///
+/// # Returns
+///
+/// Returns the payload in bytes, at most `MAX_UDP_PAYLOAD`.
+pub(crate) fn datagram_payload(mtu: Option<usize>) -> usize {
+    match mtu {
+        Some(mtu) => mtu.saturating_sub(IP_UDP_HEADER_LEN).min(MAX_UDP_PAYLOAD),
+        None => MAX_UDP_PAYLOAD,
+    }
 }
 
-/// A Client connected to a peer, which encrypts the communication.
-pub struct EncryptedClient<AC: ActiveClient> {
-    _active_client: AC,
+/// Calculates how many bytes of a message fit into a single encrypted UDP datagram without IP fragmentation.
+/// Accounts for the IP and UDP headers, the header and the checksum of the UDP client and the encryption overhead.
+/// An encrypted block never carries more than the encryption block size.
+/// This is the `ClientWriter::max_payload` of an encrypted UDP writer with checksums,
+/// without checksums `CHECKSUM_LEN` more bytes fit.
+///
+/// # Arguments
+///
+/// * `mtu` - The maximum transmission unit of the path.
+///
+/// # Returns
+///
+/// Returns the usable payload in bytes, 0 if the MTU is too small for the headers.
+pub fn usable_payload(mtu: usize) -> usize {
+    datagram_payload(Some(mtu))
+        .saturating_sub(UDP_HEADER_LEN + CHECKSUM_LEN + CRYPTO_OVERHEAD)
+        .min(BLOCK_SIZE)
 }
 
-impl<AC: ActiveClient> EncryptedClient<AC> {
-    pub fn new(
-        active_client: AC,
-        push_stream: DryocStream<Push>,
-        pull_stream: DryocStream<Pull>,
-    ) -> (EncryptedReader<AC::Reader>, EncryptedWriter<AC::Writer>) {
-        let (writer, reader) = active_client.split();
+/// Push stream of an encrypted connection, shared with the writer which encrypts the blocks, see `BlockEncoder`.
+pub(crate) type SharedPushStream = Arc<Mutex<DryocStream<Push>>>;
+
+/// Encodes the blocks of a `PlainWriter` or `EncryptedWriter` when the underlying writer sends them,
+/// see `ClientWriter::set_block_encoder`.
+/// The blocks are queued as plain blocks; the encoder encrypts them if the connection is encrypted.
+/// Blocks are encrypted in the order they are sent, so the peer can decrypt them even if they were reordered in the queue.
+pub struct BlockEncoder {
+    /// `None` sends the plain blocks unchanged.
+    push_stream: Option<SharedPushStream>,
+    /// Set if the last encoded block was the last block of its message.
+    at_boundary: bool,
+}
+
+impl BlockEncoder {
+    fn new(push_stream: Option<SharedPushStream>) -> BlockEncoder {
+        BlockEncoder {
+            push_stream,
+            at_boundary: true,
+        }
+    }
 
-        let encrypted_reader = EncryptedReader::new(pull_stream, reader);
-        let encrypted_writer = EncryptedWriter::new(push_stream, writer);
+    /// Encodes a plain block written by the `PlainWriter` or `EncryptedWriter`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the block to send, or a `P2pError` if it is no plain block or can't be encrypted.
+    pub(crate) fn encode(&mut self, block: &[u8]) -> Result<Vec<u8>, P2pError> {
+        let (tag, data) = match block.split_first() {
+            Some((&PLAIN_TAG_MESSAGE, data)) => (Tag::MESSAGE, data),
+            Some((&PLAIN_TAG_PUSH, data)) => (Tag::PUSH, data),
+            _ => return Err(P2pError::new(ErrorKind::IllegalByteStream)),
+        };
+
+        let encoded = match &self.push_stream {
+            Some(push_stream) => push_stream
+                .lock()
+                .map_err(|_| P2pError::new(ErrorKind::EncryptionFailed))?
+                .push_to_vec(&data, None, tag)?,
+            None => block.to_vec(),
+        };
+        self.at_boundary = tag == Tag::PUSH;
+
+        Ok(encoded)
+    }
 
-        (encrypted_reader, encrypted_writer)
+    /// Returns `true` if no message is sent partially, so a message of another priority can be sent next.
+    pub(crate) fn at_boundary(&self) -> bool {
+        self.at_boundary
     }
 }
 
-/// Encryption block size.
-/// Warning: cannot be too big, as it is limited by the maximum size
-/// of a UDP/TCP packet.
-const BLOCK_SIZE: usize = 1024;
+/// Splits a message into plain blocks of the encryption block size.
+/// Every block starts with a tag telling if more blocks of the message follow.
+/// An empty message is still sent as a single empty block.
+fn plain_blocks(msg: &[u8]) -> Vec<Vec<u8>> {
+    let mut blocks = msg.chunks(BLOCK_SIZE).peekable();
 
-/// Reader part of an EncryptedClient.
+    if blocks.peek().is_none() {
+        return vec![vec![PLAIN_TAG_PUSH]];
+    }
+
+    let mut plain_blocks = Vec::with_capacity(msg.len() / BLOCK_SIZE + 1);
+    while let Some(block) = blocks.next() {
+        let tag = match blocks.peek() {
+            Some(_) => PLAIN_TAG_MESSAGE,
+            None => PLAIN_TAG_PUSH,
+        };
+
+        let mut plain_block = Vec::with_capacity(block.len() + PLAIN_TAG_LEN);
+        plain_block.push(tag);
+        plain_block.extend_from_slice(block);
+        plain_blocks.push(plain_block);
+    }
+
+    plain_blocks
+}
+
+/// Reader part of an encrypted connection, see `Connection::accept`.
 pub struct EncryptedReader<CR: ClientReader> {
     pub(crate) pull_stream: DryocStream<Pull>,
     client_reader: CR,
@@ -76,7 +299,7 @@ pub struct EncryptedReader<CR: ClientReader> {
 }
 
 impl<CR: ClientReader> EncryptedReader<CR> {
-    pub fn new(pull_stream: DryocStream<Pull>, client_reader: CR) -> EncryptedReader<CR> {
+    pub(crate) fn new(pull_stream: DryocStream<Pull>, client_reader: CR) -> EncryptedReader<CR> {
         EncryptedReader {
             client_reader,
             pull_stream,
@@ -86,6 +309,16 @@ impl<CR: ClientReader> EncryptedReader<CR> {
 }
 
 impl<CR: ClientReader> ClientReader for EncryptedReader<CR> {
+    fn protocol_kind(&self) -> ProtocolKind {
+        self.client_reader.protocol_kind()
+    }
+
+    /// Checks if the underlying reader holds an encrypted block.
+    /// The block might only be the beginning of a message spanning multiple blocks.
+    fn has_message(&self) -> bool {
+        self.client_reader.has_message()
+    }
+
     fn try_read(&mut self) -> Result<Vec<u8>, P2pError> {
         let mut msg: Vec<u8> = self.buffer.take().unwrap_or(Vec::new());
 
@@ -137,23 +370,46 @@ impl<CR: ClientReader> ClientReader for EncryptedReader<CR> {
     }
 }
 
-/// Writer part of an EncryptedClient.
+/// Writer part of an encrypted connection, see `Connection::accept`.
 pub struct EncryptedWriter<CW: ClientWriter> {
-    pub(crate) push_stream: DryocStream<Push>,
+    pub(crate) push_stream: SharedPushStream,
     client_writer: CW,
+    max_msg_len: Option<usize>,
+    /// Set if the underlying writer encrypts the blocks, see `ClientWriter::set_block_encoder`.
+    delegated: bool,
 }
 
 impl<CW: ClientWriter> EncryptedWriter<CW> {
-    pub fn new(push_stream: DryocStream<Push>, client_writer: CW) -> EncryptedWriter<CW> {
+    pub(crate) fn new(push_stream: SharedPushStream, mut client_writer: CW) -> EncryptedWriter<CW> {
+        let delegated = client_writer
+            .set_block_encoder(BlockEncoder::new(Some(push_stream.clone())))
+            .is_ok();
+
         EncryptedWriter {
             client_writer,
             push_stream,
+            max_msg_len: None,
+            delegated,
         }
     }
-}
 
-impl<CW: ClientWriter> ClientWriter for EncryptedWriter<CW> {
-    fn write(&mut self, msg: &[u8]) -> Result<(), P2pError> {
+    /// Returns the maximum length of a message, as agreed with the peer. `None` if there is no limit.
+    pub fn max_msg_len(&self) -> Option<usize> {
+        self.max_msg_len
+    }
+
+    /// Sets the maximum length of a message. Longer messages are rejected with `ErrorKind::MessageTooLarge`.
+    pub fn set_max_msg_len(&mut self, max_msg_len: Option<usize>) {
+        self.max_msg_len = max_msg_len;
+    }
+
+    /// Encrypts a message block by block and writes the blocks to the underlying writer.
+    fn write_encrypted(&mut self, msg: &[u8]) -> Result<(), P2pError> {
+        let mut push_stream = self
+            .push_stream
+            .lock()
+            .map_err(|_| P2pError::new(ErrorKind::EncryptionFailed))?;
+
         for i in (BLOCK_SIZE..=msg.len()).step_by(BLOCK_SIZE) {
             let block = &msg[i - BLOCK_SIZE..i];
 
@@ -162,7 +418,7 @@ impl<CW: ClientWriter> ClientWriter for EncryptedWriter<CW> {
                 tag = Tag::PUSH;
             }
 
-            let encrypted_block = self.push_stream.push_to_vec(&block, None, tag)?;
+            let encrypted_block = push_stream.push_to_vec(&block, None, tag)?;
 
             self.client_writer.write(&encrypted_block)?;
         }
@@ -170,7 +426,7 @@ impl<CW: ClientWriter> ClientWriter for EncryptedWriter<CW> {
         if msg.len() % BLOCK_SIZE != 0 {
             let block = &msg[msg.len() - (msg.len() % BLOCK_SIZE)..msg.len()];
 
-            let encrypted_block = self.push_stream.push_to_vec(&block, None, Tag::PUSH)?;
+            let encrypted_block = push_stream.push_to_vec(&block, None, Tag::PUSH)?;
 
             self.client_writer.write(&encrypted_block)?;
         }
@@ -179,9 +435,295 @@ impl<CW: ClientWriter> ClientWriter for EncryptedWriter<CW> {
     }
 }
 
+impl<CW: ClientWriter> ClientWriter for EncryptedWriter<CW> {
+    fn write(&mut self, msg: &[u8]) -> Result<(), P2pError> {
+        self.write_priority(msg, Priority::Normal)
+    }
+
+    /// Writes a message with the given priority.
+    /// Only an underlying writer which encrypts the blocks itself can reorder them,
+    /// otherwise the message is written like any other message.
+    fn write_priority(&mut self, msg: &[u8], priority: Priority) -> Result<(), P2pError> {
+        if let Some(max_msg_len) = self.max_msg_len {
+            if msg.len() > max_msg_len {
+                return Err(P2pError::new(ErrorKind::MessageTooLarge));
+            }
+        }
+
+        if !self.delegated {
+            return self.write_encrypted(msg);
+        }
+
+        for block in plain_blocks(msg) {
+            self.client_writer.write_priority(&block, priority)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a message which can be dropped with `discard_tagged` while it is queued.
+    /// Only an underlying writer which encrypts the blocks itself can drop them,
+    /// otherwise the message is written like any other message.
+    fn write_tagged(&mut self, msg: &[u8], tag: u64) -> Result<(), P2pError> {
+        if let Some(max_msg_len) = self.max_msg_len {
+            if msg.len() > max_msg_len {
+                return Err(P2pError::new(ErrorKind::MessageTooLarge));
+            }
+        }
+
+        if !self.delegated {
+            return self.write_encrypted(msg);
+        }
+
+        for block in plain_blocks(msg) {
+            self.client_writer.write_tagged(&block, tag)?;
+        }
+
+        Ok(())
+    }
+
+    /// Drops the queued messages of `tag`. The blocks are encrypted when they are sent,
+    /// so the peer can still decrypt the stream if the underlying writer encrypts them.
+    ///
+    /// # Returns
+    ///
+    /// Returns `false` if the underlying writer does not encrypt the blocks itself or cannot drop them.
+    fn discard_tagged(&mut self, tag: u64) -> bool {
+        self.delegated && self.client_writer.discard_tagged(tag)
+    }
+
+    fn protocol_kind(&self) -> ProtocolKind {
+        self.client_writer.protocol_kind()
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.client_writer.set_write_timeout(timeout);
+    }
+
+    fn suspend(&mut self) {
+        self.client_writer.suspend();
+    }
+
+    fn resume(&mut self) {
+        self.client_writer.resume();
+    }
+
+    fn set_mtu(&mut self, mtu: Option<usize>) {
+        self.client_writer.set_mtu(mtu);
+    }
+
+    /// Returns the payload of the underlying writer without the encryption overhead, at most the encryption block size.
+    fn max_payload(&self) -> usize {
+        self.client_writer
+            .max_payload()
+            .saturating_sub(CRYPTO_OVERHEAD)
+            .min(BLOCK_SIZE)
+    }
+
+    /// Sends the probes with the underlying writer, they are not part of the stream of blocks.
+    fn probe(&mut self, len: usize, count: usize, timeout: Duration) -> Result<u64, P2pError> {
+        self.client_writer.probe(len, count, timeout)
+    }
+}
+
+/// Tag of a plain block which is followed by more blocks of the same message.
+const PLAIN_TAG_MESSAGE: u8 = 0;
+/// Tag of the last plain block of a message.
+const PLAIN_TAG_PUSH: u8 = 1;
+/// Bytes of the tag in front of every plain block.
+const PLAIN_TAG_LEN: usize = 1;
+
+/// Reader part of an unencrypted sliding window connection, see `Connection::transform_to_slide`.
+/// Joins the blocks written by a `PlainWriter` to messages, like `EncryptedReader` does without decrypting.
+pub struct PlainReader<CR: ClientReader> {
+    client_reader: CR,
+    buffer: Option<Vec<u8>>,
+}
+
+impl<CR: ClientReader> PlainReader<CR> {
+    pub(crate) fn new(client_reader: CR) -> PlainReader<CR> {
+        PlainReader {
+            client_reader,
+            buffer: None,
+        }
+    }
+
+    /// Appends a block to a message.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the block is the last block of the message.
+    fn append_block(msg: &mut Vec<u8>, block: &[u8]) -> Result<bool, P2pError> {
+        match block.split_first() {
+            Some((&PLAIN_TAG_MESSAGE, data)) => {
+                msg.extend_from_slice(data);
+                Ok(false)
+            }
+            Some((&PLAIN_TAG_PUSH, data)) => {
+                msg.extend_from_slice(data);
+                Ok(true)
+            }
+            _ => Err(P2pError::new(ErrorKind::IllegalByteStream)),
+        }
+    }
+}
+
+impl<CR: ClientReader> ClientReader for PlainReader<CR> {
+    fn protocol_kind(&self) -> ProtocolKind {
+        self.client_reader.protocol_kind()
+    }
+
+    /// Checks if the underlying reader holds a block.
+    /// The block might only be the beginning of a message spanning multiple blocks.
+    fn has_message(&self) -> bool {
+        self.client_reader.has_message()
+    }
+
+    fn try_read(&mut self) -> Result<Vec<u8>, P2pError> {
+        let mut msg: Vec<u8> = self.buffer.take().unwrap_or_default();
+
+        loop {
+            let block = match self.client_reader.try_read() {
+                Ok(block) => block,
+                Err(e) => {
+                    if !msg.is_empty() {
+                        self.buffer = Some(msg);
+                    }
+
+                    return Err(e);
+                }
+            };
+
+            if Self::append_block(&mut msg, &block)? {
+                return Ok(msg);
+            }
+        }
+    }
+
+    fn read(&mut self, timeout: Option<Duration>) -> Result<Vec<u8>, P2pError> {
+        let mut msg: Vec<u8> = self.buffer.take().unwrap_or_default();
+
+        loop {
+            let block = match self.client_reader.read(timeout) {
+                Ok(block) => block,
+                Err(e) => {
+                    if !msg.is_empty() {
+                        self.buffer = Some(msg);
+                    }
+
+                    return Err(e);
+                }
+            };
+
+            if Self::append_block(&mut msg, &block)? {
+                return Ok(msg);
+            }
+        }
+    }
+}
+
+/// Writer part of an unencrypted sliding window connection, see `Connection::transform_to_slide`.
+/// Splits messages into blocks of the encryption block size, so they fit into a datagram like encrypted messages.
+pub struct PlainWriter<CW: ClientWriter> {
+    client_writer: CW,
+    /// Set if the underlying writer keeps the blocks of a message together, see `ClientWriter::set_block_encoder`.
+    delegated: bool,
+}
+
+impl<CW: ClientWriter> PlainWriter<CW> {
+    pub(crate) fn new(mut client_writer: CW) -> PlainWriter<CW> {
+        let delegated = client_writer.set_block_encoder(BlockEncoder::new(None)).is_ok();
+
+        PlainWriter {
+            client_writer,
+            delegated,
+        }
+    }
+}
+
+impl<CW: ClientWriter> ClientWriter for PlainWriter<CW> {
+    fn write(&mut self, msg: &[u8]) -> Result<(), P2pError> {
+        self.write_priority(msg, Priority::Normal)
+    }
+
+    /// Writes a message with the given priority.
+    /// Only an underlying writer which keeps the blocks of a message together can reorder them,
+    /// otherwise the message is written like any other message.
+    fn write_priority(&mut self, msg: &[u8], priority: Priority) -> Result<(), P2pError> {
+        let priority = match self.delegated {
+            true => priority,
+            false => Priority::Normal,
+        };
+
+        for block in plain_blocks(msg) {
+            self.client_writer.write_priority(&block, priority)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a message which can be dropped with `discard_tagged` while it is queued.
+    /// Only an underlying writer which keeps the blocks of a message together can drop them,
+    /// otherwise the message is written like any other message.
+    fn write_tagged(&mut self, msg: &[u8], tag: u64) -> Result<(), P2pError> {
+        if !self.delegated {
+            return self.write(msg);
+        }
+
+        for block in plain_blocks(msg) {
+            self.client_writer.write_tagged(&block, tag)?;
+        }
+
+        Ok(())
+    }
+
+    /// Drops the queued messages of `tag`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `false` if the underlying writer does not keep the blocks of a message together or cannot drop them.
+    fn discard_tagged(&mut self, tag: u64) -> bool {
+        self.delegated && self.client_writer.discard_tagged(tag)
+    }
+
+    fn protocol_kind(&self) -> ProtocolKind {
+        self.client_writer.protocol_kind()
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.client_writer.set_write_timeout(timeout);
+    }
+
+    fn suspend(&mut self) {
+        self.client_writer.suspend();
+    }
+
+    fn resume(&mut self) {
+        self.client_writer.resume();
+    }
+
+    fn set_mtu(&mut self, mtu: Option<usize>) {
+        self.client_writer.set_mtu(mtu);
+    }
+
+    /// Returns the payload of the underlying writer without the block tag, at most the block size.
+    fn max_payload(&self) -> usize {
+        self.client_writer
+            .max_payload()
+            .saturating_sub(PLAIN_TAG_LEN)
+            .min(BLOCK_SIZE)
+    }
+
+    /// Sends the probes with the underlying writer, they are not part of the stream of blocks.
+    fn probe(&mut self, len: usize, count: usize, timeout: Duration) -> Result<u64, P2pError> {
+        self.client_writer.probe(len, count, timeout)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use dryoc::dryocstream::Header;
     use crate::protocol::{Active, Connection, Encrypted, Udp, Waiting};
     use std::net::Ipv6Addr;
     use std::thread;
@@ -213,6 +755,47 @@ mod tests {
         (c1, c2)
     }
 
+    #[test]
+    fn test_usable_payload() {
+        let (mut push_stream, _): (_, Header) = DryocStream::init_push(&[7u8; 32]);
+        let block = push_stream.push_to_vec(&[0u8; 100], None, Tag::MESSAGE).unwrap();
+        assert_eq!(block.len(), 100 + CRYPTO_OVERHEAD);
+
+        assert_eq!(
+            usable_payload(576),
+            576 - 48 - UDP_HEADER_LEN - CHECKSUM_LEN - CRYPTO_OVERHEAD
+        );
+        assert_eq!(usable_payload(576), 500);
+        assert_eq!(usable_payload(1500), BLOCK_SIZE);
+        assert_eq!(usable_payload(60), 0);
+    }
+
+    #[test]
+    fn test_max_payload() {
+        let (c1, _c2) = connect();
+        let (mut writer, _reader) = c1.accept();
+
+        assert_eq!(writer.max_payload(), BLOCK_SIZE);
+        writer.set_mtu(Some(576));
+        assert_eq!(writer.max_payload(), 576 - IP_UDP_HEADER_LEN - 6 - CRYPTO_OVERHEAD);
+        writer.set_mtu(Some(60));
+        assert_eq!(writer.max_payload(), 0);
+
+        let (c1, c2) = connect();
+        let thread_c2 = thread::spawn(move || {
+            return c2.transform_to_slide().unwrap();
+        });
+        let (mut writer, _reader) = c1.transform_to_slide().unwrap();
+        let _c2 = thread_c2.join().unwrap();
+
+        assert_eq!(writer.max_payload(), BLOCK_SIZE);
+        // the connection sends no checksums
+        writer.set_mtu(Some(576));
+        assert_eq!(writer.max_payload(), usable_payload(576) + CHECKSUM_LEN);
+        writer.set_mtu(Some(1500));
+        assert_eq!(writer.max_payload(), usable_payload(1500));
+    }
+
     #[test]
     fn test_one_block() {
         let (c1, c2) = connect();
@@ -284,4 +867,32 @@ mod tests {
 
         assert_eq!(overflow_msg.as_slice(), overflow_recv.as_slice());
     }
+
+    #[test]
+    fn test_priority_keeps_blocks_together() {
+        let (c1, c2) = connect();
+        let thread_c2 = thread::spawn(move || {
+            return c2.transform_to_slide().unwrap();
+        });
+        let (mut writer, _c1_reader) = c1.transform_to_slide().unwrap();
+        let (_c2_writer, mut reader) = thread_c2.join().unwrap();
+
+        // every bulk message spans several encrypted blocks
+        let bulk: Vec<Vec<u8>> = (0..200u8).map(|i| vec![i; BLOCK_SIZE * 3 + 7]).collect();
+        let stop = b"stop".to_vec();
+        for msg in bulk.iter() {
+            writer.write(msg).unwrap();
+        }
+        writer.write_priority(&stop, Priority::High).unwrap();
+
+        let mut received = Vec::new();
+        for _ in 0..bulk.len() + 1 {
+            received.push(reader.read(Some(Duration::from_secs(2))).unwrap());
+        }
+
+        let position = received.iter().position(|msg| *msg == stop).unwrap();
+        assert!(position < bulk.len());
+        received.remove(position);
+        assert_eq!(received, bulk);
+    }
 }
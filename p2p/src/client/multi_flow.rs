@@ -0,0 +1,437 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::client::{ClientReader, ClientWriter, Priority, ProtocolKind};
+use crate::error::Error as P2pError;
+use crate::error::ErrorKind;
+
+/// Time between two polls of the flows while no flow holds a message.
+const FLOW_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Writer part of a connection striped across several parallel flows, see `Connection::transform_to_slide_flows`.
+///
+/// Messages written with `write` or `write_priority` always use the first flow, so they keep their order.
+/// Messages written with `write_tagged`, e.g. the chunks of a file, are spread over all flows in turn.
+/// They can overtake each other and messages of the first flow, the receiver has to order them itself,
+/// e.g. by the chunk position in their header.
+pub struct MultiFlowWriter<CW: ClientWriter> {
+    flows: Vec<CW>,
+    next_flow: usize,
+}
+
+impl<CW: ClientWriter> MultiFlowWriter<CW> {
+    /// Creates a writer over the given flows.
+    ///
+    /// # Arguments
+    ///
+    /// * `flows` - The writers of the flows, the first one carries the ordered messages.
+    ///
+    /// # Returns
+    ///
+    /// Returns the writer, or a `P2pError` of kind `InvalidFlowCount` if `flows` is empty.
+    pub fn new(flows: Vec<CW>) -> Result<MultiFlowWriter<CW>, P2pError> {
+        if flows.is_empty() {
+            return Err(P2pError::new(ErrorKind::InvalidFlowCount));
+        }
+
+        Ok(MultiFlowWriter {
+            flows,
+            next_flow: 0,
+        })
+    }
+
+    /// Creates a writer over the given flows, like `new` but the first flow can't be missing.
+    ///
+    /// # Arguments
+    ///
+    /// * `first` - The writer of the first flow, which carries the ordered messages.
+    /// * `others` - The writers of the other flows.
+    pub fn with_first(first: CW, others: Vec<CW>) -> MultiFlowWriter<CW> {
+        let mut flows = Vec::with_capacity(others.len() + 1);
+        flows.push(first);
+        flows.extend(others);
+
+        MultiFlowWriter {
+            flows,
+            next_flow: 0,
+        }
+    }
+
+    /// Returns the number of flows.
+    pub fn num_flows(&self) -> usize {
+        self.flows.len()
+    }
+}
+
+impl<CW: ClientWriter> ClientWriter for MultiFlowWriter<CW> {
+    fn write(&mut self, msg: &[u8]) -> Result<(), P2pError> {
+        self.flows[0].write(msg)
+    }
+
+    /// Writes the message to the first flow with the given priority,
+    /// so a `High` message overtakes the messages queued on that flow.
+    fn write_priority(&mut self, msg: &[u8], priority: Priority) -> Result<(), P2pError> {
+        self.flows[0].write_priority(msg, priority)
+    }
+
+    /// Writes the message to the next flow in turn.
+    fn write_tagged(&mut self, msg: &[u8], tag: u64) -> Result<(), P2pError> {
+        let flow = self.next_flow;
+        self.next_flow = (self.next_flow + 1) % self.flows.len();
+
+        self.flows[flow].write_tagged(msg, tag)
+    }
+
+    /// Drops the queued messages of `tag` from every flow.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if every flow dropped the messages.
+    fn discard_tagged(&mut self, tag: u64) -> bool {
+        self.flows
+            .iter_mut()
+            .fold(true, |discarded, flow| flow.discard_tagged(tag) & discarded)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        for flow in self.flows.iter_mut() {
+            flow.set_write_timeout(timeout);
+        }
+    }
+
+    fn protocol_kind(&self) -> ProtocolKind {
+        self.flows[0].protocol_kind()
+    }
+
+    fn suspend(&mut self) {
+        for flow in self.flows.iter_mut() {
+            flow.suspend();
+        }
+    }
+
+    fn resume(&mut self) {
+        for flow in self.flows.iter_mut() {
+            flow.resume();
+        }
+    }
+
+    fn set_mtu(&mut self, mtu: Option<usize>) {
+        for flow in self.flows.iter_mut() {
+            flow.set_mtu(mtu);
+        }
+    }
+
+    /// Returns the smallest payload of all flows, so a message fits into a datagram on every flow.
+    fn max_payload(&self) -> usize {
+        self.flows
+            .iter()
+            .map(|flow| flow.max_payload())
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Probes the first flow, the other flows share its path.
+    fn probe(&mut self, len: usize, count: usize, timeout: Duration) -> Result<u64, P2pError> {
+        self.flows[0].probe(len, count, timeout)
+    }
+}
+
+/// Reader part of a connection striped across several parallel flows, see `MultiFlowWriter`.
+/// Reads the messages of all flows in the order they arrive.
+pub struct MultiFlowReader<CR: ClientReader> {
+    flows: Vec<CR>,
+    next_flow: usize,
+}
+
+impl<CR: ClientReader> MultiFlowReader<CR> {
+    /// Creates a reader over the given flows.
+    ///
+    /// # Arguments
+    ///
+    /// * `flows` - The readers of the flows, in the same order as the writers of the peer.
+    ///
+    /// # Returns
+    ///
+    /// Returns the reader, or a `P2pError` of kind `InvalidFlowCount` if `flows` is empty.
+    pub fn new(flows: Vec<CR>) -> Result<MultiFlowReader<CR>, P2pError> {
+        if flows.is_empty() {
+            return Err(P2pError::new(ErrorKind::InvalidFlowCount));
+        }
+
+        Ok(MultiFlowReader {
+            flows,
+            next_flow: 0,
+        })
+    }
+
+    /// Creates a reader over the given flows, like `new` but the first flow can't be missing.
+    ///
+    /// # Arguments
+    ///
+    /// * `first` - The reader of the first flow.
+    /// * `others` - The readers of the other flows, in the same order as the writers of the peer.
+    pub fn with_first(first: CR, others: Vec<CR>) -> MultiFlowReader<CR> {
+        let mut flows = Vec::with_capacity(others.len() + 1);
+        flows.push(first);
+        flows.extend(others);
+
+        MultiFlowReader {
+            flows,
+            next_flow: 0,
+        }
+    }
+
+    /// Returns the number of flows.
+    pub fn num_flows(&self) -> usize {
+        self.flows.len()
+    }
+
+    /// Polls every flow once, starting after the flow which returned the last message,
+    /// so a busy flow does not starve the others.
+    ///
+    /// # Returns
+    ///
+    /// Returns the first available message, `None` if no flow holds a message,
+    /// or the `P2pError` of a flow which failed.
+    fn poll_flows(&mut self) -> Result<Option<Vec<u8>>, P2pError> {
+        for i in 0..self.flows.len() {
+            let flow = (self.next_flow + i) % self.flows.len();
+
+            match self.flows[flow].read(Some(Duration::ZERO)) {
+                Ok(msg) => {
+                    self.next_flow = (flow + 1) % self.flows.len();
+                    return Ok(Some(msg));
+                }
+                Err(err) if err.kind() == &ErrorKind::TimedOut => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl<CR: ClientReader> ClientReader for MultiFlowReader<CR> {
+    /// Reads a message of any flow without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `TimedOut` if no flow holds a message, like a read with a zero timeout,
+    /// or the error of a flow which failed.
+    fn try_read(&mut self) -> Result<Vec<u8>, P2pError> {
+        match self.poll_flows()? {
+            Some(msg) => Ok(msg),
+            None => Err(P2pError::new(ErrorKind::TimedOut)),
+        }
+    }
+
+    /// Reads the next message of any flow. A single flow is read directly,
+    /// several flows are polled in turn until one of them holds a message.
+    fn read(&mut self, timeout: Option<Duration>) -> Result<Vec<u8>, P2pError> {
+        if self.flows.len() == 1 {
+            return self.flows[0].read(timeout);
+        }
+
+        let deadline = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            if let Some(msg) = self.poll_flows()? {
+                return Ok(msg);
+            }
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(P2pError::new(ErrorKind::TimedOut));
+            }
+
+            sleep(FLOW_POLL_INTERVAL);
+        }
+    }
+
+    fn has_message(&self) -> bool {
+        self.flows.iter().any(|flow| flow.has_message())
+    }
+
+    fn protocol_kind(&self) -> ProtocolKind {
+        self.flows[0].protocol_kind()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Writer of a mock flow, a thread forwards one message per `per_msg` to the reader.
+    struct CappedWriter {
+        sender: SyncSender<Vec<u8>>,
+    }
+
+    /// Reader of a mock flow.
+    struct CappedReader {
+        receiver: Receiver<Vec<u8>>,
+    }
+
+    fn capped_flow(per_msg: Duration) -> (CappedWriter, CappedReader) {
+        let (sender, queue) = sync_channel::<Vec<u8>>(1);
+        let (forward, receiver): (Sender<Vec<u8>>, _) = channel();
+
+        thread::spawn(move || {
+            for msg in queue {
+                sleep(per_msg);
+                if forward.send(msg).is_err() {
+                    return;
+                }
+            }
+        });
+
+        (CappedWriter { sender }, CappedReader { receiver })
+    }
+
+    impl ClientWriter for CappedWriter {
+        fn write(&mut self, msg: &[u8]) -> Result<(), P2pError> {
+            self.sender
+                .send(msg.to_vec())
+                .map_err(|_| P2pError::new(ErrorKind::ConnectionClosed))
+        }
+    }
+
+    impl ClientReader for CappedReader {
+        fn try_read(&mut self) -> Result<Vec<u8>, P2pError> {
+            Ok(self.receiver.try_recv()?)
+        }
+
+        fn read(&mut self, timeout: Option<Duration>) -> Result<Vec<u8>, P2pError> {
+            match self.receiver.recv_timeout(timeout.unwrap_or(Duration::MAX)) {
+                Ok(msg) => Ok(msg),
+                Err(RecvTimeoutError::Timeout) => Err(P2pError::new(ErrorKind::TimedOut)),
+                Err(RecvTimeoutError::Disconnected) => Err(P2pError::new(ErrorKind::ConnectionClosed)),
+            }
+        }
+    }
+
+    /// Writer of a mock flow which queues its messages instead of sending them, `High` messages in front of `Normal` ones.
+    #[derive(Default)]
+    struct QueuedWriter {
+        queue: Arc<Mutex<Vec<(Priority, Vec<u8>)>>>,
+    }
+
+    impl ClientWriter for QueuedWriter {
+        fn write(&mut self, msg: &[u8]) -> Result<(), P2pError> {
+            self.write_priority(msg, Priority::Normal)
+        }
+
+        fn write_priority(&mut self, msg: &[u8], priority: Priority) -> Result<(), P2pError> {
+            let mut queue = self.queue.lock().unwrap();
+            let position = match priority {
+                Priority::High => queue.iter().take_while(|(queued, _)| *queued == Priority::High).count(),
+                Priority::Normal => queue.len(),
+            };
+            queue.insert(position, (priority, msg.to_vec()));
+            Ok(())
+        }
+    }
+
+    fn capped_flows(num_flows: usize, per_msg: Duration) -> (MultiFlowWriter<CappedWriter>, MultiFlowReader<CappedReader>) {
+        let (writers, readers) = (0..num_flows).map(|_| capped_flow(per_msg)).unzip();
+        (MultiFlowWriter::new(writers).unwrap(), MultiFlowReader::new(readers).unwrap())
+    }
+
+    /// Writes `count` messages tagged with their position and returns how long it took until all were read.
+    fn striped_transfer(num_flows: usize, count: u64) -> Duration {
+        let (mut writer, mut reader) = capped_flows(num_flows, Duration::from_millis(2));
+        let start = Instant::now();
+
+        let sender = thread::spawn(move || {
+            for pos in 0..count {
+                writer.write_tagged(&pos.to_be_bytes(), 1).unwrap();
+            }
+            writer
+        });
+
+        let mut received = vec![false; count as usize];
+        for _ in 0..count {
+            let msg = reader.read(Some(Duration::from_secs(2))).unwrap();
+            let pos = u64::from_be_bytes(msg.try_into().unwrap());
+            assert!(!received[pos as usize]);
+            received[pos as usize] = true;
+        }
+        let elapsed = start.elapsed();
+        sender.join().unwrap();
+
+        assert!(received.iter().all(|received| *received));
+        elapsed
+    }
+
+    #[test]
+    fn test_no_flows() {
+        let err = MultiFlowWriter::<CappedWriter>::new(vec![]).err().unwrap();
+        assert_eq!(err.kind(), &ErrorKind::InvalidFlowCount);
+        let err = MultiFlowReader::<CappedReader>::new(vec![]).err().unwrap();
+        assert_eq!(err.kind(), &ErrorKind::InvalidFlowCount);
+    }
+
+    #[test]
+    fn test_untagged_messages_keep_their_order() {
+        let (mut writer, mut reader) = capped_flows(3, Duration::ZERO);
+
+        writer.write_tagged(b"chunk", 1).unwrap();
+        for i in 0..10u8 {
+            writer.write(&[i]).unwrap();
+        }
+
+        let mut ordered = vec![];
+        for _ in 0..11 {
+            let msg = reader.read(Some(Duration::from_secs(1))).unwrap();
+            if msg != b"chunk".to_vec() {
+                ordered.push(msg[0]);
+            }
+        }
+        assert_eq!(ordered, (0..10).collect::<Vec<u8>>());
+        assert_eq!(reader.read(Some(Duration::from_millis(10))).unwrap_err().kind(), &ErrorKind::TimedOut);
+        assert_eq!(reader.try_read().unwrap_err().kind(), &ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_priority_overtakes_tagged_messages() {
+        let flows: Vec<QueuedWriter> = (0..2).map(|_| QueuedWriter::default()).collect();
+        let queues: Vec<_> = flows.iter().map(|flow| flow.queue.clone()).collect();
+        let mut writer = MultiFlowWriter::new(flows).unwrap();
+
+        for pos in 0..4u8 {
+            writer.write_tagged(&[pos], 1).unwrap();
+        }
+        writer.write_priority(b"stop", Priority::High).unwrap();
+
+        // the stop is sent on the first flow ahead of the chunks queued there
+        let first: Vec<Vec<u8>> = queues[0].lock().unwrap().iter().map(|(_, msg)| msg.clone()).collect();
+        assert_eq!(first, vec![b"stop".to_vec(), vec![0], vec![2]]);
+        assert_eq!(queues[1].lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_flows_exceed_single_flow_rate() {
+        let single = striped_transfer(1, 100);
+        let striped = striped_transfer(2, 100);
+
+        // every flow is capped to one message per 2ms, two flows carry about twice as much
+        assert!(
+            striped.as_secs_f64() < single.as_secs_f64() * 0.75,
+            "single {:?} striped {:?}",
+            single,
+            striped
+        );
+    }
+
+    #[test]
+    fn test_closed_flow_is_reported() {
+        let (mut writer, mut reader) = capped_flows(2, Duration::ZERO);
+        writer.flows.pop();
+        drop(writer);
+        sleep(Duration::from_millis(20));
+
+        let err = reader.read(Some(Duration::from_secs(1))).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::ConnectionClosed);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::client::{ActiveClient, ClientReader, ClientWriter, WaitingClient};
+use crate::client::{ActiveClient, ClientReader, ClientWriter, ProtocolKind, WaitingClient};
 use crate::error::{ChangeStateError, Error as P2pError};
 use socket2::{Domain, SockAddr, Socket, Type};
 
@@ -94,6 +94,47 @@ impl TcpWaitingClient {
     }
 }
 
+impl TcpWaitingClient {
+    /// Waits for a peer connecting to the port of the client.
+    /// Used if the port of this side is reachable, e.g. a server with a forwarded port.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - An optional `Duration` to wait for the peer. If `None` is passed, the method blocks until a peer connects.
+    ///
+    /// # Returns
+    ///
+    /// Returns the `TcpActiveClient` together with the address of the peer,
+    /// or a `ChangeStateError` containing the waiting client if no peer connected.
+    pub fn accept(
+        self,
+        timeout: Option<Duration>,
+    ) -> Result<(TcpActiveClient, SocketAddr), ChangeStateError<Self>> {
+        if let Err(err) = self.tcp_socket.listen(1) {
+            return Err(ChangeStateError::new(self, Box::new(err)));
+        }
+
+        if let Err(err) = self.tcp_socket.set_read_timeout(timeout) {
+            return Err(ChangeStateError::new(self, Box::new(err)));
+        }
+
+        let (socket, peer_addr) = match self.tcp_socket.accept() {
+            Ok(accepted) => accepted,
+            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+        };
+
+        let peer_addr = match peer_addr.as_socket() {
+            Some(addr) => addr,
+            None => {
+                let err = std::io::Error::new(ErrorKind::InvalidData, "peer address is not an ip address");
+                return Err(ChangeStateError::new(self, Box::new(err)));
+            }
+        };
+
+        Ok((TcpActiveClient::new(TcpStream::from(socket)), peer_addr))
+    }
+}
+
 impl WaitingClient for TcpWaitingClient {
     /// Returns the port the socket is bound to.
     fn get_port(&self) -> u16 {
@@ -155,6 +196,10 @@ impl TcpClientReader {
 }
 
 impl ClientReader for TcpClientReader {
+    fn protocol_kind(&self) -> ProtocolKind {
+        ProtocolKind::Tcp
+    }
+
     fn try_read(&mut self) -> Result<Vec<u8>, P2pError> {
         let msg = self.read(None);
         return msg;
@@ -171,6 +216,8 @@ impl ClientReader for TcpClientReader {
 
                 return if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut {
                     Err(P2pError::new(error::ErrorKind::TimedOut))
+                } else if err.kind() == ErrorKind::UnexpectedEof {
+                    Err(P2pError::new(error::ErrorKind::ConnectionClosed))
                 } else {
                     println!("TCP READER");
                     println!("{}", err);
@@ -194,11 +241,15 @@ impl ClientReader for TcpClientReader {
 
 pub struct TcpClientWriter {
     tcp_stream: TcpStream,
+    timeout: Option<Duration>,
 }
 
 impl TcpClientWriter {
     fn new(tcp_stream: TcpStream) -> TcpClientWriter {
-        TcpClientWriter { tcp_stream }
+        TcpClientWriter {
+            tcp_stream,
+            timeout: Some(Duration::from_secs(10)),
+        }
     }
 
     fn prepare_msg(&mut self, msg: &[u8]) -> Vec<u8> {
@@ -213,9 +264,13 @@ impl TcpClientWriter {
 }
 
 impl ClientWriter for TcpClientWriter {
+    fn protocol_kind(&self) -> ProtocolKind {
+        ProtocolKind::Tcp
+    }
+
     fn write(&mut self, msg: &[u8]) -> Result<(), P2pError> {
         let msg = self.prepare_msg(msg);
-        self.tcp_stream.set_write_timeout(Some(Duration::from_secs(10)))?; // TODO: make this configurable
+        self.tcp_stream.set_write_timeout(self.timeout)?;
         match self.tcp_stream.write_all(&msg) {
             Ok(_) => {}
             Err(err) => {
@@ -227,6 +282,10 @@ impl ClientWriter for TcpClientWriter {
         };
         Ok(())
     }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
 }
 
 #[cfg(test)]
@@ -1,14 +1,20 @@
 use std::error::Error;
 use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
 use std::thread;
 use std::thread::{sleep, JoinHandle};
 use std::time::{Duration, Instant};
 
-use crate::client::{ActiveClient, ClientReader, ClientWriter};
+use crate::client::{
+    bind_dual_stack_udp, datagram_payload, peer_socket_addr, ActiveClient, ClientReader, ClientWriter,
+    ProtocolKind,
+};
 use crate::error::Error as P2pError;
 use crate::error::{ChangeStateError, ErrorKind};
+use crate::relay;
 
 const SEND_INTERVAL: Duration = Duration::from_millis(70);
 const KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(200); //time between each keep alive message
@@ -16,6 +22,7 @@ const KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(200); //time between
 const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 //time after which the connection is considered dead
 const RECEIVE_INTERVAL: Duration = Duration::from_millis(10); //time between each receive timeout
+const HEADER_SIZE: usize = 6; //message type, message number and message length
 
 /// A UDP client that waits for a connection.
 pub struct UdpWaitingClient {
@@ -46,6 +53,7 @@ impl From<u8> for MessageType {
 
 impl UdpWaitingClient {
     /// Creates a new `UdpWaitingClient`.
+    /// The socket is dual-stack, IPv4 peers connect to it by its IPv4 address.
     ///
     /// # Examples
     ///
@@ -72,9 +80,24 @@ impl UdpWaitingClient {
     ///
     /// Returns a `Result` that contains a `UdpWaitingClient` instance if successful, or a `P2pError` if an error occurs during socket binding.
     pub fn new(port: Option<u16>) -> Result<UdpWaitingClient, P2pError> {
-        let bind_addr = IpAddr::from(Ipv6Addr::from(0));
-        let bind_addr = SocketAddr::new(bind_addr, port.unwrap_or(0));
-        let udp_socket = UdpSocket::bind(&bind_addr)?;
+        let udp_socket = bind_dual_stack_udp(port)?;
+
+        Self::from_socket(udp_socket)
+    }
+
+    /// Creates a new `UdpWaitingClient` from an existing socket, e.g. one prepared by a port mapping library.
+    /// The socket may already be connected, `connect` connects it to the peer again.
+    ///
+    /// # Arguments
+    ///
+    /// * `udp_socket` - A bound `UdpSocket`. It is switched to blocking mode, the client relies on read timeouts.
+    ///   A socket bound to an IPv4 address reaches IPv4-mapped peers by their IPv4 address.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` that contains a `UdpWaitingClient` instance if successful, or a `P2pError` if the socket could not be configured.
+    pub fn from_socket(udp_socket: UdpSocket) -> Result<UdpWaitingClient, P2pError> {
+        udp_socket.set_nonblocking(false)?;
 
         // clear the udp buffer
         udp_socket.set_read_timeout(Some(RECEIVE_INTERVAL))?;
@@ -115,6 +138,7 @@ impl UdpWaitingClient {
     /// # Returns
     ///
     /// Returns a `Result` that contains an `UdpActiveClient` instance if successful, or a `ChangeStateError` with the previous state and the error which occurred.
+    /// After a failed attempt the waiting client in the error is still bound to the same port and can connect again.
     pub fn connect(
         mut self,
         peer: Ipv6Addr,
@@ -122,8 +146,38 @@ impl UdpWaitingClient {
         connect_timeout: Option<Duration>,
         disconnect_timeout: Option<Duration>,
     ) -> Result<UdpActiveClient, ChangeStateError<Self>> {
-        let peer_addr = IpAddr::from(peer);
-        let peer_addr = SocketAddr::new(peer_addr, port);
+        self.connect_cancellable(
+            peer,
+            port,
+            connect_timeout,
+            disconnect_timeout,
+            Arc::new(AtomicBool::new(false)),
+        )
+    }
+
+    /// Connects to a peer like `connect`, but can be aborted before the connect timeout elapsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - An `Ipv6Addr` representing the IP address of the peer.
+    /// * `port` - A `u16` value representing the port of the peer.
+    /// * `connect_timeout` - An optional `Duration` specifying the maximum time to wait for the connection to be established.
+    /// * `disconnect_timeout` - An optional `Duration` specifying the maximum time to wait after receiving no answer before closing the connection.
+    /// * `cancel` - A flag which aborts the connection attempt once it is set to `true`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` that contains an `UdpActiveClient` instance if successful, or a `ChangeStateError` with the previous state and the error which occurred.
+    /// The error is of kind `ErrorKind::Cancelled` if the attempt was aborted.
+    pub fn connect_cancellable(
+        mut self,
+        peer: Ipv6Addr,
+        port: u16,
+        connect_timeout: Option<Duration>,
+        disconnect_timeout: Option<Duration>,
+        cancel: Arc<AtomicBool>,
+    ) -> Result<UdpActiveClient, ChangeStateError<Self>> {
+        let peer_addr = peer_socket_addr(&self.udp_socket, peer, port);
 
         if self.get_port() == port {
             return Err(ChangeStateError::new(
@@ -137,17 +191,27 @@ impl UdpWaitingClient {
             Err(e) => return Err(ChangeStateError::new(self, Box::new(e))),
         }
 
-        if let Err(e) = self.ping_and_wait(connect_timeout) {
+        if let Err(e) = self.ping_and_wait(connect_timeout, &cancel) {
             return Err(ChangeStateError::new(self, Box::new(e)));
         };
 
-        // program should panic if this fails
-        let active_client = UdpActiveClient::new(self.udp_socket, disconnect_timeout).unwrap();
+        // a clone is handed over, so the waiting client is kept if the active client can't be created
+        let udp_socket = match self.udp_socket.try_clone() {
+            Ok(udp_socket) => udp_socket,
+            Err(e) => return Err(ChangeStateError::new(self, Box::new(e))),
+        };
 
-        return Ok(active_client);
+        return match UdpActiveClient::new(udp_socket, disconnect_timeout) {
+            Ok(active_client) => Ok(active_client),
+            Err(e) => Err(ChangeStateError::new(self, Box::new(e))),
+        };
     }
 
-    fn ping_and_wait(&mut self, timeout: Option<Duration>) -> Result<(), P2pError> {
+    fn ping_and_wait(
+        &mut self,
+        timeout: Option<Duration>,
+        cancel: &AtomicBool,
+    ) -> Result<(), P2pError> {
         self.udp_socket.set_read_timeout(Some(RECEIVE_INTERVAL))?;
         let timeout = timeout.unwrap_or(Duration::from_secs(0));
         let udp_socket_clone = self.udp_socket.try_clone()?;
@@ -162,17 +226,32 @@ impl UdpWaitingClient {
         });
 
         let now = Instant::now();
+        let mut result = Ok(());
 
         while !receive_thread.is_finished() {
-            self.udp_socket.send(&[MessageType::Open as u8])?;
+            if let Err(e) = self.udp_socket.send(&[MessageType::Open as u8]) {
+                result = Err(P2pError::from(e));
+                break;
+            }
             sleep(RECEIVE_INTERVAL);
 
+            if cancel.load(Ordering::SeqCst) {
+                result = Err(P2pError::new(ErrorKind::Cancelled));
+                break;
+            }
+
             if now.elapsed() > timeout {
-                stop_send.send(())?;
-                let _ = receive_thread.join();
-                return Err(P2pError::new(ErrorKind::TimedOut));
+                result = Err(P2pError::new(ErrorKind::TimedOut));
+                break;
             }
         }
+
+        if result.is_err() {
+            // the receive thread must not outlive a failed attempt, it would swallow the answer to the next one
+            let _ = stop_send.send(());
+            let _ = receive_thread.join();
+            return result;
+        }
         self.udp_socket.send(&[MessageType::Open as u8])?;
 
         let mut buf = [0; 1];
@@ -181,6 +260,22 @@ impl UdpWaitingClient {
         Ok(())
     }
 
+    /// Registers the client at a relay and waits until the peer registered with the same token.
+    /// Afterwards the client can connect to the relay address as if it was the peer.
+    ///
+    /// # Errors
+    ///
+    /// See `relay::register`.
+    pub(crate) fn register_at_relay(
+        &self,
+        relay: SocketAddr,
+        token: &[u8],
+        timeout: Option<Duration>,
+        cancel: &AtomicBool,
+    ) -> Result<(), P2pError> {
+        relay::register(&self.udp_socket, relay, token, timeout, cancel)
+    }
+
     /// Returns the local port number that the client is bound to.
     ///
     /// # Examples
@@ -228,6 +323,7 @@ pub struct UdpClientWriter {
     ack_receiver: Receiver<u8>,
     timeout: Duration,
     closed_receiver: Receiver<()>,
+    mtu: Option<usize>,
 }
 
 impl UdpClientReader {
@@ -403,6 +499,10 @@ impl UdpClientReader {
 }
 
 impl ClientReader for UdpClientReader {
+    fn protocol_kind(&self) -> ProtocolKind {
+        ProtocolKind::Udp
+    }
+
     /// Tries to read a message from the `UdpClientReader` message receiver.
     ///
     /// # Returns
@@ -475,6 +575,7 @@ impl UdpClientWriter {
             send_counter: 0,
             timeout: timeout.unwrap_or(Duration::from_secs(0)),
             closed_receiver,
+            mtu: None,
         };
     }
 
@@ -492,6 +593,10 @@ impl UdpClientWriter {
 }
 
 impl ClientWriter for UdpClientWriter {
+    fn protocol_kind(&self) -> ProtocolKind {
+        ProtocolKind::Udp
+    }
+
     /// Writes a message to the UDP socket.
     ///
     /// # Arguments
@@ -534,6 +639,22 @@ impl ClientWriter for UdpClientWriter {
         println!("[UDP] send timeout");
         return Err(P2pError::new(ErrorKind::TimedOut));
     }
+
+    /// Changes the time a write waits for the acknowledgement. `None` waits without limit.
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout.unwrap_or(Duration::ZERO);
+    }
+
+    /// Sets the MTU `max_payload` is calculated from. Longer messages are still sent, fragmented by IP.
+    fn set_mtu(&mut self, mtu: Option<usize>) {
+        self.mtu = mtu;
+    }
+
+    /// Returns the largest message which fits into a datagram of the MTU without the header.
+    /// Without an MTU, the largest UDP datagram is used.
+    fn max_payload(&self) -> usize {
+        datagram_payload(self.mtu).saturating_sub(HEADER_SIZE)
+    }
 }
 
 impl UdpActiveClient {
@@ -547,7 +668,7 @@ impl UdpActiveClient {
     /// # Returns
     ///
     /// Returns a `Result` containing the `UdpActiveClient` instance if it is successfully created, or a `P2pError` that occurred during initialization.
-    pub fn new(
+    pub(crate) fn new(
         udp_socket: UdpSocket,
         ack_timeout: Option<Duration>,
     ) -> Result<UdpActiveClient, P2pError> {
@@ -683,6 +804,39 @@ mod tests {
         assert!(thread_c2.join().unwrap());
     }
 
+    #[test]
+    fn test_connect_cancelled() {
+        let ipv6 = Ipv6Addr::from(1);
+        let timeout = Duration::from_secs(10);
+        let w1 = UdpWaitingClient::new(None).unwrap();
+        let w2 = UdpWaitingClient::new(None).unwrap();
+
+        let p2 = w2.get_port();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let cancel_clone = cancel.clone();
+
+        let thread_cancel = thread::spawn(move || {
+            sleep(Duration::from_millis(100));
+            cancel_clone.store(true, Ordering::SeqCst);
+        });
+
+        let now = Instant::now();
+        let result = w1.connect_cancellable(ipv6, p2, Some(timeout), Some(timeout), cancel);
+
+        assert!(now.elapsed() < Duration::from_secs(1));
+        thread_cancel.join().unwrap();
+
+        match result {
+            Ok(_) => panic!("connect should have been cancelled"),
+            Err(err) => {
+                let err = err.to_err();
+                let err = err.downcast_ref::<P2pError>().unwrap();
+                assert!(matches!(err.kind(), ErrorKind::Cancelled));
+            }
+        }
+        drop(w2);
+    }
+
     #[test]
     fn test_async_connect_ok() {
         let ipv6 = Ipv6Addr::from(1);
@@ -705,6 +859,91 @@ mod tests {
         drop(res.unwrap());
     }
 
+    #[test]
+    fn test_connect_from_socket() {
+        let ipv6 = Ipv6Addr::from(1);
+        let timeout = Duration::from_millis(1000);
+
+        let socket = UdpSocket::bind(SocketAddr::new(IpAddr::from(ipv6), 0)).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let w1 = UdpWaitingClient::from_socket(socket).unwrap();
+        let w2 = UdpWaitingClient::new(None).unwrap();
+
+        let p1 = w1.get_port();
+        let p2 = w2.get_port();
+
+        let thread_c2 = thread::spawn(move || {
+            return w2.connect(ipv6, p1, Some(timeout), Some(timeout)).unwrap();
+        });
+
+        let mut c1 = w1.connect(ipv6, p2, Some(timeout), Some(timeout)).unwrap();
+        let mut c2 = thread_c2.join().unwrap();
+
+        c1.writer_ref().write(&[1, 2, 3]).unwrap();
+        assert_eq!(c2.reader_ref().read(Some(timeout)).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_reconnect_after_failed_connect() {
+        let ipv6 = Ipv6Addr::from(1);
+        let timeout = Duration::from_millis(1000);
+
+        let w1 = UdpWaitingClient::new(None).unwrap();
+        let w2 = UdpWaitingClient::new(None).unwrap();
+        let p1 = w1.get_port();
+        let p2 = w2.get_port();
+
+        // a peer which never answers
+        let silent = UdpSocket::bind(SocketAddr::new(IpAddr::from(ipv6), 0)).unwrap();
+        let silent_port = silent.local_addr().unwrap().port();
+
+        let err = w1
+            .connect(ipv6, silent_port, Some(Duration::from_millis(200)), Some(timeout))
+            .err()
+            .unwrap();
+        let (w1, _) = err.split();
+        assert_eq!(w1.get_port(), p1);
+
+        // the same waiting client connects to a peer which answers
+        let thread_c2 = thread::spawn(move || {
+            return w2.connect(ipv6, p1, Some(timeout), Some(timeout)).unwrap();
+        });
+
+        let mut c1 = w1.connect(ipv6, p2, Some(timeout), Some(timeout)).unwrap();
+        let mut c2 = thread_c2.join().unwrap();
+
+        c1.writer_ref().write(&[1, 2, 3]).unwrap();
+        assert_eq!(c2.reader_ref().read(Some(timeout)).unwrap(), vec![1, 2, 3]);
+        drop(silent);
+    }
+
+    #[test]
+    fn test_connect_ipv4_peer() {
+        let timeout = Duration::from_millis(1000);
+        let ipv4 = std::net::Ipv4Addr::LOCALHOST;
+
+        let w1 = UdpWaitingClient::new(None).unwrap();
+        let socket = UdpSocket::bind(SocketAddr::new(IpAddr::from(ipv4), 0)).unwrap();
+        let w2 = UdpWaitingClient::from_socket(socket).unwrap();
+
+        let p1 = w1.get_port();
+        let p2 = w2.get_port();
+
+        // both peers only know the IPv4-mapped address of the other
+        let mapped = crate::client::to_dual_stack(IpAddr::from(ipv4));
+        let thread_c2 = thread::spawn(move || {
+            return w2.connect(mapped, p1, Some(timeout), Some(timeout)).unwrap();
+        });
+
+        let mut c1 = w1.connect(mapped, p2, Some(timeout), Some(timeout)).unwrap();
+        let mut c2 = thread_c2.join().unwrap();
+
+        c1.writer_ref().write(&[1, 2, 3]).unwrap();
+        assert_eq!(c2.reader_ref().read(Some(timeout)).unwrap(), vec![1, 2, 3]);
+        c2.writer_ref().write(&[4, 5]).unwrap();
+        assert_eq!(c1.reader_ref().read(Some(timeout)).unwrap(), vec![4, 5]);
+    }
+
     #[test]
     fn test_send_local() {
         let (mut c1, mut c2) = prepare_local();
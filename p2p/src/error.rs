@@ -5,7 +5,7 @@ use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
 use std::time::SystemTimeError;
 
 /// A list specifying general categories of P2pError error.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ErrorKind {
     /// The connection timed out.
     TimedOut,
@@ -22,6 +22,78 @@ pub enum ErrorKind {
     ChannelError,
     NoDelayGiven,
     IO,
+    /// The operation was cancelled by the caller.
+    Cancelled,
+    /// The peer closed the connection.
+    ConnectionClosed,
+    /// The handshake with the peer did not finish in time.
+    HandshakeTimedOut,
+    /// The connection was closed because no data was exchanged for the configured idle time.
+    IdleTimeout,
+    /// The message is longer than the maximum message length agreed with the peer.
+    MessageTooLarge,
+    /// The number of clock synchronization samples is outside of 1..=255.
+    InvalidSampleCount,
+    /// Exported session secrets have the wrong length.
+    InvalidSessionSecrets,
+    /// The peer is not permitted by the connect policy.
+    RefusedByPolicy,
+    /// An IP range could not be parsed or has a prefix longer than the address.
+    InvalidIpRange,
+    /// The peer uses a different application identifier, see `Connection::set_app_id`.
+    AppIdMismatch,
+    /// A throughput probe needs at least one message with at least one byte.
+    InvalidProbeSize,
+    /// The writer cannot send throughput probes, see `ClientWriter::probe`.
+    ProbeUnsupported,
+    /// An identity key or its signature is invalid, see `Identity`.
+    InvalidIdentity,
+    /// The peer presented another identity than before, e.g. during `rekey`.
+    IdentityMismatch,
+    /// The socket has no buffer space left for sending. The send can be retried shortly after.
+    SendBufferFull,
+    /// A connection needs at least one flow, see `Connection::transform_to_slide_flows`.
+    InvalidFlowCount,
+    /// The rendezvous window is zero, see `Connection::rendezvous`.
+    ZeroWindow,
+    /// Nothing was received from the peer for the disconnect timeout, the connection is closed.
+    /// Unlike `TimedOut`, reading again does not help.
+    PeerTimedOut,
+    /// The STUN server can't answer from another address as RFC 5780 describes, so the NAT type can't be detected.
+    StunUnsupported,
+    /// The connection runs through a relay, which only forwards the datagrams of the connection.
+    /// The sliding window transport and the upgrades connect to the peer directly and are not available.
+    Relayed,
+}
+
+/// The reason a connection ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisconnectReason {
+    /// The peer closed the connection.
+    PeerClosed,
+    /// The peer did not answer in time.
+    Timeout,
+    /// A message could not be decrypted.
+    DecryptionFailed,
+    /// The socket failed.
+    SocketError,
+    /// The connection was closed locally.
+    LocalShutdown,
+}
+
+impl ErrorKind {
+    /// Returns the reason of a disconnect caused by an error of this kind.
+    pub fn disconnect_reason(&self) -> DisconnectReason {
+        match self {
+            ErrorKind::ConnectionClosed => DisconnectReason::PeerClosed,
+            ErrorKind::TimedOut | ErrorKind::HandshakeTimedOut | ErrorKind::PeerTimedOut => {
+                DisconnectReason::Timeout
+            }
+            ErrorKind::EncryptionFailed => DisconnectReason::DecryptionFailed,
+            ErrorKind::Cancelled | ErrorKind::IdleTimeout => DisconnectReason::LocalShutdown,
+            _ => DisconnectReason::SocketError,
+        }
+    }
 }
 
 /// Error type for the P2p crate.
@@ -76,6 +148,9 @@ impl From<io::Error> for Error {
         let kind = match value.kind() {
             io::ErrorKind::WouldBlock => ErrorKind::TimedOut,
             io::ErrorKind::TimedOut => ErrorKind::TimedOut,
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::BrokenPipe => ErrorKind::ConnectionClosed,
             _ => ErrorKind::CommunicationFailed,
         };
 
@@ -212,6 +287,12 @@ impl<C> ChangeStateError<C> {
 #[derive(Debug)]
 pub struct ThreadError(ErrorKind);
 
+impl ThreadError {
+    pub fn kind(&self) -> ErrorKind {
+        self.0
+    }
+}
+
 impl Display for ThreadError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Thread Error of kind {:?} occurred.", self.0)
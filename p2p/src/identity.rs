@@ -0,0 +1,174 @@
+use crate::error::Error as P2pError;
+use crate::error::ErrorKind;
+use dryoc::dryocbox::Bytes;
+use dryoc::sign::{PublicKey, SecretKey, Signature, SignedMessage, SigningKeyPair};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+/// Length of an identity key.
+pub const IDENTITY_KEY_LEN: usize = 32;
+/// Length of a signature made with an identity key.
+pub(crate) const SIGNATURE_LEN: usize = 64;
+
+/// A long-lived signing key a peer presents in every handshake, see `Connection::set_identity`.
+/// Unlike the session keys it survives reconnects, so the peer can recognize this peer again.
+pub struct Identity {
+    keypair: SigningKeyPair<PublicKey, SecretKey>,
+}
+
+impl Identity {
+    /// Generates a new identity. It is lost once dropped, use `load_or_create` for a persistent one.
+    pub fn generate() -> Identity {
+        Identity {
+            keypair: SigningKeyPair::gen_with_defaults(),
+        }
+    }
+
+    /// Loads the identity stored at a path, or generates one and stores it there.
+    /// A new key file is only readable by its owner, it is never replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file holding the secret identity key.
+    ///
+    /// # Returns
+    ///
+    /// Returns the identity, or a `P2pError` of kind `IO` if the file can't be read or written
+    /// and of kind `InvalidIdentity` if the file does not hold an identity key.
+    pub fn load_or_create(path: &Path) -> Result<Identity, P2pError> {
+        if path.exists() {
+            return Identity::load(path);
+        }
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = match options.open(path) {
+            Ok(file) => file,
+            // created by somebody else in the meantime
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => return Identity::load(path),
+            Err(err) => return Err(err.into()),
+        };
+
+        let identity = Identity::generate();
+        file.write_all(identity.keypair.secret_key.as_slice())?;
+        file.sync_all()?;
+
+        return Ok(identity);
+    }
+
+    /// Loads the identity stored at a path.
+    fn load(path: &Path) -> Result<Identity, P2pError> {
+        let secret_key = fs::read(path)?;
+        let secret_key = SecretKey::try_from(secret_key.as_slice())
+            .map_err(|_| P2pError::new(ErrorKind::InvalidIdentity))?;
+
+        return Ok(Identity {
+            keypair: SigningKeyPair::from_secret_key(secret_key),
+        });
+    }
+
+    /// Returns the public part of the identity, as seen by peers.
+    pub fn peer_identity(&self) -> PeerIdentity {
+        let mut key = [0u8; IDENTITY_KEY_LEN];
+        key.copy_from_slice(self.keypair.public_key.as_slice());
+        PeerIdentity(key)
+    }
+
+    /// Signs a message.
+    pub(crate) fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, P2pError> {
+        let signed = self
+            .keypair
+            .sign_with_defaults(msg.to_vec())
+            .map_err(|_| P2pError::new(ErrorKind::EncryptionFailed))?;
+        let (signature, _) = signed.into_parts();
+
+        return Ok(signature.as_slice().to_vec());
+    }
+}
+
+/// The public identity key of a peer. Equal identities belong to the same peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PeerIdentity([u8; IDENTITY_KEY_LEN]);
+
+impl PeerIdentity {
+    /// Returns the identity key.
+    pub fn as_bytes(&self) -> &[u8; IDENTITY_KEY_LEN] {
+        &self.0
+    }
+
+    /// Reads an identity key.
+    pub(crate) fn from_slice(key: &[u8]) -> Result<PeerIdentity, P2pError> {
+        let key: [u8; IDENTITY_KEY_LEN] = key
+            .try_into()
+            .map_err(|_| P2pError::new(ErrorKind::InvalidIdentity))?;
+        Ok(PeerIdentity(key))
+    }
+
+    /// Checks that a message was signed by this identity.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `P2pError` of kind `InvalidIdentity` if the signature does not match.
+    pub(crate) fn verify(&self, msg: &[u8], signature: &[u8]) -> Result<(), P2pError> {
+        let invalid = |_| P2pError::new(ErrorKind::InvalidIdentity);
+        let public_key = PublicKey::try_from(self.0.as_slice()).map_err(invalid)?;
+        let signature = Signature::try_from(signature).map_err(invalid)?;
+
+        SignedMessage::from_parts(signature, msg.to_vec())
+            .verify(&public_key)
+            .map_err(invalid)
+    }
+}
+
+impl Display for PeerIdentity {
+    /// Formats the identity key as hex, e.g. to show it to the user for comparison.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_is_persisted() {
+        let path = std::env::temp_dir().join("rdrop_identity_test.key");
+        let _ = fs::remove_file(&path);
+
+        let created = Identity::load_or_create(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let loaded = Identity::load_or_create(&path).unwrap();
+        assert_eq!(created.peer_identity(), loaded.peer_identity());
+        assert_ne!(created.peer_identity(), Identity::generate().peer_identity());
+
+        let signature = loaded.sign(b"message").unwrap();
+        assert_eq!(signature.len(), SIGNATURE_LEN);
+        created.peer_identity().verify(b"message", &signature).unwrap();
+        let err = created.peer_identity().verify(b"other", &signature).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::InvalidIdentity);
+
+        fs::write(&path, b"too short").unwrap();
+        let err = Identity::load_or_create(&path).err().unwrap();
+        assert_eq!(err.kind(), &ErrorKind::InvalidIdentity);
+
+        fs::remove_file(path).unwrap();
+    }
+}
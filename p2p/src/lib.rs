@@ -1,5 +1,103 @@
+//! Peer to peer connections between two hosts, optionally behind NATs.
+//!
+//! # Public API
+//!
+//! Connections are made through the `protocol::Connection` state machine. Every state change
+//! consumes the connection and returns it in the next state, e.g. from `Waiting` over a plain
+//! to an encrypted UDP connection, which is then upgraded to TCP or to the sliding window UDP client.
+//! Accepting a connection splits it into a writer and a reader, used through the
+//! `client::ClientWriter` and `client::ClientReader` traits.
+//!
+//! The transports in `client::tcp`, `client::udp_send_wait` and `client::udp_slide` are public so
+//! their readers and writers can be named, but they are only created by a `Connection`.
+//!
+//! ```no_run
+//! use p2p::client::{ClientReader, ClientWriter};
+//! use p2p::protocol::Connection;
+//! use std::net::Ipv6Addr;
+//! use std::time::Duration;
+//!
+//! # fn main() -> Result<(), p2p::error::Error> {
+//! let timeout = Some(Duration::from_secs(60));
+//!
+//! let connection = Connection::new(Some(2000))?
+//!     .connect(Ipv6Addr::LOCALHOST, 2000, timeout, timeout)?
+//!     .encrypt()?
+//!     .upgrade_direct()?;
+//! let (mut writer, mut reader) = connection.accept();
+//!
+//! writer.write(b"hello")?;
+//! let response = reader.read(timeout)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The internals of the transports are private, a client can't be built from a raw socket:
+//!
+//! ```compile_fail,E0624
+//! let socket = std::net::UdpSocket::bind("[::1]:0").unwrap();
+//! let client = p2p::client::udp_slide::UdpActiveClient::new(socket, None, 0);
+//! ```
+//!
+//! ```compile_fail,E0624
+//! let socket = std::net::UdpSocket::bind("[::1]:0").unwrap();
+//! let client = p2p::client::udp_send_wait::UdpActiveClient::new(socket, None);
+//! ```
+//!
+//! Neither can the encryption streams be replaced:
+//!
+//! ```compile_fail,E0616
+//! fn streams(reader: p2p::client::EncryptedReader<p2p::client::tcp::TcpClientReader>) {
+//!     let _ = reader.pull_stream;
+//! }
+//! ```
+
 pub mod client;
 pub mod error;
 mod example;
+pub mod identity;
+pub mod nat;
 mod ntp_time;
+pub mod policy;
 pub mod protocol;
+pub mod relay;
+#[cfg(feature = "debug-crypto")]
+pub mod session;
+pub mod throughput;
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{tcp, udp_send_wait, udp_slide};
+    use crate::client::{ClientReader, ClientWriter, EncryptedReader, EncryptedWriter};
+    use crate::protocol::{Active, Connection, Encrypted, Tcp, Udp};
+
+    type EncryptedTcp = Connection<Active<Encrypted<Tcp>>>;
+    type EncryptedUdp = Connection<Active<Encrypted<Udp>>>;
+    type TcpHalves = (
+        EncryptedWriter<tcp::TcpClientWriter>,
+        EncryptedReader<tcp::TcpClientReader>,
+    );
+    type UdpHalves = (
+        EncryptedWriter<udp_send_wait::UdpClientWriter>,
+        EncryptedReader<udp_send_wait::UdpClientReader>,
+    );
+
+    fn assert_reader<R: ClientReader>() {}
+    fn assert_writer<W: ClientWriter>() {}
+
+    /// Breaks the build if the readers and writers handed out by a `Connection` change.
+    #[test]
+    fn test_public_api() {
+        assert_reader::<tcp::TcpClientReader>();
+        assert_writer::<tcp::TcpClientWriter>();
+        assert_reader::<udp_send_wait::UdpClientReader>();
+        assert_writer::<udp_send_wait::UdpClientWriter>();
+        assert_reader::<udp_slide::UdpClientReader>();
+        assert_writer::<udp_slide::UdpClientWriter>();
+        assert_reader::<EncryptedReader<udp_slide::UdpClientReader>>();
+        assert_writer::<EncryptedWriter<udp_slide::UdpClientWriter>>();
+
+        let _: fn(EncryptedTcp) -> TcpHalves = EncryptedTcp::accept;
+        let _: fn(EncryptedUdp) -> UdpHalves = EncryptedUdp::accept;
+    }
+}
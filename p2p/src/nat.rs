@@ -0,0 +1,594 @@
+use crate::error::Error as P2pError;
+use crate::error::ErrorKind;
+use rand::{thread_rng, Rng};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Public STUN servers used by `detect_nat_type_with_defaults`.
+/// They answer from another address on request and name it in `OTHER-ADDRESS` or `CHANGED-ADDRESS`,
+/// which the filtering tests need.
+pub const STUN_SERVERS: [&str; 3] = [
+    "stun.stunprotocol.org:3478",
+    "stun.sipgate.net:3478",
+    "stun.ekiga.net:3478",
+];
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_RESPONSE: u16 = 0x0101;
+const BINDING_ERROR_RESPONSE: u16 = 0x0111;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const HEADER_SIZE: usize = 20;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_CHANGE_REQUEST: u16 = 0x0003;
+const ATTR_CHANGED_ADDRESS: u16 = 0x0005;
+const ATTR_ERROR_CODE: u16 = 0x0009;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const ATTR_OTHER_ADDRESS: u16 = 0x802C;
+const CHANGE_IP: u32 = 0x04;
+const CHANGE_PORT: u32 = 0x02;
+/// The error code of a server which does not know the `CHANGE-REQUEST` attribute.
+const ERROR_UNKNOWN_ATTRIBUTE: u16 = 420;
+
+/// The type of the NAT in front of the local host.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NatType {
+    /// The host is reachable under its local address.
+    Open,
+    /// Any host can send to the mapped address.
+    FullCone,
+    /// Only hosts the client sent to can answer, from any port.
+    Restricted,
+    /// Only the address and port the client sent to can answer.
+    PortRestricted,
+    /// Every destination gets a different mapping.
+    Symmetric,
+}
+
+impl NatType {
+    /// Checks if hole punching is likely to succeed behind this NAT.
+    pub fn direct_connection_likely(&self) -> bool {
+        *self != NatType::Symmetric
+    }
+}
+
+impl Display for NatType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NatType::Open => write!(f, "open"),
+            NatType::FullCone => write!(f, "full-cone NAT"),
+            NatType::Restricted => write!(f, "restricted NAT"),
+            NatType::PortRestricted => write!(f, "port-restricted NAT"),
+            NatType::Symmetric => write!(f, "symmetric NAT, direct connection unlikely"),
+        }
+    }
+}
+
+/// A binding response of a STUN server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BindingResponse {
+    /// The address the server saw the request from.
+    mapped: SocketAddr,
+    /// The address the server answers change requests from, if it names one.
+    other: Option<SocketAddr>,
+}
+
+/// Detects the NAT type using the servers in `STUN_SERVERS`.
+/// Only IPv4 addresses of the servers are used.
+/// Every server is asked for the change requests in turn until one of them succeeds.
+///
+/// # Errors
+///
+/// Returns the error of the last attempt if no server works, see `detect_nat_type`,
+/// or an error of kind `Other` if less than two servers can be resolved.
+pub fn detect_nat_type_with_defaults(timeout: Duration) -> Result<NatType, P2pError> {
+    let mut servers = Vec::new();
+
+    for server in STUN_SERVERS {
+        // a server which can't be resolved is left out
+        match server.to_socket_addrs() {
+            Ok(mut addrs) => {
+                if let Some(addr) = addrs.find(|addr| addr.is_ipv4()) {
+                    servers.push(addr);
+                }
+            }
+            Err(e) => println!("[NAT] : can't resolve {} {}", server, e),
+        }
+    }
+
+    let mut result = Err(P2pError::new(ErrorKind::Other));
+    for _ in 0..servers.len() {
+        result = detect_nat_type(&servers, timeout);
+        if result.is_ok() || servers.len() < 2 {
+            return result;
+        }
+        servers.rotate_left(1);
+    }
+
+    return result;
+}
+
+/// Classifies the NAT of the local host with STUN binding requests as described in RFC 5780.
+///
+/// The mapping reported by the first server is compared with the local address.
+/// Requests asking the first server to answer from its other address or port show how the NAT filters.
+/// Only responses from the address the server names in `OTHER-ADDRESS` count,
+/// so a server ignoring the change request does not make the NAT appear open.
+/// The mapping reported by another server shows if the NAT maps every destination differently.
+///
+/// # Arguments
+///
+/// * `servers` - At least two STUN servers with different addresses.
+///     The first one answers the change requests, the others are asked for the mapping in turn until one answers.
+/// * `timeout` - The time to wait for each response.
+///
+/// # Errors
+///
+/// Returns an error of kind `Other` if less than two servers are given,
+/// an error of kind `TimedOut` if the servers don't answer a plain binding request,
+/// an error of kind `StunUnsupported` if the first server can't answer from another address,
+/// and an error of kind `IllegalByteStream` if a response is malformed.
+pub fn detect_nat_type(servers: &[SocketAddr], timeout: Duration) -> Result<NatType, P2pError> {
+    if servers.len() < 2 {
+        return Err(P2pError::new(ErrorKind::Other));
+    }
+
+    let server = servers[0];
+    let socket = bind_local(server)?;
+    let local_addr = socket.local_addr()?;
+
+    let response = match binding(&socket, server, 0, server, timeout)? {
+        Some(response) => response,
+        None => return Err(P2pError::new(ErrorKind::TimedOut)),
+    };
+
+    if response.mapped == local_addr {
+        return Ok(NatType::Open);
+    }
+
+    let other = match response.other {
+        Some(other) if other.ip() != server.ip() && other.port() != server.port() => other,
+        _ => return Err(P2pError::new(ErrorKind::StunUnsupported)),
+    };
+
+    if binding(&socket, server, CHANGE_IP | CHANGE_PORT, other, timeout)?.is_some() {
+        return Ok(NatType::FullCone);
+    }
+
+    let mut other_mapped = None;
+    for other_server in servers[1..].iter() {
+        if let Some(response) = binding(&socket, *other_server, 0, *other_server, timeout)? {
+            other_mapped = Some(response.mapped);
+            break;
+        }
+    }
+
+    match other_mapped {
+        None => return Err(P2pError::new(ErrorKind::TimedOut)),
+        Some(other_mapped) if other_mapped != response.mapped => return Ok(NatType::Symmetric),
+        Some(_) => {}
+    }
+
+    let changed_port = SocketAddr::new(server.ip(), other.port());
+    if binding(&socket, server, CHANGE_PORT, changed_port, timeout)?.is_some() {
+        return Ok(NatType::Restricted);
+    }
+
+    return Ok(NatType::PortRestricted);
+}
+
+/// Binds a socket to the local address used to reach `server`, so it can be compared with the mapping.
+fn bind_local(server: SocketAddr) -> Result<UdpSocket, P2pError> {
+    let unspecified = match server {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+
+    let probe = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+    probe.connect(server)?;
+    let local_ip = probe.local_addr()?.ip();
+
+    return Ok(UdpSocket::bind(SocketAddr::new(local_ip, 0))?);
+}
+
+/// Sends a binding request and waits for the matching response.
+///
+/// # Arguments
+///
+/// * `socket` - The socket the request is sent from.
+/// * `server` - The server the request is sent to.
+/// * `change` - The `CHANGE-REQUEST` flags.
+/// * `source` - The address the response has to come from. Responses from other addresses are ignored.
+/// * `timeout` - The time to wait for the response.
+///
+/// # Returns
+///
+/// Returns the response, or `None` if no response arrived in time.
+///
+/// # Errors
+///
+/// Returns an error of kind `StunUnsupported` if the server answers a change request from its own address
+/// or rejects the `CHANGE-REQUEST` attribute.
+fn binding(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    change: u32,
+    source: SocketAddr,
+    timeout: Duration,
+) -> Result<Option<BindingResponse>, P2pError> {
+    let mut transaction_id = [0u8; 12];
+    thread_rng().fill(&mut transaction_id);
+
+    socket.send_to(&create_binding_request(&transaction_id, change), server)?;
+
+    let start = Instant::now();
+    let mut buffer = [0u8; 512];
+
+    loop {
+        let remaining = match timeout.checked_sub(start.elapsed()) {
+            Some(remaining) if !remaining.is_zero() => remaining,
+            _ => return Ok(None),
+        };
+        socket.set_read_timeout(Some(remaining))?;
+
+        let (size, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // responses to earlier requests are ignored
+        if size < HEADER_SIZE || buffer[8..HEADER_SIZE] != transaction_id {
+            continue;
+        }
+
+        if from == source {
+            return Ok(Some(read_binding_response(&buffer[..size], &transaction_id)?));
+        }
+
+        // the server ignored the change request and answered from the address the request was sent to
+        if change != 0 && from == server {
+            return Err(P2pError::new(ErrorKind::StunUnsupported));
+        }
+    }
+}
+
+/// Creates a STUN binding request.
+///
+/// # Arguments
+///
+/// * `transaction_id` - The id the response has to contain.
+/// * `change` - The `CHANGE-REQUEST` flags. No attribute is added if they are `0`.
+fn create_binding_request(transaction_id: &[u8; 12], change: u32) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(HEADER_SIZE + 8);
+    let length: u16 = if change != 0 { 8 } else { 0 };
+
+    msg.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    msg.extend_from_slice(&length.to_be_bytes());
+    msg.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    msg.extend_from_slice(transaction_id);
+
+    if change != 0 {
+        msg.extend_from_slice(&ATTR_CHANGE_REQUEST.to_be_bytes());
+        msg.extend_from_slice(&4u16.to_be_bytes());
+        msg.extend_from_slice(&change.to_be_bytes());
+    }
+
+    return msg;
+}
+
+/// Reads a STUN binding response.
+/// `XOR-MAPPED-ADDRESS` is preferred over `MAPPED-ADDRESS` and `OTHER-ADDRESS` over `CHANGED-ADDRESS`.
+///
+/// # Errors
+///
+/// Returns an error of kind `StunUnsupported` if the server rejected the `CHANGE-REQUEST` attribute,
+/// and an error of kind `IllegalByteStream` if the message is no binding response or contains no mapped address.
+fn read_binding_response(msg: &[u8], transaction_id: &[u8; 12]) -> Result<BindingResponse, P2pError> {
+    if msg.len() < HEADER_SIZE || msg[8..HEADER_SIZE] != transaction_id[..] {
+        return Err(P2pError::new(ErrorKind::IllegalByteStream));
+    }
+
+    let msg_type = u16::from_be_bytes([msg[0], msg[1]]);
+    if msg_type != BINDING_RESPONSE && msg_type != BINDING_ERROR_RESPONSE {
+        return Err(P2pError::new(ErrorKind::IllegalByteStream));
+    }
+
+    let length = u16::from_be_bytes([msg[2], msg[3]]) as usize;
+    let attributes = match msg.get(HEADER_SIZE..HEADER_SIZE + length) {
+        Some(attributes) => attributes,
+        None => return Err(P2pError::new(ErrorKind::IllegalByteStream)),
+    };
+
+    let mut xor_mapped = None;
+    let mut mapped = None;
+    let mut other = None;
+    let mut changed = None;
+    let mut error_code = None;
+    let mut pos = 0;
+
+    while pos + 4 <= attributes.len() {
+        let attr_type = u16::from_be_bytes([attributes[pos], attributes[pos + 1]]);
+        let attr_length = u16::from_be_bytes([attributes[pos + 2], attributes[pos + 3]]) as usize;
+        let value = match attributes.get(pos + 4..pos + 4 + attr_length) {
+            Some(value) => value,
+            None => return Err(P2pError::new(ErrorKind::IllegalByteStream)),
+        };
+
+        match attr_type {
+            ATTR_XOR_MAPPED_ADDRESS => xor_mapped = Some(read_address(value, Some(transaction_id))?),
+            ATTR_MAPPED_ADDRESS => mapped = Some(read_address(value, None)?),
+            ATTR_OTHER_ADDRESS => other = Some(read_address(value, None)?),
+            ATTR_CHANGED_ADDRESS => changed = Some(read_address(value, None)?),
+            // the class is stored in the hundreds, the number below them
+            ATTR_ERROR_CODE if value.len() >= 4 => {
+                error_code = Some((value[2] & 0x07) as u16 * 100 + value[3] as u16)
+            }
+            _ => {}
+        }
+
+        // attributes are padded to a multiple of four bytes
+        pos += 4 + (attr_length + 3) / 4 * 4;
+    }
+
+    if msg_type == BINDING_ERROR_RESPONSE {
+        return match error_code {
+            Some(ERROR_UNKNOWN_ATTRIBUTE) => Err(P2pError::new(ErrorKind::StunUnsupported)),
+            _ => Err(P2pError::new(ErrorKind::IllegalByteStream)),
+        };
+    }
+
+    return match xor_mapped.or(mapped) {
+        Some(mapped) => Ok(BindingResponse {
+            mapped,
+            other: other.or(changed),
+        }),
+        None => Err(P2pError::new(ErrorKind::IllegalByteStream)),
+    };
+}
+
+/// Reads an address attribute. The address is unmasked if the transaction id of an xor attribute is given.
+fn read_address(value: &[u8], xor_transaction_id: Option<&[u8; 12]>) -> Result<SocketAddr, P2pError> {
+    if value.len() < 4 {
+        return Err(P2pError::new(ErrorKind::IllegalByteStream));
+    }
+
+    let mut mask = MAGIC_COOKIE.to_be_bytes().to_vec();
+    match xor_transaction_id {
+        Some(transaction_id) => mask.extend_from_slice(transaction_id),
+        None => mask = vec![0; 16],
+    }
+
+    let port = u16::from_be_bytes([value[2] ^ mask[0], value[3] ^ mask[1]]);
+    let address: Vec<u8> = value[4..].iter().zip(mask.iter()).map(|(a, b)| a ^ b).collect();
+
+    let ip = match (value[1], address.len()) {
+        (0x01, 4) => IpAddr::V4(Ipv4Addr::new(address[0], address[1], address[2], address[3])),
+        (0x02, 16) => {
+            let address: [u8; 16] = address.as_slice().try_into()?;
+            IpAddr::V6(Ipv6Addr::from(address))
+        }
+        _ => return Err(P2pError::new(ErrorKind::IllegalByteStream)),
+    };
+
+    return Ok(SocketAddr::new(ip, port));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// How a mock STUN server handles change requests.
+    #[derive(Clone, Copy, PartialEq)]
+    enum MockSupport {
+        /// Answers from its other address or port as requested.
+        Rfc5780,
+        /// Names another address, but always answers from the address the request was sent to.
+        IgnoresChange,
+        /// Names another address, but rejects the change request with error 420.
+        UnknownAttribute,
+    }
+
+    /// Behaviour of a mock STUN server, which simulates the NAT in front of the client.
+    #[derive(Clone, Copy)]
+    struct MockNat {
+        /// The mapping reported to the client. `None` reports the real address.
+        mapped: Option<SocketAddr>,
+        /// Responses from another address pass the NAT.
+        pass_change_ip: bool,
+        /// Responses from another port pass the NAT.
+        pass_change_port: bool,
+        support: MockSupport,
+    }
+
+    fn push_address(msg: &mut Vec<u8>, attr_type: u16, addr: SocketAddr, mask: [u8; 4]) {
+        let ip = match addr.ip() {
+            IpAddr::V4(ip) => ip.octets(),
+            IpAddr::V6(_) => panic!("mock only supports IPv4"),
+        };
+
+        msg.extend_from_slice(&attr_type.to_be_bytes());
+        msg.extend_from_slice(&8u16.to_be_bytes());
+        msg.extend_from_slice(&[0, 0x01]);
+        msg.extend_from_slice(&(addr.port() ^ u16::from_be_bytes([mask[0], mask[1]])).to_be_bytes());
+        msg.extend(ip.iter().zip(mask.iter()).map(|(a, b)| a ^ b));
+    }
+
+    fn create_binding_response(transaction_id: &[u8], mapped: SocketAddr, other: Option<SocketAddr>) -> Vec<u8> {
+        let cookie = MAGIC_COOKIE.to_be_bytes();
+
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&BINDING_RESPONSE.to_be_bytes());
+        msg.extend_from_slice(&0u16.to_be_bytes());
+        msg.extend_from_slice(&cookie);
+        msg.extend_from_slice(transaction_id);
+        push_address(&mut msg, ATTR_XOR_MAPPED_ADDRESS, mapped, cookie);
+        if let Some(other) = other {
+            push_address(&mut msg, ATTR_OTHER_ADDRESS, other, [0; 4]);
+        }
+
+        let length = (msg.len() - HEADER_SIZE) as u16;
+        msg[2..4].copy_from_slice(&length.to_be_bytes());
+        msg
+    }
+
+    fn create_error_response(transaction_id: &[u8], code: u16) -> Vec<u8> {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&BINDING_ERROR_RESPONSE.to_be_bytes());
+        msg.extend_from_slice(&8u16.to_be_bytes());
+        msg.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        msg.extend_from_slice(transaction_id);
+        msg.extend_from_slice(&ATTR_ERROR_CODE.to_be_bytes());
+        msg.extend_from_slice(&4u16.to_be_bytes());
+        msg.extend_from_slice(&[0, 0, (code / 100) as u8, (code % 100) as u8]);
+        msg
+    }
+
+    /// Starts a mock server answering from 127.0.0.1 and, for change requests, from another port or from 127.0.0.2.
+    fn mock_server(nat: MockNat) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let other_ip = UdpSocket::bind("127.0.0.2:0").unwrap();
+        let other = other_ip.local_addr().unwrap();
+        let other_port = UdpSocket::bind(SocketAddr::new(addr.ip(), other.port())).unwrap();
+
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((size, client)) = socket.recv_from(&mut buffer) {
+                let msg = &buffer[..size];
+                let transaction_id = &msg[8..HEADER_SIZE];
+                let change = match msg.len() {
+                    28 => u32::from_be_bytes([msg[24], msg[25], msg[26], msg[27]]),
+                    _ => 0,
+                };
+
+                let response = create_binding_response(transaction_id, nat.mapped.unwrap_or(client), Some(other));
+                let (response, from) = match (change, nat.support) {
+                    (0, _) | (_, MockSupport::IgnoresChange) => (response, &socket),
+                    (_, MockSupport::UnknownAttribute) => {
+                        (create_error_response(transaction_id, ERROR_UNKNOWN_ATTRIBUTE), &socket)
+                    }
+                    (_, MockSupport::Rfc5780) if change & CHANGE_IP != 0 => (response, &other_ip),
+                    (_, MockSupport::Rfc5780) => (response, &other_port),
+                };
+
+                let passes = (from.local_addr().unwrap().ip() == addr.ip() || nat.pass_change_ip)
+                    && (from.local_addr().unwrap().port() == addr.port() || nat.pass_change_port);
+                if passes {
+                    from.send_to(&response, client).unwrap();
+                }
+            }
+        });
+
+        addr
+    }
+
+    fn nat(mapped: Option<SocketAddr>, pass_change_ip: bool, pass_change_port: bool) -> MockNat {
+        MockNat {
+            mapped,
+            pass_change_ip,
+            pass_change_port,
+            support: MockSupport::Rfc5780,
+        }
+    }
+
+    fn detect(first: MockNat, second: MockNat) -> Result<NatType, P2pError> {
+        let servers = [mock_server(first), mock_server(second)];
+        detect_nat_type(&servers, Duration::from_millis(200))
+    }
+
+    #[test]
+    fn test_binding_message_roundtrip() {
+        let transaction_id = [7u8; 12];
+        let request = create_binding_request(&transaction_id, CHANGE_PORT);
+        assert_eq!(request.len(), 28);
+        assert_eq!(&request[4..8], &MAGIC_COOKIE.to_be_bytes());
+
+        let mapped: SocketAddr = "203.0.113.9:40000".parse().unwrap();
+        let other: SocketAddr = "198.51.100.1:3479".parse().unwrap();
+        let response = create_binding_response(&transaction_id, mapped, Some(other));
+        let expected = BindingResponse {
+            mapped,
+            other: Some(other),
+        };
+        assert_eq!(read_binding_response(&response, &transaction_id).unwrap(), expected);
+
+        assert!(read_binding_response(&response, &[8u8; 12]).is_err());
+        assert!(read_binding_response(&response[..HEADER_SIZE + 4], &transaction_id).is_err());
+
+        let error = create_error_response(&transaction_id, ERROR_UNKNOWN_ATTRIBUTE);
+        let err = read_binding_response(&error, &transaction_id).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::StunUnsupported));
+        let error = create_error_response(&transaction_id, 400);
+        let err = read_binding_response(&error, &transaction_id).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::IllegalByteStream));
+    }
+
+    #[test]
+    fn test_detect_nat_type() {
+        let mapped: SocketAddr = "203.0.113.9:40000".parse().unwrap();
+        let other: SocketAddr = "203.0.113.9:40001".parse().unwrap();
+
+        let open = nat(None, false, false);
+        assert_eq!(detect(open, open).unwrap(), NatType::Open);
+
+        let full_cone = nat(Some(mapped), true, true);
+        assert_eq!(detect(full_cone, full_cone).unwrap(), NatType::FullCone);
+
+        let restricted = nat(Some(mapped), false, true);
+        assert_eq!(detect(restricted, restricted).unwrap(), NatType::Restricted);
+
+        let port_restricted = nat(Some(mapped), false, false);
+        assert_eq!(detect(port_restricted, port_restricted).unwrap(), NatType::PortRestricted);
+
+        let symmetric = detect(port_restricted, nat(Some(other), false, false)).unwrap();
+        assert_eq!(symmetric, NatType::Symmetric);
+        assert!(!symmetric.direct_connection_likely());
+    }
+
+    #[test]
+    fn test_detect_nat_type_unsupported_server() {
+        let mapped: SocketAddr = "203.0.113.9:40000".parse().unwrap();
+
+        // the response to the change request comes from the wrong address, it must not look like a full cone
+        let ignores_change = MockNat {
+            support: MockSupport::IgnoresChange,
+            ..nat(Some(mapped), true, true)
+        };
+        let err = detect(ignores_change, ignores_change).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::StunUnsupported));
+
+        let unknown_attribute = MockNat {
+            support: MockSupport::UnknownAttribute,
+            ..nat(Some(mapped), true, true)
+        };
+        let err = detect(unknown_attribute, unknown_attribute).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::StunUnsupported));
+    }
+
+    #[test]
+    fn test_detect_nat_type_no_response() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let servers = [silent.local_addr().unwrap(), silent.local_addr().unwrap()];
+
+        let err = detect_nat_type(&servers, Duration::from_millis(200)).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::TimedOut));
+
+        let err = detect_nat_type(&servers[..1], Duration::from_millis(200)).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Other));
+
+        // a silent server is skipped when the mapping is compared
+        let mapped: SocketAddr = "203.0.113.9:40000".parse().unwrap();
+        let port_restricted = nat(Some(mapped), false, false);
+        let servers = [mock_server(port_restricted), silent.local_addr().unwrap(), mock_server(port_restricted)];
+        assert_eq!(
+            detect_nat_type(&servers, Duration::from_millis(200)).unwrap(),
+            NatType::PortRestricted
+        );
+    }
+}
@@ -2,6 +2,8 @@ use sntpc::{NtpContext, NtpTimestampGenerator, NtpUdpSocket};
 use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
 use std::time::Duration;
 
+const NTP_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[derive(Copy, Clone, Default)]
 struct StdTimestampGen {
     duration: Duration,
@@ -44,7 +46,10 @@ impl NtpUdpSocket for UdpSocketWrapper {
 
 pub fn get_diff() -> Result<(Duration, i64), crate::error::Error> {
     let ntp_context = NtpContext::new(StdTimestampGen::default());
-    let socket = UdpSocketWrapper(UdpSocket::bind("0.0.0.0:0").expect("something"));
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    // an unreachable time server must not block the caller
+    socket.set_read_timeout(Some(NTP_TIMEOUT))?;
+    let socket = UdpSocketWrapper(socket);
     //#TODO change time server to be dynamic
     let result = sntpc::get_time("83.168.200.199:123", socket, ntp_context);
 
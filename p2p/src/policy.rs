@@ -0,0 +1,167 @@
+use crate::client::to_dual_stack;
+use crate::error::Error as P2pError;
+use crate::error::ErrorKind;
+use std::net::{IpAddr, Ipv6Addr};
+use std::str::FromStr;
+
+/// A range of IP addresses given by a network address and a prefix length, e.g. `10.0.0.0/8` or `fd00::/8`.
+/// IPv4 ranges are stored as IPv4-mapped IPv6 ranges, so they also match the mapped form of their addresses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRange {
+    network: Ipv6Addr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// Creates a range.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Any address of the range. Bits after the prefix are ignored.
+    /// * `prefix_len` - The number of leading bits which have to match, at most 32 for IPv4 and 128 for IPv6.
+    ///
+    /// # Returns
+    ///
+    /// Returns the range, or a `P2pError` of kind `InvalidIpRange` if the prefix length is too large.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<IpRange, P2pError> {
+        let (max_len, offset) = match addr {
+            IpAddr::V4(_) => (32, 96),
+            IpAddr::V6(_) => (128, 0),
+        };
+
+        if prefix_len > max_len {
+            return Err(P2pError::new(ErrorKind::InvalidIpRange));
+        }
+
+        let prefix_len = prefix_len + offset;
+        let network = Ipv6Addr::from(u128::from(to_dual_stack(addr)) & IpRange::mask(prefix_len));
+
+        Ok(IpRange {
+            network,
+            prefix_len,
+        })
+    }
+
+    /// Checks if an address is part of the range.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = u128::from(to_dual_stack(addr));
+        addr & IpRange::mask(self.prefix_len) == u128::from(self.network)
+    }
+
+    fn mask(prefix_len: u8) -> u128 {
+        match prefix_len {
+            0 => 0,
+            len => u128::MAX << (128 - len as u32),
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = P2pError;
+
+    /// Parses a range in CIDR notation. A single address is a range of only this address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+
+        let addr =
+            IpAddr::from_str(addr.trim()).map_err(|_| P2pError::new(ErrorKind::InvalidIpRange))?;
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .trim()
+                .parse::<u8>()
+                .map_err(|_| P2pError::new(ErrorKind::InvalidIpRange))?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+
+        IpRange::new(addr, prefix_len)
+    }
+}
+
+/// Decides which peers a connection may be made to, see `Connection::connect_with_policy`.
+/// The policy is checked before any packet is sent to the peer.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ConnectPolicy {
+    /// Every peer is permitted.
+    #[default]
+    AllowAll,
+    /// Only peers in one of the ranges are permitted.
+    Allow(Vec<IpRange>),
+    /// Peers in one of the ranges are refused.
+    Block(Vec<IpRange>),
+}
+
+impl ConnectPolicy {
+    /// Checks if a peer is permitted.
+    pub fn permits(&self, peer: IpAddr) -> bool {
+        match self {
+            ConnectPolicy::AllowAll => true,
+            ConnectPolicy::Allow(ranges) => ranges.iter().any(|range| range.contains(peer)),
+            ConnectPolicy::Block(ranges) => !ranges.iter().any(|range| range.contains(peer)),
+        }
+    }
+
+    /// Checks if a peer is permitted.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `P2pError` of kind `RefusedByPolicy` if the peer is not permitted.
+    pub fn check(&self, peer: IpAddr) -> Result<(), P2pError> {
+        if !self.permits(peer) {
+            return Err(P2pError::new(ErrorKind::RefusedByPolicy));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(s: &str) -> IpRange {
+        IpRange::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_ip_range_contains() {
+        let private = range("10.0.0.0/8");
+        assert!(private.contains("10.1.2.3".parse().unwrap()));
+        assert!(private.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!private.contains("11.0.0.1".parse().unwrap()));
+
+        let unique_local = range("fd00::/8");
+        assert!(unique_local.contains("fd12::1".parse().unwrap()));
+        assert!(!unique_local.contains("fe80::1".parse().unwrap()));
+
+        assert!(range("::1").contains("::1".parse().unwrap()));
+        assert!(!range("::1").contains("::2".parse().unwrap()));
+        assert!(range("::/0").contains("10.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ip_range_invalid() {
+        for invalid in ["10.0.0.0/33", "::/129", "10.0.0/8", "::1/x", ""] {
+            let err = IpRange::from_str(invalid).unwrap_err();
+            assert_eq!(err.kind(), &ErrorKind::InvalidIpRange);
+        }
+    }
+
+    #[test]
+    fn test_connect_policy() {
+        let peer = "192.168.1.5".parse().unwrap();
+
+        assert!(ConnectPolicy::default().permits(peer));
+        assert!(ConnectPolicy::Allow(vec![range("192.168.0.0/16")]).permits(peer));
+        assert!(!ConnectPolicy::Allow(vec![range("10.0.0.0/8")]).permits(peer));
+        assert!(!ConnectPolicy::Allow(Vec::new()).permits(peer));
+        assert!(ConnectPolicy::Block(vec![range("10.0.0.0/8")]).permits(peer));
+
+        let err = ConnectPolicy::Block(vec![range("192.168.1.0/24")])
+            .check(peer)
+            .unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::RefusedByPolicy);
+    }
+}
@@ -1,20 +1,66 @@
 use crate::client::tcp::{TcpActiveClient, TcpClientReader, TcpClientWriter, TcpWaitingClient};
 use crate::client::udp_send_wait::{UdpActiveClient, UdpClientReader, UdpClientWriter, UdpWaitingClient};
-use crate::client::{ActiveClient, ClientReader, ClientWriter, EncryptedReader, EncryptedWriter, udp_slide, WaitingClient};
+use crate::client::multi_flow::{MultiFlowReader, MultiFlowWriter};
+use crate::client::{ActiveClient, ClientReader, ClientWriter, EncryptedReader, EncryptedWriter, PlainReader, PlainWriter, ProtocolKind, SharedPushStream, udp_slide, WaitingClient};
 use crate::error::Error as P2pError;
 use crate::error::{ChangeStateError, ErrorKind};
+use crate::identity::{Identity, PeerIdentity, IDENTITY_KEY_LEN, SIGNATURE_LEN};
 use crate::ntp_time::get_diff;
+use crate::policy::ConnectPolicy;
+#[cfg(feature = "debug-crypto")]
+use crate::session::SessionSecrets;
 use dryoc::dryocbox::{Bytes, KeyPair};
 use dryoc::dryocstream::{DryocStream, Header, Pull, Push};
 use dryoc::kx::{Session, SessionKey};
 use dryoc::sign::PublicKey;
 use rand::{thread_rng, Rng};
 use std::fmt::Debug;
-use std::net::Ipv6Addr;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
 use std::thread::sleep;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use crate::protocol::Role::Client;
 
+/// Number of synchronized connect attempts made by `upgrade_direct` and `upgrade_ntp`.
+pub const DEFAULT_UPGRADE_TRIES: u8 = 10;
+/// Number of clock difference samples collected per connect attempt by `upgrade_direct`.
+pub const DEFAULT_SAMPLE_COUNT: u8 = 100;
+/// Number of delay samples collected per connect attempt by `upgrade_ntp`.
+pub const DEFAULT_NTP_SAMPLE_COUNT: u8 = 10;
+/// Maximum total duration of the role negotiation.
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum number of rounds of the role negotiation, each tie starts a new round.
+const MAX_NEGOTIATION_ROUNDS: u8 = 16;
+/// Maximum length of an application message offered by `encrypt`.
+/// Large enough for a chunk of the chunk crate including its header.
+pub const DEFAULT_MAX_MSG_LEN: usize = 1024 * 1024;
+/// Application identifier exchanged by `encrypt` unless another one is set with `set_app_id`.
+/// Peers with different identifiers refuse each other, so unrelated applications or protocol versions can't talk.
+pub const DEFAULT_APP_ID: &[u8] = b"rdrop/1";
+/// Length of the challenge each peer signs when a connection is not encrypted.
+const CHALLENGE_LEN: usize = 32;
+/// Prefix of the message signed with the identity key during the key exchange.
+const IDENTITY_CONTEXT: &[u8] = b"rdrop identity";
+
+/// Progress of the handshake made by `encrypt` and the upgrades.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandshakePhase {
+    /// The peers agreed on which of them acts as server.
+    RolesNegotiated,
+    /// The session keys were derived.
+    KeysExchanged,
+    /// The connection is encrypted.
+    Encrypted,
+    /// An upgrade to TCP started.
+    Upgrading,
+    /// A clock difference sample was exchanged. Contains the number of exchanged samples and the total amount.
+    SamplingProgress(u8, u8),
+    /// The connection was upgraded to TCP.
+    Upgraded,
+}
+
 pub trait EncryptionState {}
 
 pub trait ConnectionState {}
@@ -22,18 +68,40 @@ pub trait ConnectionState {}
 pub trait ProtocolState {
     type Writer: ClientWriter;
     type Reader: ClientReader;
+    /// The transport protocol of the state.
+    const KIND: ProtocolKind;
 }
 
+/// Writer and reader of a connection striped across several sliding window flows, see `Connection::transform_to_slide_flows`.
+pub type SlideFlows = (
+    MultiFlowWriter<EncryptedWriter<udp_slide::UdpClientWriter>>,
+    MultiFlowReader<EncryptedReader<udp_slide::UdpClientReader>>,
+);
+
+/// Writer and reader of a sliding window connection without encryption and the identity of the peer,
+/// see `Connection::transform_to_slide` of a plain UDP connection.
+pub type PlainSlide = (
+    PlainWriter<udp_slide::UdpClientWriter>,
+    PlainReader<udp_slide::UdpClientReader>,
+    PeerIdentity,
+);
+
 pub struct Encrypted<P: ProtocolState> {
     encrypted_reader: EncryptedReader<P::Reader>,
     encrypted_writer: EncryptedWriter<P::Writer>,
     clock_diff_samples: Vec<i128>,
     max_delay: u128,
+    #[cfg(feature = "debug-crypto")]
+    session_secrets: SessionSecrets,
+    identity: Identity,
+    peer_identity: PeerIdentity,
 }
 
 pub struct Plain<P: ProtocolState> {
     plain_reader: P::Reader,
     plain_writer: P::Writer,
+    app_id: Vec<u8>,
+    identity: Identity,
 }
 
 impl<P: ProtocolState> EncryptionState for Encrypted<P> {}
@@ -47,12 +115,35 @@ enum Role {
     None,
 }
 
+impl Role {
+    /// Returns the byte sent to the peer to confirm the role.
+    fn to_byte(&self) -> u8 {
+        match self {
+            Role::None => 0,
+            Role::Server => 1,
+            Role::Client => 2,
+        }
+    }
+
+    /// Reads a role sent by `to_byte`. Unknown bytes are read as `Role::None`.
+    fn from_byte(byte: u8) -> Role {
+        match byte {
+            1 => Role::Server,
+            2 => Role::Client,
+            _ => Role::None,
+        }
+    }
+}
+
 pub struct Active<E: EncryptionState> {
     role: Role,
     timeout: Option<Duration>,
     client: E,
     peer_ip: Ipv6Addr,
     port: u16,
+    phase_sender: Option<Sender<HandshakePhase>>,
+    /// Whether the datagrams run through a relay, see `Connection::connect_via_relay`.
+    relayed: bool,
 }
 
 pub struct Waiting {
@@ -70,11 +161,13 @@ pub struct Tcp {}
 impl ProtocolState for Udp {
     type Writer = UdpClientWriter;
     type Reader = UdpClientReader;
+    const KIND: ProtocolKind = ProtocolKind::Udp;
 }
 
 impl ProtocolState for Tcp {
     type Writer = TcpClientWriter;
     type Reader = TcpClientReader;
+    const KIND: ProtocolKind = ProtocolKind::Tcp;
 }
 
 pub struct Connection<C: ConnectionState> {
@@ -88,10 +181,24 @@ impl Connection<Waiting> {
         Ok(Connection { state })
     }
 
+    /// Creates a connection using an existing socket, see `UdpWaitingClient::from_socket`.
+    ///
+    /// # Arguments
+    ///
+    /// * `udp_socket` - A bound IPv6 `UdpSocket`.
+    pub fn from_socket(udp_socket: UdpSocket) -> Result<Connection<Waiting>, P2pError> {
+        let waiting_client = UdpWaitingClient::from_socket(udp_socket)?;
+        let state = Waiting { waiting_client };
+        Ok(Connection { state })
+    }
+
     pub fn get_port(&self) -> u16 {
         self.state.waiting_client.get_port()
     }
 
+    /// Connects to a peer.
+    /// A failed attempt returns the waiting connection in the `ChangeStateError`. It is still bound to the same port
+    /// and can connect again.
     pub fn connect(
         self,
         peer: Ipv6Addr,
@@ -99,12 +206,69 @@ impl Connection<Waiting> {
         connect_timeout: Option<Duration>,
         disconnect_timeout: Option<Duration>,
     ) -> Result<Connection<Active<Plain<Udp>>>, ChangeStateError<Self>> {
+        self.connect_cancellable(
+            peer,
+            port,
+            connect_timeout,
+            disconnect_timeout,
+            Arc::new(AtomicBool::new(false)),
+        )
+    }
+
+    /// Connects to a peer. The attempt is aborted with `ErrorKind::Cancelled` as soon as `cancel` is set.
+    pub fn connect_cancellable(
+        self,
+        peer: Ipv6Addr,
+        port: u16,
+        connect_timeout: Option<Duration>,
+        disconnect_timeout: Option<Duration>,
+        cancel: Arc<AtomicBool>,
+    ) -> Result<Connection<Active<Plain<Udp>>>, ChangeStateError<Self>> {
+        self.connect_with_policy(
+            peer,
+            port,
+            connect_timeout,
+            disconnect_timeout,
+            cancel,
+            &ConnectPolicy::AllowAll,
+        )
+    }
+
+    /// Connects to a peer if the policy permits it.
+    /// The attempt is aborted with `ErrorKind::Cancelled` as soon as `cancel` is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - The address of the peer.
+    /// * `port` - The port of the peer.
+    /// * `connect_timeout` - An optional `Duration` specifying the maximum time to wait for the connection to be established.
+    /// * `disconnect_timeout` - An optional `Duration` after which the connection is considered dead.
+    /// * `cancel` - Aborts the attempt once set.
+    /// * `policy` - Decides if the peer may be connected to.
+    ///
+    /// # Returns
+    ///
+    /// Returns the plain UDP connection, or a `ChangeStateError` with the waiting connection and the error which occurred.
+    /// A peer which is not permitted fails with `ErrorKind::RefusedByPolicy` before any packet is sent.
+    pub fn connect_with_policy(
+        self,
+        peer: Ipv6Addr,
+        port: u16,
+        connect_timeout: Option<Duration>,
+        disconnect_timeout: Option<Duration>,
+        cancel: Arc<AtomicBool>,
+        policy: &ConnectPolicy,
+    ) -> Result<Connection<Active<Plain<Udp>>>, ChangeStateError<Self>> {
+        if let Err(e) = policy.check(IpAddr::from(peer)) {
+            return Err(ChangeStateError::new(self, Box::new(e)));
+        }
+
         let own_port = self.get_port();
 
         let udp_active_client = self
             .state
             .waiting_client
-            .connect(peer, port, connect_timeout, disconnect_timeout)
+            .connect_cancellable(peer, port, connect_timeout, disconnect_timeout, cancel)
             .map_err(|err| {
                 let err = err.split();
                 ChangeStateError::new(
@@ -124,6 +288,240 @@ impl Connection<Waiting> {
             own_port,
         ))
     }
+
+    /// Connects to a peer through a relay, e.g. if a peer is behind a symmetric NAT.
+    /// Both peers register with the same token, afterwards the relay forwards their datagrams.
+    /// Encryption stays end to end, the relay only sees ciphertext of an encrypted connection.
+    /// The connection can't be upgraded or transformed, since these connect to the peer directly.
+    /// They fail with `ErrorKind::Relayed` instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `relay` - The address of the relay.
+    /// * `port` - The port of the relay.
+    /// * `token` - The token both peers agreed on, at most `relay::MAX_TOKEN_LENGTH` bytes.
+    /// * `connect_timeout` - An optional `Duration` used for waiting for the peer at the relay and for connecting.
+    /// * `disconnect_timeout` - An optional `Duration` after which the connection is considered dead.
+    /// * `policy` - Decides if the relay may be connected to.
+    ///
+    /// # Returns
+    ///
+    /// Returns the plain UDP connection, or a `ChangeStateError` with the waiting connection and the error which occurred.
+    /// A relay which is not permitted fails with `ErrorKind::RefusedByPolicy` before any packet is sent.
+    pub fn connect_via_relay(
+        self,
+        relay: Ipv6Addr,
+        port: u16,
+        token: &[u8],
+        connect_timeout: Option<Duration>,
+        disconnect_timeout: Option<Duration>,
+        policy: &ConnectPolicy,
+    ) -> Result<Connection<Active<Plain<Udp>>>, ChangeStateError<Self>> {
+        if let Err(e) = policy.check(IpAddr::from(relay)) {
+            return Err(ChangeStateError::new(self, Box::new(e)));
+        }
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let relay_addr = SocketAddr::new(IpAddr::from(relay), port);
+
+        if let Err(e) = self
+            .state
+            .waiting_client
+            .register_at_relay(relay_addr, token, connect_timeout, &cancel)
+        {
+            return Err(ChangeStateError::new(self, Box::new(e)));
+        }
+
+        let mut connection =
+            self.connect_with_policy(relay, port, connect_timeout, disconnect_timeout, cancel, policy)?;
+        connection.state.relayed = true;
+        Ok(connection)
+    }
+
+    /// Connects to a peer which dials at the same time, e.g. if both peers are behind NATs.
+    /// Attempts start at the beginning of every `window` on the NTP corrected clock, or on the local clock if no time server answers.
+    /// The open messages of both peers cross the NATs at about the same time, even if the peers were started at different times.
+    /// Each attempt lasts three quarters of a window, so the next attempt starts at the next window and not one later.
+    /// Attempts which time out are repeated until `timeout` elapsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - The address of the peer.
+    /// * `port` - The port of the peer.
+    /// * `window` - The length of an attempt. Both peers have to use the same window.
+    /// * `timeout` - An optional `Duration` after which no further attempt is started. `None` makes a single attempt.
+    /// * `disconnect_timeout` - An optional `Duration` after which the connection is considered dead.
+    ///
+    /// # Returns
+    ///
+    /// Returns the plain UDP connection, or a `ChangeStateError` with the waiting connection and the error of the last attempt.
+    /// A zero window fails with `ErrorKind::ZeroWindow`.
+    pub fn rendezvous(
+        self,
+        peer: Ipv6Addr,
+        port: u16,
+        window: Duration,
+        timeout: Option<Duration>,
+        disconnect_timeout: Option<Duration>,
+    ) -> Result<Connection<Active<Plain<Udp>>>, ChangeStateError<Self>> {
+        let clock_offset = match get_diff() {
+            Ok((_, offset)) => offset,
+            Err(_) => {
+                println!("[UDP] no time server, rendezvous on the local clock");
+                0
+            }
+        };
+
+        self.rendezvous_with_clock_offset(peer, port, window, timeout, disconnect_timeout, clock_offset)
+    }
+
+    /// Connects to a peer which dials at the same time like `rendezvous`, with a known offset of the shared clock.
+    ///
+    /// # Arguments
+    ///
+    /// * `clock_offset` - The offset of the shared clock to the local clock in microseconds, e.g. from `ntp_time::get_diff`.
+    ///
+    /// The other arguments and the result are the ones of `rendezvous`.
+    pub fn rendezvous_with_clock_offset(
+        mut self,
+        peer: Ipv6Addr,
+        port: u16,
+        window: Duration,
+        timeout: Option<Duration>,
+        disconnect_timeout: Option<Duration>,
+        clock_offset: i64,
+    ) -> Result<Connection<Active<Plain<Udp>>>, ChangeStateError<Self>> {
+        if window.is_zero() {
+            return Err(ChangeStateError::new(
+                self,
+                Box::new(P2pError::new(ErrorKind::ZeroWindow)),
+            ));
+        }
+
+        let deadline = Instant::now() + timeout.unwrap_or(Duration::ZERO);
+        // the rest of the window absorbs the delay of a timed out attempt
+        let attempt_timeout = window * 3 / 4;
+
+        loop {
+            match time_to_next_window(window, clock_offset) {
+                Ok(wait) => sleep(wait),
+                Err(e) => return Err(ChangeStateError::new(self, Box::new(e))),
+            }
+
+            let (waiting, err) = match self.connect(peer, port, Some(attempt_timeout), disconnect_timeout) {
+                Ok(connection) => return Ok(connection),
+                Err(err) => err.split(),
+            };
+
+            let timed_out = matches!(
+                err.downcast_ref::<P2pError>().map(|err| err.kind()),
+                Some(ErrorKind::TimedOut)
+            );
+            if !timed_out || Instant::now() >= deadline {
+                return Err(ChangeStateError::new(waiting, err));
+            }
+
+            println!("[UDP] rendezvous attempt timed out.. retrying");
+            self = waiting;
+        }
+    }
+}
+
+/// Calculates the time until the next window starts on the shared clock.
+///
+/// # Arguments
+///
+/// * `window` - The length of a window, not zero.
+/// * `clock_offset` - The offset of the shared clock to the local clock in microseconds.
+fn time_to_next_window(window: Duration, clock_offset: i64) -> Result<Duration, P2pError> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_micros() as i128 + clock_offset as i128;
+    let window = window.as_micros().max(1) as i128;
+
+    Ok(Duration::from_micros((window - now.rem_euclid(window)) as u64))
+}
+
+impl Connection<Waiting> {
+    /// Connects directly to a peer which accepts TCP connections, e.g. a server with a forwarded port.
+    /// The UDP phase is skipped, the peer has to wait with `accept_tcp`.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - The address of the peer.
+    /// * `port` - The TCP port of the peer.
+    /// * `timeout` - An optional `Duration` used for connecting and as read timeout of the connection.
+    ///
+    /// # Returns
+    ///
+    /// Returns the plain TCP connection, or a `P2pError` if the peer could not be reached.
+    pub fn connect_tcp(
+        peer: Ipv6Addr,
+        port: u16,
+        timeout: Option<Duration>,
+    ) -> Result<Connection<Active<Plain<Tcp>>>, P2pError> {
+        let waiting_client = TcpWaitingClient::new(None)?;
+        let own_port = waiting_client.get_port();
+
+        let tcp_active_client = waiting_client
+            .connect(peer, port, None, timeout)
+            .map_err(P2pError::from)?;
+
+        Ok(Connection::<Active<Plain<Tcp>>>::new(
+            tcp_active_client,
+            timeout,
+            peer,
+            own_port,
+            Role::Client,
+        ))
+    }
+
+    /// Waits for a peer connecting with `connect_tcp`.
+    ///
+    /// # Arguments
+    ///
+    /// * `port` - The TCP port to listen on. If `None` is passed, a random port is used.
+    /// * `timeout` - An optional `Duration` used for waiting and as read timeout of the connection.
+    ///
+    /// # Returns
+    ///
+    /// Returns the plain TCP connection, or a `P2pError` if no peer connected.
+    pub fn accept_tcp(
+        port: Option<u16>,
+        timeout: Option<Duration>,
+    ) -> Result<Connection<Active<Plain<Tcp>>>, P2pError> {
+        let waiting_client = TcpWaitingClient::new(port)?;
+        let own_port = waiting_client.get_port();
+
+        let (tcp_active_client, peer_addr) = waiting_client
+            .accept(timeout)
+            .map_err(P2pError::from)?;
+
+        let peer_ip = match peer_addr.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+
+        Ok(Connection::<Active<Plain<Tcp>>>::new(
+            tcp_active_client,
+            timeout,
+            peer_ip,
+            own_port,
+            Role::Server,
+        ))
+    }
+}
+
+impl Connection<Active<Plain<Tcp>>> {
+    fn new(
+        tcp_active_client: TcpActiveClient,
+        timeout: Option<Duration>,
+        peer_ip: Ipv6Addr,
+        port: u16,
+        role: Role,
+    ) -> Connection<Active<Plain<Tcp>>> {
+        let (writer, reader) = tcp_active_client.split();
+
+        Self::with_role(writer, reader, timeout, peer_ip, port, role)
+    }
 }
 
 impl Connection<Active<Plain<Udp>>> {
@@ -135,40 +533,177 @@ impl Connection<Active<Plain<Udp>>> {
     ) -> Connection<Active<Plain<Udp>>> {
         let (writer, reader) = udp_active_client.split();
 
+        Self::with_role(writer, reader, timeout, peer_ip, port, Role::None)
+    }
+
+    /// Creates a sliding window connection to the peer without encrypting it.
+    /// Only meant for trusted networks, e.g. a LAN, where the encryption overhead is unwanted.
+    /// Both peers have to skip `encrypt`, otherwise the handshake of the other peer fails.
+    /// The application identifier and the identities are exchanged like during `encrypt`,
+    /// but the identity is not bound to a session, as there are no session keys.
+    ///
+    /// # Returns
+    ///
+    /// Returns the plain writer and reader of the sliding window connection and the identity of the peer,
+    /// or the unchanged connection and the error if the connection could not be established.
+    pub fn transform_to_slide(mut self) -> Result<PlainSlide, ChangeStateError<Self>> {
+        if let Err(err) = self.check_direct() {
+            return Err(ChangeStateError::new(self, Box::new(err)));
+        }
+        if let Err(err) = self.verify_app_id() {
+            return Err(ChangeStateError::new(self, Box::new(err)));
+        }
+        let peer_identity = match exchange_identities(
+            &mut self.state.client.plain_writer,
+            &mut self.state.client.plain_reader,
+            self.state.timeout,
+            &self.state.client.identity,
+        ) {
+            Ok(peer_identity) => peer_identity,
+            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+        };
+        let udp_client = match udp_slide::UdpWaitingClient::new(None) {
+            Ok(client) => client,
+            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+        };
+
+        let peer_port = match self.exchange_ports(udp_client.get_port()) {
+            Ok(p) => p,
+            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+        };
+        println!("Peer port: {}", peer_port);
+
+        let udp_client = match udp_client.connect(self.state.peer_ip, peer_port, self.state.timeout, self.state.timeout) {
+            Ok(client) => client,
+            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+        };
+
+        let (udp_writer, udp_reader) = udp_client.split();
+
+        sleep(Duration::from_millis(10));
+        return Ok((PlainWriter::new(udp_writer), PlainReader::new(udp_reader), peer_identity));
+    }
+
+    fn exchange_ports(&mut self, port: u16) -> Result<u16, P2pError> {
+        self.state.client.plain_writer.write(&port.to_be_bytes())?;
+
+        let peer_port = self.state.client.plain_reader.read(self.state.timeout)?;
+        let peer_port: [u8; 2] = match peer_port.try_into() {
+            Ok(t) => t,
+            Err(_) => return Err(P2pError::new(ErrorKind::IllegalByteStream)),
+        };
+
+        Ok(u16::from_be_bytes(peer_port))
+    }
+}
+
+impl<P: ProtocolState> Connection<Active<Plain<P>>> {
+    /// Creates a connection over an already connected transport, e.g. a QUIC stream provided by another crate.
+    /// The transport only has to implement `ProtocolState` with its `ClientWriter` and `ClientReader`,
+    /// `encrypt` and `accept` work the same way as for the built-in transports.
+    /// The roles are negotiated by `encrypt`. The TCP upgrades are only available for `Udp`.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - The writer of the connected transport.
+    /// * `reader` - The reader of the connected transport.
+    /// * `peer_ip` - The address of the peer.
+    /// * `port` - The local port of the transport.
+    /// * `timeout` - The timeout used when reading from the peer during the handshake.
+    pub fn from_transport(
+        writer: P::Writer,
+        reader: P::Reader,
+        peer_ip: Ipv6Addr,
+        port: u16,
+        timeout: Option<Duration>,
+    ) -> Connection<Active<Plain<P>>> {
+        Self::with_role(writer, reader, timeout, peer_ip, port, Role::None)
+    }
+
+    fn with_role(
+        writer: P::Writer,
+        reader: P::Reader,
+        timeout: Option<Duration>,
+        peer_ip: Ipv6Addr,
+        port: u16,
+        role: Role,
+    ) -> Connection<Active<Plain<P>>> {
         Connection {
             state: Active {
                 peer_ip,
                 timeout,
-                role: Role::None,
+                role,
                 client: Plain {
                     plain_reader: reader,
                     plain_writer: writer,
+                    app_id: DEFAULT_APP_ID.to_vec(),
+                    identity: Identity::generate(),
                 },
                 port,
+                phase_sender: None,
+                relayed: false,
             },
         }
     }
+}
+
+impl<P: ProtocolState> Connection<Active<Plain<P>>> {
+    pub fn encrypt(self) -> Result<Connection<Active<Encrypted<P>>>, ChangeStateError<Self>> {
+        self.encrypt_with_max_msg_len(DEFAULT_MAX_MSG_LEN)
+    }
 
-    pub fn encrypt(mut self) -> Result<Connection<Active<Encrypted<Udp>>>, ChangeStateError<Self>> {
+    /// Encrypts the connection like `encrypt` and agrees on a maximum message length with the peer.
+    /// Both peers offer a maximum, the smaller one is used on both sides.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_msg_len` - The longest application message this peer accepts.
+    ///
+    /// # Returns
+    ///
+    /// Returns the encrypted connection, or a `ChangeStateError` with the plain connection and the error which occurred.
+    pub fn encrypt_with_max_msg_len(
+        mut self,
+        max_msg_len: usize,
+    ) -> Result<Connection<Active<Encrypted<P>>>, ChangeStateError<Self>> {
+        if let Err(e) = self.verify_app_id() {
+            return Err(ChangeStateError::new(self, Box::new(e)));
+        }
         if self.state.role == Role::None {
             if let Err(e) = self.negotiate_roles() {
                 return Err(ChangeStateError::new(self, Box::new(e)));
             }
         }
+        if let Err(e) = self.confirm_roles() {
+            return Err(ChangeStateError::new(self, Box::new(e)));
+        }
+        self.send_phase(HandshakePhase::RolesNegotiated);
 
-        let (decrypt_key, encrypt_key) = match self.exchange_keys() {
+        let (decrypt_key, encrypt_key, peer_identity) = match self.exchange_keys() {
             Ok(keys) => keys,
             Err(e) => return Err(ChangeStateError::new(self, Box::new(e))),
         };
+        self.send_phase(HandshakePhase::KeysExchanged);
 
-        let (pull_stream, push_stream) =
-            match self.generate_crypto_streams(decrypt_key, encrypt_key) {
-                Ok(streams) => streams,
-                Err(e) => return Err(ChangeStateError::new(self, Box::new(e))),
-            };
+        let streams = match self.generate_crypto_streams(&decrypt_key, &encrypt_key) {
+            Ok(streams) => streams,
+            Err(e) => return Err(ChangeStateError::new(self, Box::new(e))),
+        };
+        #[cfg(feature = "debug-crypto")]
+        let session_secrets = streams.secrets(decrypt_key, encrypt_key);
+
+        let max_msg_len = match self.negotiate_max_msg_len(max_msg_len) {
+            Ok(max_msg_len) => max_msg_len,
+            Err(e) => return Err(ChangeStateError::new(self, Box::new(e))),
+        };
 
-        let encrypted_reader = EncryptedReader::new(pull_stream, self.state.client.plain_reader);
-        let encrypted_writer = EncryptedWriter::new(push_stream, self.state.client.plain_writer);
+        self.send_phase(HandshakePhase::Encrypted);
+
+        let encrypted_reader =
+            EncryptedReader::new(streams.pull_stream, self.state.client.plain_reader);
+        let mut encrypted_writer =
+            EncryptedWriter::new(Arc::new(Mutex::new(streams.push_stream)), self.state.client.plain_writer);
+        encrypted_writer.set_max_msg_len(Some(max_msg_len));
 
         let connection = Connection {
             state: Active {
@@ -180,66 +715,122 @@ impl Connection<Active<Plain<Udp>>> {
                     clock_diff_samples: Vec::new(),
                     encrypted_writer,
                     encrypted_reader,
+                    #[cfg(feature = "debug-crypto")]
+                    session_secrets,
+                    identity: self.state.client.identity,
+                    peer_identity,
                 },
                 port: self.state.port,
+                phase_sender: self.state.phase_sender,
+                relayed: self.state.relayed,
             },
         };
 
         Ok(connection)
     }
 
-    fn exchange_keys(&mut self) -> Result<(SessionKey, SessionKey), P2pError> {
-        if self.state.role == Role::None {
-            return Err(P2pError::new(ErrorKind::UndefinedRole));
+    /// Exchanges the application identifier with the peer. This is the first message of the handshake.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `AppIdMismatch` if the peer sent a different identifier.
+    fn verify_app_id(&mut self) -> Result<(), P2pError> {
+        self.state.client.plain_writer.write(&self.state.client.app_id)?;
+        let peer_app_id = self.state.client.plain_reader.read(self.state.timeout)?;
+
+        if peer_app_id != self.state.client.app_id {
+            return Err(P2pError::new(ErrorKind::AppIdMismatch));
         }
+        return Ok(());
+    }
 
-        let my_keypair = KeyPair::gen();
-
-        self.state
-            .client
-            .plain_writer
-            .write(my_keypair.public_key.as_slice())?;
-
-        let peer_public_key = self.state.client.plain_reader.read(self.state.timeout)?;
-
-        let peer_public_key: [u8; 32] = peer_public_key.as_slice().try_into()?;
+    /// Exchanges the maximum message length with the peer.
+    ///
+    /// # Returns
+    ///
+    /// Returns the smaller of both maxima, or a `P2pError` if the exchange failed.
+    fn negotiate_max_msg_len(&mut self, max_msg_len: usize) -> Result<usize, P2pError> {
+        let offer = u32::try_from(max_msg_len).unwrap_or(u32::MAX);
+        self.state.client.plain_writer.write(&offer.to_be_bytes())?;
 
-        let peer_public_key = PublicKey::from(peer_public_key);
+        let peer_offer = self.state.client.plain_reader.read(self.state.timeout)?;
+        let peer_offer: [u8; 4] = peer_offer.as_slice().try_into()?;
+        let peer_offer = u32::from_be_bytes(peer_offer);
 
-        let my_session_keys = match self.state.role {
-            Role::Server => Session::new_server_with_defaults(&my_keypair, &peer_public_key)?,
-            Role::Client => Session::new_client_with_defaults(&my_keypair, &peer_public_key)?,
-            Role::None => return Err(P2pError::new(ErrorKind::UndefinedRole)),
-        };
+        return Ok(offer.min(peer_offer) as usize);
+    }
 
-        return Ok(my_session_keys.into_parts());
+    fn exchange_keys(&mut self) -> Result<(SessionKey, SessionKey, PeerIdentity), P2pError> {
+        return exchange_keys(
+            &mut self.state.client.plain_writer,
+            &mut self.state.client.plain_reader,
+            &self.state.role,
+            self.state.timeout,
+            &self.state.client.identity,
+        );
     }
 
     fn generate_crypto_streams(
         &mut self,
-        decrypt_key: SessionKey,
-        encrypt_key: SessionKey,
-    ) -> Result<(DryocStream<Pull>, DryocStream<Push>), P2pError> {
-        let (push_stream, header): (_, Header) = DryocStream::init_push(&encrypt_key);
-
-        self.state.client.plain_writer.write(header.as_slice())?;
-
-        let header = self.state.client.plain_reader.read(self.state.timeout)?;
+        decrypt_key: &SessionKey,
+        encrypt_key: &SessionKey,
+    ) -> Result<CryptoStreams, P2pError> {
+        return generate_crypto_streams(
+            &mut self.state.client.plain_writer,
+            &mut self.state.client.plain_reader,
+            decrypt_key,
+            encrypt_key,
+            self.state.timeout,
+        );
+    }
 
-        let pull_stream = DryocStream::init_pull(&decrypt_key, &header);
+    fn negotiate_roles(&mut self) -> Result<(), P2pError> {
+        return self.negotiate_roles_with(thread_rng());
+    }
 
-        return Ok((pull_stream, push_stream));
+    /// Negotiates the roles using the given random number generator.
+    /// A seeded generator makes the resulting roles deterministic.
+    fn negotiate_roles_with<R: Rng>(&mut self, rng: R) -> Result<(), P2pError> {
+        return self.negotiate_roles_within(rng, NEGOTIATION_TIMEOUT);
     }
 
-    fn negotiate_roles(&mut self) -> Result<(), P2pError> {
-        let mut rng = thread_rng();
+    /// Negotiates which peer acts as server by exchanging random numbers.
+    ///
+    /// # Arguments
+    ///
+    /// * `rng` - The generator of the exchanged numbers.
+    /// * `limit` - The maximum total duration of the negotiation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `HandshakeTimedOut` if the negotiation takes longer than `limit`,
+    /// and an error of kind `UndefinedRole` if all `MAX_NEGOTIATION_ROUNDS` rounds end in a tie.
+    fn negotiate_roles_within<R: Rng>(&mut self, mut rng: R, limit: Duration) -> Result<(), P2pError> {
+        let start = Instant::now();
+
+        for _ in 0..MAX_NEGOTIATION_ROUNDS {
+            let remaining = match limit.checked_sub(start.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => remaining,
+                _ => return Err(P2pError::new(ErrorKind::HandshakeTimedOut)),
+            };
+            let read_timeout = match self.state.timeout {
+                Some(timeout) => timeout.min(remaining),
+                None => remaining,
+            };
 
-        loop {
             let my_number: [u8; 2] = [rng.gen(), rng.gen()];
             self.state.client.plain_writer.write(my_number.as_slice())?;
-            let peer_number = self.state.client.plain_reader.read(self.state.timeout)?;
+            let peer_number = match self.state.client.plain_reader.read(Some(read_timeout)) {
+                Ok(peer_number) => peer_number,
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut) && start.elapsed() >= limit => {
+                    return Err(P2pError::new(ErrorKind::HandshakeTimedOut));
+                }
+                Err(e) => return Err(e),
+            };
 
-            assert_eq!(peer_number.len(), 2);
+            if peer_number.len() != 2 {
+                return Err(P2pError::new(ErrorKind::IllegalByteStream));
+            }
 
             if my_number.as_slice() != peer_number.as_slice() {
                 match my_number.as_slice() > peer_number.as_slice() {
@@ -247,82 +838,230 @@ impl Connection<Active<Plain<Udp>>> {
                     false => self.state.role = Role::Client,
                 }
 
-                break;
+                return Ok(());
             }
         }
 
-        Ok(())
+        Err(P2pError::new(ErrorKind::UndefinedRole))
     }
-}
-
-impl Connection<Active<Encrypted<Udp>>> {
 
-    pub fn transform_to_slide(mut self) -> Result<(EncryptedWriter<udp_slide::UdpClientWriter>, EncryptedReader<udp_slide::UdpClientReader>), ChangeStateError<Self>> {
-        let udp_client = match udp_slide::UdpWaitingClient::new(None) {
-            Ok(client) => client,
-            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+    /// Exchanges the roles with the peer and checks that both peers picked different roles.
+    /// Equal roles would derive mismatching session keys, which only show up as failing decryption later.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `UndefinedRole` if the role of the peer is not the complement of this role,
+    /// and an error of kind `IllegalByteStream` if the peer sent something else than a role.
+    fn confirm_roles(&mut self) -> Result<(), P2pError> {
+        self.state.client.plain_writer.write(&[self.state.role.to_byte()])?;
+        let peer_role = self.state.client.plain_reader.read(self.state.timeout)?;
+
+        let peer_role = match peer_role.as_slice() {
+            [byte] => Role::from_byte(*byte),
+            _ => return Err(P2pError::new(ErrorKind::IllegalByteStream)),
         };
-        println!("timeout: {:?}", self.state.timeout.unwrap_or_default());
 
-        println!("UDP client created");
-
-        let peer_port = match self.exchange_ports(udp_client.get_port()) {
-            Ok(p) => p,
-            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+        return match (&self.state.role, peer_role) {
+            (Role::Server, Role::Client) | (Role::Client, Role::Server) => Ok(()),
+            _ => Err(P2pError::new(ErrorKind::UndefinedRole)),
         };
-        println!("Peer port: {}", peer_port);
+    }
+}
 
-        let udp_client = match udp_client.connect(self.state.peer_ip, peer_port, self.state.timeout, self.state.timeout) {
-            Ok(client) => client,
+impl Connection<Active<Encrypted<Udp>>> {
+
+    pub fn transform_to_slide(mut self) -> Result<(EncryptedWriter<udp_slide::UdpClientWriter>, EncryptedReader<udp_slide::UdpClientReader>), ChangeStateError<Self>> {
+        if let Err(err) = self.check_direct() {
+            return Err(ChangeStateError::new(self, Box::new(err)));
+        }
+        let (udp_writer, udp_reader) = match self.connect_slide() {
+            Ok(wr) => wr,
             Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
         };
 
-        println!("UDP client connected");
-
-        let (udp_writer, udp_reader) = udp_client.split();
-
         let encrypted_reader =
             EncryptedReader::new(self.state.client.encrypted_reader.pull_stream, udp_reader);
-        let encrypted_writer =
+        let max_msg_len = self.state.client.encrypted_writer.max_msg_len();
+        let mut encrypted_writer =
             EncryptedWriter::new(self.state.client.encrypted_writer.push_stream, udp_writer);
+        encrypted_writer.set_max_msg_len(max_msg_len);
 
 
         sleep(Duration::from_millis(10));
         return Ok((encrypted_writer, encrypted_reader));
     }
 
-    /// Upgrades the client to a TCP connection by sampling the time difference.
-    pub fn upgrade_direct(mut self) -> Result<Connection<Active<Encrypted<Tcp>>>, ChangeStateError<Self>> {
-        let tcp_client = match TcpWaitingClient::new(None) {
-            Ok(client) => client,
-            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
-        };
-        let tcp_client_2 = match TcpWaitingClient::new(None) {
-            Ok(client) => client,
+    /// Creates a connection striped across `num_flows` parallel sliding window UDP flows,
+    /// each with its own socket, sequence space and encryption streams.
+    /// The encryption streams of the additional flows are the streams replaced by a `rekey` each,
+    /// the first flow continues with the streams of the connection like `transform_to_slide`.
+    /// Both peers offer a number of flows, the smaller one is used on both sides.
+    ///
+    /// # Arguments
+    ///
+    /// * `num_flows` - The largest number of flows this peer uses, 1 behaves like `transform_to_slide`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the writer and reader of all flows, or the unchanged connection and the error,
+    /// of kind `InvalidFlowCount` if `num_flows` or the number of the peer is 0.
+    pub fn transform_to_slide_flows(
+        mut self,
+        num_flows: usize,
+    ) -> Result<SlideFlows, ChangeStateError<Self>> {
+        if num_flows == 0 {
+            return Err(ChangeStateError::new(self, Box::new(P2pError::new(ErrorKind::InvalidFlowCount))));
+        }
+        if let Err(err) = self.check_direct() {
+            return Err(ChangeStateError::new(self, Box::new(err)));
+        }
+        let num_flows = match self.negotiate_num_flows(num_flows) {
+            Ok(num_flows) => num_flows,
             Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
         };
 
-        let peer_port = match self.exchange_ports(tcp_client.get_port()) {
-            Ok(p) => p,
-            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
-        };
+        let mut writers = Vec::with_capacity(num_flows - 1);
+        let mut readers = Vec::with_capacity(num_flows - 1);
 
-        let peer_port_2 = match self.exchange_ports(tcp_client_2.get_port()) {
-            Ok(p) => p,
-            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
-        };
+        for _ in 1..num_flows {
+            let (udp_writer, udp_reader) = match self.connect_slide() {
+                Ok(wr) => wr,
+                Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+            };
+            let (pull_stream, push_stream) = match self.rekey_streams() {
+                Ok(streams) => streams,
+                Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+            };
 
-        let tcp_client = match self.multi_sample_and_connect(tcp_client, peer_port, 1) {
-            Ok(client) => client,
-            Err(client) => match self.sample_and_connect(client, peer_port) {
+            let mut encrypted_writer = EncryptedWriter::new(push_stream, udp_writer);
+            encrypted_writer.set_max_msg_len(self.state.client.encrypted_writer.max_msg_len());
+            writers.push(encrypted_writer);
+            readers.push(EncryptedReader::new(pull_stream, udp_reader));
+        }
+
+        let (writer, reader) = self.transform_to_slide()?;
+
+        let writer = MultiFlowWriter::with_first(writer, writers);
+        let reader = MultiFlowReader::with_first(reader, readers);
+        return Ok((writer, reader));
+    }
+
+    /// Exchanges the number of flows with the peer.
+    ///
+    /// # Returns
+    ///
+    /// Returns the smaller of both numbers, or a `P2pError` of kind `InvalidFlowCount` if the peer offered 0 flows.
+    fn negotiate_num_flows(&mut self, num_flows: usize) -> Result<usize, P2pError> {
+        let offer = u32::try_from(num_flows).unwrap_or(u32::MAX);
+        self.state.client.encrypted_writer.write(&offer.to_be_bytes())?;
+
+        let peer_offer = self.state.client.encrypted_reader.read(self.state.timeout)?;
+        let peer_offer: [u8; 4] = peer_offer.as_slice().try_into()?;
+        let peer_offer = u32::from_be_bytes(peer_offer);
+        if peer_offer == 0 {
+            return Err(P2pError::new(ErrorKind::InvalidFlowCount));
+        }
+
+        return Ok(offer.min(peer_offer) as usize);
+    }
+
+    /// Creates a sliding window UDP client and connects it to the one of the peer.
+    ///
+    /// # Returns
+    ///
+    /// Returns the writer and reader of the sliding window client, or a `P2pError` if it could not connect.
+    fn connect_slide(&mut self) -> Result<(udp_slide::UdpClientWriter, udp_slide::UdpClientReader), P2pError> {
+        let udp_client = udp_slide::UdpWaitingClient::new(None)?;
+        println!("timeout: {:?}", self.state.timeout.unwrap_or_default());
+
+        println!("UDP client created");
+
+        let peer_port = self.exchange_ports(udp_client.get_port())?;
+        println!("Peer port: {}", peer_port);
+
+        let udp_client = udp_client.connect(self.state.peer_ip, peer_port, self.state.timeout, self.state.timeout)?;
+
+        println!("UDP client connected");
+
+        return Ok(udp_client.split());
+    }
+
+    /// Upgrades the client to a TCP connection by sampling the time difference.
+    /// Makes `DEFAULT_UPGRADE_TRIES` synchronized connect attempts.
+    pub fn upgrade_direct(self) -> Result<Connection<Active<Encrypted<Tcp>>>, ChangeStateError<Self>> {
+        self.upgrade_direct_with_tries(DEFAULT_UPGRADE_TRIES)
+    }
+
+    /// Upgrades the client to a TCP connection by sampling the time difference.
+    ///
+    /// # Arguments
+    ///
+    /// * `tries` - The number of synchronized connect attempts before giving up. At least one attempt is made.
+    ///   Both peers have to use the same number of tries.
+    pub fn upgrade_direct_with_tries(
+        self,
+        tries: u8,
+    ) -> Result<Connection<Active<Encrypted<Tcp>>>, ChangeStateError<Self>> {
+        self.upgrade_direct_with_samples(tries, DEFAULT_SAMPLE_COUNT)
+    }
+
+    /// Upgrades the client to a TCP connection by sampling the time difference.
+    /// More samples make the agreed connect time more precise on jittery links, but take longer.
+    ///
+    /// # Arguments
+    ///
+    /// * `tries` - The number of synchronized connect attempts before giving up. At least one attempt is made.
+    ///   Both peers have to use the same number of tries.
+    /// * `samples` - The number of clock difference samples collected per attempt. Only the samples of the peer
+    ///   acting as server are used.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidSampleCount` if `samples` is 0.
+    pub fn upgrade_direct_with_samples(
+        mut self,
+        tries: u8,
+        samples: u8,
+    ) -> Result<Connection<Active<Encrypted<Tcp>>>, ChangeStateError<Self>> {
+        if samples == 0 {
+            return Err(ChangeStateError::new(self, Box::new(P2pError::new(ErrorKind::InvalidSampleCount))));
+        }
+        if let Err(err) = self.check_direct() {
+            return Err(ChangeStateError::new(self, Box::new(err)));
+        }
+
+        self.send_phase(HandshakePhase::Upgrading);
+
+        let tcp_client = match TcpWaitingClient::new(None) {
+            Ok(client) => client,
+            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+        };
+        let tcp_client_2 = match TcpWaitingClient::new(None) {
+            Ok(client) => client,
+            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+        };
+
+        let peer_port = match self.exchange_ports(tcp_client.get_port()) {
+            Ok(p) => p,
+            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+        };
+
+        let peer_port_2 = match self.exchange_ports(tcp_client_2.get_port()) {
+            Ok(p) => p,
+            Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
+        };
+
+        let tcp_client = match self.multi_sample_and_connect(tcp_client, peer_port, tries.saturating_sub(1), samples) {
+            Ok(client) => client,
+            Err(client) => match self.sample_and_connect(client, peer_port, samples) {
                 Ok(c) => c,
                 Err(err) => return Err(ChangeStateError::new(self, err.to_err())),
             },
         };
 
-        let tcp_client_2 = match self.multi_sample_and_connect(tcp_client_2, peer_port_2, 1) {
+        let tcp_client_2 = match self.multi_sample_and_connect(tcp_client_2, peer_port_2, tries.saturating_sub(1), samples) {
             Ok(client) => client,
-            Err(client) => match self.sample_and_connect(client, peer_port_2) {
+            Err(client) => match self.sample_and_connect(client, peer_port_2, samples) {
                 Ok(c) => c,
                 Err(err) => return Err(ChangeStateError::new(self, err.to_err())),
             },
@@ -338,8 +1077,10 @@ impl Connection<Active<Encrypted<Udp>>> {
 
         let encrypted_reader =
             EncryptedReader::new(self.state.client.encrypted_reader.pull_stream, tcp_reader);
-        let encrypted_writer =
+        let max_msg_len = self.state.client.encrypted_writer.max_msg_len();
+        let mut encrypted_writer =
             EncryptedWriter::new(self.state.client.encrypted_writer.push_stream, tcp_writer);
+        encrypted_writer.set_max_msg_len(max_msg_len);
 
         let connection = Connection::<Active<Encrypted<Tcp>>> {
             state: Active {
@@ -351,19 +1092,67 @@ impl Connection<Active<Encrypted<Udp>>> {
                     clock_diff_samples: self.state.client.clock_diff_samples,
                     encrypted_reader,
                     max_delay: self.state.client.max_delay,
+                    #[cfg(feature = "debug-crypto")]
+                    session_secrets: self.state.client.session_secrets,
+                    identity: self.state.client.identity,
+                    peer_identity: self.state.client.peer_identity,
                 },
                 port: self.state.port,
+                phase_sender: self.state.phase_sender,
+                relayed: self.state.relayed,
             },
         };
+        connection.send_phase(HandshakePhase::Upgraded);
 
         return Ok(connection);
     }
 
 
     /// Upgrades the client to a TCP connection by syncing with a ntp server.
-    pub fn upgrade_ntp(
+    /// Makes `DEFAULT_UPGRADE_TRIES` synchronized connect attempts.
+    pub fn upgrade_ntp(self) -> Result<Connection<Active<Encrypted<Tcp>>>, ChangeStateError<Self>> {
+        self.upgrade_ntp_with_tries(DEFAULT_UPGRADE_TRIES)
+    }
+
+    /// Upgrades the client to a TCP connection by syncing with a ntp server.
+    ///
+    /// # Arguments
+    ///
+    /// * `tries` - The number of synchronized connect attempts before giving up. At least one attempt is made.
+    ///   Both peers have to use the same number of tries.
+    pub fn upgrade_ntp_with_tries(
+        self,
+        tries: u8,
+    ) -> Result<Connection<Active<Encrypted<Tcp>>>, ChangeStateError<Self>> {
+        self.upgrade_ntp_with_samples(tries, DEFAULT_NTP_SAMPLE_COUNT)
+    }
+
+    /// Upgrades the client to a TCP connection by syncing with a ntp server.
+    ///
+    /// # Arguments
+    ///
+    /// * `tries` - The number of synchronized connect attempts before giving up. At least one attempt is made.
+    ///   Both peers have to use the same number of tries.
+    /// * `samples` - The number of delay samples collected per attempt. Only the samples of the peer
+    ///   acting as server are used.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidSampleCount` if `samples` is 0.
+    pub fn upgrade_ntp_with_samples(
         mut self,
+        tries: u8,
+        samples: u8,
     ) -> Result<Connection<Active<Encrypted<Tcp>>>, ChangeStateError<Self>> {
+        if samples == 0 {
+            return Err(ChangeStateError::new(self, Box::new(P2pError::new(ErrorKind::InvalidSampleCount))));
+        }
+        if let Err(err) = self.check_direct() {
+            return Err(ChangeStateError::new(self, Box::new(err)));
+        }
+
+        self.send_phase(HandshakePhase::Upgrading);
+
         let tcp_client = match TcpWaitingClient::new(None) {
             Ok(client) => client,
             Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
@@ -374,9 +1163,9 @@ impl Connection<Active<Encrypted<Udp>>> {
             Err(err) => return Err(ChangeStateError::new(self, Box::new(err))),
         };
 
-        let tcp_client = match self.multi_ntp_and_connect(tcp_client, peer_port, 1) {
+        let tcp_client = match self.multi_ntp_and_connect(tcp_client, peer_port, tries.saturating_sub(1), samples) {
             Ok(client) => client,
-            Err(client) => match self.ntp_and_connect(client, peer_port) {
+            Err(client) => match self.ntp_and_connect(client, peer_port, samples) {
                 Ok(c) => c,
                 Err(err) => return Err(ChangeStateError::new(self, err.to_err())),
             },
@@ -386,8 +1175,10 @@ impl Connection<Active<Encrypted<Udp>>> {
 
         let encrypted_reader =
             EncryptedReader::new(self.state.client.encrypted_reader.pull_stream, tcp_reader);
-        let encrypted_writer =
+        let max_msg_len = self.state.client.encrypted_writer.max_msg_len();
+        let mut encrypted_writer =
             EncryptedWriter::new(self.state.client.encrypted_writer.push_stream, tcp_writer);
+        encrypted_writer.set_max_msg_len(max_msg_len);
 
         let connection = Connection::<Active<Encrypted<Tcp>>> {
             state: Active {
@@ -399,52 +1190,54 @@ impl Connection<Active<Encrypted<Udp>>> {
                     clock_diff_samples: self.state.client.clock_diff_samples,
                     encrypted_reader,
                     max_delay: self.state.client.max_delay,
+                    #[cfg(feature = "debug-crypto")]
+                    session_secrets: self.state.client.session_secrets,
+                    identity: self.state.client.identity,
+                    peer_identity: self.state.client.peer_identity,
                 },
                 port: self.state.port,
+                phase_sender: self.state.phase_sender,
+                relayed: self.state.relayed,
             },
         };
+        connection.send_phase(HandshakePhase::Upgraded);
 
         return Ok(connection);
     }
 
     fn multi_sample_and_connect(
         &mut self,
-        mut tcp_client: TcpWaitingClient,
+        tcp_client: TcpWaitingClient,
         peer_port: u16,
         tries: u8,
+        samples: u8,
     ) -> Result<TcpActiveClient, TcpWaitingClient> {
-        for _ in 0..tries {
-            tcp_client = match self.sample_and_connect(tcp_client, peer_port) {
-                Ok(c) => return Ok(c),
-                Err(err) => err.to_state(),
-            };
-        }
-
-        Err(tcp_client)
+        retry(tcp_client, tries, |client| {
+            self.sample_and_connect(client, peer_port, samples)
+                .map_err(|err| err.to_state())
+        })
     }
 
     fn multi_ntp_and_connect(
         &mut self,
-        mut tcp_client: TcpWaitingClient,
+        tcp_client: TcpWaitingClient,
         peer_port: u16,
         tries: u8,
+        samples: u8,
     ) -> Result<TcpActiveClient, TcpWaitingClient> {
-        for _ in 0..tries {
-            tcp_client = match self.ntp_and_connect(tcp_client, peer_port) {
-                Ok(c) => return Ok(c),
-                Err(err) => err.to_state(),
-            };
-        }
-
-        Err(tcp_client)
+        retry(tcp_client, tries, |client| {
+            self.ntp_and_connect(client, peer_port, samples)
+                .map_err(|err| err.to_state())
+        })
     }
 
     fn ntp_and_connect(
         &mut self,
         tcp_client: TcpWaitingClient,
         peer_port: u16,
+        samples: u8,
     ) -> Result<TcpActiveClient, ChangeStateError<TcpWaitingClient>> {
-        let wait_time = match self.prepare_ntp() {
+        let wait_time = match self.prepare_ntp(samples) {
             Ok(dur) => dur,
             Err(err) => return Err(ChangeStateError::new(tcp_client, Box::new(err))),
         };
@@ -457,14 +1250,14 @@ impl Connection<Active<Encrypted<Udp>>> {
         );
     }
 
-    fn prepare_ntp(&mut self) -> Result<Duration, P2pError> {
+    fn prepare_ntp(&mut self, samples: u8) -> Result<Duration, P2pError> {
         let diff = get_diff()?;
         println!("diff            : {:?}", diff);
 
         return match self.state.role {
             Role::Server => {
                 println!("SERVER");
-                self.collect_samples(10)?;
+                self.collect_samples(samples)?;
 
                 if self.state.client.max_delay == 0 {
                     return Err(P2pError::new(ErrorKind::NoDelayGiven));
@@ -538,12 +1331,13 @@ impl Connection<Active<Encrypted<Udp>>> {
         &mut self,
         tcp_client: TcpWaitingClient,
         peer_port: u16,
+        samples: u8,
     ) -> Result<TcpActiveClient, ChangeStateError<TcpWaitingClient>> {
         let wait_time: Duration;
 
         match self.state.role {
             Role::Server => {
-                match self.collect_samples(100) {
+                match self.collect_samples(samples) {
                     Ok(_) => {}
                     Err(err) => return Err(ChangeStateError::new(tcp_client, Box::new(err))),
                 };
@@ -579,14 +1373,7 @@ impl Connection<Active<Encrypted<Udp>>> {
     }
 
     fn set_connect_time(&mut self) -> Result<Duration, P2pError> {
-        self.state.client.clock_diff_samples.sort();
-        let diffs = self.state.client.clock_diff_samples.as_mut_slice();
-
-        let median_diff = if diffs.len() % 2 == 0 {
-            (diffs[diffs.len() / 2] + diffs[diffs.len() / 2 - 1]) / 2
-        } else {
-            diffs[diffs.len() / 2]
-        };
+        let median_diff = median_clock_diff(self.state.client.clock_diff_samples.as_mut_slice());
 
         let connect_time = (SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos()
             + self.state.client.max_delay * 10) as i128;
@@ -675,12 +1462,17 @@ impl Connection<Active<Encrypted<Udp>>> {
             if self.state.client.max_delay < elapsed_nanos {
                 self.state.client.max_delay = elapsed_nanos;
             }
+
+            self.send_phase(HandshakePhase::SamplingProgress(i + 1, amount));
         }
 
         Ok(())
     }
 
     fn provide_samples(&mut self) -> Result<(), P2pError> {
+        let mut provided: u8 = 0;
+        let mut amount: Option<u8> = None;
+
         loop {
             let num = self
                 .state
@@ -688,6 +1480,10 @@ impl Connection<Active<Encrypted<Udp>>> {
                 .encrypted_reader
                 .read(self.state.timeout)?;
 
+            if num.is_empty() {
+                return Err(P2pError::new(ErrorKind::IllegalByteStream));
+            }
+
             let now = SystemTime::now();
             let now_nanos = now.duration_since(UNIX_EPOCH)?.as_nanos();
 
@@ -696,6 +1492,11 @@ impl Connection<Active<Encrypted<Udp>>> {
                 .encrypted_writer
                 .write(now_nanos.to_be_bytes().as_slice())?;
 
+            // the first message counts down from the total amount of samples
+            let amount = *amount.get_or_insert(num[0].saturating_add(1));
+            provided = provided.saturating_add(1);
+            self.send_phase(HandshakePhase::SamplingProgress(provided, amount));
+
             if num[0] == 0 {
                 return Ok(());
             }
@@ -704,12 +1505,80 @@ impl Connection<Active<Encrypted<Udp>>> {
 }
 
 impl<E: EncryptionState> Connection<Active<E>> {
+    /// Checks that the peer is reached directly, which the sliding window transport and the upgrades need.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Relayed` if the connection runs through a relay.
+    fn check_direct(&self) -> Result<(), P2pError> {
+        if self.state.relayed {
+            return Err(P2pError::new(ErrorKind::Relayed));
+        }
+        return Ok(());
+    }
+
     pub fn get_port(&self) -> u16 {
         self.state.port
     }
+
+    /// Returns the timeout used when the connection reads from the peer.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.state.timeout
+    }
+
+    /// Changes the timeout used by subsequent reads of the connection, e.g. during encryption or upgrading.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The new timeout. If `None` is passed, reads block until a message is received.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.state.timeout = timeout;
+    }
+
+    /// Reports the progress of subsequent handshake steps, e.g. `encrypt` and the upgrades, to the given channel.
+    /// The sender is kept when the connection changes its state.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The channel receiving the phases. If `None` is passed, no progress is reported.
+    pub fn set_phase_sender(&mut self, sender: Option<Sender<HandshakePhase>>) {
+        self.state.phase_sender = sender;
+    }
+
+    fn send_phase(&self, phase: HandshakePhase) {
+        if let Some(sender) = &self.state.phase_sender {
+            // progress is informational, a dropped receiver must not abort the handshake
+            let _ = sender.send(phase);
+        }
+    }
 }
 
 impl<P: ProtocolState> Connection<Active<Plain<P>>> {
+    /// Returns the transport protocol of the connection.
+    pub fn protocol_kind(&self) -> ProtocolKind {
+        P::KIND
+    }
+
+    /// Sets the application identifier exchanged at the start of `encrypt`, `DEFAULT_APP_ID` by default.
+    /// Both peers have to use the same identifier, otherwise `encrypt` fails with `ErrorKind::AppIdMismatch`.
+    ///
+    /// # Arguments
+    ///
+    /// * `app_id` - A short byte string identifying the application and its protocol version.
+    pub fn set_app_id(&mut self, app_id: &[u8]) {
+        self.state.client.app_id = app_id.to_vec();
+    }
+
+    /// Sets the identity presented to the peer during `encrypt`. By default a new identity is generated for every connection.
+    /// A persistent identity, see `Identity::load_or_create`, lets the peer recognize this peer after a reconnect.
+    ///
+    /// # Arguments
+    ///
+    /// * `identity` - The identity of this peer.
+    pub fn set_identity(&mut self, identity: Identity) {
+        self.state.client.identity = identity;
+    }
+
     pub fn accept(self) -> (P::Writer, P::Reader) {
         (
             self.state.client.plain_writer,
@@ -719,81 +1588,843 @@ impl<P: ProtocolState> Connection<Active<Plain<P>>> {
 }
 
 impl<P: ProtocolState> Connection<Active<Encrypted<P>>> {
+    /// Returns the transport protocol of the connection.
+    pub fn protocol_kind(&self) -> ProtocolKind {
+        P::KIND
+    }
+
+    /// Exports the keys and stream headers of the session, so captured ciphertext can be decrypted offline.
+    ///
+    /// **Insecure:** anybody holding the secrets can decrypt all traffic of the session. Only for debugging.
+    #[cfg(feature = "debug-crypto")]
+    pub fn export_session(&self) -> SessionSecrets {
+        self.state.client.session_secrets.clone()
+    }
+
     pub fn accept(self) -> (EncryptedWriter<P::Writer>, EncryptedReader<P::Reader>) {
         (
             self.state.client.encrypted_writer,
             self.state.client.encrypted_reader,
         )
     }
-}
 
-impl Connection<Active<Encrypted<Udp>>> {}
+    /// Returns the identity the peer proved during `encrypt`.
+    /// The same identity after a reconnect means the same peer, e.g. to resume a transfer with it.
+    pub fn peer_identity(&self) -> PeerIdentity {
+        self.state.client.peer_identity
+    }
+
+    /// Returns the maximum length of an application message agreed with the peer during `encrypt`.
+    pub fn max_msg_len(&self) -> Option<usize> {
+        self.state.client.encrypted_writer.max_msg_len()
+    }
+
+    /// Sends a request to the peer and waits for a single reply.
+    ///
+    /// Messages are not tagged, so the reply is simply the next message received.
+    /// Both sides must agree on who sends requests. If both peers call `request` at the same time,
+    /// each of them receives the request of the other one as reply.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The request to send.
+    /// * `timeout` - An optional `Duration` to wait for the reply. If `None` is passed, the method blocks until the reply is received.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the reply, or a `P2pError` if writing, reading or decrypting failed.
+    pub fn request(&mut self, msg: &[u8], timeout: Option<Duration>) -> Result<Vec<u8>, P2pError> {
+        self.state.client.encrypted_writer.write(msg)?;
+
+        self.state.client.encrypted_reader.read(timeout)
+    }
+
+    /// Replaces the encryption streams of the connection with new ones.
+    /// A fresh key exchange is made over the encrypted channel, which limits the amount of data encrypted with a single key.
+    /// Both peers must call `rekey` at the same time and no other messages may be in transit.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `P2pError` if the key exchange failed, of kind `IdentityMismatch` if the peer presented another identity.
+    /// The current streams are kept in this case.
+    pub fn rekey(&mut self) -> Result<(), P2pError> {
+        self.rekey_streams()?;
+
+        return Ok(());
+    }
+
+    /// Replaces the encryption streams and returns the previous ones.
+    fn rekey_streams(&mut self) -> Result<(DryocStream<Pull>, SharedPushStream), P2pError> {
+        let (decrypt_key, encrypt_key, peer_identity) = exchange_keys(
+            &mut self.state.client.encrypted_writer,
+            &mut self.state.client.encrypted_reader,
+            &self.state.role,
+            self.state.timeout,
+            &self.state.client.identity,
+        )?;
+        if peer_identity != self.state.client.peer_identity {
+            return Err(P2pError::new(ErrorKind::IdentityMismatch));
+        }
+
+        let streams = generate_crypto_streams(
+            &mut self.state.client.encrypted_writer,
+            &mut self.state.client.encrypted_reader,
+            &decrypt_key,
+            &encrypt_key,
+            self.state.timeout,
+        )?;
+        #[cfg(feature = "debug-crypto")]
+        {
+            self.state.client.session_secrets = streams.secrets(decrypt_key, encrypt_key);
+        }
+
+        // both streams are replaced together once the exchange succeeded
+        let old_pull_stream = std::mem::replace(&mut self.state.client.encrypted_reader.pull_stream, streams.pull_stream);
+        let old_push_stream = std::mem::replace(&mut self.state.client.encrypted_writer.push_stream, Arc::new(Mutex::new(streams.push_stream)));
+
+        return Ok((old_pull_stream, old_push_stream));
+    }
+}
+
+impl Connection<Active<Encrypted<Udp>>> {
+    /// Returns statistics of the clock difference samples collected for a TCP upgrade,
+    /// e.g. to tell after a failed upgrade whether the timing was too noisy to hole-punch.
+    /// Only the server role collects samples, the samples of all upgrade attempts are included.
+    ///
+    /// # Returns
+    ///
+    /// Returns the statistics, or `None` if no samples were collected.
+    pub fn clock_sync_stats(&self) -> Option<ClockSyncStats> {
+        let samples = &self.state.client.clock_diff_samples;
+        if samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted = samples.clone();
+        let median_diff = median_clock_diff(sorted.as_mut_slice());
+
+        return Some(ClockSyncStats {
+            sample_count: samples.len(),
+            median_diff,
+            min_diff: sorted[0],
+            max_diff: sorted[sorted.len() - 1],
+            max_delay: Duration::from_nanos(self.state.client.max_delay.min(u64::MAX as u128) as u64),
+        });
+    }
+}
+
+/// Statistics of the clock difference samples, see `Connection::clock_sync_stats`.
+/// Clock differences are in nanoseconds, positive if the clock of this peer is ahead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockSyncStats {
+    /// Number of collected samples.
+    pub sample_count: usize,
+    /// Median clock difference, used to agree on the connect time.
+    pub median_diff: i128,
+    /// Smallest clock difference.
+    pub min_diff: i128,
+    /// Largest clock difference.
+    pub max_diff: i128,
+    /// Longest round trip of a sample.
+    pub max_delay: Duration,
+}
+
+/// Returns the median of the clock difference samples. The samples are sorted in place.
+fn median_clock_diff(diffs: &mut [i128]) -> i128 {
+    diffs.sort();
+
+    return if diffs.len() % 2 == 0 {
+        (diffs[diffs.len() / 2] + diffs[diffs.len() / 2 - 1]) / 2
+    } else {
+        diffs[diffs.len() / 2]
+    };
+}
+
+/// Exchanges public keys with the peer and derives the session keys for the given role.
+/// Afterwards both peers present their identity key with a signature of both ephemeral public keys,
+/// which binds the identity to this session.
+///
+/// # Returns
+///
+/// Returns the decryption and encryption key and the identity of the peer,
+/// or a `P2pError` if the role is undefined, the exchange failed or the identity of the peer is invalid.
+fn exchange_keys<W: ClientWriter, R: ClientReader>(
+    writer: &mut W,
+    reader: &mut R,
+    role: &Role,
+    timeout: Option<Duration>,
+    identity: &Identity,
+) -> Result<(SessionKey, SessionKey, PeerIdentity), P2pError> {
+    if *role == Role::None {
+        return Err(P2pError::new(ErrorKind::UndefinedRole));
+    }
+
+    let my_keypair = KeyPair::gen();
+
+    writer.write(my_keypair.public_key.as_slice())?;
+
+    let peer_public_key = reader.read(timeout)?;
+
+    let peer_public_key: [u8; 32] = peer_public_key.as_slice().try_into()?;
+
+    let peer_identity = prove_identity(
+        writer,
+        reader,
+        timeout,
+        identity,
+        my_keypair.public_key.as_slice(),
+        &peer_public_key,
+    )?;
+
+    let peer_public_key = PublicKey::from(peer_public_key);
+
+    let my_session_keys = match role {
+        Role::Server => Session::new_server_with_defaults(&my_keypair, &peer_public_key)?,
+        Role::Client => Session::new_client_with_defaults(&my_keypair, &peer_public_key)?,
+        Role::None => return Err(P2pError::new(ErrorKind::UndefinedRole)),
+    };
+
+    let (decrypt_key, encrypt_key) = my_session_keys.into_parts();
+
+    return Ok((decrypt_key, encrypt_key, peer_identity));
+}
+
+/// Presents the identity key to the peer with a signature of both values exchanged before,
+/// and checks the identity the peer presents the same way.
+///
+/// # Arguments
+///
+/// * `own` - The value this peer sent, e.g. its ephemeral public key.
+/// * `peer` - The value the peer sent.
+///
+/// # Returns
+///
+/// Returns the identity of the peer, or a `P2pError` of kind `InvalidIdentity` if its signature does not match.
+fn prove_identity<W: ClientWriter, R: ClientReader>(
+    writer: &mut W,
+    reader: &mut R,
+    timeout: Option<Duration>,
+    identity: &Identity,
+    own: &[u8],
+    peer: &[u8],
+) -> Result<PeerIdentity, P2pError> {
+    let signed = [IDENTITY_CONTEXT, own, peer].concat();
+    let mut identity_msg = identity.peer_identity().as_bytes().to_vec();
+    identity_msg.extend_from_slice(&identity.sign(&signed)?);
+    writer.write(&identity_msg)?;
+
+    let peer_identity_msg = reader.read(timeout)?;
+    if peer_identity_msg.len() != IDENTITY_KEY_LEN + SIGNATURE_LEN {
+        return Err(P2pError::new(ErrorKind::InvalidIdentity));
+    }
+    let (peer_identity, peer_signature) = peer_identity_msg.split_at(IDENTITY_KEY_LEN);
+    let peer_identity = PeerIdentity::from_slice(peer_identity)?;
+    let peer_signed = [IDENTITY_CONTEXT, peer, own].concat();
+    peer_identity.verify(&peer_signed, peer_signature)?;
+
+    return Ok(peer_identity);
+}
+
+/// Exchanges random challenges with the peer and proves the identities with them, see `prove_identity`.
+/// Used instead of the key exchange by connections which are not encrypted.
+///
+/// # Returns
+///
+/// Returns the identity of the peer, or a `P2pError` if the exchange failed or the identity of the peer is invalid.
+fn exchange_identities<W: ClientWriter, R: ClientReader>(
+    writer: &mut W,
+    reader: &mut R,
+    timeout: Option<Duration>,
+    identity: &Identity,
+) -> Result<PeerIdentity, P2pError> {
+    let challenge: [u8; CHALLENGE_LEN] = thread_rng().gen();
+    writer.write(&challenge)?;
+
+    let peer_challenge = reader.read(timeout)?;
+    let peer_challenge: [u8; CHALLENGE_LEN] = peer_challenge.as_slice().try_into()?;
+
+    return prove_identity(writer, reader, timeout, identity, &challenge, &peer_challenge);
+}
+
+/// Encryption streams of a session and the headers they were created with.
+struct CryptoStreams {
+    pull_stream: DryocStream<Pull>,
+    push_stream: DryocStream<Push>,
+    /// Header received from the peer.
+    #[cfg_attr(not(feature = "debug-crypto"), allow(dead_code))]
+    pull_header: Vec<u8>,
+    /// Header sent to the peer.
+    #[cfg_attr(not(feature = "debug-crypto"), allow(dead_code))]
+    push_header: Header,
+}
+
+#[cfg(feature = "debug-crypto")]
+impl CryptoStreams {
+    /// Returns the key material of the streams.
+    fn secrets(&self, decrypt_key: SessionKey, encrypt_key: SessionKey) -> SessionSecrets {
+        SessionSecrets {
+            decrypt_key,
+            encrypt_key,
+            pull_header: self.pull_header.clone(),
+            push_header: self.push_header.as_slice().to_vec(),
+        }
+    }
+}
+
+/// Creates the encryption streams and exchanges the stream headers with the peer.
+///
+/// # Returns
+///
+/// Returns the pull and push stream, or a `P2pError` if the exchange failed.
+fn generate_crypto_streams<W: ClientWriter, R: ClientReader>(
+    writer: &mut W,
+    reader: &mut R,
+    decrypt_key: &SessionKey,
+    encrypt_key: &SessionKey,
+    timeout: Option<Duration>,
+) -> Result<CryptoStreams, P2pError> {
+    let (push_stream, push_header): (_, Header) = DryocStream::init_push(encrypt_key);
+
+    writer.write(push_header.as_slice())?;
+
+    let pull_header = reader.read(timeout)?;
+
+    let pull_stream = DryocStream::init_pull(decrypt_key, &pull_header);
+
+    return Ok(CryptoStreams {
+        pull_stream,
+        push_stream,
+        pull_header,
+        push_header,
+    });
+}
+
+/// Calls `attempt` up to `tries` times until it succeeds.
+/// A failed attempt returns the state which is passed to the next attempt.
+///
+/// # Returns
+///
+/// Returns the result of the first successful attempt, or the state after the last failed attempt.
+fn retry<S, T>(
+    mut state: S,
+    tries: u8,
+    mut attempt: impl FnMut(S) -> Result<T, S>,
+) -> Result<T, S> {
+    for _ in 0..tries {
+        state = match attempt(state) {
+            Ok(result) => return Ok(result),
+            Err(state) => state,
+        };
+    }
+
+    Err(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::thread::sleep;
+    use std::time::Instant;
+
+    use dryoc::dryocstream::Tag;
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+
+    #[test]
+    fn test_connect_err() {
+        let timeout = Duration::from_millis(10);
+
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        let c2 = Connection::<Waiting>::new(None).unwrap();
+
+        let ipv6 = Ipv6Addr::from(1);
+
+        assert!(c1
+            .connect(ipv6, c2.get_port(), Some(timeout), Some(timeout))
+            .is_err());
+    }
+
+    #[test]
+    fn test_connect_refused_by_policy() {
+        let timeout = Duration::from_millis(100);
+        let policy = ConnectPolicy::Block(vec!["::1".parse().unwrap()]);
+
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        let port = c1.get_port();
+
+        let start = Instant::now();
+        let err = c1
+            .connect_with_policy(
+                Ipv6Addr::LOCALHOST,
+                port + 1,
+                Some(timeout),
+                Some(timeout),
+                Arc::new(AtomicBool::new(false)),
+                &policy,
+            )
+            .map(|_| ())
+            .unwrap_err();
+        assert!(start.elapsed() < timeout);
+
+        let (c1, err) = err.split();
+        let err = err.downcast::<P2pError>().unwrap();
+        assert_eq!(err.kind(), &ErrorKind::RefusedByPolicy);
+        assert_eq!(c1.get_port(), port);
+    }
+
+    #[test]
+    fn test_connect_allowed_by_policy() {
+        let timeout = Duration::from_millis(100);
+        let policy = ConnectPolicy::Allow(vec!["::1/128".parse().unwrap()]);
+
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        let c2 = Connection::<Waiting>::new(None).unwrap();
+
+        let p1 = c1.get_port();
+        let p2 = c2.get_port();
+
+        let ipv6 = Ipv6Addr::from(1);
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let thread_policy = policy.clone();
+        let thread_cancel = cancel.clone();
+        let thread_c2 = thread::spawn(move || {
+            return c2
+                .connect_with_policy(ipv6, p1, Some(timeout), Some(timeout), thread_cancel, &thread_policy)
+                .is_ok();
+        });
+
+        assert!(c1
+            .connect_with_policy(ipv6, p2, Some(timeout), Some(timeout), cancel, &policy)
+            .is_ok());
+        assert!(thread_c2.join().unwrap());
+    }
+
+    #[test]
+    fn test_connect_ok() {
+        let timeout = Duration::from_millis(100);
+
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        let c2 = Connection::<Waiting>::new(None).unwrap();
+
+        let p1 = c1.get_port();
+        let p2 = c2.get_port();
+
+        let ipv6 = Ipv6Addr::from(1);
+
+        let thread_c2 = thread::spawn(move || {
+            return c2.connect(ipv6, p1, Some(timeout), Some(timeout)).is_ok();
+        });
+
+        assert!(c1.connect(ipv6, p2, Some(timeout), Some(timeout)).is_ok());
+        assert!(thread_c2.join().unwrap());
+    }
+
+    fn connect() -> (
+        Connection<Active<Plain<Udp>>>,
+        Connection<Active<Plain<Udp>>>,
+    ) {
+        let timeout = Duration::from_millis(5000);
+
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        let c2 = Connection::<Waiting>::new(None).unwrap();
+
+        let p1 = c1.get_port();
+        let p2 = c2.get_port();
+
+        let ipv6 = Ipv6Addr::from(1);
+
+        let thread_c2 = thread::spawn(move || {
+            return c2.connect(ipv6, p1, Some(timeout), Some(timeout)).unwrap();
+        });
+
+        let c1 = c1.connect(ipv6, p2, Some(timeout), Some(timeout)).unwrap();
+        let c2 = thread_c2.join().unwrap();
+
+        (c1, c2)
+    }
+
+    #[test]
+    fn test_connect_tcp() {
+        let timeout = Some(Duration::from_secs(2));
+        let port = std::net::TcpListener::bind("[::]:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let thread_server = thread::spawn(move || {
+            let server = Connection::<Waiting>::accept_tcp(Some(port), timeout).unwrap();
+            return server.encrypt().unwrap();
+        });
+
+        let mut client = Connection::<Waiting>::connect_tcp(Ipv6Addr::LOCALHOST, port, timeout);
+        for _ in 0..10 {
+            if client.is_ok() {
+                break;
+            }
+            sleep(Duration::from_millis(50));
+            client = Connection::<Waiting>::connect_tcp(Ipv6Addr::LOCALHOST, port, timeout);
+        }
+        let client = client.unwrap().encrypt().unwrap();
+        let server = thread_server.join().unwrap();
+
+        let (mut client_writer, mut client_reader) = client.accept();
+        let (mut server_writer, mut server_reader) = server.accept();
+
+        let client_msg = b"Das ist ein Test. Diese Nachricht wird vom Client versendet.";
+        let server_msg = b"Das ist ein Test. Diese Nachricht wird vom Server versendet.";
+
+        client_writer.write(client_msg).unwrap();
+        server_writer.write(server_msg).unwrap();
+
+        assert_eq!(server_reader.read(timeout).unwrap(), client_msg.to_vec());
+        assert_eq!(client_reader.read(timeout).unwrap(), server_msg.to_vec());
+    }
+
+    #[test]
+    fn test_retry_until_peer_listens() {
+        // the peer starts listening only after the first connect attempts were refused
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let attempt = |attempts: u8| match std::net::TcpStream::connect_timeout(&addr, Duration::from_millis(100)) {
+            Ok(stream) => Ok((stream, attempts + 1)),
+            Err(_) => {
+                sleep(Duration::from_millis(100));
+                Err(attempts + 1)
+            }
+        };
+
+        assert_eq!(retry(0, 1, attempt).unwrap_err(), 1);
+
+        let peer = thread::spawn(move || {
+            sleep(Duration::from_millis(300));
+            std::net::TcpListener::bind(addr).unwrap().accept().unwrap();
+        });
+
+        let (_stream, attempts) = retry(0, DEFAULT_UPGRADE_TRIES, attempt).unwrap();
+        assert!(attempts > 1);
+        assert!(attempts <= DEFAULT_UPGRADE_TRIES);
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn test_sample_count_tightens_connect_time() {
+        // simulated clock difference samples: every sample is off by the jitter of its round trip
+        fn worst_deviation(samples: u8) -> i128 {
+            let mut seed: u64 = 42;
+            let mut jitter = move || {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                ((seed >> 33) % 2000) as i128 - 1000
+            };
+
+            (0..200)
+                .map(|_| {
+                    let mut diffs: Vec<i128> = (0..samples).map(|_| 5000 + jitter()).collect();
+                    (median_clock_diff(&mut diffs) - 5000).abs()
+                })
+                .max()
+                .unwrap()
+        }
+
+        let single = worst_deviation(1);
+        let default = worst_deviation(DEFAULT_SAMPLE_COUNT);
+        let maximum = worst_deviation(u8::MAX);
+        assert!(single > 900);
+        assert!(default < single / 2);
+        assert!(maximum <= default);
+    }
+
+    #[test]
+    fn test_upgrade_invalid_sample_count() {
+        let (c1, c2) = connect();
+
+        let thread_c2 = thread::spawn(move || {
+            return c2.encrypt().unwrap();
+        });
+        let c1 = c1.encrypt().unwrap();
+        let _c2 = thread_c2.join().unwrap();
+
+        let err = match c1.upgrade_direct_with_samples(1, 0) {
+            Ok(_) => panic!("upgrade with 0 samples succeeded"),
+            Err(err) => err,
+        };
+        let (c1, err) = err.split();
+        let err = err.downcast_ref::<P2pError>().unwrap();
+        assert_eq!(err.kind(), &ErrorKind::InvalidSampleCount);
+
+        let err = match c1.upgrade_ntp_with_samples(1, 0) {
+            Ok(_) => panic!("upgrade with 0 samples succeeded"),
+            Err(err) => err,
+        };
+        assert_eq!(err.split().1.downcast_ref::<P2pError>().unwrap().kind(), &ErrorKind::InvalidSampleCount);
+    }
+
+    /// In-memory transport, standing in for a transport of another crate.
+    struct Memory {}
+
+    struct MemoryWriter {
+        sender: mpsc::Sender<Vec<u8>>,
+    }
+
+    struct MemoryReader {
+        receiver: mpsc::Receiver<Vec<u8>>,
+    }
+
+    impl ClientWriter for MemoryWriter {
+        fn write(&mut self, msg: &[u8]) -> Result<(), P2pError> {
+            self.sender.send(msg.to_vec())?;
+            Ok(())
+        }
+    }
+
+    impl ClientReader for MemoryReader {
+        fn try_read(&mut self) -> Result<Vec<u8>, P2pError> {
+            Ok(self.receiver.try_recv()?)
+        }
+
+        fn read(&mut self, timeout: Option<Duration>) -> Result<Vec<u8>, P2pError> {
+            match timeout {
+                Some(timeout) => Ok(self.receiver.recv_timeout(timeout)?),
+                None => Ok(self.receiver.recv()?),
+            }
+        }
+    }
+
+    impl ProtocolState for Memory {
+        type Writer = MemoryWriter;
+        type Reader = MemoryReader;
+        const KIND: ProtocolKind = ProtocolKind::Other;
+    }
+
+    fn connect_memory() -> (
+        Connection<Active<Plain<Memory>>>,
+        Connection<Active<Plain<Memory>>>,
+    ) {
+        let timeout = Some(Duration::from_secs(5));
+        let (sender_1, receiver_2) = mpsc::channel();
+        let (sender_2, receiver_1) = mpsc::channel();
+        let ipv6 = Ipv6Addr::from(1);
+
+        let c1 = Connection::<Active<Plain<Memory>>>::from_transport(
+            MemoryWriter { sender: sender_1 },
+            MemoryReader { receiver: receiver_1 },
+            ipv6,
+            1,
+            timeout,
+        );
+        let c2 = Connection::<Active<Plain<Memory>>>::from_transport(
+            MemoryWriter { sender: sender_2 },
+            MemoryReader { receiver: receiver_2 },
+            ipv6,
+            2,
+            timeout,
+        );
+
+        (c1, c2)
+    }
+
+    #[test]
+    fn test_encrypt_custom_transport() {
+        let (c1, c2) = connect_memory();
+        assert_eq!(c1.protocol_kind(), ProtocolKind::Other);
+
+        let thread_c2 = thread::spawn(move || {
+            return c2.encrypt().unwrap();
+        });
+
+        let c1 = c1.encrypt().unwrap();
+        let c2 = thread_c2.join().unwrap();
+        assert_ne!(c1.state.role, c2.state.role);
+
+        let (mut w1, _r1) = c1.accept();
+        let (_w2, mut r2) = c2.accept();
+        assert_eq!(w1.protocol_kind(), ProtocolKind::Other);
+
+        let timeout = Some(Duration::from_secs(1));
+        w1.write(b"over memory").unwrap();
+        assert_eq!(r2.read(timeout).unwrap(), b"over memory".to_vec());
+    }
+
+    #[test]
+    fn test_protocol_kind() {
+        let (c1, c2) = connect();
+        assert_eq!(c1.protocol_kind(), ProtocolKind::Udp);
+
+        let thread_c2 = thread::spawn(move || {
+            let c2 = c2.encrypt().unwrap();
+            return c2.transform_to_slide().unwrap();
+        });
+        let c1 = c1.encrypt().unwrap();
+        assert_eq!(c1.protocol_kind(), ProtocolKind::Udp);
+
+        let (c1_writer, c1_reader) = c1.transform_to_slide().unwrap();
+        assert_eq!(c1_writer.protocol_kind(), ProtocolKind::Udp);
+        assert_eq!(c1_reader.protocol_kind(), ProtocolKind::Udp);
+        drop(c1_reader);
+        drop(thread_c2.join().unwrap());
+
+        let timeout = Some(Duration::from_secs(2));
+        let port = std::net::TcpListener::bind("[::]:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let thread_server =
+            thread::spawn(move || Connection::<Waiting>::accept_tcp(Some(port), timeout).unwrap());
+        let mut client = Connection::<Waiting>::connect_tcp(Ipv6Addr::LOCALHOST, port, timeout);
+        for _ in 0..10 {
+            if client.is_ok() {
+                break;
+            }
+            sleep(Duration::from_millis(50));
+            client = Connection::<Waiting>::connect_tcp(Ipv6Addr::LOCALHOST, port, timeout);
+        }
+        let client = client.unwrap();
+        let _server = thread_server.join().unwrap();
+        assert_eq!(client.protocol_kind(), ProtocolKind::Tcp);
+
+        let (tcp_writer, tcp_reader) = client.accept();
+        assert_eq!(tcp_writer.protocol_kind(), ProtocolKind::Tcp);
+        assert_eq!(tcp_reader.protocol_kind(), ProtocolKind::Tcp);
+    }
+
+    #[test]
+    fn test_set_read_timeout() {
+        let (mut c1, c2) = connect();
+        let timeout = Duration::from_millis(200);
 
-#[cfg(test)]
-mod tests {
-    use std::thread;
-    use std::thread::sleep;
+        c1.set_read_timeout(Some(timeout));
+        assert_eq!(c1.read_timeout(), Some(timeout));
 
-    use dryoc::dryocstream::Tag;
+        // the peer never answers, so the read has to time out after the new timeout
+        let now = Instant::now();
+        assert!(c1.negotiate_roles().is_err());
+        assert!(now.elapsed() >= timeout);
+        assert!(now.elapsed() < Duration::from_millis(2000));
 
-    use super::*;
+        drop(c2);
+    }
 
     #[test]
-    fn test_connect_err() {
-        let timeout = Duration::from_millis(10);
+    fn test_negotiate_roles_silent_peer() {
+        let (mut c1, c2) = connect();
+        let limit = Duration::from_millis(300);
+        c1.set_read_timeout(None);
+
+        // the peer never answers, the negotiation has to give up after the limit
+        let now = Instant::now();
+        let err = c1.negotiate_roles_within(thread_rng(), limit).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::HandshakeTimedOut));
+        assert!(now.elapsed() >= limit);
+        assert!(now.elapsed() < Duration::from_millis(2000));
+
+        drop(c2);
+    }
 
-        let c1 = Connection::<Waiting>::new(None).unwrap();
-        let c2 = Connection::<Waiting>::new(None).unwrap();
+    #[test]
+    fn test_negotiate_roles_seeded() {
+        let (mut c1, mut c2) = connect();
 
-        let ipv6 = Ipv6Addr::from(1);
+        let thread_c2 = thread::spawn(move || {
+            c2.negotiate_roles_with(StepRng::new(0, 0)).unwrap();
+            return c2;
+        });
 
-        assert!(c1
-            .connect(ipv6, c2.get_port(), Some(timeout), Some(timeout))
-            .is_err());
+        c1.negotiate_roles_with(StepRng::new(u64::MAX, 0)).unwrap();
+        let mut c2 = thread_c2.join().unwrap();
+
+        assert_eq!(c1.state.role, Role::Server);
+        assert_eq!(c2.state.role, Role::Client);
+
+        // the second number decides if the first ones are equal
+        let thread_c2 = thread::spawn(move || {
+            c2.negotiate_roles_with(StepRng::new(7, 1)).unwrap();
+            return c2;
+        });
+
+        c1.negotiate_roles_with(StepRng::new(7, 0)).unwrap();
+        let c2 = thread_c2.join().unwrap();
+
+        assert_eq!(c1.state.role, Role::Client);
+        assert_eq!(c2.state.role, Role::Server);
     }
 
     #[test]
-    fn test_connect_ok() {
-        let timeout = Duration::from_millis(100);
+    fn test_confirm_roles_mismatch() {
+        let (mut c1, mut c2) = connect();
+        c1.state.role = Role::Server;
+        c2.state.role = Role::Server;
 
-        let c1 = Connection::<Waiting>::new(None).unwrap();
-        let c2 = Connection::<Waiting>::new(None).unwrap();
+        let thread_c2 = thread::spawn(move || {
+            return c2.encrypt().err().unwrap().split().1.to_string();
+        });
 
-        let p1 = c1.get_port();
-        let p2 = c2.get_port();
+        let err = c1.encrypt().err().unwrap().to_err();
+        let err = err.downcast_ref::<P2pError>().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::UndefinedRole));
+        assert_eq!(thread_c2.join().unwrap(), err.to_string());
+    }
 
-        let ipv6 = Ipv6Addr::from(1);
+    #[test]
+    fn test_app_id_mismatch() {
+        let (mut c1, mut c2) = connect();
+        c1.set_app_id(b"app-a/1");
+        c2.set_app_id(b"app-b/1");
 
         let thread_c2 = thread::spawn(move || {
-            return c2.connect(ipv6, p1, Some(timeout), Some(timeout)).is_ok();
+            return c2.encrypt().err().unwrap().to_err().to_string();
         });
 
-        assert!(c1.connect(ipv6, p2, Some(timeout), Some(timeout)).is_ok());
-        assert!(thread_c2.join().unwrap());
+        let err = c1.encrypt().err().unwrap().to_err();
+        let err = err.downcast_ref::<P2pError>().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::AppIdMismatch));
+        assert_eq!(thread_c2.join().unwrap(), err.to_string());
     }
 
-    fn connect() -> (
-        Connection<Active<Plain<Udp>>>,
-        Connection<Active<Plain<Udp>>>,
-    ) {
-        let timeout = Duration::from_millis(5000);
+    #[test]
+    fn test_app_id_match() {
+        let (mut c1, mut c2) = connect();
+        c1.set_app_id(b"app-a/1");
+        c2.set_app_id(b"app-a/1");
 
-        let c1 = Connection::<Waiting>::new(None).unwrap();
-        let c2 = Connection::<Waiting>::new(None).unwrap();
+        let thread_c2 = thread::spawn(move || {
+            return c2.encrypt().is_ok();
+        });
 
-        let p1 = c1.get_port();
-        let p2 = c2.get_port();
+        assert!(c1.encrypt().is_ok());
+        assert!(thread_c2.join().unwrap());
+    }
 
-        let ipv6 = Ipv6Addr::from(1);
+    /// Connects and encrypts, c2 presents the given identity. Returns the identity c1 sees.
+    fn handshake_with_identity(identity: Identity) -> PeerIdentity {
+        let (c1, mut c2) = connect();
+        c2.set_identity(identity);
 
         let thread_c2 = thread::spawn(move || {
-            return c2.connect(ipv6, p1, Some(timeout), Some(timeout)).unwrap();
+            return c2.encrypt().is_ok();
         });
 
-        let c1 = c1.connect(ipv6, p2, Some(timeout), Some(timeout)).unwrap();
-        let c2 = thread_c2.join().unwrap();
+        let c1 = c1.encrypt().ok().unwrap();
+        assert!(thread_c2.join().unwrap());
+        c1.peer_identity()
+    }
 
-        (c1, c2)
+    #[test]
+    fn test_stable_peer_identity() {
+        let path = std::env::temp_dir().join("rdrop_protocol_identity.key");
+        let _ = std::fs::remove_file(&path);
+
+        let first = handshake_with_identity(Identity::load_or_create(&path).unwrap());
+        let reconnect = handshake_with_identity(Identity::load_or_create(&path).unwrap());
+        let other = handshake_with_identity(Identity::generate());
+
+        assert_eq!(first, reconnect);
+        assert_eq!(first, Identity::load_or_create(&path).unwrap().peer_identity());
+        assert_ne!(first, other);
+
+        std::fs::remove_file(path).unwrap();
     }
 
     #[test]
@@ -831,12 +2462,13 @@ mod tests {
             return c2.exchange_keys().unwrap();
         });
 
-        let (c1_decrypt_key, c1_encrypt_key) = c1.exchange_keys().unwrap();
-        let (c2_decrypt_key, c2_encrypt_key) = thread_c2.join().unwrap();
+        let (c1_decrypt_key, c1_encrypt_key, c1_peer) = c1.exchange_keys().unwrap();
+        let (c2_decrypt_key, c2_encrypt_key, c2_peer) = thread_c2.join().unwrap();
 
         assert_eq!(c1_decrypt_key, c2_encrypt_key);
         assert_eq!(c2_decrypt_key, c1_encrypt_key);
         assert_ne!(c1_decrypt_key, c1_encrypt_key);
+        assert_ne!(c1_peer, c2_peer);
     }
 
     #[test]
@@ -845,19 +2477,21 @@ mod tests {
 
         let thread_c2 = thread::spawn(move || {
             c2.negotiate_roles().unwrap();
-            let (c2_decrypt_key, c2_encrypt_key) = c2.exchange_keys().unwrap();
+            let (c2_decrypt_key, c2_encrypt_key, _) = c2.exchange_keys().unwrap();
 
-            return c2
-                .generate_crypto_streams(c2_decrypt_key, c2_encrypt_key)
+            let streams = c2
+                .generate_crypto_streams(&c2_decrypt_key, &c2_encrypt_key)
                 .unwrap();
+            return (streams.pull_stream, streams.push_stream);
         });
 
         c1.negotiate_roles().unwrap();
-        let (c1_decrypt_key, c1_encrypt_key) = c1.exchange_keys().unwrap();
+        let (c1_decrypt_key, c1_encrypt_key, _) = c1.exchange_keys().unwrap();
 
-        let (mut c1_pull, mut c1_push) = c1
-            .generate_crypto_streams(c1_decrypt_key, c1_encrypt_key)
+        let streams = c1
+            .generate_crypto_streams(&c1_decrypt_key, &c1_encrypt_key)
             .unwrap();
+        let (mut c1_pull, mut c1_push) = (streams.pull_stream, streams.push_stream);
         let (mut c2_pull, mut c2_push) = thread_c2.join().unwrap();
 
         let c1_msg = b"Hallo wie gehts c2?";
@@ -885,6 +2519,225 @@ mod tests {
         let _c2 = thread_c2.join().unwrap();
     }
 
+    #[test]
+    fn test_rekey() {
+        let (c1, c2) = connect();
+
+        let thread_c2 = thread::spawn(move || {
+            let mut c2 = c2.encrypt().unwrap();
+            let old_streams = c2.rekey_streams().unwrap();
+            return (c2, old_streams);
+        });
+
+        let mut c1 = c1.encrypt().unwrap();
+        let (mut c1_old_pull, c1_old_push) = c1.rekey_streams().unwrap();
+        let (mut c2, (mut c2_old_pull, _)) = thread_c2.join().unwrap();
+
+        // both peers can still communicate
+        let reply = thread::spawn(move || {
+            let msg = c2.state.client.encrypted_reader.read(Some(Duration::from_secs(1))).unwrap();
+            c2.state.client.encrypted_writer.write(&msg).unwrap();
+            return c2;
+        });
+        let msg = b"Hallo nach dem Schluesselwechsel";
+        assert_eq!(c1.request(msg, Some(Duration::from_secs(1))).unwrap(), msg.to_vec());
+        let mut c2 = reply.join().unwrap();
+
+        // ciphertext of the new streams does not validate with the old ones and vice versa
+        let new_enc = c1.state.client.encrypted_writer.push_stream.lock().unwrap().push_to_vec(msg, None, Tag::PUSH).unwrap();
+        assert!(c2_old_pull.pull_to_vec(&new_enc, None).is_err());
+        let old_enc = c1_old_push.lock().unwrap().push_to_vec(msg, None, Tag::PUSH).unwrap();
+        assert!(c2.state.client.encrypted_reader.pull_stream.pull_to_vec(&old_enc, None).is_err());
+        let (dec, _) = c2.state.client.encrypted_reader.pull_stream.pull_to_vec(&new_enc, None).unwrap();
+        assert_eq!(dec, msg.to_vec());
+
+        let c2_enc = c2.state.client.encrypted_writer.push_stream.lock().unwrap().push_to_vec(msg, None, Tag::PUSH).unwrap();
+        assert!(c1_old_pull.pull_to_vec(&c2_enc, None).is_err());
+    }
+
+    #[cfg(feature = "debug-crypto")]
+    #[test]
+    fn test_export_session() {
+        use crate::session::SessionSecrets;
+
+        let (c1, c2) = connect();
+
+        let thread_c2 = thread::spawn(move || {
+            return c2.encrypt().unwrap();
+        });
+
+        let mut c1 = c1.encrypt().unwrap();
+        let c2 = thread_c2.join().unwrap();
+
+        // ciphertext as it is sent over the transport
+        let msg = b"Hallo wie gehts c2?";
+        let captured = c1.state.client.encrypted_writer.push_stream.lock().unwrap().push_to_vec(msg, None, Tag::PUSH).unwrap();
+
+        let exported = c2.export_session().to_bytes();
+        let imported = SessionSecrets::from_bytes(&exported).unwrap();
+        assert_eq!(imported, c2.export_session());
+
+        let (dec, tag) = imported.decryptor().pull_to_vec(&captured, None).unwrap();
+        assert_eq!(dec, msg.to_vec());
+        assert_eq!(tag, Tag::PUSH);
+
+        let (dec, _) = c1.export_session().peer_decryptor().pull_to_vec(&captured, None).unwrap();
+        assert_eq!(dec, msg.to_vec());
+
+        assert!(SessionSecrets::from_bytes(&exported[1..]).is_err());
+    }
+
+    #[test]
+    fn test_connect_via_relay() {
+        let timeout = Some(Duration::from_millis(5000));
+        let relay = crate::relay::RelayServer::new(None).unwrap();
+        let relay_port = relay.get_port();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_relay = stop.clone();
+        let relay_thread = thread::spawn(move || relay.run(&stop_relay).is_ok());
+
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        let c2 = Connection::<Waiting>::new(None).unwrap();
+        let ipv6 = Ipv6Addr::from(1);
+
+        let thread_c2 = thread::spawn(move || {
+            let c2 = c2.connect_via_relay(ipv6, relay_port, b"session", timeout, timeout, &ConnectPolicy::AllowAll).unwrap();
+            let (mut writer, mut reader) = c2.encrypt().unwrap().accept();
+            let msg = reader.read(timeout).unwrap();
+            writer.write(&msg).unwrap();
+            sleep(Duration::from_millis(100));
+        });
+
+        let c1 = c1.connect_via_relay(ipv6, relay_port, b"session", timeout, timeout, &ConnectPolicy::AllowAll).unwrap();
+        let mut c1 = c1.encrypt().unwrap();
+        let msg = b"Hallo ueber das Relay";
+        assert_eq!(c1.request(msg, timeout).unwrap(), msg.to_vec());
+
+        // the sliding window transport would connect to the relay as if it was the peer
+        let (_, err) = c1.transform_to_slide().err().unwrap().split();
+        assert!(err.to_string().contains("Relayed"));
+
+        thread_c2.join().unwrap();
+        stop.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert!(relay_thread.join().unwrap());
+    }
+
+    #[test]
+    fn test_rendezvous_offset_start() {
+        let window = Duration::from_millis(500);
+        let timeout = Some(Duration::from_millis(5000));
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        let c2 = Connection::<Waiting>::new(None).unwrap();
+        let p1 = c1.get_port();
+        let p2 = c2.get_port();
+        let ipv6 = Ipv6Addr::from(1);
+
+        let thread_c2 = thread::spawn(move || {
+            sleep(Duration::from_millis(700));
+            let c2 = c2.rendezvous_with_clock_offset(ipv6, p1, window, timeout, timeout, 0).unwrap();
+            let (mut writer, mut reader) = c2.encrypt().unwrap().accept();
+            let msg = reader.read(timeout).unwrap();
+            writer.write(&msg).unwrap();
+            sleep(Duration::from_millis(100));
+        });
+
+        let c1 = c1.rendezvous_with_clock_offset(ipv6, p2, window, timeout, timeout, 0).unwrap();
+        let mut c1 = c1.encrypt().unwrap();
+        let msg = b"Hallo zur gleichen Zeit";
+        assert_eq!(c1.request(msg, timeout).unwrap(), msg.to_vec());
+
+        thread_c2.join().unwrap();
+    }
+
+    #[test]
+    fn test_rendezvous_attempts_aligned() {
+        let window = Duration::from_millis(400);
+        let clock_offset = 123_456;
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        // a peer which never answers lets every attempt time out
+        let silent = UdpSocket::bind("[::1]:0").unwrap();
+        let port = silent.local_addr().unwrap().port();
+
+        let started = Instant::now();
+        let err = c1
+            .rendezvous_with_clock_offset(
+                Ipv6Addr::from(1),
+                port,
+                window,
+                Some(Duration::from_millis(1000)),
+                None,
+                clock_offset,
+            )
+            .err()
+            .unwrap();
+        let (_, err) = err.split();
+        assert_eq!(err.downcast_ref::<P2pError>().unwrap().kind(), &ErrorKind::TimedOut);
+
+        // several attempts were made, the last one ended three quarters into a window of the shared clock
+        assert!(started.elapsed() >= Duration::from_millis(1000));
+        let to_next_window = time_to_next_window(window, clock_offset).unwrap();
+        let drift = to_next_window.as_millis() as i64 - (window / 4).as_millis() as i64;
+        assert!(drift.abs() < 60, "attempt ended {}ms off the window", drift);
+    }
+
+    #[test]
+    fn test_rendezvous_zero_window() {
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        let err = c1
+            .rendezvous_with_clock_offset(Ipv6Addr::from(1), 1, Duration::ZERO, None, None, 0)
+            .err()
+            .unwrap();
+        let (_, err) = err.split();
+        let err = err.downcast_ref::<P2pError>().unwrap();
+        assert!(matches!(err.kind(), ErrorKind::ZeroWindow));
+    }
+
+    #[test]
+    fn test_connect_via_relay_invalid_token() {
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        let err = c1
+            .connect_via_relay(Ipv6Addr::from(1), 1, &[0u8; 65], None, None, &ConnectPolicy::AllowAll)
+            .err()
+            .unwrap();
+        let (_, err) = err.split();
+        assert!(err.to_string().contains("Other"));
+    }
+
+    #[test]
+    fn test_connect_via_relay_refused_by_policy() {
+        let policy = ConnectPolicy::Block(vec!["::1".parse().unwrap()]);
+        let c1 = Connection::<Waiting>::new(None).unwrap();
+        let err = c1
+            .connect_via_relay(Ipv6Addr::LOCALHOST, 1, b"session", None, None, &policy)
+            .err()
+            .unwrap();
+        let (_, err) = err.split();
+        let err = err.downcast::<P2pError>().unwrap();
+        assert_eq!(err.kind(), &ErrorKind::RefusedByPolicy);
+    }
+
+    #[test]
+    fn test_negotiate_max_msg_len() {
+        let (c1, c2) = connect();
+
+        let thread_c2 = thread::spawn(move || {
+            return c2.encrypt().unwrap();
+        });
+
+        let mut c1 = c1.encrypt_with_max_msg_len(2000).unwrap();
+        let mut c2 = thread_c2.join().unwrap();
+
+        assert_eq!(c1.max_msg_len(), Some(2000));
+        assert_eq!(c2.max_msg_len(), Some(2000));
+
+        let err = c2.state.client.encrypted_writer.write(&[1u8; 2001]).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::MessageTooLarge));
+
+        c2.state.client.encrypted_writer.write(&[1u8; 2000]).unwrap();
+        let msg = c1.state.client.encrypted_reader.read(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(msg.len(), 2000);
+    }
+
     #[test]
     fn test_exchange_ports() {
         let (c1, c2) = connect();
@@ -902,6 +2755,28 @@ mod tests {
         assert_eq!(p2, 2000);
     }
 
+    #[test]
+    fn test_request() {
+        let (c1, c2) = connect();
+        let timeout = Some(Duration::from_secs(2));
+
+        let thread_c2 = thread::spawn(move || {
+            let mut c2 = c2.encrypt().unwrap();
+            let request = c2.state.client.encrypted_reader.read(timeout).unwrap();
+            let mut reply = request.clone();
+            reply.reverse();
+            c2.state.client.encrypted_writer.write(&reply).unwrap();
+            return request;
+        });
+
+        let mut c1 = c1.encrypt().unwrap();
+        let reply = c1.request(b"ping", timeout).unwrap();
+        let request = thread_c2.join().unwrap();
+
+        assert_eq!(request, b"ping");
+        assert_eq!(reply, b"gnip");
+    }
+
     #[test]
     fn test_exchange_samples() {
         let (c1, c2) = connect();
@@ -920,6 +2795,62 @@ mod tests {
         assert_eq!(c1.state.client.clock_diff_samples.len(), 0);
     }
 
+    #[test]
+    fn test_clock_sync_stats() {
+        let (c1, c2) = connect();
+
+        let thread_c2 = thread::spawn(move || {
+            return c2.encrypt().unwrap();
+        });
+        let mut c1 = c1.encrypt().unwrap();
+        let _c2 = thread_c2.join().unwrap();
+
+        assert_eq!(c1.clock_sync_stats(), None);
+
+        c1.state.client.clock_diff_samples = vec![40, -10, 25, 5000, 30];
+        c1.state.client.max_delay = 3_000_000;
+
+        let stats = c1.clock_sync_stats().unwrap();
+        assert_eq!(stats.sample_count, 5);
+        assert_eq!(stats.median_diff, 30);
+        assert_eq!(stats.min_diff, -10);
+        assert_eq!(stats.max_diff, 5000);
+        assert_eq!(stats.max_delay, Duration::from_millis(3));
+
+        // the samples keep their order for the upgrade
+        assert_eq!(c1.state.client.clock_diff_samples, vec![40, -10, 25, 5000, 30]);
+    }
+
+    #[test]
+    fn test_handshake_phases() {
+        let (mut c1, mut c2) = connect();
+        let (sender_1, receiver_1) = mpsc::channel();
+        let (sender_2, receiver_2) = mpsc::channel();
+        c1.set_phase_sender(Some(sender_1));
+        c2.set_phase_sender(Some(sender_2));
+
+        let thread_c2 = thread::spawn(move || {
+            let mut c2 = c2.encrypt().unwrap();
+            c2.collect_samples(3).unwrap();
+        });
+
+        let mut c1 = c1.encrypt().unwrap();
+        c1.provide_samples().unwrap();
+        thread_c2.join().unwrap();
+        drop(c1);
+
+        let expected = vec![
+            HandshakePhase::RolesNegotiated,
+            HandshakePhase::KeysExchanged,
+            HandshakePhase::Encrypted,
+            HandshakePhase::SamplingProgress(1, 3),
+            HandshakePhase::SamplingProgress(2, 3),
+            HandshakePhase::SamplingProgress(3, 3),
+        ];
+        assert_eq!(receiver_1.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(receiver_2.iter().collect::<Vec<_>>(), expected);
+    }
+
     #[test]
     fn test_exchange_connect_time() {
         let (c1, c2) = connect();
@@ -1040,6 +2971,140 @@ mod tests {
         drop(c2_reader);
     }
 
+    #[test]
+    fn test_transform_udp_flows() {
+        let (c1, c2) = connect();
+
+        let thread_c2 = thread::spawn(move || {
+            let c2 = c2.encrypt().unwrap();
+            return c2.transform_to_slide_flows(3).unwrap();
+        });
+        let c1 = c1.encrypt().unwrap();
+        // the peers agree on the smaller number of flows
+        let (mut writer, _c1_reader) = c1.transform_to_slide_flows(2).unwrap();
+        let (_c2_writer, mut reader) = thread_c2.join().unwrap();
+        assert_eq!(writer.num_flows(), 2);
+        assert_eq!(reader.num_flows(), 2);
+
+        // chunks are striped over both flows and put back together by their position
+        let content: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let chunk_len = 5000;
+        for (pos, chunk) in content.chunks(chunk_len).enumerate() {
+            let mut msg = (pos as u32).to_be_bytes().to_vec();
+            msg.extend_from_slice(chunk);
+            writer.write_tagged(&msg, 1).unwrap();
+        }
+        writer.write(b"done").unwrap();
+
+        let timeout = Some(Duration::from_secs(2));
+        let mut received = vec![0u8; content.len()];
+        let mut chunks = 0;
+        let mut done = false;
+        while chunks < content.len() / chunk_len || !done {
+            let msg = reader.read(timeout).unwrap();
+            if msg == b"done".to_vec() {
+                done = true;
+                continue;
+            }
+            let pos = u32::from_be_bytes(msg[..4].try_into().unwrap()) as usize;
+            received[pos * chunk_len..pos * chunk_len + msg.len() - 4].copy_from_slice(&msg[4..]);
+            chunks += 1;
+        }
+        assert_eq!(received, content);
+    }
+
+    #[test]
+    fn test_discard_tagged_encrypted() {
+        let (c1, c2) = connect();
+
+        let thread_c2 = thread::spawn(move || {
+            let c2 = c2.encrypt().unwrap();
+            return c2.transform_to_slide().unwrap();
+        });
+        let c1 = c1.encrypt().unwrap();
+        let (mut writer, _c1_reader) = c1.transform_to_slide().unwrap();
+        let (_c2_writer, mut reader) = thread_c2.join().unwrap();
+
+        // messages of several blocks are dropped as a whole, the peer decrypts the remaining ones
+        writer.suspend();
+        for _ in 0..50 {
+            writer.write_tagged(&[7u8; 3000], 1).unwrap();
+            writer.write(b"keep").unwrap();
+        }
+        assert!(writer.discard_tagged(1));
+        writer.write_tagged(b"after", 1).unwrap();
+        writer.resume();
+
+        let timeout = Some(Duration::from_secs(2));
+        for _ in 0..50 {
+            assert_eq!(reader.read(timeout).unwrap(), b"keep".to_vec());
+        }
+        assert_eq!(reader.read(timeout).unwrap(), b"after".to_vec());
+        let err = reader.read(Some(Duration::from_millis(300))).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_transform_udp_no_flows() {
+        let (c1, c2) = connect();
+
+        let thread_c2 = thread::spawn(move || {
+            return c2.encrypt().unwrap();
+        });
+        let c1 = c1.encrypt().unwrap();
+        let _c2 = thread_c2.join().unwrap();
+
+        let err = c1.transform_to_slide_flows(0).err().unwrap();
+        let (_c1, err) = err.split();
+        let err = err.downcast_ref::<P2pError>().unwrap();
+        assert_eq!(err.kind(), &ErrorKind::InvalidFlowCount);
+    }
+
+    #[test]
+    fn test_transform_plain_udp() {
+        let (mut c1, mut c2) = connect();
+        let (i1, i2) = (Identity::generate(), Identity::generate());
+        let (p1, p2) = (i1.peer_identity(), i2.peer_identity());
+        c1.set_identity(i1);
+        c2.set_identity(i2);
+
+        let thread_c2 = thread::spawn(move || {
+            return c2.transform_to_slide().unwrap();
+        });
+        let (mut c1_writer, _c1_reader, c1_peer) = c1.transform_to_slide().unwrap();
+        let (_c2_writer, mut c2_reader, c2_peer) = thread_c2.join().unwrap();
+        assert_eq!(c1_peer, p2);
+        assert_eq!(c2_peer, p1);
+
+        let timeout = Some(Duration::from_secs(1));
+        let msg = b"Diese Nachricht wird unverschluesselt von c1 an c2 versendet.";
+        c1_writer.write(msg).unwrap();
+        assert_eq!(c2_reader.read(timeout).unwrap(), msg.to_vec());
+
+        // messages larger than a datagram are split into blocks and joined again
+        let large: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        c1_writer.write(&large).unwrap();
+        c1_writer.write(&[]).unwrap();
+        assert_eq!(c2_reader.read(timeout).unwrap(), large);
+        assert_eq!(c2_reader.read(timeout).unwrap(), Vec::<u8>::new());
+        assert_eq!(c1_writer.protocol_kind(), ProtocolKind::Udp);
+    }
+
+    #[test]
+    fn test_transform_plain_udp_app_id_mismatch() {
+        let (mut c1, mut c2) = connect();
+        c1.set_app_id(b"app-a/1");
+        c2.set_app_id(b"app-b/1");
+
+        let thread_c2 = thread::spawn(move || {
+            return c2.transform_to_slide().is_err();
+        });
+        let (_c1, err) = c1.transform_to_slide().err().unwrap().split();
+        let err = err.downcast_ref::<P2pError>().unwrap();
+        assert_eq!(err.kind(), &ErrorKind::AppIdMismatch);
+        assert!(thread_c2.join().unwrap());
+    }
+
     #[test]
     fn test_read_writer_encrypted_tcp() {
         let (c1, c2) = connect();
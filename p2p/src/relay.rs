@@ -0,0 +1,303 @@
+use crate::error::Error as P2pError;
+use crate::error::ErrorKind;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Marks relay control messages. Every other datagram of a paired peer is forwarded unchanged.
+pub const RELAY_MAGIC: [u8; 4] = *b"RDRL";
+/// The maximum length of a session token.
+pub const MAX_TOKEN_LENGTH: usize = 64;
+
+/// The time after which a session without any datagram is forgotten by `RelayServer`.
+/// Connected peers send keep alive messages far more often.
+pub const DEFAULT_ROUTE_TIMEOUT: Duration = Duration::from_secs(60);
+
+const REGISTER_INTERVAL: Duration = Duration::from_millis(100);
+const RECEIVE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Control messages of the relay. A message consists of `RELAY_MAGIC`, the type and its content.
+#[repr(u8)]
+enum RelayMessage {
+    /// Sent by a peer with the token of the session it wants to join.
+    Register = 0x01,
+    /// Sent by the relay once both peers of a session registered.
+    Paired = 0x02,
+}
+
+/// A relay forwarding datagrams between two peers which registered with the same token.
+/// The relay only sees the exchanged bytes, which are encrypted once the connection is encrypted.
+pub struct RelayServer {
+    udp_socket: UdpSocket,
+    route_timeout: Duration,
+}
+
+/// The destination of the datagrams of a paired peer.
+struct Route {
+    destination: SocketAddr,
+    last_seen: Instant,
+}
+
+impl RelayServer {
+    /// Creates a relay bound to the given port.
+    ///
+    /// # Arguments
+    ///
+    /// * `port` - The port to bind to. If `None` is passed, a random port is used.
+    pub fn new(port: Option<u16>) -> Result<RelayServer, P2pError> {
+        let bind_addr = SocketAddr::new(IpAddr::from(Ipv6Addr::from(0)), port.unwrap_or(0));
+        let udp_socket = UdpSocket::bind(bind_addr)?;
+
+        Ok(RelayServer {
+            udp_socket,
+            route_timeout: DEFAULT_ROUTE_TIMEOUT,
+        })
+    }
+
+    /// Sets the time after which a session is forgotten if neither peer sent a datagram,
+    /// and after which an unanswered registration is dropped. Defaults to `DEFAULT_ROUTE_TIMEOUT`.
+    pub fn set_route_timeout(&mut self, route_timeout: Duration) {
+        self.route_timeout = route_timeout;
+    }
+
+    /// Returns the local port number the relay is bound to.
+    pub fn get_port(&self) -> u16 {
+        // program should panic if this fails
+        self.udp_socket.local_addr().unwrap().port()
+    }
+
+    /// Pairs registering peers and forwards their datagrams until `stop` is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `P2pError` if the socket fails.
+    pub fn run(self, stop: &AtomicBool) -> Result<(), P2pError> {
+        let mut waiting: HashMap<Vec<u8>, (SocketAddr, Instant)> = HashMap::new();
+        let mut routes: HashMap<SocketAddr, Route> = HashMap::new();
+        let mut buffer = vec![0u8; u16::MAX as usize];
+        self.udp_socket.set_read_timeout(Some(RECEIVE_INTERVAL))?;
+
+        while !stop.load(Ordering::SeqCst) {
+            self.expire(&mut waiting, &mut routes);
+
+            let (size, source) = match self.udp_socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    continue
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let msg = &buffer[..size];
+
+            let token = match read_register_msg(msg) {
+                Some(token) => token,
+                None => {
+                    if let Some(route) = routes.get_mut(&source) {
+                        route.last_seen = Instant::now();
+                        self.udp_socket.send_to(msg, route.destination)?;
+                    }
+                    continue;
+                }
+            };
+
+            // the registration is repeated until the peer received the answer
+            if routes.contains_key(&source) {
+                self.udp_socket.send_to(&create_paired_msg(), source)?;
+                continue;
+            }
+
+            match waiting.get(token) {
+                Some(&(other, _)) if other != source => {
+                    waiting.remove(token);
+                    let now = Instant::now();
+                    routes.insert(source, Route { destination: other, last_seen: now });
+                    routes.insert(other, Route { destination: source, last_seen: now });
+                    println!("[RELAY] paired {} with {}", source, other);
+
+                    self.udp_socket.send_to(&create_paired_msg(), source)?;
+                    self.udp_socket.send_to(&create_paired_msg(), other)?;
+                }
+                _ => {
+                    waiting.insert(token.to_vec(), (source, Instant::now()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops registrations older than the route timeout and sessions in which neither peer sent for that long.
+    fn expire(
+        &self,
+        waiting: &mut HashMap<Vec<u8>, (SocketAddr, Instant)>,
+        routes: &mut HashMap<SocketAddr, Route>,
+    ) {
+        waiting.retain(|_, (_, registered)| registered.elapsed() < self.route_timeout);
+
+        let expired: Vec<SocketAddr> = routes
+            .iter()
+            .filter(|(_, route)| route.last_seen.elapsed() >= self.route_timeout)
+            .filter(|(_, route)| {
+                routes
+                    .get(&route.destination)
+                    .map_or(true, |back| back.last_seen.elapsed() >= self.route_timeout)
+            })
+            .map(|(source, _)| *source)
+            .collect();
+
+        for source in expired {
+            if routes.remove(&source).is_some() {
+                println!("[RELAY] expired {}", source);
+            }
+        }
+    }
+}
+
+/// Registers the socket at the relay and waits until the peer registered with the same token.
+///
+/// # Arguments
+///
+/// * `udp_socket` - The unconnected socket used for the connection.
+/// * `relay` - The address of the relay.
+/// * `token` - The token both peers agreed on.
+/// * `timeout` - The maximum time to wait for the peer. If `None` is passed, the method waits until `cancel` is set.
+/// * `cancel` - A flag which aborts the registration once it is set to `true`.
+///
+/// # Errors
+///
+/// Returns an error of kind `Other` if the token is empty or longer than `MAX_TOKEN_LENGTH`,
+/// an error of kind `TimedOut` if the peer did not register in time
+/// and an error of kind `Cancelled` if the registration was aborted.
+pub(crate) fn register(
+    udp_socket: &UdpSocket,
+    relay: SocketAddr,
+    token: &[u8],
+    timeout: Option<Duration>,
+    cancel: &AtomicBool,
+) -> Result<(), P2pError> {
+    if token.is_empty() || token.len() > MAX_TOKEN_LENGTH {
+        return Err(P2pError::new(ErrorKind::Other));
+    }
+
+    let register_msg = create_register_msg(token);
+    let start = Instant::now();
+    let mut register_time: Option<Instant> = None;
+    let mut buffer = [0u8; 16];
+    udp_socket.set_read_timeout(Some(RECEIVE_INTERVAL))?;
+
+    loop {
+        if cancel.load(Ordering::SeqCst) {
+            return Err(P2pError::new(ErrorKind::Cancelled));
+        }
+
+        if let Some(timeout) = timeout {
+            if start.elapsed() > timeout {
+                return Err(P2pError::new(ErrorKind::TimedOut));
+            }
+        }
+
+        if register_time.map_or(true, |time| time.elapsed() > REGISTER_INTERVAL) {
+            udp_socket.send_to(&register_msg, relay)?;
+            register_time = Some(Instant::now());
+        }
+
+        match udp_socket.recv_from(&mut buffer) {
+            Ok((size, source)) => {
+                if source == relay && buffer[..size] == create_paired_msg()[..] {
+                    return Ok(());
+                }
+            }
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn create_register_msg(token: &[u8]) -> Vec<u8> {
+    let mut msg = RELAY_MAGIC.to_vec();
+    msg.push(RelayMessage::Register as u8);
+    msg.extend_from_slice(token);
+
+    return msg;
+}
+
+fn create_paired_msg() -> Vec<u8> {
+    let mut msg = RELAY_MAGIC.to_vec();
+    msg.push(RelayMessage::Paired as u8);
+
+    return msg;
+}
+
+/// Returns the token if the message is a registration.
+fn read_register_msg(msg: &[u8]) -> Option<&[u8]> {
+    if msg.len() <= RELAY_MAGIC.len() + 1
+        || msg[..RELAY_MAGIC.len()] != RELAY_MAGIC
+        || msg[RELAY_MAGIC.len()] != RelayMessage::Register as u8
+    {
+        return None;
+    }
+
+    let token = &msg[RELAY_MAGIC.len() + 1..];
+    if token.len() > MAX_TOKEN_LENGTH {
+        return None;
+    }
+
+    return Some(token);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::thread::sleep;
+
+    #[test]
+    fn test_register_msg() {
+        let msg = create_register_msg(b"session");
+        assert_eq!(read_register_msg(&msg), Some(b"session".as_slice()));
+
+        assert_eq!(read_register_msg(&create_paired_msg()), None);
+        assert_eq!(read_register_msg(&[0x02, 0x00, 0x00]), None);
+        assert_eq!(read_register_msg(&create_register_msg(&[1u8; 65])), None);
+    }
+
+    #[test]
+    fn test_idle_routes_expire() {
+        let mut relay = RelayServer::new(None).unwrap();
+        relay.set_route_timeout(Duration::from_millis(300));
+        let relay_addr = SocketAddr::new(IpAddr::from(Ipv6Addr::LOCALHOST), relay.get_port());
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_relay = stop.clone();
+        let relay_thread = thread::spawn(move || relay.run(&stop_relay).is_ok());
+
+        let peer = |token: &'static [u8]| {
+            thread::spawn(move || {
+                let socket = UdpSocket::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+                register(&socket, relay_addr, token, Some(Duration::from_secs(2)), &AtomicBool::new(false)).unwrap();
+                socket
+            })
+        };
+        let (p1, p2) = (peer(b"session"), peer(b"session"));
+        let (p1, p2) = (p1.join().unwrap(), p2.join().unwrap());
+        // late answers to repeated registrations
+        sleep(Duration::from_millis(50));
+        while p2.recv(&mut [0u8; 16]).is_ok() {}
+        p2.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+
+        let mut buffer = [0u8; 16];
+        p1.send_to(b"data", relay_addr).unwrap();
+        let size = p2.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"data");
+
+        // neither peer sends, the session is forgotten
+        sleep(Duration::from_millis(500));
+        p1.send_to(b"data", relay_addr).unwrap();
+        assert!(p2.recv(&mut buffer).is_err());
+
+        stop.store(true, Ordering::SeqCst);
+        assert!(relay_thread.join().unwrap());
+    }
+}
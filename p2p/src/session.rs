@@ -0,0 +1,86 @@
+//! Export of the key material of an encrypted session, for debugging only.
+//!
+//! **This is insecure.** Anybody holding the exported key material can decrypt all traffic of the
+//! session. The module is only compiled with the `debug-crypto` feature, which must never be enabled
+//! in a release build.
+
+use crate::error::Error as P2pError;
+use crate::error::ErrorKind;
+use dryoc::dryocbox::Bytes;
+use dryoc::dryocstream::{DryocStream, Pull};
+use dryoc::kx::SessionKey;
+
+/// Length of a session key.
+const SESSION_KEY_LEN: usize = 32;
+/// Length of the header of an encryption stream.
+const STREAM_HEADER_LEN: usize = 24;
+/// Length of exported session secrets, see `SessionSecrets::to_bytes`.
+pub const SESSION_SECRETS_LEN: usize = 2 * SESSION_KEY_LEN + 2 * STREAM_HEADER_LEN;
+
+/// Key material of an encrypted session, as exported by `Connection::export_session`.
+///
+/// **Insecure:** allows decrypting all traffic of the session. Only use it to inspect captured ciphertext while debugging.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionSecrets {
+    /// Key of the stream received from the peer.
+    pub decrypt_key: SessionKey,
+    /// Key of the stream sent to the peer.
+    pub encrypt_key: SessionKey,
+    /// Header of the stream received from the peer.
+    pub pull_header: Vec<u8>,
+    /// Header of the stream sent to the peer.
+    pub push_header: Vec<u8>,
+}
+
+impl SessionSecrets {
+    /// Creates a decryptor for the messages received from the peer.
+    /// The decryptor starts at the beginning of the stream, so all captured blocks have to be pulled in order.
+    pub fn decryptor(&self) -> DryocStream<Pull> {
+        DryocStream::init_pull(&self.decrypt_key, &self.pull_header)
+    }
+
+    /// Creates a decryptor for the messages sent to the peer.
+    /// The decryptor starts at the beginning of the stream, so all captured blocks have to be pulled in order.
+    pub fn peer_decryptor(&self) -> DryocStream<Pull> {
+        DryocStream::init_pull(&self.encrypt_key, &self.push_header)
+    }
+
+    /// Serializes the secrets as decrypt key, encrypt key, pull header and push header.
+    ///
+    /// # Returns
+    ///
+    /// Returns `SESSION_SECRETS_LEN` bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SESSION_SECRETS_LEN);
+        bytes.extend_from_slice(self.decrypt_key.as_slice());
+        bytes.extend_from_slice(self.encrypt_key.as_slice());
+        bytes.extend_from_slice(&self.pull_header);
+        bytes.extend_from_slice(&self.push_header);
+        return bytes;
+    }
+
+    /// Reads secrets serialized by `to_bytes`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `P2pError` of kind `InvalidSessionSecrets` if `bytes` has the wrong length.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SessionSecrets, P2pError> {
+        if bytes.len() != SESSION_SECRETS_LEN {
+            return Err(P2pError::new(ErrorKind::InvalidSessionSecrets));
+        }
+
+        let (decrypt_key, rest) = bytes.split_at(SESSION_KEY_LEN);
+        let (encrypt_key, rest) = rest.split_at(SESSION_KEY_LEN);
+        let (pull_header, push_header) = rest.split_at(STREAM_HEADER_LEN);
+
+        let decrypt_key: [u8; SESSION_KEY_LEN] = decrypt_key.try_into()?;
+        let encrypt_key: [u8; SESSION_KEY_LEN] = encrypt_key.try_into()?;
+
+        return Ok(SessionSecrets {
+            decrypt_key: SessionKey::from(decrypt_key),
+            encrypt_key: SessionKey::from(encrypt_key),
+            pull_header: pull_header.to_vec(),
+            push_header: push_header.to_vec(),
+        });
+    }
+}
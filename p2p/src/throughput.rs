@@ -0,0 +1,252 @@
+use crate::client::ClientWriter;
+use crate::error::Error as P2pError;
+use crate::error::ErrorKind;
+use std::time::{Duration, Instant};
+
+/// Estimates how long a transfer takes.
+///
+/// # Arguments
+///
+/// * `size` - The number of bytes to transfer, e.g. the size of an offer.
+/// * `throughput` - The throughput in bytes per second, e.g. measured by `probe_throughput`.
+///
+/// # Returns
+///
+/// Returns the estimated duration, or `Duration::MAX` if the throughput is not positive.
+pub fn estimate_transfer(size: u64, throughput: f64) -> Duration {
+    if throughput.is_nan() || throughput <= 0.0 {
+        return Duration::MAX;
+    }
+
+    return Duration::try_from_secs_f64(size as f64 / throughput).unwrap_or(Duration::MAX);
+}
+
+/// Measures the throughput of a connection by sending a burst of probes and timing their acknowledgements.
+/// The peer acknowledges and drops the probes, see `ClientWriter::probe`, so they do not reach its reader.
+/// Lost probes are missing from the acknowledged bytes and lower the measured rate.
+///
+/// # Arguments
+///
+/// * `writer` - The writer of the connection.
+/// * `msg_len` - The length of each probe, usually the negotiated chunk size.
+/// * `count` - The number of probes.
+/// * `timeout` - The time after which probes which are not acknowledged count as lost.
+///
+/// # Returns
+///
+/// Returns the acknowledged bytes per second, or a `P2pError` of kind `InvalidProbeSize` if `msg_len` or `count` is zero,
+/// of kind `ProbeUnsupported` if the writer cannot send probes, or the error of the connection.
+pub fn probe_throughput<W: ClientWriter>(
+    writer: &mut W,
+    msg_len: usize,
+    count: usize,
+    timeout: Duration,
+) -> Result<f64, P2pError> {
+    if msg_len == 0 || count == 0 {
+        return Err(P2pError::new(ErrorKind::InvalidProbeSize));
+    }
+
+    let start = Instant::now();
+    let acknowledged = writer.probe(msg_len, count, timeout)?;
+
+    let elapsed = start.elapsed().as_secs_f64().max(f64::EPSILON);
+    return Ok(acknowledged as f64 / elapsed);
+}
+
+/// Picks the chunk size with the highest throughput by probing each candidate with `probe_throughput`.
+/// The candidates are probed in order until the time budget is used up. Each probe only waits for the rest of the budget,
+/// so a slow candidate can't hold up the tuning beyond the budget. Candidates whose probe fails are skipped, e.g. sizes the transport does not accept.
+///
+/// # Arguments
+///
+/// * `writer` - The writer of the connection.
+/// * `candidates` - The chunk sizes to probe.
+/// * `count` - The number of probes sent per candidate.
+/// * `budget` - The time the tuning may take.
+/// * `default` - The chunk size used if no candidate could be measured.
+///
+/// # Returns
+///
+/// Returns the candidate with the highest throughput, or `default` if none was measured.
+pub fn tune_chunk_size<W: ClientWriter>(
+    writer: &mut W,
+    candidates: &[usize],
+    count: usize,
+    budget: Duration,
+    default: usize,
+) -> usize {
+    let start = Instant::now();
+    let mut best: Option<(usize, f64)> = None;
+
+    for &candidate in candidates {
+        let remaining = budget.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            break;
+        }
+
+        let rate = match probe_throughput(writer, candidate, count, remaining) {
+            Ok(rate) => rate,
+            Err(_) => continue,
+        };
+
+        if best.map_or(true, |(_, best_rate)| rate > best_rate) {
+            best = Some((candidate, rate));
+        }
+    }
+
+    return best.map_or(default, |(candidate, _)| candidate);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::udp_send_wait::UdpWaitingClient as WaitingClient;
+    use crate::client::udp_slide::UdpWaitingClient;
+    use crate::client::{ActiveClient, ClientReader};
+    use crate::client::ProtocolKind;
+    use std::net::Ipv6Addr;
+    use std::thread;
+    use std::thread::sleep;
+
+    /// Transport with a fixed cost per message, where messages above `fragment_len` take much longer.
+    /// A probe is acknowledged once it was sent, probes which do not fit into the timeout are lost.
+    struct MockWriter {
+        per_msg: Duration,
+        fragment_len: usize,
+        fragment_penalty: Duration,
+        max_len: usize,
+    }
+
+    impl ClientWriter for MockWriter {
+        fn write(&mut self, msg: &[u8]) -> Result<(), P2pError> {
+            if msg.len() > self.max_len {
+                return Err(P2pError::new(ErrorKind::IllegalByteStream));
+            }
+
+            sleep(self.per_msg);
+            if msg.len() > self.fragment_len {
+                sleep(self.fragment_penalty);
+            }
+            Ok(())
+        }
+
+        fn set_write_timeout(&mut self, _timeout: Option<Duration>) {}
+
+        fn protocol_kind(&self) -> ProtocolKind {
+            ProtocolKind::Other
+        }
+
+        fn probe(&mut self, len: usize, count: usize, timeout: Duration) -> Result<u64, P2pError> {
+            let start = Instant::now();
+            let msg = vec![0u8; len];
+            let mut acknowledged = 0;
+            for _ in 0..count {
+                if start.elapsed() >= timeout {
+                    break;
+                }
+                self.write(&msg)?;
+                acknowledged += len as u64;
+            }
+            Ok(acknowledged)
+        }
+    }
+
+    fn mock_writer() -> MockWriter {
+        MockWriter {
+            per_msg: Duration::from_millis(1),
+            fragment_len: 16 * 1024,
+            fragment_penalty: Duration::from_millis(20),
+            max_len: 1024 * 1024,
+        }
+    }
+
+    #[test]
+    fn test_estimate_transfer() {
+        assert_eq!(estimate_transfer(10_000_000, 1_000_000.0), Duration::from_secs(10));
+        assert_eq!(estimate_transfer(500, 1000.0), Duration::from_millis(500));
+        assert_eq!(estimate_transfer(0, 1000.0), Duration::ZERO);
+        assert_eq!(estimate_transfer(1, 0.0), Duration::MAX);
+    }
+
+    #[test]
+    fn test_tune_chunk_size() {
+        let mut writer = mock_writer();
+        let budget = Duration::from_secs(5);
+        let candidates = [1024, 8 * 1024, 64 * 1024];
+
+        // small chunks pay the per message cost, large chunks the fragmentation penalty
+        assert_eq!(tune_chunk_size(&mut writer, &candidates, 4, budget, 1024), 8 * 1024);
+
+        // sizes the transport rejects are skipped
+        writer.max_len = 4 * 1024;
+        assert_eq!(tune_chunk_size(&mut writer, &candidates, 4, budget, 512), 1024);
+    }
+
+    #[test]
+    fn test_tune_chunk_size_fallback() {
+        let mut writer = mock_writer();
+
+        assert_eq!(tune_chunk_size(&mut writer, &[1024, 8 * 1024], 4, Duration::ZERO, 300), 300);
+        assert_eq!(tune_chunk_size(&mut writer, &[], 4, Duration::from_secs(1), 300), 300);
+
+        writer.max_len = 0;
+        assert_eq!(tune_chunk_size(&mut writer, &[1024], 4, Duration::from_secs(1), 300), 300);
+    }
+
+    #[test]
+    fn test_tune_chunk_size_budget() {
+        let mut writer = mock_writer();
+        let start = Instant::now();
+
+        // the first probe is cut short by the budget, the remaining candidates are not probed
+        let candidates = [64 * 1024, 1024, 8 * 1024];
+        let size = tune_chunk_size(&mut writer, &candidates, 100, Duration::from_millis(50), 300);
+
+        assert_eq!(size, 64 * 1024);
+        assert!(start.elapsed() < Duration::from_millis(200), "tuning took {:?}", start.elapsed());
+    }
+
+    #[test]
+    fn test_probe_throughput_loopback() {
+        let ipv6 = Ipv6Addr::from(1);
+        let timeout = Duration::from_secs(2);
+        let w1 = UdpWaitingClient::new(None).unwrap();
+        let w2 = UdpWaitingClient::new(None).unwrap();
+        let (p1, p2) = (w1.get_port(), w2.get_port());
+
+        let peer = thread::spawn(move || {
+            let mut c2 = w2.connect(ipv6, p1, Some(timeout), Some(timeout)).unwrap();
+            // the probes are dropped, only the message written after them arrives
+            let msg = c2.reader_ref().read(Some(timeout)).unwrap();
+            assert_eq!(msg, b"after probe");
+        });
+        let mut c1 = w1.connect(ipv6, p2, Some(timeout), Some(timeout)).unwrap();
+
+        let rate = probe_throughput(c1.writer_ref(), 1024, 16, timeout).unwrap();
+        c1.writer_ref().write(b"after probe").unwrap();
+        peer.join().unwrap();
+
+        assert!(rate > 1000.0, "implausible rate {}", rate);
+        assert!(rate.is_finite());
+
+        let err = probe_throughput(c1.writer_ref(), 0, 16, timeout).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::InvalidProbeSize);
+    }
+
+    #[test]
+    fn test_probe_unsupported() {
+        let ipv6 = Ipv6Addr::from(1);
+        let timeout = Duration::from_secs(2);
+        let w1 = WaitingClient::new(None).unwrap();
+        let w2 = WaitingClient::new(None).unwrap();
+        let (p1, p2) = (w1.get_port(), w2.get_port());
+
+        let peer = thread::spawn(move || w2.connect(ipv6, p1, Some(timeout), Some(timeout)).unwrap());
+        let mut c1 = w1.connect(ipv6, p2, Some(timeout), Some(timeout)).unwrap();
+        let _c2 = peer.join().unwrap();
+
+        // a stop and wait writer has no probes, its writes would reach the reader of the peer
+        let err = probe_throughput(c1.writer_ref(), 256, 16, timeout).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::ProbeUnsupported);
+    }
+}